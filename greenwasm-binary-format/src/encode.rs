@@ -0,0 +1,592 @@
+//! Encoder for the binary format.
+//!
+//! Every `encode_*` function mirrors the `parse_*` function for the same
+//! grammar rule, so that `parse(encode(x)) == x` holds for all inputs.
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+
+use CustomSection;
+
+type Out = Vec<u8>;
+
+// 5.1.3. Vectors
+fn encode_vec<B, F>(out: &mut Out, v: &[B], encode_b: F)
+    where F: Fn(&mut Out, &B)
+{
+    encode_u32(out, v.len() as u32);
+    for b in v {
+        encode_b(out, b);
+    }
+}
+
+// 5.2.1. Bytes
+fn encode_byte(out: &mut Out, b: &u8) {
+    out.push(*b);
+}
+
+// 5.2.2. Integers
+pub(crate) fn encode_uN(out: &mut Out, mut n: u64) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+pub(crate) fn encode_sN(out: &mut Out, mut n: i64) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        // Done once the remaining bits are pure sign extension
+        // of the sign bit (0x40) of the current byte.
+        if (n == 0 && b & 0x40 == 0) || (n == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn encode_u32(out: &mut Out, n: u32) { encode_uN(out, n as u64) }
+
+fn encode_i32(out: &mut Out, n: u32) { encode_sN(out, n as i32 as i64) }
+fn encode_i64(out: &mut Out, n: u64) { encode_sN(out, n as i64) }
+
+// 5.2.3. Floating-Point
+fn encode_f32(out: &mut Out, z: f32) {
+    out.extend_from_slice(&z.to_bits().to_le_bytes());
+}
+fn encode_f64(out: &mut Out, z: f64) {
+    out.extend_from_slice(&z.to_bits().to_le_bytes());
+}
+
+// 5.2.4. Names
+fn encode_name(out: &mut Out, name: &Name) {
+    encode_vec(out, name.as_bytes(), encode_byte);
+}
+
+// 5.3.1 Value Types
+fn encode_valtype(out: &mut Out, t: &ValType) {
+    out.push(match *t {
+        ValType::I32 => 0x7f,
+        ValType::I64 => 0x7e,
+        ValType::F32 => 0x7d,
+        ValType::F64 => 0x7c,
+    });
+}
+
+// 5.3.2 Result Types
+fn encode_blocktype(out: &mut Out, rt: &ResultType) {
+    match rt.get(0) {
+        None => out.push(0x40),
+        Some(t) => encode_valtype(out, t),
+    }
+}
+
+// 5.3.3 Function Types
+fn encode_functype(out: &mut Out, ft: &FuncType) {
+    out.push(0x60);
+    encode_vec(out, &ft.args, encode_valtype);
+    encode_vec(out, &ft.results, encode_valtype);
+}
+
+// 5.3.4 Limits
+fn encode_limits(out: &mut Out, limits: &Limits) {
+    match limits.max {
+        None => {
+            out.push(0x00);
+            encode_u32(out, limits.min);
+        }
+        Some(max) => {
+            out.push(0x01);
+            encode_u32(out, limits.min);
+            encode_u32(out, max);
+        }
+    }
+}
+
+// 5.3.5 Memory Types
+fn encode_memtype(out: &mut Out, mt: &MemType) {
+    encode_limits(out, &mt.limits);
+}
+
+// 5.3.6. Table Types
+fn encode_tabletype(out: &mut Out, tt: &TableType) {
+    encode_elemtype(out, &tt.elemtype);
+    encode_limits(out, &tt.limits);
+}
+fn encode_elemtype(out: &mut Out, et: &ElemType) {
+    match *et {
+        ElemType::AnyFunc => out.push(0x70),
+    }
+}
+
+// 5.3.7. Global Types
+fn encode_globaltype(out: &mut Out, gt: &GlobalType) {
+    encode_valtype(out, &gt.valtype);
+    encode_mut(out, &gt.mutability);
+}
+fn encode_mut(out: &mut Out, m: &Mut) {
+    out.push(match *m {
+        Mut::Const => 0x00,
+        Mut::Var => 0x01,
+    });
+}
+
+// 5.4. Instructions
+
+// NB: Like the parser, the encoder does not recurse for nested control
+// instructions, and instead keeps a stack of pending
+// instruction sequences in the heap.
+
+fn encode_instrs_end(out: &mut Out, instrs: &[Instr]) {
+    // Each entry is a sequence still to be written, followed by the
+    // byte that terminates it (`else` or `end`).
+    let mut stack: Vec<(&[Instr], u8)> = vec![(instrs, 0x0B)];
+
+    'seqs: while let Some((seq, term)) = stack.pop() {
+        let mut rest = seq;
+        while let Some((instr, tail)) = rest.split_first() {
+            rest = tail;
+            match *instr {
+                Instr::Block(ref rt, ref body) => {
+                    out.push(0x02);
+                    encode_blocktype(out, rt);
+                    stack.push((rest, term));
+                    stack.push((body, 0x0B));
+                    continue 'seqs;
+                }
+                Instr::Loop(ref rt, ref body) => {
+                    out.push(0x03);
+                    encode_blocktype(out, rt);
+                    stack.push((rest, term));
+                    stack.push((body, 0x0B));
+                    continue 'seqs;
+                }
+                Instr::IfElse(ref rt, ref if_body, ref else_body) => {
+                    out.push(0x04);
+                    encode_blocktype(out, rt);
+                    stack.push((rest, term));
+                    if else_body.is_empty() {
+                        stack.push((if_body, 0x0B));
+                    } else {
+                        stack.push((else_body, 0x0B));
+                        stack.push((if_body, 0x05));
+                    }
+                    continue 'seqs;
+                }
+                ref instr => encode_plain_instr(out, instr),
+            }
+        }
+        out.push(term);
+    }
+}
+
+fn encode_plain_instr(out: &mut Out, instr: &Instr) {
+    use self::Instr::*;
+
+    macro_rules! ins {
+        ($b:expr) => ({ out.push($b); });
+        ($b:expr; $f:ident($x:expr)) => ({ out.push($b); $f(out, $x); });
+    }
+
+    match *instr {
+        // 5.4.1. Control Instructions
+        Unreachable => ins!(0x00),
+        Nop => ins!(0x01),
+        Block(..) | Loop(..) | IfElse(..) => {
+            unreachable!("structured instructions are handled by encode_instrs_end")
+        }
+        Br(l) => ins!(0x0c; encode_labelidx(l)),
+        BrIf(l) => ins!(0x0d; encode_labelidx(l)),
+        BrTable(ref ls, lN) => {
+            out.push(0x0e);
+            encode_vec(out, ls, |out, l| encode_labelidx(out, *l));
+            encode_labelidx(out, lN);
+        }
+        Return => ins!(0x0f),
+        Call(x) => ins!(0x10; encode_funcidx(x)),
+        CallIndirect(x) => {
+            out.push(0x11);
+            encode_typeidx(out, x);
+            out.push(0x00);
+        }
+
+        // 5.4.2. Parametric Instructions
+        Drop => ins!(0x1A),
+        Select => ins!(0x1B),
+
+        // 5.4.3. Variable Instructions
+        GetLocal(x) => ins!(0x20; encode_localidx(x)),
+        SetLocal(x) => ins!(0x21; encode_localidx(x)),
+        TeeLocal(x) => ins!(0x22; encode_localidx(x)),
+        GetGlobal(x) => ins!(0x23; encode_globalidx(x)),
+        SetGlobal(x) => ins!(0x24; encode_globalidx(x)),
+
+        // 5.4.4. Memory Instructions
+        I32Load(m) => ins!(0x28; encode_memarg(m)),
+        I64Load(m) => ins!(0x29; encode_memarg(m)),
+        F32Load(m) => ins!(0x2A; encode_memarg(m)),
+        F64Load(m) => ins!(0x2B; encode_memarg(m)),
+
+        I32Load8S(m) => ins!(0x2C; encode_memarg(m)),
+        I32Load8U(m) => ins!(0x2D; encode_memarg(m)),
+        I32Load16S(m) => ins!(0x2E; encode_memarg(m)),
+        I32Load16U(m) => ins!(0x2F; encode_memarg(m)),
+
+        I64Load8S(m) => ins!(0x30; encode_memarg(m)),
+        I64Load8U(m) => ins!(0x31; encode_memarg(m)),
+        I64Load16S(m) => ins!(0x32; encode_memarg(m)),
+        I64Load16U(m) => ins!(0x33; encode_memarg(m)),
+        I64Load32S(m) => ins!(0x34; encode_memarg(m)),
+        I64Load32U(m) => ins!(0x35; encode_memarg(m)),
+
+        I32Store(m) => ins!(0x36; encode_memarg(m)),
+        I64Store(m) => ins!(0x37; encode_memarg(m)),
+        F32Store(m) => ins!(0x38; encode_memarg(m)),
+        F64Store(m) => ins!(0x39; encode_memarg(m)),
+
+        I32Store8(m) => ins!(0x3A; encode_memarg(m)),
+        I32Store16(m) => ins!(0x3B; encode_memarg(m)),
+
+        I64Store8(m) => ins!(0x3C; encode_memarg(m)),
+        I64Store16(m) => ins!(0x3D; encode_memarg(m)),
+        I64Store32(m) => ins!(0x3E; encode_memarg(m)),
+
+        CurrentMemory => { out.push(0x3F); out.push(0x00); }
+        GrowMemory => { out.push(0x40); out.push(0x00); }
+
+        // 5.4.5. Numeric Instructions
+        I32Const(n) => ins!(0x41; encode_i32(n)),
+        I64Const(n) => ins!(0x42; encode_i64(n)),
+        F32Const(z) => ins!(0x43; encode_f32(z)),
+        F64Const(z) => ins!(0x44; encode_f64(z)),
+
+        I32EqZ => ins!(0x45),
+        I32Eq => ins!(0x46),
+        I32Ne => ins!(0x47),
+        I32LtS => ins!(0x48),
+        I32LtU => ins!(0x49),
+        I32GtS => ins!(0x4A),
+        I32GtU => ins!(0x4B),
+        I32LeS => ins!(0x4C),
+        I32LeU => ins!(0x4D),
+        I32GeS => ins!(0x4E),
+        I32GeU => ins!(0x4F),
+
+        I64EqZ => ins!(0x50),
+        I64Eq => ins!(0x51),
+        I64Ne => ins!(0x52),
+        I64LtS => ins!(0x53),
+        I64LtU => ins!(0x54),
+        I64GtS => ins!(0x55),
+        I64GtU => ins!(0x56),
+        I64LeS => ins!(0x57),
+        I64LeU => ins!(0x58),
+        I64GeS => ins!(0x59),
+        I64GeU => ins!(0x5A),
+
+        F32Eq => ins!(0x5B),
+        F32Ne => ins!(0x5C),
+        F32Lt => ins!(0x5D),
+        F32Gt => ins!(0x5E),
+        F32Le => ins!(0x5F),
+        F32Ge => ins!(0x60),
+
+        F64Eq => ins!(0x61),
+        F64Ne => ins!(0x62),
+        F64Lt => ins!(0x63),
+        F64Gt => ins!(0x64),
+        F64Le => ins!(0x65),
+        F64Ge => ins!(0x66),
+
+        I32Clz => ins!(0x67),
+        I32Ctz => ins!(0x68),
+        I32Popcnt => ins!(0x69),
+        I32Add => ins!(0x6A),
+        I32Sub => ins!(0x6B),
+        I32Mul => ins!(0x6C),
+        I32DivS => ins!(0x6D),
+        I32DivU => ins!(0x6E),
+        I32RemS => ins!(0x6F),
+        I32RemU => ins!(0x70),
+        I32And => ins!(0x71),
+        I32Or => ins!(0x72),
+        I32Xor => ins!(0x73),
+        I32Shl => ins!(0x74),
+        I32ShrS => ins!(0x75),
+        I32ShrU => ins!(0x76),
+        I32Rotl => ins!(0x77),
+        I32Rotr => ins!(0x78),
+
+        I64Clz => ins!(0x79),
+        I64Ctz => ins!(0x7A),
+        I64Popcnt => ins!(0x7B),
+        I64Add => ins!(0x7C),
+        I64Sub => ins!(0x7D),
+        I64Mul => ins!(0x7E),
+        I64DivS => ins!(0x7F),
+        I64DivU => ins!(0x80),
+        I64RemS => ins!(0x81),
+        I64RemU => ins!(0x82),
+        I64And => ins!(0x83),
+        I64Or => ins!(0x84),
+        I64Xor => ins!(0x85),
+        I64Shl => ins!(0x86),
+        I64ShrS => ins!(0x87),
+        I64ShrU => ins!(0x88),
+        I64Rotl => ins!(0x89),
+        I64Rotr => ins!(0x8A),
+
+        F32Abs => ins!(0x8B),
+        F32Neg => ins!(0x8C),
+        F32Ceil => ins!(0x8D),
+        F32Floor => ins!(0x8E),
+        F32Trunc => ins!(0x8F),
+        F32Nearest => ins!(0x90),
+        F32Sqrt => ins!(0x91),
+        F32Add => ins!(0x92),
+        F32Sub => ins!(0x93),
+        F32Mul => ins!(0x94),
+        F32Div => ins!(0x95),
+        F32Min => ins!(0x96),
+        F32Max => ins!(0x97),
+        F32CopySign => ins!(0x98),
+
+        F64Abs => ins!(0x99),
+        F64Neg => ins!(0x9A),
+        F64Ceil => ins!(0x9B),
+        F64Floor => ins!(0x9C),
+        F64Trunc => ins!(0x9D),
+        F64Nearest => ins!(0x9E),
+        F64Sqrt => ins!(0x9F),
+        F64Add => ins!(0xA0),
+        F64Sub => ins!(0xA1),
+        F64Mul => ins!(0xA2),
+        F64Div => ins!(0xA3),
+        F64Min => ins!(0xA4),
+        F64Max => ins!(0xA5),
+        F64CopySign => ins!(0xA6),
+
+        I32WrapI64 => ins!(0xA7),
+        I32TruncSF32 => ins!(0xA8),
+        I32TruncUF32 => ins!(0xA9),
+        I32TruncSF64 => ins!(0xAA),
+        I32TruncUF64 => ins!(0xAB),
+
+        I64ExtendSI32 => ins!(0xAC),
+        I64ExtendUI32 => ins!(0xAD),
+        I64TruncSF32 => ins!(0xAE),
+        I64TruncUF32 => ins!(0xAF),
+        I64TruncSF64 => ins!(0xB0),
+        I64TruncUF64 => ins!(0xB1),
+
+        F32ConvertSI32 => ins!(0xB2),
+        F32ConvertUI32 => ins!(0xB3),
+        F32ConvertSI64 => ins!(0xB4),
+        F32ConvertUI64 => ins!(0xB5),
+        F32DemoteF64 => ins!(0xB6),
+
+        F64ConvertSI32 => ins!(0xB7),
+        F64ConvertUI32 => ins!(0xB8),
+        F64ConvertSI64 => ins!(0xB9),
+        F64ConvertUI64 => ins!(0xBA),
+        F64PromoteF32 => ins!(0xBB),
+
+        I32ReinterpretF32 => ins!(0xBC),
+        I64ReinterpretF64 => ins!(0xBD),
+        F32ReinterpretI32 => ins!(0xBE),
+        F64ReinterpretI64 => ins!(0xBF),
+
+        I32Extend8S => ins!(0xC0),
+        I32Extend16S => ins!(0xC1),
+        I64Extend8S => ins!(0xC2),
+        I64Extend16S => ins!(0xC3),
+        I64Extend32S => ins!(0xC4),
+    }
+}
+
+fn encode_memarg(out: &mut Out, m: Memarg) {
+    encode_u32(out, m.align);
+    encode_u32(out, m.offset);
+}
+
+// 5.4.6. Expressions
+fn encode_expr(out: &mut Out, e: &Expr) {
+    encode_instrs_end(out, &e.body);
+}
+
+// 5.5.1. Indices
+fn encode_typeidx(out: &mut Out, x: TypeIdx)     { encode_u32(out, x.0) }
+fn encode_funcidx(out: &mut Out, x: FuncIdx)     { encode_u32(out, x.0) }
+fn encode_tableidx(out: &mut Out, x: TableIdx)   { encode_u32(out, x.0) }
+fn encode_memidx(out: &mut Out, x: MemIdx)       { encode_u32(out, x.0) }
+fn encode_globalidx(out: &mut Out, x: GlobalIdx) { encode_u32(out, x.0) }
+fn encode_localidx(out: &mut Out, x: LocalIdx)   { encode_u32(out, x.0) }
+fn encode_labelidx(out: &mut Out, x: LabelIdx)   { encode_u32(out, x.0) }
+
+// 5.5.1. Sections
+fn encode_section<F>(out: &mut Out, N: u8, encode_B: F)
+    where F: FnOnce(&mut Out)
+{
+    let mut cont = vec![];
+    encode_B(&mut cont);
+
+    out.push(N);
+    encode_u32(out, cont.len() as u32);
+    out.extend(cont);
+}
+
+// Sections whose content is a vector are omitted entirely if it is empty,
+// which the parser reads back as the same empty vector.
+fn encode_vec_section<B, F>(out: &mut Out, N: u8, v: &[B], encode_b: F)
+    where F: Fn(&mut Out, &B)
+{
+    if !v.is_empty() {
+        encode_section(out, N, |out| encode_vec(out, v, encode_b));
+    }
+}
+
+// 5.5.3. Custom Section
+fn encode_customsec(out: &mut Out, cs: &CustomSection) {
+    encode_section(out, 0, |out| {
+        encode_name(out, &cs.name);
+        out.extend_from_slice(&cs.bytes);
+    });
+}
+
+// 5.5.5. Import Section
+fn encode_import(out: &mut Out, import: &Import) {
+    encode_name(out, &import.module);
+    encode_name(out, &import.name);
+    encode_importdesc(out, &import.desc);
+}
+fn encode_importdesc(out: &mut Out, desc: &ImportDesc) {
+    match *desc {
+        ImportDesc::Func(x) => { out.push(0x00); encode_typeidx(out, x); }
+        ImportDesc::Table(ref tt) => { out.push(0x01); encode_tabletype(out, tt); }
+        ImportDesc::Mem(ref mt) => { out.push(0x02); encode_memtype(out, mt); }
+        ImportDesc::Global(ref gt) => { out.push(0x03); encode_globaltype(out, gt); }
+    }
+}
+
+// 5.5.7. Table Section
+fn encode_table(out: &mut Out, table: &Table) {
+    encode_tabletype(out, &table.type_);
+}
+
+// 5.5.8. Memory Section
+fn encode_mem(out: &mut Out, mem: &Mem) {
+    encode_memtype(out, &mem.type_);
+}
+
+// 5.5.9. Global Section
+fn encode_global(out: &mut Out, global: &Global) {
+    encode_globaltype(out, &global.type_);
+    encode_expr(out, &global.init);
+}
+
+// 5.5.10. Export Section
+fn encode_export(out: &mut Out, export: &Export) {
+    encode_name(out, &export.name);
+    encode_exportdesc(out, &export.desc);
+}
+fn encode_exportdesc(out: &mut Out, desc: &ExportDesc) {
+    match *desc {
+        ExportDesc::Func(x) => { out.push(0x00); encode_funcidx(out, x); }
+        ExportDesc::Table(x) => { out.push(0x01); encode_tableidx(out, x); }
+        ExportDesc::Mem(x) => { out.push(0x02); encode_memidx(out, x); }
+        ExportDesc::Global(x) => { out.push(0x03); encode_globalidx(out, x); }
+    }
+}
+
+// 5.5.11. Start Section
+fn encode_start(out: &mut Out, start: &Start) {
+    encode_funcidx(out, start.func);
+}
+
+// 5.5.12. Element Section
+fn encode_elem(out: &mut Out, elem: &Elem) {
+    encode_tableidx(out, elem.table);
+    encode_expr(out, &elem.offset);
+    encode_vec(out, &elem.init, |out, y| encode_funcidx(out, *y));
+}
+
+// 5.5.13. Code Section
+fn encode_locals(out: &mut Out, locals: &[ValType]) {
+    // Compress runs of equal types into (n, t) pairs
+    let mut runs: Vec<(u32, ValType)> = vec![];
+    for &t in locals {
+        match runs.last_mut() {
+            Some(&mut (ref mut n, t2)) if t2 == t => *n += 1,
+            _ => runs.push((1, t)),
+        }
+    }
+    encode_vec(out, &runs, |out, &(n, t)| {
+        encode_u32(out, n);
+        encode_valtype(out, &t);
+    });
+}
+fn encode_func(out: &mut Out, func: &Func) {
+    encode_locals(out, &func.locals);
+    encode_expr(out, &func.body);
+}
+fn encode_code(out: &mut Out, func: &Func) {
+    let mut code = vec![];
+    encode_func(&mut code, func);
+
+    encode_u32(out, code.len() as u32);
+    out.extend(code);
+}
+
+// 5.5.14. Data Section
+fn encode_data(out: &mut Out, data: &Data) {
+    encode_memidx(out, data.data);
+    encode_expr(out, &data.offset);
+    encode_vec(out, &data.init, encode_byte);
+}
+
+// 5.5.15. Modules
+pub fn encode_binary_format(module: &Module, customs: &[CustomSection]) -> Vec<u8> {
+    let mut out = vec![];
+
+    // magic and version
+    out.extend_from_slice(&[0x00, 0x61, 0x73, 0x6D]);
+    out.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+
+    let funcs = &module.funcs;
+
+    for N in 0..12u8 {
+        let out = &mut out;
+        match N {
+            0 => (),
+            1 => encode_vec_section(out, 1, &module.types, encode_functype),
+            2 => encode_vec_section(out, 2, &module.imports, encode_import),
+            3 => encode_vec_section(out, 3, funcs, |out, f| encode_typeidx(out, f.type_)),
+            4 => encode_vec_section(out, 4, &module.tables, encode_table),
+            5 => encode_vec_section(out, 5, &module.mems, encode_mem),
+            6 => encode_vec_section(out, 6, &module.globals, encode_global),
+            7 => encode_vec_section(out, 7, &module.exports, encode_export),
+            8 => if let Some(ref start) = module.start {
+                encode_section(out, 8, |out| encode_start(out, start));
+            },
+            9 => encode_vec_section(out, 9, &module.elem, encode_elem),
+            10 => encode_vec_section(out, 10, funcs, encode_code),
+            11 => encode_vec_section(out, 11, &module.data, encode_data),
+            _ => unreachable!(),
+        }
+
+        // NB: Sections after an unknown id are written at the end
+        for cs in customs.iter().filter(|cs| cs.after == N || (N == 11 && cs.after > 11)) {
+            encode_customsec(out, cs);
+        }
+    }
+
+    out
+}
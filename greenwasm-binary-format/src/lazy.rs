@@ -0,0 +1,144 @@
+//! Parsing of a module without decoding the function bodies.
+//!
+//! `parse_binary_format_lazy` only records where each entry of the code
+//! section is located in the input, so that inspecting the other parts of a
+//! large module does not pay for building the instruction trees.
+//! The bodies are decoded on demand with `LazyFunc::decode()`.
+
+use std::ops::Range;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::modules::*;
+
+use super::*;
+
+#[derive(Debug)]
+pub struct LazyFunc<'a> {
+    pub type_: TypeIdx,
+    // Index in the function index space
+    index: FuncIdx,
+    body: FuncBody<'a>,
+}
+
+impl<'a> LazyFunc<'a> {
+    /// The encoded locals and body of the function.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.body.bytes()
+    }
+
+    /// The location of `bytes()` in the module.
+    pub fn range(&self) -> Range<usize> {
+        self.body.range()
+    }
+
+    /// Decodes the function body.
+    ///
+    /// NB: Each call is charged against `ParseLimits::max_alloc` on its own,
+    /// on top of the rest of the module, so repeated calls do not add up.
+    pub fn decode(&self) -> Result<Func, ParseError> {
+        self.decode_with(self.body.renew_budget())
+    }
+
+    fn decode_with(&self, budget: Arc<Budget>) -> Result<Func, ParseError> {
+        match self.body.decode_code_with(budget) {
+            Ok(Code { locals, body }) => Ok(Func { type_: self.type_, locals, body }),
+            Err(DecodeError { offset, kind }) => {
+                Err(ParseError {
+                    offset,
+                    section: Some(10),
+                    func: Some(self.index),
+                    kind,
+                })
+            }
+        }
+    }
+}
+
+/// A module whose function bodies have not been decoded yet.
+///
+/// All other fields are the same as in `Module`.
+#[derive(Debug)]
+pub struct LazyModule<'a> {
+    pub types: Wec<FuncType>,
+    pub funcs: Vec<LazyFunc<'a>>,
+    pub tables: Wec<Table>,
+    pub mems: Wec<Mem>,
+    pub globals: Wec<Global>,
+    pub elem: Wec<Elem>,
+    pub data: Wec<Data>,
+    pub start: Option<Start>,
+    pub imports: Wec<Import>,
+    pub exports: Wec<Export>,
+}
+
+impl<'a> LazyModule<'a> {
+    /// Looks up a function defined in the module by its index in the
+    /// function index space. Returns `None` for imported functions.
+    pub fn func(&self, x: FuncIdx) -> Option<&LazyFunc<'a>> {
+        let imported = count_func_imports(&self.imports);
+        x.0.checked_sub(imported).and_then(|i| self.funcs.get(i as usize))
+    }
+
+    /// Decodes all function bodies.
+    ///
+    /// With the `parallel` feature, this happens on a thread pool.
+    /// The error of the function with the lowest index is reported either way.
+    pub fn decode(self) -> Result<Module, ParseError> {
+        // NB: The bodies share one budget, same as when parsing eagerly
+        let budget = self.funcs.first().map(|f| f.body.renew_budget());
+        let funcs = decode_all(&self.funcs, |f| f.decode_with(budget.clone().unwrap()))?;
+
+        Ok(Module {
+            types: self.types,
+            funcs: funcs.into(),
+            tables: self.tables,
+            mems: self.mems,
+            globals: self.globals,
+            elem: self.elem,
+            data: self.data,
+            start: self.start,
+            imports: self.imports,
+            exports: self.exports,
+        })
+    }
+}
+
+/// Like `parse_binary_format`, but only decodes the function bodies on demand.
+pub fn parse_binary_format_lazy(b: &[u8]) -> Result<(LazyModule, Vec<CustomSection>), ParseError> {
+    parse_binary_format_lazy_with_config(b, &ParseConfig::default())
+}
+
+/// Like `parse_binary_format_lazy`, but with the limits and features of
+/// `config`. They also apply when the function bodies are decoded.
+pub fn parse_binary_format_lazy_with_config<'a>(b: &'a [u8], config: &ParseConfig)
+    -> Result<(LazyModule<'a>, Vec<CustomSection>), ParseError>
+{
+    let parts = parse_module_parts(b, config, Ok)?;
+    let ModuleParts { module, typeindices, codes, customs } = parts;
+
+    let imported = count_func_imports(&module.imports);
+    let funcs = typeindices.into_iter().zip(codes).enumerate()
+        .map(|(i, (type_, body))| {
+            LazyFunc {
+                type_,
+                index: FuncIdx(imported + i as u32),
+                body,
+            }
+        })
+        .collect();
+
+    let Module { types, tables, mems, globals, elem, data, start, imports, exports, .. } = module;
+    let module = LazyModule {
+        types,
+        funcs,
+        tables,
+        mems,
+        globals,
+        elem,
+        data,
+        start,
+        imports,
+        exports,
+    };
+    Ok((module, customs))
+}
@@ -1,238 +1,421 @@
 #![feature(int_to_from_bytes)]
 
-#![recursion_limit="256"]
-
 #![allow(non_snake_case, unused_imports)]
 
-#[macro_use]
-extern crate nom;
-
 extern crate greenwasm_structure;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use greenwasm_structure::features::{WasmFeatures, Feature};
+
+// NB: The parsers below read from a `Decoder`, a cursor into the input.
+// They never backtrack: a parser decides what to decode by looking at the
+// next byte, and reports malformed input as a `DecodeError` at the offset
+// of the problem. This way the first error is also the most specific one.
+
+/// A cursor over `input[pos..end]`.
+///
+/// Offsets are relative to the start of `input`, even for
+/// a decoder that has been split off for a section.
+#[derive(Clone)]
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    end: usize,
+    budget: Arc<Budget>,
+}
+
+/// The limits and enabled features of a parse, and the bytes allocated so far.
+///
+/// NB: This is shared by all decoders of a module, including
+/// the ones that decode function bodies in parallel.
+#[derive(Debug)]
+struct Budget {
+    limits: ParseLimits,
+    features: WasmFeatures,
+    allocated: AtomicUsize,
+}
+
+impl Budget {
+    fn new(config: &ParseConfig) -> Arc<Self> {
+        Arc::new(Budget {
+            limits: config.limits.clone(),
+            features: config.features,
+            allocated: AtomicUsize::new(0),
+        })
+    }
 
-pub use nom::ErrorKind as NomErrorKind;
-
-// TODO: Open PR in nom for where applicable
-macro_rules! verify_ref (
-  // Internal parser, do not use directly
-  (__impl $i:expr, $submac:ident!( $($args:tt)* ), $submac2:ident!( $($args2:tt)* )) => (
-    {
-      use nom::lib::std::result::Result::*;
-      use nom::{Err,ErrorKind};
-
-      let i_ = $i.clone();
-      match $submac!(i_, $($args)*) {
-        Err(e)     => Err(e),
-        Ok((i, o)) => if $submac2!(&o, $($args2)*) {
-          Ok((i, o))
+    /// A budget with the same limits that starts out with
+    /// the bytes allocated so far, but is charged separately.
+    fn renew(&self) -> Arc<Self> {
+        Arc::new(Budget {
+            limits: self.limits.clone(),
+            features: self.features,
+            allocated: AtomicUsize::new(self.allocated.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct DecodeError {
+    offset: usize,
+    kind: ParseErrorKind,
+}
+
+type PResult<T> = Result<T, DecodeError>;
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Decoder::with_budget(input, Budget::new(&ParseConfig::default()))
+    }
+
+    fn with_budget(input: &'a [u8], budget: Arc<Budget>) -> Self {
+        Decoder { input, pos: 0, end: input.len(), budget }
+    }
+
+    fn limits(&self) -> &ParseLimits {
+        &self.budget.limits
+    }
+
+    fn features(&self) -> &WasmFeatures {
+        &self.budget.features
+    }
+
+    /// Fails if a limit is exceeded by `n`.
+    fn check_limit(&self, offset: usize, limit: Limit, n: u64) -> PResult<()> {
+        if n > self.limits().max(limit) {
+            self.fail_at(offset, ParseErrorKind::LimitExceeded(limit))
         } else {
-          Err(Err::Error(error_position!($i, ErrorKind::Verify)))
+            Ok(())
         }
-      }
-    }
-  );
-  ($i:expr, $submac:ident!( $($args:tt)* ), $g:expr) => (
-    verify_ref!(__impl $i, $submac!($($args)*), call!($g));
-  );
-  ($i:expr, $submac:ident!( $($args:tt)* ), $submac2:ident!( $($args2:tt)* )) => (
-    verify_ref!(__impl $i, $submac!($($args)*), $submac2!($($args2)*));
-  );
-  ($i:expr, $f:expr, $g:expr) => (
-    verify_ref!(__impl $i, call!($f), call!($g));
-  );
-  ($i:expr, $f:expr, $submac:ident!( $($args:tt)* )) => (
-    verify_ref!(__impl $i, call!($f), $submac!($($args)*));
-  );
-);
-macro_rules! btag {
-    ($i:expr, $b:expr) => (tag!($i, &[$b][..]))
-}
-macro_rules! btagmap {
-    ($i:expr, $b:expr, $r:expr) => (value!($i, $r, btag!($b)))
-}
-
-use nom::IResult;
-use nom::types::CompleteByteSlice;
-
-type Inp<'a> = CompleteByteSlice<'a>;
+    }
+
+    /// Accounts for `n` bytes that are about to be allocated.
+    fn alloc(&self, n: usize) -> PResult<()> {
+        let max = self.limits().max_alloc;
+        let before = self.budget.allocated.fetch_add(n, Ordering::Relaxed);
+        if n > max || before.saturating_add(n) > max {
+            self.fail(ParseErrorKind::LimitExceeded(Limit::Alloc))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.pos
+    }
+
+    fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.end
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.as_slice().first().cloned()
+    }
+
+    /// The unconsumed bytes.
+    fn as_slice(&self) -> &'a [u8] {
+        &self.input[self.pos..self.end]
+    }
+
+    fn fail_at<T>(&self, offset: usize, kind: ParseErrorKind) -> PResult<T> {
+        Err(DecodeError { offset, kind })
+    }
+
+    fn fail<T>(&self, kind: ParseErrorKind) -> PResult<T> {
+        self.fail_at(self.pos, kind)
+    }
+
+    /// Fails with `SizeMismatch` if not all bytes have been consumed.
+    fn finish(&self) -> PResult<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            self.fail(ParseErrorKind::SizeMismatch)
+        }
+    }
+
+    fn take(&mut self, n: usize) -> PResult<&'a [u8]> {
+        if n > self.remaining() {
+            return self.fail_at(self.end, ParseErrorKind::UnexpectedEnd);
+        }
+        let bytes = &self.input[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn take_rest(&mut self) -> &'a [u8] {
+        let bytes = self.as_slice();
+        self.pos = self.end;
+        bytes
+    }
+
+    /// Consumes the next `n` bytes and returns a decoder for them.
+    fn split(&mut self, n: usize) -> PResult<Decoder<'a>> {
+        let start = self.pos;
+        self.take(n)?;
+        Ok(Decoder { input: self.input, pos: start, end: self.pos, budget: self.budget.clone() })
+    }
+}
+
+/// Decodes the next byte with `f`, which returns `None` if
+/// the byte is not an encoding of what was `expected`.
+fn parse_tag<T, F>(d: &mut Decoder, expected: &'static str, f: F) -> PResult<T>
+    where F: FnOnce(u8) -> Option<T>
+{
+    let found = match d.peek() {
+        Some(b) => b,
+        None => return d.fail(ParseErrorKind::UnexpectedEnd),
+    };
+    match f(found) {
+        Some(x) => {
+            d.pos += 1;
+            Ok(x)
+        }
+        None => d.fail(ParseErrorKind::UnexpectedByte { expected, found }),
+    }
+}
+
+fn parse_zero_byte(d: &mut Decoder) -> PResult<()> {
+    parse_tag(d, "zero byte", |b| if b == 0x00 { Some(()) } else { None })
+}
 
 // 5.1.3. Vectors
 use greenwasm_structure::types::Wec;
-fn parse_vec<'a, F, B>(input: Inp<'a>, parse_b: F) -> IResult<Inp<'a>, Wec<B>>
-    where F: Fn(Inp<'a>) -> IResult<Inp<'a>, B>
+fn parse_vec<'a, F, B>(d: &mut Decoder<'a>, parse_b: F) -> PResult<Wec<B>>
+    where F: Fn(&mut Decoder<'a>) -> PResult<B>
+{
+    let n = parse_u32(d)?;
+    parse_vec_items(d, n, parse_b)
+}
+
+/// Parses the length of a vector whose length is capped by `limit`.
+fn parse_len(d: &mut Decoder, limit: Limit) -> PResult<u32> {
+    let start = d.pos;
+    let n = parse_u32(d)?;
+    d.check_limit(start, limit, n as u64)?;
+    Ok(n)
+}
+
+/// Parses the `n` elements of a vector after its length.
+fn parse_vec_items<'a, F, B>(d: &mut Decoder<'a>, n: u32, parse_b: F) -> PResult<Wec<B>>
+    where F: Fn(&mut Decoder<'a>) -> PResult<B>
 {
-    do_parse!(input,
-        n: apply!(parse_uN, 32)
-        >> res: many_m_n!(n as usize, n as usize, parse_b)
-        >> (res.into())
-    )
+    d.alloc((n as usize).saturating_mul(mem::size_of::<B>()))?;
+
+    // NB: Each element is encoded with at least one byte, so this
+    // does not trust a length that the input can not possibly hold.
+    let mut res = Vec::with_capacity((n as usize).min(d.remaining()));
+    for _ in 0..n {
+        res.push(parse_b(d)?);
+    }
+    Ok(res.into())
 }
 
 // 5.2.1. Bytes
-named!(parse_byte <Inp, u8>, map!(take!(1), |n| n[0]));
+fn parse_byte(d: &mut Decoder) -> PResult<u8> {
+    match d.peek() {
+        Some(b) => {
+            d.pos += 1;
+            Ok(b)
+        }
+        None => d.fail(ParseErrorKind::UnexpectedEnd),
+    }
+}
+fn parse_bytes(d: &mut Decoder) -> PResult<Wec<u8>> {
+    let n = parse_u32(d)?;
+    let bs = d.take(n as usize)?;
+    d.alloc(bs.len())?;
+    Ok(bs.to_vec().into())
+}
 
 // 5.2.2. Integers
-named_args!(parse_uN(N: u32) <Inp, u64>, alt!(
-    do_parse!(
-        n: verify!(parse_byte, |n| {
+
+// NB: The canonical grammar recurses for each byte with N - 7 bits left.
+// The loops below do the same with N - shift as the number of bits left.
+
+fn parse_uN(d: &mut Decoder, N: u32) -> PResult<u64> {
+    let mut res = 0;
+    let mut shift = 0;
+    loop {
+        let start = d.pos;
+        let n = parse_byte(d)? as u64;
+        let N = N - shift;
+        if n < (1 << 7) && (n as u128) < (1 << N) {
             // n < 2^7 ∧ n < 2^N
-            let n = n as u128;
-            let v27 = 1 << 7;
-            let v2N = 1 << N;
-            n < v27 && n < v2N
-        })
-        >> (n as u64)
-    )
-    | do_parse!(
-        n: verify!(parse_byte, |n| {
+            return Ok(res | n << shift);
+        } else if n >= (1 << 7) && N > 7 {
             // n ≥ 2^7 ∧ N > 7
-            let n = n as u128;
-            let v27 = 1 << 7;
-            n >= v27 && N > 7
-        })
-        >> m: apply!(parse_uN, N - 7)
-        >> ((1 << 7) * m + ((n as u64) - (1 << 7)))
-    )
-));
+            res |= (n - (1 << 7)) << shift;
+            shift += 7;
+        } else {
+            return d.fail_at(start, ParseErrorKind::BadLeb128);
+        }
+    }
+}
 
-named!(parse_u32 <Inp, u32>, map!(apply!(parse_uN, 32), |x| x as u32));
-named!(parse_u64 <Inp, u64>, apply!(parse_uN, 64));
+fn parse_u32(d: &mut Decoder) -> PResult<u32> {
+    parse_uN(d, 32).map(|x| x as u32)
+}
+#[allow(dead_code)]
+fn parse_u64(d: &mut Decoder) -> PResult<u64> {
+    parse_uN(d, 64)
+}
 
-named_args!(parse_sN(N: u32) <Inp, i64>, alt!(
-    do_parse!(
-        n: verify!(parse_byte, |n| {
+fn parse_sN(d: &mut Decoder, N: u32) -> PResult<i64> {
+    let mut res = 0;
+    let mut shift = 0;
+    loop {
+        let start = d.pos;
+        let n = parse_byte(d)? as i64;
+        let N = N - shift;
+        let v2N1 = 1i128 << (N - 1);
+        if n < (1 << 6) && (n as i128) < v2N1 {
             // n < 2^6 ∧ n < 2^(N−1)
-            let n = n as i128;
-            let v26 = 1 << 6;
-            let v2N1 = 1 << (N - 1);
-            n < v26 && n < v2N1
-        })
-        >> (n as i64)
-    )
-    | do_parse!(
-        n: verify!(parse_byte, |n| {
+            return Ok(res | n << shift);
+        } else if (1 << 6) <= n && n < (1 << 7) && (n as i128) >= (1 << 7) - v2N1 {
             // 2^6 ≤ n < 2^7 ∧ n ≥ 2^7 − 2^(N − 1)
-            let n = n as i128;
-            let v26 = 1 << 6;
-            let v27 = 1 << 7;
-            let v2N1 = 1 << (N - 1);
-            v26 <= n && n < v27 && n >= (v27 - v2N1)
-        })
-        >> (n as i64 - (1 << 7))
-    )
-    | do_parse!(
-        n: verify!(parse_byte, |n| {
+            return Ok(res | (n - (1 << 7)) << shift);
+        } else if n >= (1 << 7) && N > 7 {
             // n ≥ 2^7 ∧ N > 7
-            let n = n as i128;
-            let v27 = 1 << 7;
-            n >= v27 && N > 7
-        })
-        >> m: apply!(parse_sN, N - 7)
-        >> ((1 << 7) * m + ((n as i64) - (1 << 7)))
-    )
-));
+            res |= (n - (1 << 7)) << shift;
+            shift += 7;
+        } else {
+            return d.fail_at(start, ParseErrorKind::BadLeb128);
+        }
+    }
+}
 
-named!(parse_s32 <Inp, i32>, map!(apply!(parse_sN, 32), |x| x as i32));
-named!(parse_s64 <Inp, i64>, apply!(parse_sN, 64));
+fn parse_s32(d: &mut Decoder) -> PResult<i32> {
+    parse_sN(d, 32).map(|x| x as i32)
+}
+fn parse_s64(d: &mut Decoder) -> PResult<i64> {
+    parse_sN(d, 64)
+}
 
-named!(parse_i32 <Inp, u32>, map!(parse_s32, |x| x as u32));
-named!(parse_i64 <Inp, u64>, map!(parse_s64, |x| x as u64));
+fn parse_i32(d: &mut Decoder) -> PResult<u32> {
+    parse_s32(d).map(|x| x as u32)
+}
+fn parse_i64(d: &mut Decoder) -> PResult<u64> {
+    parse_s64(d).map(|x| x as u64)
+}
 
 // 5.2.3. Floating-Point
-named!(parse_f32 <Inp, f32>, do_parse!(
-    bs: map!(take!(4), |s| { let mut b = [0; 4]; b.copy_from_slice(&**s); b })
-    >> (f32::from_bits(u32::from_le_bytes(bs)))
-));
-named!(parse_f64 <Inp, f64>, do_parse!(
-    bs: map!(take!(8), |s| { let mut b = [0; 8]; b.copy_from_slice(&**s); b })
-    >> (f64::from_bits(u64::from_le_bytes(bs)))
-));
+fn parse_f32(d: &mut Decoder) -> PResult<f32> {
+    let mut b = [0; 4];
+    b.copy_from_slice(d.take(4)?);
+    Ok(f32::from_bits(u32::from_le_bytes(b)))
+}
+fn parse_f64(d: &mut Decoder) -> PResult<f64> {
+    let mut b = [0; 8];
+    b.copy_from_slice(d.take(8)?);
+    Ok(f64::from_bits(u64::from_le_bytes(b)))
+}
 
 // 5.2.4. Names
 use greenwasm_structure::types::Name;
-named!(parse_name <Inp, Name>, do_parse!(
-    bs: map!(
-        verify_ref!(
-            map!(
-                call!(parse_vec, parse_byte),
-                |v| String::from_utf8(v.into())
-            ),
-            |res: &Result<_, _>| res.is_ok()
-        ),
-        |res| res.unwrap().into()
-    )
-    >> (bs)
-));
+fn parse_name(d: &mut Decoder) -> PResult<Name> {
+    let start = d.pos;
+    let n = parse_u32(d)?;
+    let bs = d.take(n as usize)?;
+    d.alloc(bs.len())?;
+    match ::std::str::from_utf8(bs) {
+        Ok(s) => Ok(s.into()),
+        Err(_) => d.fail_at(start, ParseErrorKind::InvalidUtf8),
+    }
+}
 
 // 5.3.1 Value Types
 use greenwasm_structure::types::ValType;
-named!(parse_valtype <Inp, ValType>, alt!(
-    btagmap!(0x7f, ValType::I32)
-    | btagmap!(0x7e, ValType::I64)
-    | btagmap!(0x7d, ValType::F32)
-    | btagmap!(0x7c, ValType::F64)
-));
+fn valtype(b: u8) -> Option<ValType> {
+    match b {
+        0x7f => Some(ValType::I32),
+        0x7e => Some(ValType::I64),
+        0x7d => Some(ValType::F32),
+        0x7c => Some(ValType::F64),
+        _ => None,
+    }
+}
+fn parse_valtype(d: &mut Decoder) -> PResult<ValType> {
+    parse_tag(d, "value type", valtype)
+}
 
 // 5.3.2 Result Types
 use greenwasm_structure::types::ResultType;
-named!(parse_blocktype <Inp, ResultType>, alt!(
-    btagmap!(0x40, None.into())
-    | map!(parse_valtype, |v| Some(v).into())
-));
+fn parse_blocktype(d: &mut Decoder) -> PResult<ResultType> {
+    parse_tag(d, "block type", |b| match b {
+        0x40 => Some(None.into()),
+        b => valtype(b).map(|t| Some(t).into()),
+    })
+}
 
 // 5.3.3 Function Types
 use greenwasm_structure::types::FuncType;
-named!(parse_functype <Inp, FuncType>, do_parse!(
-    btag!(0x60)
-    >> t1s: call!(parse_vec, parse_valtype)
-    >> t2s: call!(parse_vec, parse_valtype)
-    >> (FuncType {
+fn parse_functype(d: &mut Decoder) -> PResult<FuncType> {
+    parse_tag(d, "function type", |b| if b == 0x60 { Some(()) } else { None })?;
+    let t1s = parse_vec(d, parse_valtype)?;
+    let t2s = parse_vec(d, parse_valtype)?;
+    Ok(FuncType {
         args: t1s,
         results: t2s,
     })
-));
+}
 
 // 5.3.4 Limits
 use greenwasm_structure::types::Limits;
-named!(parse_limits <Inp, Limits>, alt!(
-    do_parse!(
-        btag!(0x00)
-        >> n: parse_u32
-        >> (Limits { min: n, max: None })
-    )
-    |do_parse!(
-        btag!(0x01)
-        >> n: parse_u32
-        >> m: parse_u32
-        >> (Limits { min: n, max: Some(m) })
-    )
-));
+fn parse_limits(d: &mut Decoder) -> PResult<Limits> {
+    let has_max = parse_tag(d, "limits", |b| match b {
+        0x00 => Some(false),
+        0x01 => Some(true),
+        _ => None,
+    })?;
+    let n = parse_u32(d)?;
+    let m = if has_max { Some(parse_u32(d)?) } else { None };
+    Ok(Limits { min: n, max: m })
+}
 
 // 5.3.5 Memory Types
 use greenwasm_structure::types::MemType;
-named!(parse_memtype <Inp, MemType>, map!(parse_limits, |limits| MemType { limits }));
+fn parse_memtype(d: &mut Decoder) -> PResult<MemType> {
+    let limits = parse_limits(d)?;
+    Ok(MemType { limits })
+}
 
 // 5.3.6. Table Types
 use greenwasm_structure::types::TableType;
 use greenwasm_structure::types::ElemType;
-named!(parse_tabletype <Inp, TableType>, do_parse!(
-    et: parse_elemtype
-    >> lim: parse_limits
-    >> (TableType{ limits: lim, elemtype: et })
-));
-named!(parse_elemtype <Inp, ElemType>, btagmap!(0x70, ElemType::AnyFunc));
+fn parse_tabletype(d: &mut Decoder) -> PResult<TableType> {
+    let et = parse_elemtype(d)?;
+    let lim = parse_limits(d)?;
+    Ok(TableType{ limits: lim, elemtype: et })
+}
+fn parse_elemtype(d: &mut Decoder) -> PResult<ElemType> {
+    parse_tag(d, "element type", |b| if b == 0x70 { Some(ElemType::AnyFunc) } else { None })
+}
 
 // 5.3.7. Global Types
 use greenwasm_structure::types::GlobalType;
 use greenwasm_structure::types::Mut;
-named!(parse_globaltype <Inp, GlobalType>, do_parse!(
-    t: parse_valtype
-    >> m: parse_mut
-    >> (GlobalType{ mutability: m, valtype: t })
-));
-named!(parse_mut <Inp, Mut>, alt!(
-    btagmap!(0x00, Mut::Const)
-    | btagmap!(0x01, Mut::Var)
-));
+fn parse_globaltype(d: &mut Decoder) -> PResult<GlobalType> {
+    let t = parse_valtype(d)?;
+    let m = parse_mut(d)?;
+    Ok(GlobalType{ mutability: m, valtype: t })
+}
+fn parse_mut(d: &mut Decoder) -> PResult<Mut> {
+    parse_tag(d, "mutability", |b| match b {
+        0x00 => Some(Mut::Const),
+        0x01 => Some(Mut::Var),
+        _ => None,
+    })
+}
 
 // 5.4. Instructions
 use greenwasm_structure::instructions::Instr;
@@ -262,14 +445,14 @@ struct InstrStack {
     stack: Vec<(InstrEvent, Vec<Instr>)>,
 }
 #[derive(Debug)]
-struct InstrStackError(&'static str);
+struct InstrStackError(ParseErrorKind);
 impl InstrStack {
     fn new() -> Self { Default::default() }
     fn top(&mut self) -> &mut (InstrEvent, Vec<Instr>) {
         self.stack.last_mut().unwrap()
     }
-    fn error(&mut self, msg: &'static str) -> Result<(), InstrStackError> {
-        Err(InstrStackError(msg))
+    fn error(&mut self, kind: ParseErrorKind) -> Result<(), InstrStackError> {
+        Err(InstrStackError(kind))
     }
     fn event(&mut self, e: InstrEvent) -> Result<Option<Vec<Instr>>, InstrStackError> {
         match e {
@@ -295,7 +478,7 @@ impl InstrStack {
                 if let InstrEvent::If(_) = self.top().0 {
                     self.stack.push((InstrEvent::Else, vec![]));
                 } else {
-                    self.error("else without previous if")?;
+                    self.error(ParseErrorKind::ElseWithoutIf)?;
                 }
             }
             InstrEvent::End => {
@@ -340,283 +523,272 @@ impl InstrStack {
         Ok(None)
     }
 }
-fn parse_instrs_end(i: Inp) -> IResult<Inp, Vec<Instr>> {
-    use nom::Err;
-
-    let mut input = i;
+fn parse_instrs_end(d: &mut Decoder) -> PResult<Vec<Instr>> {
     let mut stack = InstrStack::new();
     stack.event(InstrEvent::Expr).expect("stack has exactly one Expr at its bottom");
 
-    loop {
-        match parse_instr_event(input) {
-            Ok((i, o)) => {
-                // loop trip must always consume (otherwise infinite loops)
-                if i == input {
-                    return Err(Err::Error(error_position!(input, nom::ErrorKind::Custom(0))));
-                }
-
-                match stack.event(o) {
-                    Ok(o) => {
-                        input = i;
+    // Number of enclosing blocks, loops and ifs
+    let mut depth = 0;
 
-                        if let Some(ins) = o {
-                            return Ok((input, ins));
-                        }
-                    }
-                    Err(_e) => {
-                        // TODO: Expose error message
-                        return Err(Err::Error(error_position!(input, nom::ErrorKind::Custom(1))));
-                    }
-                }
-            },
-            Err(e) => {
-                return Err(e);
-            },
+    loop {
+        let start = d.pos;
+        let event = parse_instr_event(d)?;
+        match event {
+            InstrEvent::Block(_) | InstrEvent::Loop(_) | InstrEvent::If(_) => {
+                depth += 1;
+                d.check_limit(start, Limit::Nesting, depth)?;
+                d.alloc(mem::size_of::<Instr>())?;
+            }
+            InstrEvent::Instr(_) => d.alloc(mem::size_of::<Instr>())?,
+            InstrEvent::End => depth = depth.saturating_sub(1),
+            InstrEvent::Expr | InstrEvent::Else => {}
+        }
+        match stack.event(event) {
+            Ok(Some(ins)) => return Ok(ins),
+            Ok(None) => {}
+            Err(InstrStackError(kind)) => return d.fail_at(start, kind),
         }
     }
 }
 
-macro_rules! ins {
-    ($i:expr, $b:expr, $r:expr; $($t:tt)*) => (
-        do_parse!($i, btag!($b) >> $($t)* >> ($r.into()))
-    );
-    ($i:expr, $b:expr, $r:expr) => (
-        do_parse!($i, btag!($b) >> ($r.into()))
-    )
-}
-named!(parse_instr_event <Inp, InstrEvent>, alt!(
-    // 5.4.1. Control Instructions
-    ins!(0x00, Instr::Unreachable)
-    | ins!(0x01, Instr::Nop)
-    | ins!(0x02, InstrEvent::Block(rt);
-        rt: parse_blocktype
-    )
-    | ins!(0x03, InstrEvent::Loop(rt);
-        rt: parse_blocktype
-    )
-    | ins!(0x04, InstrEvent::If(rt);
-        rt: parse_blocktype
-    )
-    | ins!(0x05, InstrEvent::Else)
-    | ins!(0x0B, InstrEvent::End)
-    | ins!(0x0c, Instr::Br(l);
-        l: parse_labelidx
-    )
-    | ins!(0x0d, Instr::BrIf(l);
-        l: parse_labelidx
-    )
-    | ins!(0x0e, Instr::BrTable(ls, lN);
-        ls: call!(parse_vec, parse_labelidx)
-        >> lN: parse_labelidx
-    )
-    | ins!(0x0f, Instr::Return)
-    | ins!(0x10, Instr::Call(x);
-        x: parse_funcidx
-    )
-    | ins!(0x11, Instr::CallIndirect(x);
-        x: parse_typeidx
-        >> btag!(0x00)
-    )
-
-    // 5.4.2. Parametric Instructions
-    | ins!(0x1A, Instr::Drop)
-    | ins!(0x1B, Instr::Select)
-
-    // 5.4.3. Variable Instructions
-    | ins!(0x20, Instr::GetLocal(x); x: parse_localidx)
-    | ins!(0x21, Instr::SetLocal(x); x: parse_localidx)
-    | ins!(0x22, Instr::TeeLocal(x); x: parse_localidx)
-    | ins!(0x23, Instr::GetGlobal(x); x: parse_globalidx)
-    | ins!(0x24, Instr::SetGlobal(x); x: parse_globalidx)
-
-    // 5.4.4. Memory Instructions
-    | ins!(0x28, Instr::I32Load(m); m: parse_memarg)
-    | ins!(0x29, Instr::I64Load(m); m: parse_memarg)
-    | ins!(0x2A, Instr::F32Load(m); m: parse_memarg)
-    | ins!(0x2B, Instr::F64Load(m); m: parse_memarg)
-
-    | ins!(0x2C, Instr::I32Load8S(m); m: parse_memarg)
-    | ins!(0x2D, Instr::I32Load8U(m); m: parse_memarg)
-    | ins!(0x2E, Instr::I32Load16S(m); m: parse_memarg)
-    | ins!(0x2F, Instr::I32Load16U(m); m: parse_memarg)
-
-    | ins!(0x30, Instr::I64Load8S(m); m: parse_memarg)
-    | ins!(0x31, Instr::I64Load8U(m); m: parse_memarg)
-    | ins!(0x32, Instr::I64Load16S(m); m: parse_memarg)
-    | ins!(0x33, Instr::I64Load16U(m); m: parse_memarg)
-    | ins!(0x34, Instr::I64Load32S(m); m: parse_memarg)
-    | ins!(0x35, Instr::I64Load32U(m); m: parse_memarg)
-
-    | ins!(0x36, Instr::I32Store(m); m: parse_memarg)
-    | ins!(0x37, Instr::I64Store(m); m: parse_memarg)
-    | ins!(0x38, Instr::F32Store(m); m: parse_memarg)
-    | ins!(0x39, Instr::F64Store(m); m: parse_memarg)
-
-    | ins!(0x3A, Instr::I32Store8(m); m: parse_memarg)
-    | ins!(0x3B, Instr::I32Store16(m); m: parse_memarg)
-
-    | ins!(0x3C, Instr::I64Store8(m); m: parse_memarg)
-    | ins!(0x3D, Instr::I64Store16(m); m: parse_memarg)
-    | ins!(0x3E, Instr::I64Store32(m); m: parse_memarg)
-
-    | ins!(0x3F, Instr::CurrentMemory; verify!(parse_byte, |b| b == 0))
-    | ins!(0x40, Instr::GrowMemory; verify!(parse_byte, |b| b == 0))
-
-    // 5.4.5. Numeric Instructions
-    | ins!(0x41, Instr::I32Const(n); n: parse_i32)
-    | ins!(0x42, Instr::I64Const(n); n: parse_i64)
-    | ins!(0x43, Instr::F32Const(z); z: parse_f32)
-    | ins!(0x44, Instr::F64Const(z); z: parse_f64)
-
-    | ins!(0x45, Instr::I32EqZ)
-    | ins!(0x46, Instr::I32Eq)
-    | ins!(0x47, Instr::I32Ne)
-    | ins!(0x48, Instr::I32LtS)
-    | ins!(0x49, Instr::I32LtU)
-    | ins!(0x4A, Instr::I32GtS)
-    | ins!(0x4B, Instr::I32GtU)
-    | ins!(0x4C, Instr::I32LeS)
-    | ins!(0x4D, Instr::I32LeU)
-    | ins!(0x4E, Instr::I32GeS)
-    | ins!(0x4F, Instr::I32GeU)
-
-    | ins!(0x50, Instr::I64EqZ)
-    | ins!(0x51, Instr::I64Eq)
-    | ins!(0x52, Instr::I64Ne)
-    | ins!(0x53, Instr::I64LtS)
-    | ins!(0x54, Instr::I64LtU)
-    | ins!(0x55, Instr::I64GtS)
-    | ins!(0x56, Instr::I64GtU)
-    | ins!(0x57, Instr::I64LeS)
-    | ins!(0x58, Instr::I64LeU)
-    | ins!(0x59, Instr::I64GeS)
-    | ins!(0x5A, Instr::I64GeU)
-
-    | ins!(0x5B, Instr::F32Eq)
-    | ins!(0x5C, Instr::F32Ne)
-    | ins!(0x5D, Instr::F32Lt)
-    | ins!(0x5E, Instr::F32Gt)
-    | ins!(0x5F, Instr::F32Le)
-    | ins!(0x60, Instr::F32Ge)
-
-    | ins!(0x61, Instr::F64Eq)
-    | ins!(0x62, Instr::F64Ne)
-    | ins!(0x63, Instr::F64Lt)
-    | ins!(0x64, Instr::F64Gt)
-    | ins!(0x65, Instr::F64Le)
-    | ins!(0x66, Instr::F64Ge)
-
-    | ins!(0x67, Instr::I32Clz)
-    | ins!(0x68, Instr::I32Ctz)
-    | ins!(0x69, Instr::I32Popcnt)
-    | ins!(0x6A, Instr::I32Add)
-    | ins!(0x6B, Instr::I32Sub)
-    | ins!(0x6C, Instr::I32Mul)
-    | ins!(0x6D, Instr::I32DivS)
-    | ins!(0x6E, Instr::I32DivU)
-    | ins!(0x6F, Instr::I32RemS)
-    | ins!(0x70, Instr::I32RemU)
-    | ins!(0x71, Instr::I32And)
-    | ins!(0x72, Instr::I32Or)
-    | ins!(0x73, Instr::I32Xor)
-    | ins!(0x74, Instr::I32Shl)
-    | ins!(0x75, Instr::I32ShrS)
-    | ins!(0x76, Instr::I32ShrU)
-    | ins!(0x77, Instr::I32Rotl)
-    | ins!(0x78, Instr::I32Rotr)
-
-    | ins!(0x79, Instr::I64Clz)
-    | ins!(0x7A, Instr::I64Ctz)
-    | ins!(0x7B, Instr::I64Popcnt)
-    | ins!(0x7C, Instr::I64Add)
-    | ins!(0x7D, Instr::I64Sub)
-    | ins!(0x7E, Instr::I64Mul)
-    | ins!(0x7F, Instr::I64DivS)
-    | ins!(0x80, Instr::I64DivU)
-    | ins!(0x81, Instr::I64RemS)
-    | ins!(0x82, Instr::I64RemU)
-    | ins!(0x83, Instr::I64And)
-    | ins!(0x84, Instr::I64Or)
-    | ins!(0x85, Instr::I64Xor)
-    | ins!(0x86, Instr::I64Shl)
-    | ins!(0x87, Instr::I64ShrS)
-    | ins!(0x88, Instr::I64ShrU)
-    | ins!(0x89, Instr::I64Rotl)
-    | ins!(0x8A, Instr::I64Rotr)
-
-    | ins!(0x8B, Instr::F32Abs)
-    | ins!(0x8C, Instr::F32Neg)
-    | ins!(0x8D, Instr::F32Ceil)
-    | ins!(0x8E, Instr::F32Floor)
-    | ins!(0x8F, Instr::F32Trunc)
-    | ins!(0x90, Instr::F32Nearest)
-    | ins!(0x91, Instr::F32Sqrt)
-    | ins!(0x92, Instr::F32Add)
-    | ins!(0x93, Instr::F32Sub)
-    | ins!(0x94, Instr::F32Mul)
-    | ins!(0x95, Instr::F32Div)
-    | ins!(0x96, Instr::F32Min)
-    | ins!(0x97, Instr::F32Max)
-    | ins!(0x98, Instr::F32CopySign)
-
-    | ins!(0x99, Instr::F64Abs)
-    | ins!(0x9A, Instr::F64Neg)
-    | ins!(0x9B, Instr::F64Ceil)
-    | ins!(0x9C, Instr::F64Floor)
-    | ins!(0x9D, Instr::F64Trunc)
-    | ins!(0x9E, Instr::F64Nearest)
-    | ins!(0x9F, Instr::F64Sqrt)
-    | ins!(0xA0, Instr::F64Add)
-    | ins!(0xA1, Instr::F64Sub)
-    | ins!(0xA2, Instr::F64Mul)
-    | ins!(0xA3, Instr::F64Div)
-    | ins!(0xA4, Instr::F64Min)
-    | ins!(0xA5, Instr::F64Max)
-    | ins!(0xA6, Instr::F64CopySign)
-
-    | ins!(0xA7, Instr::I32WrapI64)
-    | ins!(0xA8, Instr::I32TruncSF32)
-    | ins!(0xA9, Instr::I32TruncUF32)
-    | ins!(0xAA, Instr::I32TruncSF64)
-    | ins!(0xAB, Instr::I32TruncUF64)
-
-    | ins!(0xAC, Instr::I64ExtendSI32)
-    | ins!(0xAD, Instr::I64ExtendUI32)
-    | ins!(0xAE, Instr::I64TruncSF32)
-    | ins!(0xAF, Instr::I64TruncUF32)
-    | ins!(0xB0, Instr::I64TruncSF64)
-    | ins!(0xB1, Instr::I64TruncUF64)
-
-    | ins!(0xB2, Instr::F32ConvertSI32)
-    | ins!(0xB3, Instr::F32ConvertUI32)
-    | ins!(0xB4, Instr::F32ConvertSI64)
-    | ins!(0xB5, Instr::F32ConvertUI64)
-    | ins!(0xB6, Instr::F32DemoteF64)
-
-    | ins!(0xB7, Instr::F64ConvertSI32)
-    | ins!(0xB8, Instr::F64ConvertUI32)
-    | ins!(0xB9, Instr::F64ConvertSI64)
-    | ins!(0xBA, Instr::F64ConvertUI64)
-    | ins!(0xBB, Instr::F64PromoteF32)
-
-    | ins!(0xBC, Instr::I32ReinterpretF32)
-    | ins!(0xBD, Instr::I64ReinterpretF64)
-    | ins!(0xBE, Instr::F32ReinterpretI32)
-    | ins!(0xBF, Instr::F64ReinterpretI64)
-));
+fn parse_instr_event(d: &mut Decoder) -> PResult<InstrEvent> {
+    let start = d.pos;
+    let opcode = parse_byte(d)?;
+    Ok(match opcode {
+        // 5.4.1. Control Instructions
+        0x00 => Instr::Unreachable.into(),
+        0x01 => Instr::Nop.into(),
+        0x02 => InstrEvent::Block(parse_blocktype(d)?),
+        0x03 => InstrEvent::Loop(parse_blocktype(d)?),
+        0x04 => InstrEvent::If(parse_blocktype(d)?),
+        0x05 => InstrEvent::Else,
+        0x0B => InstrEvent::End,
+        0x0c => Instr::Br(parse_labelidx(d)?).into(),
+        0x0d => Instr::BrIf(parse_labelidx(d)?).into(),
+        0x0e => {
+            let ls = parse_vec(d, parse_labelidx)?;
+            let lN = parse_labelidx(d)?;
+            Instr::BrTable(ls, lN).into()
+        }
+        0x0f => Instr::Return.into(),
+        0x10 => Instr::Call(parse_funcidx(d)?).into(),
+        0x11 => {
+            let x = parse_typeidx(d)?;
+            parse_zero_byte(d)?;
+            Instr::CallIndirect(x).into()
+        }
+
+        // 5.4.2. Parametric Instructions
+        0x1A => Instr::Drop.into(),
+        0x1B => Instr::Select.into(),
+
+        // 5.4.3. Variable Instructions
+        0x20 => Instr::GetLocal(parse_localidx(d)?).into(),
+        0x21 => Instr::SetLocal(parse_localidx(d)?).into(),
+        0x22 => Instr::TeeLocal(parse_localidx(d)?).into(),
+        0x23 => Instr::GetGlobal(parse_globalidx(d)?).into(),
+        0x24 => Instr::SetGlobal(parse_globalidx(d)?).into(),
+
+        // 5.4.4. Memory Instructions
+        0x28 => Instr::I32Load(parse_memarg(d)?).into(),
+        0x29 => Instr::I64Load(parse_memarg(d)?).into(),
+        0x2A => Instr::F32Load(parse_memarg(d)?).into(),
+        0x2B => Instr::F64Load(parse_memarg(d)?).into(),
+
+        0x2C => Instr::I32Load8S(parse_memarg(d)?).into(),
+        0x2D => Instr::I32Load8U(parse_memarg(d)?).into(),
+        0x2E => Instr::I32Load16S(parse_memarg(d)?).into(),
+        0x2F => Instr::I32Load16U(parse_memarg(d)?).into(),
+
+        0x30 => Instr::I64Load8S(parse_memarg(d)?).into(),
+        0x31 => Instr::I64Load8U(parse_memarg(d)?).into(),
+        0x32 => Instr::I64Load16S(parse_memarg(d)?).into(),
+        0x33 => Instr::I64Load16U(parse_memarg(d)?).into(),
+        0x34 => Instr::I64Load32S(parse_memarg(d)?).into(),
+        0x35 => Instr::I64Load32U(parse_memarg(d)?).into(),
+
+        0x36 => Instr::I32Store(parse_memarg(d)?).into(),
+        0x37 => Instr::I64Store(parse_memarg(d)?).into(),
+        0x38 => Instr::F32Store(parse_memarg(d)?).into(),
+        0x39 => Instr::F64Store(parse_memarg(d)?).into(),
+
+        0x3A => Instr::I32Store8(parse_memarg(d)?).into(),
+        0x3B => Instr::I32Store16(parse_memarg(d)?).into(),
+
+        0x3C => Instr::I64Store8(parse_memarg(d)?).into(),
+        0x3D => Instr::I64Store16(parse_memarg(d)?).into(),
+        0x3E => Instr::I64Store32(parse_memarg(d)?).into(),
+
+        0x3F => { parse_zero_byte(d)?; Instr::CurrentMemory.into() }
+        0x40 => { parse_zero_byte(d)?; Instr::GrowMemory.into() }
+
+        // 5.4.5. Numeric Instructions
+        0x41 => Instr::I32Const(parse_i32(d)?).into(),
+        0x42 => Instr::I64Const(parse_i64(d)?).into(),
+        0x43 => Instr::F32Const(parse_f32(d)?).into(),
+        0x44 => Instr::F64Const(parse_f64(d)?).into(),
+
+        0x45 => Instr::I32EqZ.into(),
+        0x46 => Instr::I32Eq.into(),
+        0x47 => Instr::I32Ne.into(),
+        0x48 => Instr::I32LtS.into(),
+        0x49 => Instr::I32LtU.into(),
+        0x4A => Instr::I32GtS.into(),
+        0x4B => Instr::I32GtU.into(),
+        0x4C => Instr::I32LeS.into(),
+        0x4D => Instr::I32LeU.into(),
+        0x4E => Instr::I32GeS.into(),
+        0x4F => Instr::I32GeU.into(),
+
+        0x50 => Instr::I64EqZ.into(),
+        0x51 => Instr::I64Eq.into(),
+        0x52 => Instr::I64Ne.into(),
+        0x53 => Instr::I64LtS.into(),
+        0x54 => Instr::I64LtU.into(),
+        0x55 => Instr::I64GtS.into(),
+        0x56 => Instr::I64GtU.into(),
+        0x57 => Instr::I64LeS.into(),
+        0x58 => Instr::I64LeU.into(),
+        0x59 => Instr::I64GeS.into(),
+        0x5A => Instr::I64GeU.into(),
+
+        0x5B => Instr::F32Eq.into(),
+        0x5C => Instr::F32Ne.into(),
+        0x5D => Instr::F32Lt.into(),
+        0x5E => Instr::F32Gt.into(),
+        0x5F => Instr::F32Le.into(),
+        0x60 => Instr::F32Ge.into(),
+
+        0x61 => Instr::F64Eq.into(),
+        0x62 => Instr::F64Ne.into(),
+        0x63 => Instr::F64Lt.into(),
+        0x64 => Instr::F64Gt.into(),
+        0x65 => Instr::F64Le.into(),
+        0x66 => Instr::F64Ge.into(),
+
+        0x67 => Instr::I32Clz.into(),
+        0x68 => Instr::I32Ctz.into(),
+        0x69 => Instr::I32Popcnt.into(),
+        0x6A => Instr::I32Add.into(),
+        0x6B => Instr::I32Sub.into(),
+        0x6C => Instr::I32Mul.into(),
+        0x6D => Instr::I32DivS.into(),
+        0x6E => Instr::I32DivU.into(),
+        0x6F => Instr::I32RemS.into(),
+        0x70 => Instr::I32RemU.into(),
+        0x71 => Instr::I32And.into(),
+        0x72 => Instr::I32Or.into(),
+        0x73 => Instr::I32Xor.into(),
+        0x74 => Instr::I32Shl.into(),
+        0x75 => Instr::I32ShrS.into(),
+        0x76 => Instr::I32ShrU.into(),
+        0x77 => Instr::I32Rotl.into(),
+        0x78 => Instr::I32Rotr.into(),
+
+        0x79 => Instr::I64Clz.into(),
+        0x7A => Instr::I64Ctz.into(),
+        0x7B => Instr::I64Popcnt.into(),
+        0x7C => Instr::I64Add.into(),
+        0x7D => Instr::I64Sub.into(),
+        0x7E => Instr::I64Mul.into(),
+        0x7F => Instr::I64DivS.into(),
+        0x80 => Instr::I64DivU.into(),
+        0x81 => Instr::I64RemS.into(),
+        0x82 => Instr::I64RemU.into(),
+        0x83 => Instr::I64And.into(),
+        0x84 => Instr::I64Or.into(),
+        0x85 => Instr::I64Xor.into(),
+        0x86 => Instr::I64Shl.into(),
+        0x87 => Instr::I64ShrS.into(),
+        0x88 => Instr::I64ShrU.into(),
+        0x89 => Instr::I64Rotl.into(),
+        0x8A => Instr::I64Rotr.into(),
+
+        0x8B => Instr::F32Abs.into(),
+        0x8C => Instr::F32Neg.into(),
+        0x8D => Instr::F32Ceil.into(),
+        0x8E => Instr::F32Floor.into(),
+        0x8F => Instr::F32Trunc.into(),
+        0x90 => Instr::F32Nearest.into(),
+        0x91 => Instr::F32Sqrt.into(),
+        0x92 => Instr::F32Add.into(),
+        0x93 => Instr::F32Sub.into(),
+        0x94 => Instr::F32Mul.into(),
+        0x95 => Instr::F32Div.into(),
+        0x96 => Instr::F32Min.into(),
+        0x97 => Instr::F32Max.into(),
+        0x98 => Instr::F32CopySign.into(),
+
+        0x99 => Instr::F64Abs.into(),
+        0x9A => Instr::F64Neg.into(),
+        0x9B => Instr::F64Ceil.into(),
+        0x9C => Instr::F64Floor.into(),
+        0x9D => Instr::F64Trunc.into(),
+        0x9E => Instr::F64Nearest.into(),
+        0x9F => Instr::F64Sqrt.into(),
+        0xA0 => Instr::F64Add.into(),
+        0xA1 => Instr::F64Sub.into(),
+        0xA2 => Instr::F64Mul.into(),
+        0xA3 => Instr::F64Div.into(),
+        0xA4 => Instr::F64Min.into(),
+        0xA5 => Instr::F64Max.into(),
+        0xA6 => Instr::F64CopySign.into(),
+
+        0xA7 => Instr::I32WrapI64.into(),
+        0xA8 => Instr::I32TruncSF32.into(),
+        0xA9 => Instr::I32TruncUF32.into(),
+        0xAA => Instr::I32TruncSF64.into(),
+        0xAB => Instr::I32TruncUF64.into(),
+
+        0xAC => Instr::I64ExtendSI32.into(),
+        0xAD => Instr::I64ExtendUI32.into(),
+        0xAE => Instr::I64TruncSF32.into(),
+        0xAF => Instr::I64TruncUF32.into(),
+        0xB0 => Instr::I64TruncSF64.into(),
+        0xB1 => Instr::I64TruncUF64.into(),
+
+        0xB2 => Instr::F32ConvertSI32.into(),
+        0xB3 => Instr::F32ConvertUI32.into(),
+        0xB4 => Instr::F32ConvertSI64.into(),
+        0xB5 => Instr::F32ConvertUI64.into(),
+        0xB6 => Instr::F32DemoteF64.into(),
+
+        0xB7 => Instr::F64ConvertSI32.into(),
+        0xB8 => Instr::F64ConvertUI32.into(),
+        0xB9 => Instr::F64ConvertSI64.into(),
+        0xBA => Instr::F64ConvertUI64.into(),
+        0xBB => Instr::F64PromoteF32.into(),
+
+        0xBC => Instr::I32ReinterpretF32.into(),
+        0xBD => Instr::I64ReinterpretF64.into(),
+        0xBE => Instr::F32ReinterpretI32.into(),
+        0xBF => Instr::F64ReinterpretI64.into(),
+
+        0xC0 ..= 0xC4 if !d.features().sign_extension => {
+            return d.fail_at(start, ParseErrorKind::FeatureDisabled(Feature::SignExtension));
+        }
+        0xC0 => Instr::I32Extend8S.into(),
+        0xC1 => Instr::I32Extend16S.into(),
+        0xC2 => Instr::I64Extend8S.into(),
+        0xC3 => Instr::I64Extend16S.into(),
+        0xC4 => Instr::I64Extend32S.into(),
+
+        _ => return d.fail_at(start, ParseErrorKind::UnknownOpcode(opcode)),
+    })
+}
 use greenwasm_structure::instructions::Memarg;
-named!(parse_memarg <Inp, Memarg>, do_parse!(
-    a: parse_u32
-    >> o: parse_u32
-    >> (Memarg { offset: o, align: a })
-));
+fn parse_memarg(d: &mut Decoder) -> PResult<Memarg> {
+    let a = parse_u32(d)?;
+    let o = parse_u32(d)?;
+    Ok(Memarg { offset: o, align: a })
+}
 
 // 5.4.6. Expressions
 use greenwasm_structure::instructions::Expr;
-named!(parse_expr <Inp, Expr>, do_parse!(
-    ins: parse_instrs_end
-    >> (Expr { body: ins })
-));
+fn parse_expr(d: &mut Decoder) -> PResult<Expr> {
+    let ins = parse_instrs_end(d)?;
+    Ok(Expr { body: ins })
+}
 
 // 5.5.1. Indices
 use greenwasm_structure::modules::TypeIdx;
@@ -626,26 +798,57 @@ use greenwasm_structure::modules::MemIdx;
 use greenwasm_structure::modules::GlobalIdx;
 use greenwasm_structure::modules::LocalIdx;
 use greenwasm_structure::modules::LabelIdx;
-named!(parse_typeidx <Inp, TypeIdx>,     map!(parse_u32, TypeIdx));
-named!(parse_funcidx <Inp, FuncIdx>,     map!(parse_u32, FuncIdx));
-named!(parse_tableidx <Inp, TableIdx>,   map!(parse_u32, TableIdx));
-named!(parse_memidx <Inp, MemIdx>,       map!(parse_u32, MemIdx));
-named!(parse_globalidx <Inp, GlobalIdx>, map!(parse_u32, GlobalIdx));
-named!(parse_localidx <Inp, LocalIdx>,   map!(parse_u32, LocalIdx));
-named!(parse_labelidx <Inp, LabelIdx>,   map!(parse_u32, LabelIdx));
+fn parse_typeidx(d: &mut Decoder) -> PResult<TypeIdx>     { parse_u32(d).map(TypeIdx) }
+fn parse_funcidx(d: &mut Decoder) -> PResult<FuncIdx>     { parse_u32(d).map(FuncIdx) }
+fn parse_tableidx(d: &mut Decoder) -> PResult<TableIdx>   { parse_u32(d).map(TableIdx) }
+fn parse_memidx(d: &mut Decoder) -> PResult<MemIdx>       { parse_u32(d).map(MemIdx) }
+fn parse_globalidx(d: &mut Decoder) -> PResult<GlobalIdx> { parse_u32(d).map(GlobalIdx) }
+fn parse_localidx(d: &mut Decoder) -> PResult<LocalIdx>   { parse_u32(d).map(LocalIdx) }
+fn parse_labelidx(d: &mut Decoder) -> PResult<LabelIdx>   { parse_u32(d).map(LabelIdx) }
 
 // 5.5.1. Sections
-fn parse_section<'a, F, B>(input: Inp<'a>, N: u8, parse_B: F) -> IResult<Inp<'a>, B>
-    where F: Fn(Inp<'a>) -> IResult<Inp<'a>, B>
+fn parse_section<'a, F, B>(d: &mut Decoder<'a>, N: u8, parse_B: F) -> PResult<B>
+    where F: FnOnce(&mut Decoder<'a>) -> PResult<B>
+{
+    parse_tag(d, "section id", |b| if b == N { Some(()) } else { None })?;
+    parse_sized(d, parse_B)
+}
+
+/// Parses the section with id `N` if it comes next.
+fn parse_opt_section<'a, F, B>(d: &mut Decoder<'a>, N: u8, parse_B: F) -> PResult<Option<B>>
+    where F: FnOnce(&mut Decoder<'a>) -> PResult<B>
+{
+    if d.peek() == Some(N) {
+        parse_section(d, N, parse_B).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parses a u32 size, followed by exactly that many bytes
+/// that have to be consumed by `parse_B`.
+fn parse_sized<'a, F, B>(d: &mut Decoder<'a>, parse_B: F) -> PResult<B>
+    where F: FnOnce(&mut Decoder<'a>) -> PResult<B>
 {
-    do_parse!(input,
-        btag!(N)
-        >> cont: length_value!(
-            parse_u32,
-            exact!(parse_B)
-        )
-        >> (cont)
-    )
+    parse_sized_max(d, None, parse_B)
+}
+
+/// Like `parse_sized`, with the size capped by `limit`.
+fn parse_sized_max<'a, F, B>(d: &mut Decoder<'a>, limit: Option<Limit>, parse_B: F) -> PResult<B>
+    where F: FnOnce(&mut Decoder<'a>) -> PResult<B>
+{
+    let start = d.pos;
+    let size = parse_u32(d)? as usize;
+    if let Some(limit) = limit {
+        d.check_limit(start, limit, size as u64)?;
+    }
+    if size > d.remaining() {
+        return d.fail_at(start, ParseErrorKind::SizeMismatch);
+    }
+    let mut cont = d.split(size)?;
+    let res = parse_B(&mut cont)?;
+    cont.finish()?;
+    Ok(res)
 }
 
 // 5.5.3. Custom Section
@@ -653,126 +856,129 @@ fn parse_section<'a, F, B>(input: Inp<'a>, N: u8, parse_B: F) -> IResult<Inp<'a>
 pub struct CustomSection {
     pub name: Name,
     pub bytes: Vec<u8>,
+
+    /// Id of the known section this custom section follows,
+    /// or 0 if it comes before all of them.
+    ///
+    /// Valid ids are 0 to 11. The encoder writes a custom section
+    /// with a larger id at the end of the module, same as for 11.
+    ///
+    /// This is not part of the spec, but is needed to write
+    /// the section back out at its original position.
+    pub after: u8,
+}
+fn parse_custom(d: &mut Decoder) -> PResult<CustomSection> {
+    let name = parse_name(d)?;
+    let bytes = d.take_rest().to_vec();
+    Ok(CustomSection { name, bytes, after: 0 })
 }
-named!(parse_custom <Inp, CustomSection>, do_parse!(
-    name: parse_name
-    >> bytes: many0!(parse_byte)
-    >> (CustomSection { name, bytes })
-));
-named!(parse_customsec <Inp, CustomSection>,
-    call!(parse_section, 0, parse_custom)
-);
-named!(parse_customsecs <Inp, Vec<CustomSection>>,
-    many0!(parse_customsec)
-);
 
 // 5.5.4. Type Section
-named!(parse_type <Inp, Wec<FuncType>>, call!(parse_vec, parse_functype));
-named!(parse_typesec <Inp, Wec<FuncType>>,
-    map!(opt!(call!(parse_section, 1, parse_type)), |x| x.unwrap_or_default())
-);
+fn parse_type(d: &mut Decoder) -> PResult<Wec<FuncType>> {
+    let n = parse_len(d, Limit::Types)?;
+    parse_vec_items(d, n, parse_functype)
+}
 
 // 5.5.5. Import Section
 use greenwasm_structure::modules::Import;
 use greenwasm_structure::modules::ImportDesc;
-named!(parse_import <Inp, Import>, do_parse!(
-    module: parse_name
-    >> name: parse_name
-    >> desc: parse_importdesc
-    >> (Import { module, name, desc })
-));
-named!(parse_imports <Inp, Wec<Import>>, call!(parse_vec, parse_import));
-named!(parse_importsec <Inp, Wec<Import>>,
-    map!(opt!(call!(parse_section, 2, parse_imports)), |x| x.unwrap_or_default())
-);
-named!(parse_importdesc <Inp, ImportDesc>, alt!(
-      do_parse!(btag!(0x00) >> x:  parse_typeidx    >> (ImportDesc::Func(x)))
-    | do_parse!(btag!(0x01) >> tt: parse_tabletype  >> (ImportDesc::Table(tt)))
-    | do_parse!(btag!(0x02) >> mt: parse_memtype    >> (ImportDesc::Mem(mt)))
-    | do_parse!(btag!(0x03) >> gt: parse_globaltype >> (ImportDesc::Global(gt)))
-));
+fn parse_import(d: &mut Decoder) -> PResult<Import> {
+    let module = parse_name(d)?;
+    let name = parse_name(d)?;
+    let desc = parse_importdesc(d)?;
+    Ok(Import { module, name, desc })
+}
+fn parse_imports(d: &mut Decoder) -> PResult<Wec<Import>> {
+    let n = parse_len(d, Limit::Imports)?;
+    parse_vec_items(d, n, parse_import)
+}
+fn parse_importdesc(d: &mut Decoder) -> PResult<ImportDesc> {
+    let kind = parse_tag(d, "import kind", |b| if b <= 0x03 { Some(b) } else { None })?;
+    Ok(match kind {
+        0x00 => ImportDesc::Func(parse_typeidx(d)?),
+        0x01 => ImportDesc::Table(parse_tabletype(d)?),
+        0x02 => ImportDesc::Mem(parse_memtype(d)?),
+        _    => ImportDesc::Global(parse_globaltype(d)?),
+    })
+}
 
 // 5.5.6. Function Section
-named!(parse_func_ <Inp, Wec<TypeIdx>>, call!(parse_vec, parse_typeidx));
-named!(parse_funcsec <Inp, Wec<TypeIdx>>,
-    map!(opt!(call!(parse_section, 3, parse_func_)), |x| x.unwrap_or_default())
-);
+fn parse_func_(d: &mut Decoder) -> PResult<Wec<TypeIdx>> {
+    let n = parse_len(d, Limit::Funcs)?;
+    parse_vec_items(d, n, parse_typeidx)
+}
 
 // 5.5.7. Table Section
 use greenwasm_structure::modules::Table;
-named!(parse_table <Inp, Table>, do_parse!(
-    tt: parse_tabletype
-    >> (Table { type_: tt })
-));
-named!(parse_tables <Inp, Wec<Table>>, call!(parse_vec, parse_table));
-named!(parse_tablesec <Inp, Wec<Table>>,
-    map!(opt!(call!(parse_section, 4, parse_tables)), |x| x.unwrap_or_default())
-);
+fn parse_table(d: &mut Decoder) -> PResult<Table> {
+    let tt = parse_tabletype(d)?;
+    Ok(Table { type_: tt })
+}
+fn parse_tables(d: &mut Decoder) -> PResult<Wec<Table>> {
+    parse_vec(d, parse_table)
+}
 
 // 5.5.8. Memory Section
 use greenwasm_structure::modules::Mem;
-named!(parse_mem <Inp, Mem>, do_parse!(
-    mt: parse_memtype
-    >> (Mem { type_: mt })
-));
-named!(parse_mems <Inp, Wec<Mem>>, call!(parse_vec, parse_mem));
-named!(parse_memsec <Inp, Wec<Mem>>,
-    map!(opt!(call!(parse_section, 5, parse_mems)), |x| x.unwrap_or_default())
-);
+fn parse_mem(d: &mut Decoder) -> PResult<Mem> {
+    let mt = parse_memtype(d)?;
+    Ok(Mem { type_: mt })
+}
+fn parse_mems(d: &mut Decoder) -> PResult<Wec<Mem>> {
+    parse_vec(d, parse_mem)
+}
 
 // 5.5.9. Global Section
 use greenwasm_structure::modules::Global;
-named!(parse_global <Inp, Global>, do_parse!(
-    gt: parse_globaltype
-    >> e: parse_expr
-    >> (Global { type_: gt, init: e })
-));
-named!(parse_globals <Inp, Wec<Global>>, call!(parse_vec, parse_global));
-named!(parse_globalsec <Inp, Wec<Global>>,
-    map!(opt!(call!(parse_section, 6, parse_globals)), |x| x.unwrap_or_default())
-);
+fn parse_global(d: &mut Decoder) -> PResult<Global> {
+    let gt = parse_globaltype(d)?;
+    let e = parse_expr(d)?;
+    Ok(Global { type_: gt, init: e })
+}
+fn parse_globals(d: &mut Decoder) -> PResult<Wec<Global>> {
+    parse_vec(d, parse_global)
+}
 
 // 5.5.10. Export Section
 use greenwasm_structure::modules::Export;
 use greenwasm_structure::modules::ExportDesc;
-named!(parse_export <Inp, Export>, do_parse!(
-    name: parse_name
-    >> desc: parse_exportdesc
-    >> (Export { name, desc })
-));
-named!(parse_exports <Inp, Wec<Export>>, call!(parse_vec, parse_export));
-named!(parse_exportsec <Inp, Wec<Export>>,
-    map!(opt!(call!(parse_section, 7, parse_exports)), |x| x.unwrap_or_default())
-);
-named!(parse_exportdesc <Inp, ExportDesc>, alt!(
-      do_parse!(btag!(0x00) >> x: parse_funcidx    >> (ExportDesc::Func(x)))
-    | do_parse!(btag!(0x01) >> x: parse_tableidx   >> (ExportDesc::Table(x)))
-    | do_parse!(btag!(0x02) >> x: parse_memidx     >> (ExportDesc::Mem(x)))
-    | do_parse!(btag!(0x03) >> x: parse_globalidx  >> (ExportDesc::Global(x)))
-));
+fn parse_export(d: &mut Decoder) -> PResult<Export> {
+    let name = parse_name(d)?;
+    let desc = parse_exportdesc(d)?;
+    Ok(Export { name, desc })
+}
+fn parse_exports(d: &mut Decoder) -> PResult<Wec<Export>> {
+    let n = parse_len(d, Limit::Exports)?;
+    parse_vec_items(d, n, parse_export)
+}
+fn parse_exportdesc(d: &mut Decoder) -> PResult<ExportDesc> {
+    let kind = parse_tag(d, "export kind", |b| if b <= 0x03 { Some(b) } else { None })?;
+    Ok(match kind {
+        0x00 => ExportDesc::Func(parse_funcidx(d)?),
+        0x01 => ExportDesc::Table(parse_tableidx(d)?),
+        0x02 => ExportDesc::Mem(parse_memidx(d)?),
+        _    => ExportDesc::Global(parse_globalidx(d)?),
+    })
+}
 
 // 5.5.11. Start Section
 use greenwasm_structure::modules::Start;
-named!(parse_start <Inp, Start>, do_parse!(
-    x: parse_funcidx
-    >> (Start { func: x })
-));
-named!(parse_startsec <Inp, Option<Start>>,
-    opt!(call!(parse_section, 8, parse_start))
-);
+fn parse_start(d: &mut Decoder) -> PResult<Start> {
+    let x = parse_funcidx(d)?;
+    Ok(Start { func: x })
+}
 
 // 5.5.12. Element Section
 use greenwasm_structure::modules::Elem;
-named!(parse_elem <Inp, Elem>, do_parse!(
-    x: parse_tableidx
-    >> e: parse_expr
-    >> ys: call!(parse_vec, parse_funcidx)
-    >> (Elem { table: x, offset: e, init: ys })
-));
-named!(parse_elems <Inp, Wec<Elem>>, call!(parse_vec, parse_elem));
-named!(parse_elemsec <Inp, Wec<Elem>>,
-    map!(opt!(call!(parse_section, 9, parse_elems)), |x| x.unwrap_or_default())
-);
+fn parse_elem(d: &mut Decoder) -> PResult<Elem> {
+    let x = parse_tableidx(d)?;
+    let e = parse_expr(d)?;
+    let ys = parse_vec(d, parse_funcidx)?;
+    Ok(Elem { table: x, offset: e, init: ys })
+}
+fn parse_elems(d: &mut Decoder) -> PResult<Wec<Elem>> {
+    parse_vec(d, parse_elem)
+}
 
 // 5.5.13. Code Section
 #[derive(Debug, PartialEq)]
@@ -780,146 +986,549 @@ struct Code {
     locals: Wec<ValType>,
     body: Expr,
 }
-named!(parse_locals <Inp, (usize, ValType)>, do_parse!(
-    n: parse_u32
-    >> t: parse_valtype
-    >> ((n as usize, t))
-));
-named!(parse_func <Inp, Code>, do_parse!(
-    tss: call!(parse_vec, parse_locals)
-    >> tss: verify_ref!(
-        value!(tss),
-        |tss: &Wec<(_, _)>|
-            tss.iter().map(|x| x.0 as u64).sum::<u64>()
-            <= ::greenwasm_structure::types::WEC_MAX_SIZE as u64
-    )
-    >> ts: map!(
-        value!(tss),
-        |tss| tss.into_iter()
-                 .flat_map(|(n, t)| ::std::iter::repeat(t).take(n))
-                 .collect::<Vec<_>>().into()
-    )
-    >> e: parse_expr
-    >> (Code { locals: ts, body: e })
-));
-named!(parse_code <Inp, Code>, do_parse!(
-    code: length_value!(
-        parse_u32,
-        exact!(parse_func)
-    )
-    >> (code)
-));
-named!(parse_codes <Inp, Wec<Code>>, call!(parse_vec, parse_code));
-named!(parse_codesec <Inp, Wec<Code>>,
-    map!(opt!(call!(parse_section, 10, parse_codes)), |x| x.unwrap_or_default())
-);
+fn parse_locals(d: &mut Decoder) -> PResult<(usize, ValType)> {
+    let n = parse_u32(d)?;
+    let t = parse_valtype(d)?;
+    Ok((n as usize, t))
+}
+fn parse_func(d: &mut Decoder) -> PResult<Code> {
+    let tss = parse_vec(d, parse_locals)?;
+    let n = tss.iter().map(|x| x.0 as u64).sum::<u64>();
+    if n > ::greenwasm_structure::types::WEC_MAX_SIZE as u64 {
+        return d.fail(ParseErrorKind::TooManyLocals);
+    }
+    d.check_limit(d.pos, Limit::Locals, n)?;
+    d.alloc(n as usize * mem::size_of::<ValType>())?;
+    let ts = tss.into_iter()
+        .flat_map(|(n, t)| ::std::iter::repeat(t).take(n))
+        .collect::<Vec<_>>().into();
+    let e = parse_expr(d)?;
+    Ok(Code { locals: ts, body: e })
+}
+fn parse_code(d: &mut Decoder) -> PResult<Code> {
+    parse_sized_max(d, Some(Limit::BodySize), parse_func)
+}
 
 // 5.5.14. Data Section
 use greenwasm_structure::modules::Data;
-named!(parse_data <Inp, Data>, do_parse!(
-    x: parse_memidx
-    >> e: parse_expr
-    >> bs: call!(parse_vec, parse_byte)
-    >> (Data { data: x, offset: e, init: bs })
-));
-named!(parse_datas <Inp, Wec<Data>>, call!(parse_vec, parse_data));
-named!(parse_datasec <Inp, Wec<Data>>,
-    map!(opt!(call!(parse_section, 11, parse_datas)), |x| x.unwrap_or_default())
-);
+fn parse_data(d: &mut Decoder) -> PResult<Data> {
+    let x = parse_memidx(d)?;
+    let e = parse_expr(d)?;
+    let bs = parse_bytes(d)?;
+    Ok(Data { data: x, offset: e, init: bs })
+}
+fn parse_datas(d: &mut Decoder) -> PResult<Wec<Data>> {
+    parse_vec(d, parse_data)
+}
 
 // 5.5.15. Modules
 use greenwasm_structure::modules::Module;
 use greenwasm_structure::modules::Func;
-named!(parse_magic <Inp, ()>,
-    value!((), tag!(&[0x00, 0x61, 0x73, 0x6D][..]))
-);
-named!(parse_version <Inp, ()>,
-    value!((), tag!(&[0x01, 0x00, 0x00, 0x00][..]))
-);
-named!(parse_module <Inp, (Module, Vec<CustomSection>)>, do_parse!(
-    parse_magic
-    >> parse_version
-
-    >> cs00: parse_customsecs
-    >> types: parse_typesec
-    >> cs01: parse_customsecs
-    >> imports: parse_importsec
-    >> cs02: parse_customsecs
-    >> typeindices_n: parse_funcsec
-    >> cs03: parse_customsecs
-    >> tables: parse_tablesec
-    >> cs04: parse_customsecs
-    >> mems: parse_memsec
-    >> cs05: parse_customsecs
-    >> globals: parse_globalsec
-    >> cs06: parse_customsecs
-    >> exports: parse_exportsec
-    >> cs07: parse_customsecs
-    >> start: parse_startsec
-    >> cs08: parse_customsecs
-    >> elem: parse_elemsec
-    >> cs09: parse_customsecs
-    >> codes_n: parse_codesec
-    >> cs10: parse_customsecs
-    >> data: parse_datasec
-    >> cs11: parse_customsecs
-
-    >> verify!(value!(()), |_| typeindices_n.len() == codes_n.len())
-
-    >> ({
-        let funcs = typeindices_n.into_iter().zip(codes_n)
-            .map(|(type_, Code { locals, body })| {
-                Func { type_, locals, body }
-            })
-            .collect();
-        let customs = vec![
-            cs00,
-            cs01,
-            cs02,
-            cs03,
-            cs04,
-            cs05,
-            cs06,
-            cs07,
-            cs08,
-            cs09,
-            cs10,
-            cs11,
-        ].into_iter().flat_map(|cs| cs).collect();
-
-        (
-            Module {
-                types,
-                funcs,
-                tables,
-                mems,
-                globals,
-                elem,
-                data,
-                start,
-                imports,
-                exports,
-            },
-            customs
-        )
+fn parse_magic(d: &mut Decoder) -> PResult<()> {
+    if d.as_slice().starts_with(&[0x00, 0x61, 0x73, 0x6D]) {
+        d.pos += 4;
+        Ok(())
+    } else {
+        d.fail(ParseErrorKind::BadMagic)
+    }
+}
+fn parse_version(d: &mut Decoder) -> PResult<()> {
+    if d.as_slice().starts_with(&[0x01, 0x00, 0x00, 0x00]) {
+        d.pos += 4;
+        Ok(())
+    } else {
+        d.fail(ParseErrorKind::BadVersion)
+    }
+}
+
+/// A module whose function bodies have been decoded with `decode_codes`.
+struct ModuleParts<C> {
+    // NB: `funcs` is empty
+    module: Module,
+    typeindices: Wec<TypeIdx>,
+    codes: Wec<C>,
+    customs: Vec<CustomSection>,
+}
+fn parse_module_parts<'a, C, F>(b: &'a [u8], config: &ParseConfig, decode_codes: F)
+    -> Result<ModuleParts<C>, ParseError>
+    where F: Fn(Vec<FuncBody<'a>>) -> Result<Vec<C>, ParseError>
+{
+    let mut types = Wec::default();
+    let mut imports = Wec::default();
+    let mut typeindices = Wec::default();
+    let mut tables = Wec::default();
+    let mut mems = Wec::default();
+    let mut globals = Wec::default();
+    let mut exports = Wec::default();
+    let mut start = None;
+    let mut elem = Wec::default();
+    let mut codes = Wec::default();
+    let mut data = Wec::default();
+    let mut customs = vec![];
+
+    fn collect<T>(r: VecReader<T>) -> Result<Wec<T>, ParseError> {
+        Ok(r.collect::<Result<Vec<_>, _>>()?.into())
+    }
+
+    let mut after = 0;
+    for section in SectionReader::with_config(b, config) {
+        let section = section?;
+        match section.payload()? {
+            Payload::Custom(name, bytes) => {
+                customs.push(CustomSection { name, bytes: bytes.to_vec(), after });
+            }
+            Payload::Types(r) => types = collect(r)?,
+            Payload::Imports(r) => imports = collect(r)?,
+            Payload::Funcs(r) => typeindices = collect(r)?,
+            Payload::Tables(r) => tables = collect(r)?,
+            Payload::Mems(r) => mems = collect(r)?,
+            Payload::Globals(r) => globals = collect(r)?,
+            Payload::Exports(r) => exports = collect(r)?,
+            Payload::Start(s) => start = Some(s),
+            Payload::Elem(r) => elem = collect(r)?,
+            Payload::Code(r) => {
+                // NB: The bodies are decoded before the following sections
+                // are parsed, and before a malformed entry of the code section
+                // is reported, so that the first error in the input wins.
+                let mut bodies = vec![];
+                let mut rest = Ok(());
+                for body in r {
+                    match body {
+                        Ok(body) => bodies.push(body),
+                        Err(e) => {
+                            rest = Err(e);
+                            break;
+                        }
+                    }
+                }
+                codes = decode_codes(bodies)?.into();
+                rest?;
+            }
+            Payload::Data(r) => data = collect(r)?,
+        }
+        if section.id != 0 {
+            after = section.id;
+        }
+    }
+
+    if typeindices.len() != codes.len() {
+        return Err(ParseError::new(b, DecodeError {
+            offset: b.len(),
+            kind: ParseErrorKind::FuncCodeMismatch,
+        }));
+    }
+
+    Ok(ModuleParts {
+        module: Module {
+            types,
+            funcs: Wec::default(),
+            tables,
+            mems,
+            globals,
+            elem,
+            data,
+            start,
+            imports,
+            exports,
+        },
+        typeindices,
+        codes,
+        customs,
     })
-));
+}
 
-#[derive(Debug)]
-pub enum ParseError<'a> {
-    NomError(::nom::Err<CompleteByteSlice<'a>, u32>)
+// 7.4.1. Name Section
+
+/// The decoded contents of the custom section called `name`.
+///
+/// Name maps are kept in the order of the section, which
+/// is required to be sorted by index.
+#[derive(Debug, PartialEq, Default)]
+pub struct NameSection {
+    pub module: Option<Name>,
+    pub funcs: Vec<(FuncIdx, Name)>,
+    pub locals: Vec<(FuncIdx, Vec<(LocalIdx, Name)>)>,
 }
-pub fn parse_binary_format(b: &[u8]) -> Result<(Module, Vec<CustomSection>), ParseError> {
-    let res = exact!(CompleteByteSlice(b), parse_module);
-    match res {
-        Ok((CompleteByteSlice(s), res)) => {
-            assert!(s.len() == 0);
-            Ok(res)
+fn parse_nameassoc(d: &mut Decoder) -> PResult<(u32, Name)> {
+    let idx = parse_u32(d)?;
+    let name = parse_name(d)?;
+    Ok((idx, name))
+}
+type NameMap = Wec<(u32, Name)>;
+fn parse_namemap(d: &mut Decoder) -> PResult<NameMap> {
+    parse_vec(d, parse_nameassoc)
+}
+fn parse_indirectnameassoc(d: &mut Decoder) -> PResult<(u32, NameMap)> {
+    let idx = parse_u32(d)?;
+    let map = parse_namemap(d)?;
+    Ok((idx, map))
+}
+fn parse_indirectnamemap(d: &mut Decoder) -> PResult<Wec<(u32, NameMap)>> {
+    parse_vec(d, parse_indirectnameassoc)
+}
+fn parse_namesec(d: &mut Decoder) -> PResult<NameSection> {
+    let module = parse_opt_section(d, 0, parse_name)?;
+    let funcs = parse_opt_section(d, 1, parse_namemap)?;
+    let locals = parse_opt_section(d, 2, parse_indirectnamemap)?;
+    while let Some(id) = d.peek() {
+        if id <= 2 {
+            return d.fail(ParseErrorKind::SectionOrder(id));
+        }
+        d.pos += 1;
+        parse_sized(d, |d| Ok(d.take_rest()))?;
+    }
+    Ok(NameSection {
+        module,
+        funcs: funcs.into_iter().flatten().map(|(x, n)| (FuncIdx(x), n)).collect(),
+        locals: locals.into_iter().flatten().map(|(x, m)| {
+            (FuncIdx(x), m.into_iter().map(|(y, n)| (LocalIdx(y), n)).collect())
+        }).collect(),
+    })
+}
+
+/// Decodes the payload of a custom section called `name`.
+///
+/// Since the name section is only used for debugging purposes, a malformed
+/// section should not cause the module to be rejected. This returns `None`
+/// in that case, and ignores unknown subsections.
+pub fn parse_name_section(bytes: &[u8]) -> Option<NameSection> {
+    parse_namesec(&mut Decoder::new(bytes)).ok()
+}
+
+/// Caps on the resources used for parsing a module.
+///
+/// The default values of the counts and sizes are the limits that
+/// the JavaScript embedding imposes, so that no module that is usable
+/// on the web is rejected. Nesting and allocation are not limited by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseLimits {
+    pub max_types: u32,
+    /// The number of entries in the function and code section.
+    pub max_funcs: u32,
+    pub max_imports: u32,
+    pub max_exports: u32,
+    /// The number of locals of a function, not counting its parameters.
+    pub max_locals: u32,
+    /// The number of blocks, loops and ifs enclosing an instruction.
+    pub max_nesting: u32,
+    /// The size of an entry of the code section in bytes.
+    pub max_body_size: u32,
+    /// The total size of the vectors and strings of the parsed module in bytes.
+    ///
+    /// NB: This does not include the bytes of custom sections, which
+    /// are always smaller than the input.
+    pub max_alloc: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_types: 1_000_000,
+            max_funcs: 1_000_000,
+            max_imports: 100_000,
+            max_exports: 100_000,
+            max_locals: 50_000,
+            max_nesting: ::std::u32::MAX,
+            max_body_size: 7_654_321,
+            max_alloc: ::std::usize::MAX,
         }
-        Err(x) => Err(ParseError::NomError(x))
     }
 }
 
+impl ParseLimits {
+    fn max(&self, limit: Limit) -> u64 {
+        match limit {
+            Limit::Types => self.max_types as u64,
+            Limit::Funcs => self.max_funcs as u64,
+            Limit::Imports => self.max_imports as u64,
+            Limit::Exports => self.max_exports as u64,
+            Limit::Locals => self.max_locals as u64,
+            Limit::Nesting => self.max_nesting as u64,
+            Limit::BodySize => self.max_body_size as u64,
+            Limit::Alloc => self.max_alloc as u64,
+        }
+    }
+}
+
+/// The limits and enabled features of a parse.
+///
+/// The instructions of proposals that are not enabled
+/// in `features` fail with `FeatureDisabled`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseConfig {
+    pub limits: ParseLimits,
+    pub features: WasmFeatures,
+}
+
+/// Identifies a field of `ParseLimits`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Types,
+    Funcs,
+    Imports,
+    Exports,
+    Locals,
+    Nesting,
+    BodySize,
+    Alloc,
+}
+
+impl ::std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let s = match *self {
+            Limit::Types => "number of types",
+            Limit::Funcs => "number of functions",
+            Limit::Imports => "number of imports",
+            Limit::Exports => "number of exports",
+            Limit::Locals => "number of locals",
+            Limit::Nesting => "nesting depth",
+            Limit::BodySize => "function body size",
+            Limit::Alloc => "allocated bytes",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedEnd,
+    BadMagic,
+    BadVersion,
+    /// An integer is encoded with too many bytes, or with unused bits set.
+    BadLeb128,
+    UnknownOpcode(u8),
+    UnexpectedByte {
+        expected: &'static str,
+        found: u8,
+    },
+    ElseWithoutIf,
+    /// A section or function body is not consumed exactly by its contents.
+    SizeMismatch,
+    /// A known section appears out of order, or more than once.
+    SectionOrder(u8),
+    UnknownSection(u8),
+    InvalidUtf8,
+    FuncCodeMismatch,
+    TooManyLocals,
+    /// A cap of the `ParseLimits` has been hit.
+    LimitExceeded(Limit),
+    /// An instruction of a proposal that is not enabled in the `WasmFeatures`.
+    FeatureDisabled(Feature),
+    Malformed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Offset of the offending byte from the start of the module.
+    pub offset: usize,
+    /// Id of the section that contains the offending byte.
+    pub section: Option<u8>,
+    /// Index of the function in the code section that contains the offending byte.
+    ///
+    /// NB: This is an index into the function index space,
+    /// so it includes the imported functions.
+    pub func: Option<FuncIdx>,
+    pub kind: ParseErrorKind,
+}
+
+pub fn section_name(id: u8) -> &'static str {
+    match id {
+        0 => "custom",
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        _ => "unknown",
+    }
+}
+
+impl ParseError {
+    pub fn section_name(&self) -> Option<&'static str> {
+        self.section.map(section_name)
+    }
+
+    fn new(b: &[u8], e: DecodeError) -> Self {
+        let DecodeError { offset, kind } = e;
+
+        // NB: An error at the end of a section, like a missing byte,
+        // belongs to that section rather than to the next one.
+        let at_end = kind == ParseErrorKind::UnexpectedEnd || kind == ParseErrorKind::SizeMismatch;
+        let (section, func) = locate(b, offset, at_end);
+
+        ParseError { offset, section, func, kind }
+    }
+}
+
+fn count_func_imports(imports: &[Import]) -> u32 {
+    imports.iter().filter(|i| {
+        if let ImportDesc::Func(_) = i.desc { true } else { false }
+    }).count() as u32
+}
+
+/// Finds the section and the function body that contain `offset`
+/// by walking the section headers of a module that failed to parse.
+fn locate(b: &[u8], offset: usize, at_end: bool) -> (Option<u8>, Option<FuncIdx>) {
+    let contains = |start: usize, end: usize| {
+        start <= offset && (offset < end || (at_end && offset == end))
+    };
+    // Returns the value and the end of a u32 at `pos`
+    let read_u32 = |pos: usize| {
+        let mut d = Decoder::new(b);
+        d.pos = pos.min(b.len());
+        parse_u32(&mut d).ok().map(|n| (n as usize, d.offset()))
+    };
+
+    let mut imported_funcs = 0;
+    let mut pos = 8;
+    while pos < b.len() {
+        let id = b[pos];
+        let (size, start) = match read_u32(pos + 1) {
+            Some(x) => x,
+            None => return (if offset >= pos { Some(id) } else { None }, None),
+        };
+        let end = start.saturating_add(size).min(b.len());
+        if !contains(pos, end) {
+            if id == 2 {
+                let mut d = Decoder::new(b);
+                d.pos = start;
+                d.end = end;
+                if let Ok(imports) = parse_imports(&mut d) {
+                    imported_funcs = count_func_imports(&imports) as usize;
+                }
+            }
+            pos = end;
+            continue;
+        }
+
+        let mut func = None;
+        if id == 10 {
+            if let Some((n, mut p)) = read_u32(start) {
+                for i in 0..n {
+                    let (size, body) = match read_u32(p) {
+                        Some(x) => x,
+                        None => break,
+                    };
+                    let body_end = body.saturating_add(size).min(end);
+                    if contains(p, body_end) {
+                        func = Some(FuncIdx((imported_funcs + i) as u32));
+                        break;
+                    }
+                    p = body_end;
+                }
+            }
+        }
+        return (Some(id), func);
+    }
+    (None, None)
+}
+
+impl ::std::fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        use self::ParseErrorKind::*;
+        match *self {
+            UnexpectedEnd => write!(f, "unexpected end"),
+            BadMagic => write!(f, "magic header not detected"),
+            BadVersion => write!(f, "unknown binary version"),
+            BadLeb128 => write!(f, "integer representation too long or too large"),
+            UnknownOpcode(b) => write!(f, "unknown opcode 0x{:02x}", b),
+            UnexpectedByte { expected, found } => {
+                write!(f, "unexpected byte 0x{:02x}, expected {}", found, expected)
+            }
+            ElseWithoutIf => write!(f, "else without matching if"),
+            SizeMismatch => write!(f, "size does not match contents"),
+            SectionOrder(id) => write!(f, "{} section out of order", section_name(id)),
+            UnknownSection(id) => write!(f, "unknown section id {}", id),
+            InvalidUtf8 => write!(f, "name is not valid UTF-8"),
+            FuncCodeMismatch => write!(f, "function and code section have inconsistent lengths"),
+            TooManyLocals => write!(f, "too many locals"),
+            LimitExceeded(limit) => write!(f, "{} exceeds the limit", limit),
+            FeatureDisabled(feature) => write!(f, "feature disabled: {}", feature),
+            Malformed => write!(f, "malformed module"),
+        }
+    }
+}
+
+impl ::std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{} at offset 0x{:x}", self.kind, self.offset)?;
+        if let Some(name) = self.section_name() {
+            write!(f, " in {} section", name)?;
+        }
+        if let Some(FuncIdx(x)) = self.func {
+            write!(f, " of function {}", x)?;
+        }
+        Ok(())
+    }
+}
+
+impl ::std::error::Error for ParseError {}
+
+/// Parses a complete module.
+///
+/// With the `parallel` feature, the function bodies are decoded on a thread pool.
+/// The first error in the input is reported either way.
+pub fn parse_binary_format(b: &[u8]) -> Result<(Module, Vec<CustomSection>), ParseError> {
+    parse_binary_format_with_config(b, &ParseConfig::default())
+}
+
+/// Like `parse_binary_format`, but fails with `LimitExceeded` if the
+/// module exceeds one of the `limits` of `config`, and accepts
+/// the instructions of its enabled `features`.
+pub fn parse_binary_format_with_config(b: &[u8], config: &ParseConfig)
+    -> Result<(Module, Vec<CustomSection>), ParseError>
+{
+    let parts = parse_module_parts(b, config, |bodies| {
+        decode_all(&bodies, |body| body.decode_code().map_err(|e| ParseError::new(b, e)))
+    })?;
+    let ModuleParts { mut module, typeindices, codes, customs } = parts;
+    module.funcs = typeindices.into_iter().zip(codes)
+        .map(|(type_, Code { locals, body })| {
+            Func { type_, locals, body }
+        })
+        .collect();
+    Ok((module, customs))
+}
+
+/// Decodes all `items`, on a thread pool with the `parallel` feature.
+/// The error of the first item that fails is reported either way.
+fn decode_all<T, U, F>(items: &[T], decode: F) -> Result<Vec<U>, ParseError>
+    where T: Sync,
+          U: Send,
+          F: Fn(&T) -> Result<U, ParseError> + Sync + Send,
+{
+    #[cfg(not(feature = "parallel"))]
+    let results = items.iter().map(decode);
+
+    #[cfg(feature = "parallel")]
+    let results = {
+        use rayon::prelude::*;
+
+        items.par_iter().map(decode).collect::<Vec<_>>().into_iter()
+    };
+
+    // NB: The results are in order, so this stops at the first error
+    results.collect()
+}
+
+mod encode;
+pub use encode::encode_binary_format;
+
+mod stream;
+pub use stream::{StreamParser, Event, ReadParser, StreamError, parse_binary_stream};
+
+mod reader;
+pub use reader::{SectionReader, Section, Payload, VecReader, FuncBody};
+
+mod lazy;
+pub use lazy::{LazyModule, LazyFunc, parse_binary_format_lazy, parse_binary_format_lazy_with_config};
+
 #[cfg(test)]
 #[path="tests_binary_format.rs"]
 mod tests;
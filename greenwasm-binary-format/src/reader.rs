@@ -0,0 +1,288 @@
+//! Low-level access to the sections of a module.
+//!
+//! `SectionReader` iterates over the sections of a module and reports where
+//! each one is located, without decoding its contents. The contents can then
+//! be read one entry at a time with `Section::payload()`.
+//! This is what `parse_binary_format` is built on.
+
+use std::fmt;
+use std::ops::Range;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::modules::*;
+
+use super::*;
+
+pub struct SectionReader<'a> {
+    d: Decoder<'a>,
+    header: bool,
+    last_id: u8,
+    done: bool,
+}
+
+impl<'a> SectionReader<'a> {
+    pub fn new(b: &'a [u8]) -> Self {
+        SectionReader::with_config(b, &ParseConfig::default())
+    }
+
+    /// Like `new`, but the readers of the sections fail with `LimitExceeded`
+    /// if one of the `limits` of `config` is exceeded, and the function bodies
+    /// may also contain the instructions of its enabled `features`.
+    pub fn with_config(b: &'a [u8], config: &ParseConfig) -> Self {
+        SectionReader {
+            d: Decoder::with_budget(b, Budget::new(config)),
+            header: false,
+            last_id: 0,
+            done: false,
+        }
+    }
+
+    fn next_section(&mut self) -> PResult<Option<Section<'a>>> {
+        let d = &mut self.d;
+        if !self.header {
+            parse_magic(d)?;
+            parse_version(d)?;
+            self.header = true;
+        }
+
+        let start = d.offset();
+        let id = match d.peek() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        if id > 11 {
+            return d.fail(ParseErrorKind::UnknownSection(id));
+        }
+        if id != 0 && id <= self.last_id {
+            return d.fail(ParseErrorKind::SectionOrder(id));
+        }
+        d.pos += 1;
+
+        let size_start = d.offset();
+        let size = parse_u32(d)? as usize;
+        if size > d.remaining() {
+            return d.fail_at(size_start, ParseErrorKind::SizeMismatch);
+        }
+        let payload = d.split(size)?;
+
+        if id != 0 {
+            self.last_id = id;
+        }
+        Ok(Some(Section { id, start, payload }))
+    }
+}
+
+impl<'a> Iterator for SectionReader<'a> {
+    type Item = Result<Section<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_section() {
+            Ok(Some(section)) => Some(Ok(section)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(ParseError::new(self.d.input, e)))
+            }
+        }
+    }
+}
+
+pub struct Section<'a> {
+    pub id: u8,
+    // Offset of the id
+    start: usize,
+    payload: Decoder<'a>,
+}
+
+impl<'a> Section<'a> {
+    pub fn name(&self) -> &'static str {
+        section_name(self.id)
+    }
+
+    /// The location of the contents in the module,
+    /// without the section id and size.
+    pub fn range(&self) -> Range<usize> {
+        self.payload.pos..self.payload.end
+    }
+
+    /// The location of the whole section in the module.
+    pub fn section_range(&self) -> Range<usize> {
+        self.start..self.payload.end
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.payload.as_slice()
+    }
+
+    /// Returns a reader for the contents of the section.
+    pub fn payload(&self) -> Result<Payload<'a>, ParseError> {
+        self.payload_().map_err(|e| ParseError::new(self.payload.input, e))
+    }
+
+    fn payload_(&self) -> PResult<Payload<'a>> {
+        let mut d = self.payload.clone();
+        Ok(match self.id {
+            0 => {
+                let name = parse_name(&mut d)?;
+                Payload::Custom(name, d.take_rest())
+            }
+            1 => Payload::Types(VecReader::new(d, Some(Limit::Types), parse_functype)?),
+            2 => Payload::Imports(VecReader::new(d, Some(Limit::Imports), parse_import)?),
+            3 => Payload::Funcs(VecReader::new(d, Some(Limit::Funcs), parse_typeidx)?),
+            4 => Payload::Tables(VecReader::new(d, None, parse_table)?),
+            5 => Payload::Mems(VecReader::new(d, None, parse_mem)?),
+            6 => Payload::Globals(VecReader::new(d, None, parse_global)?),
+            7 => Payload::Exports(VecReader::new(d, Some(Limit::Exports), parse_export)?),
+            8 => {
+                let start = parse_start(&mut d)?;
+                d.finish()?;
+                Payload::Start(start)
+            }
+            9 => Payload::Elem(VecReader::new(d, None, parse_elem)?),
+            10 => Payload::Code(VecReader::new(d, Some(Limit::Funcs), parse_func_body)?),
+            11 => Payload::Data(VecReader::new(d, None, parse_data)?),
+            _ => unreachable!("unknown sections are rejected by SectionReader"),
+        })
+    }
+}
+
+impl<'a> fmt::Debug for Section<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Section")
+            .field("id", &self.id)
+            .field("range", &self.range())
+            .finish()
+    }
+}
+
+/// The contents of a section, by section kind.
+pub enum Payload<'a> {
+    /// The name and the remaining bytes of a custom section.
+    Custom(Name, &'a [u8]),
+    Types(VecReader<'a, FuncType>),
+    Imports(VecReader<'a, Import>),
+    /// The type indices of the function section.
+    Funcs(VecReader<'a, TypeIdx>),
+    Tables(VecReader<'a, Table>),
+    Mems(VecReader<'a, Mem>),
+    Globals(VecReader<'a, Global>),
+    Exports(VecReader<'a, Export>),
+    Start(Start),
+    Elem(VecReader<'a, Elem>),
+    Code(VecReader<'a, FuncBody<'a>>),
+    Data(VecReader<'a, Data>),
+}
+
+/// Iterator over the entries of a section that consists of a vector.
+///
+/// Fails after the last entry if the section contains any bytes after it.
+/// The space for all entries is accounted for in `ParseLimits::max_alloc`
+/// up front, as if they were collected.
+pub struct VecReader<'a, T> {
+    d: Decoder<'a>,
+    remaining: u32,
+    parse: fn(&mut Decoder<'a>) -> PResult<T>,
+    done: bool,
+}
+
+impl<'a, T> VecReader<'a, T> {
+    fn new(mut d: Decoder<'a>, limit: Option<Limit>, parse: fn(&mut Decoder<'a>) -> PResult<T>)
+        -> PResult<Self>
+    {
+        let remaining = match limit {
+            Some(limit) => parse_len(&mut d, limit)?,
+            None => parse_u32(&mut d)?,
+        };
+        d.alloc((remaining as usize).saturating_mul(mem::size_of::<T>()))?;
+        Ok(VecReader { d, remaining, parse, done: false })
+    }
+
+    /// The number of entries that have not been read yet.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+impl<'a, T> Iterator for VecReader<'a, T> {
+    type Item = Result<T, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = if self.remaining == 0 {
+            self.done = true;
+            match self.d.finish() {
+                Ok(()) => return None,
+                Err(e) => Err(e),
+            }
+        } else {
+            self.remaining -= 1;
+            (self.parse)(&mut self.d)
+        };
+        match res {
+            Ok(x) => Some(Ok(x)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(ParseError::new(self.d.input, e)))
+            }
+        }
+    }
+}
+
+/// An entry of the code section whose locals and body have not been decoded.
+#[derive(Clone)]
+pub struct FuncBody<'a> {
+    // Offset of `bytes` in the module
+    offset: usize,
+    bytes: &'a [u8],
+    budget: Arc<Budget>,
+}
+
+impl<'a> FuncBody<'a> {
+    /// The encoded locals and body of the function, without the size.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// The location of `bytes()` in the module.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.bytes.len()
+    }
+
+    pub(crate) fn decode_code(&self) -> PResult<Code> {
+        self.decode_code_with(self.budget.clone())
+    }
+
+    /// A budget for decoding the body again after the module has been parsed.
+    pub(crate) fn renew_budget(&self) -> Arc<Budget> {
+        self.budget.renew()
+    }
+
+    pub(crate) fn decode_code_with(&self, budget: Arc<Budget>) -> PResult<Code> {
+        let mut d = Decoder::with_budget(self.bytes, budget);
+        parse_func(&mut d)
+            .and_then(|code| d.finish().map(|_| code))
+            .map_err(|e| DecodeError { offset: self.offset + e.offset, ..e })
+    }
+}
+
+impl<'a> fmt::Debug for FuncBody<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FuncBody")
+            .field("range", &self.range())
+            .finish()
+    }
+}
+
+fn parse_func_body<'a>(d: &mut Decoder<'a>) -> PResult<FuncBody<'a>> {
+    parse_sized_max(d, Some(Limit::BodySize), |d| {
+        Ok(FuncBody { offset: d.offset(), bytes: d.take_rest(), budget: d.budget.clone() })
+    })
+}
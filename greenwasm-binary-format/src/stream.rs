@@ -0,0 +1,371 @@
+//! Incremental parsing of the binary format.
+//!
+//! `StreamParser` is fed the bytes of a module in arbitrary chunks and
+//! emits an `Event` for each section as soon as it is complete, and
+//! one for each function body in the code section. Only the bytes of the
+//! section or function body currently being parsed are kept in memory.
+
+use std::io::{self, Read};
+use std::fmt;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::modules::*;
+
+use super::*;
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Types(Wec<FuncType>),
+    Imports(Wec<Import>),
+    /// The type indices of the function section.
+    Funcs(Wec<TypeIdx>),
+    Tables(Wec<Table>),
+    Mems(Wec<Mem>),
+    Globals(Wec<Global>),
+    Exports(Wec<Export>),
+    Start(Start),
+    Elem(Wec<Elem>),
+    /// A single entry of the code section, combined with its type index.
+    Func(FuncIdx, Func),
+    Data(Wec<Data>),
+    Custom(CustomSection),
+    /// The module has been parsed completely.
+    End,
+}
+
+enum State {
+    Header,
+    Sections,
+    // Number of remaining bodies and the offset of the end of the code section
+    Code(u32, usize),
+    Done,
+}
+
+pub struct StreamParser {
+    buf: Vec<u8>,
+    // Start of the unconsumed bytes in `buf`
+    pos: usize,
+    // Offset of `buf[0]` in the module
+    offset: usize,
+    eof: bool,
+    state: State,
+
+    last_id: u8,
+    section: Option<u8>,
+    imported_funcs: u32,
+    func_types: Vec<TypeIdx>,
+    codes: usize,
+    budget: Arc<Budget>,
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        StreamParser {
+            buf: vec![],
+            pos: 0,
+            offset: 0,
+            eof: false,
+            state: State::Header,
+            last_id: 0,
+            section: None,
+            imported_funcs: 0,
+            func_types: vec![],
+            codes: 0,
+            budget: Budget::new(&ParseConfig::default()),
+        }
+    }
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Like `new`, but fails with `LimitExceeded` if the module exceeds
+    /// one of the `limits` of `config`, and the function bodies may
+    /// also contain the instructions of its enabled `features`.
+    pub fn with_config(config: &ParseConfig) -> Self {
+        StreamParser {
+            budget: Budget::new(config),
+            ..Default::default()
+        }
+    }
+
+    /// Appends the next chunk of the module.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        assert!(!self.eof, "fed after finish()");
+        self.buf.drain(..self.pos);
+        self.offset += self.pos;
+        self.pos = 0;
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Signals that all bytes of the module have been fed.
+    pub fn finish(&mut self) {
+        self.eof = true;
+    }
+
+    fn input(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    fn error(&self, offset: usize, kind: ParseErrorKind) -> ParseError {
+        let func = match self.state {
+            State::Code(..) => Some(FuncIdx(self.imported_funcs + self.codes as u32)),
+            _ => None,
+        };
+        ParseError {
+            offset: self.offset + self.pos + offset,
+            section: self.section,
+            func,
+            kind,
+        }
+    }
+
+    /// Runs `parse` on the unconsumed input.
+    ///
+    /// Returns `None` if it ran out of input before the end of the module,
+    /// or else the number of consumed bytes and the result.
+    fn run<'a, T, F>(&'a self, parse: F) -> Result<Option<(usize, T)>, ParseError>
+        where F: FnOnce(&mut Decoder<'a>) -> PResult<T>
+    {
+        let input = self.input();
+        let mut d = Decoder::with_budget(input, self.budget.clone());
+        match parse(&mut d) {
+            Ok(o) => Ok(Some((d.offset(), o))),
+            Err(DecodeError { offset, kind }) => {
+                if kind == ParseErrorKind::UnexpectedEnd && offset == input.len() && !self.eof {
+                    Ok(None)
+                } else {
+                    Err(self.error(offset, kind))
+                }
+            }
+        }
+    }
+
+    /// Returns the next event, or `None` if more input is needed
+    /// to complete it, or if the `End` event has already been returned.
+    pub fn next_event(&mut self) -> Result<Option<Event>, ParseError> {
+        loop {
+            match self.state {
+                State::Header => {
+                    if self.input().len() < 8 && !self.eof {
+                        return Ok(None);
+                    }
+                    let r = self.run(|d| {
+                        parse_magic(d)?;
+                        parse_version(d)
+                    })?;
+                    match r {
+                        Some((n, ())) => {
+                            self.pos += n;
+                            self.state = State::Sections;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                State::Sections => {
+                    self.section = None;
+                    if self.input().is_empty() {
+                        if !self.eof {
+                            return Ok(None);
+                        }
+                        if self.func_types.len() != self.codes {
+                            return Err(self.error(0, ParseErrorKind::FuncCodeMismatch));
+                        }
+                        self.state = State::Done;
+                        return Ok(Some(Event::End));
+                    }
+
+                    let id = self.input()[0];
+                    self.section = Some(id);
+                    if id > 11 {
+                        return Err(self.error(0, ParseErrorKind::UnknownSection(id)));
+                    }
+                    if id != 0 && id <= self.last_id {
+                        return Err(self.error(0, ParseErrorKind::SectionOrder(id)));
+                    }
+
+                    let r = self.run(|d| {
+                        parse_byte(d)?;
+                        parse_u32(d)
+                    })?;
+                    let (n, size) = match r {
+                        Some(x) => x,
+                        None => return Ok(None),
+                    };
+                    if id == 10 {
+                        let r = self.run(|d| {
+                            d.take(n)?;
+                            parse_len(d, Limit::Funcs)
+                        })?;
+                        match r {
+                            Some((m, count)) => {
+                                let end = self.offset + self.pos + n + size as usize;
+                                self.pos += m;
+                                self.last_id = id;
+                                self.state = State::Code(count, end);
+                                continue;
+                            }
+                            None => return Ok(None),
+                        }
+                    }
+
+                    if self.input().len() < n + size as usize && !self.eof {
+                        return Ok(None);
+                    }
+                    return self.section_event(id);
+                }
+                State::Code(0, end) => {
+                    if self.offset + self.pos != end {
+                        return Err(self.error(0, ParseErrorKind::SizeMismatch));
+                    }
+                    self.state = State::Sections;
+                }
+                State::Code(count, end) => {
+                    if self.codes >= self.func_types.len() {
+                        return Err(self.error(0, ParseErrorKind::FuncCodeMismatch));
+                    }
+                    let avail = end.saturating_sub(self.offset + self.pos);
+
+                    let (n, size) = match self.run(parse_u32)? {
+                        Some(x) => x,
+                        None => return Ok(None),
+                    };
+                    if size > self.budget.limits.max_body_size {
+                        return Err(self.error(0, ParseErrorKind::LimitExceeded(Limit::BodySize)));
+                    }
+                    if n + size as usize > avail {
+                        return Err(self.error(0, ParseErrorKind::SizeMismatch));
+                    }
+                    if self.input().len() < n + size as usize && !self.eof {
+                        return Ok(None);
+                    }
+
+                    let (m, code) = match self.run(parse_code)? {
+                        Some(x) => x,
+                        None => return Ok(None),
+                    };
+                    let func = Func {
+                        type_: self.func_types[self.codes],
+                        locals: code.locals,
+                        body: code.body,
+                    };
+                    let x = FuncIdx(self.imported_funcs + self.codes as u32);
+
+                    self.pos += m;
+                    self.codes += 1;
+                    self.state = State::Code(count - 1, end);
+                    return Ok(Some(Event::Func(x, func)));
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    fn section_event(&mut self, id: u8) -> Result<Option<Event>, ParseError> {
+        macro_rules! section {
+            ($parse:expr, $event:expr) => ({
+                let r = self.run(|d| parse_section(d, id, $parse))?;
+                match r {
+                    Some((n, x)) => {
+                        self.pos += n;
+                        if id != 0 {
+                            self.last_id = id;
+                        }
+                        $event(x)
+                    }
+                    None => return Ok(None),
+                }
+            })
+        }
+
+        let event = match id {
+            0 => section!(parse_custom, |c| Event::Custom(CustomSection { after: self.last_id, ..c })),
+            1 => section!(parse_type, Event::Types),
+            2 => section!(parse_imports, |imports: Wec<Import>| {
+                self.imported_funcs = count_func_imports(&imports);
+                Event::Imports(imports)
+            }),
+            3 => section!(parse_func_, |types: Wec<TypeIdx>| {
+                self.func_types = types.iter().cloned().collect();
+                Event::Funcs(types)
+            }),
+            4 => section!(parse_tables, Event::Tables),
+            5 => section!(parse_mems, Event::Mems),
+            6 => section!(parse_globals, Event::Globals),
+            7 => section!(parse_exports, Event::Exports),
+            8 => section!(parse_start, Event::Start),
+            9 => section!(parse_elems, Event::Elem),
+            11 => section!(parse_datas, Event::Data),
+            _ => unreachable!(),
+        };
+        Ok(Some(event))
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamError::Io(ref e) => write!(f, "{}", e),
+            StreamError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for StreamError {}
+
+/// Iterator over the events of a module read from `R`.
+pub struct ReadParser<R> {
+    reader: R,
+    parser: StreamParser,
+    chunk: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> Iterator for ReadParser<R> {
+    type Item = Result<Event, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.parser.next_event() {
+                Ok(Some(event)) => {
+                    self.done = if let Event::End = event { true } else { false };
+                    return Some(Ok(event));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(StreamError::Parse(e)));
+                }
+            }
+
+            match self.reader.read(&mut self.chunk) {
+                Ok(0) => self.parser.finish(),
+                Ok(n) => self.parser.feed(&self.chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(StreamError::Io(e)));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses a module incrementally while reading it from `reader`.
+pub fn parse_binary_stream<R: Read>(reader: R) -> ReadParser<R> {
+    ReadParser {
+        reader,
+        parser: StreamParser::new(),
+        chunk: vec![0; 64 * 1024],
+        done: false,
+    }
+}
@@ -11,11 +11,12 @@ use self::CheckRes::*;
 
 fn check<'a, T, F>(parse: F, input: &'a [u8], res: CheckRes<T>)
     where T: Debug + PartialEq,
-          F: Fn(Inp<'a>) -> IResult<Inp<'a>, T>,
+          F: Fn(&mut Decoder<'a>) -> PResult<T>,
 {
     let mut err = None;
-    match parse(CompleteByteSlice(input)) {
-        Ok((CompleteByteSlice(&[]), is)) => {
+    let mut d = Decoder::new(input);
+    match parse(&mut d) {
+        Ok(is) if d.is_empty() => {
             if let OkWith(should) = res {
                 if is != should {
                     err = Some(format!("Should have parsed with {:?}, but is {:?}", should, is));
@@ -24,8 +25,8 @@ fn check<'a, T, F>(parse: F, input: &'a [u8], res: CheckRes<T>)
                 err = Some(format!("Should have failed, but parsed with {:?}", is));
             }
         }
-        Ok((CompleteByteSlice(x), is)) => {
-            err = Some(format!("Accepted without parsing all input. Remaining: {:?}, result: {:?}", x, is));
+        Ok(is) => {
+            err = Some(format!("Accepted without parsing all input. Remaining: {:?}, result: {:?}", d.as_slice(), is));
         }
         Err(x) => {
             if let OkWith(should) = res {
@@ -45,7 +46,7 @@ macro_rules! wec {
 }
 
 fn test_parse_uN<F>(parse: F, bits: u32)
-    where F: Fn(Inp) -> IResult<Inp, u64>,
+    where F: Fn(&mut Decoder) -> PResult<u64>,
 {
     check(&parse, &[0x00], OkWith(0x00));
 
@@ -73,7 +74,7 @@ fn test_parse_uN<F>(parse: F, bits: u32)
 
 #[test]
 fn test_parse_u32() {
-    test_parse_uN(|inp| parse_u32(inp).map(|(i, x)| (i, x as u64)), 32);
+    test_parse_uN(|d| parse_u32(d).map(|x| x as u64), 32);
 }
 
 #[test]
@@ -82,7 +83,7 @@ fn test_parse_u64() {
 }
 
 fn test_parse_sN<F>(parse: F, bits: u32)
-    where F: Fn(Inp) -> IResult<Inp, i64>,
+    where F: Fn(&mut Decoder) -> PResult<i64>,
 {
     check(&parse, &[0x00], OkWith(0x00));
 
@@ -118,7 +119,7 @@ fn test_parse_sN<F>(parse: F, bits: u32)
 
 #[test]
 fn test_parse_s32() {
-    test_parse_sN(|inp| parse_s32(inp).map(|(i, x)| (i, x as i64)), 32);
+    test_parse_sN(|d| parse_s32(d).map(|x| x as i64), 32);
 }
 
 #[test]
@@ -127,7 +128,7 @@ fn test_parse_s64() {
 }
 
 fn test_parse_iN<F>(parse: F, bits: u32)
-    where F: Fn(Inp) -> IResult<Inp, u64>,
+    where F: Fn(&mut Decoder) -> PResult<u64>,
 {
     let minus1 = if bits == 32 {
         0xff_ff_ff_ff
@@ -169,7 +170,7 @@ fn test_parse_iN<F>(parse: F, bits: u32)
 
 #[test]
 fn test_parse_i32() {
-    test_parse_iN(|inp| parse_i32(inp).map(|(i, x)| (i, x as u64)), 32);
+    test_parse_iN(|d| parse_i32(d).map(|x| x as u64), 32);
 }
 
 #[test]
@@ -265,12 +266,24 @@ fn test_parse_limits() {
     ));
 }
 
+fn parse_customsec(d: &mut Decoder) -> PResult<CustomSection> {
+    parse_section(d, 0, parse_custom)
+}
+
+fn parse_customsecs(d: &mut Decoder) -> PResult<Vec<CustomSection>> {
+    let mut cs = vec![];
+    while !d.is_empty() {
+        cs.push(parse_customsec(d)?);
+    }
+    Ok(cs)
+}
+
 #[test]
 fn test_parse_customsecs() {
     check(&parse_customsec, &[
         0, 6, 3, 97, 98, 99, 0xff, 0xee,
     ], OkWith(
-        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee] }
+        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee], after: 0 }
     ));
 
     check(&parse_customsec, &[
@@ -284,15 +297,15 @@ fn test_parse_customsecs() {
     check(&parse_customsecs, &[
         0, 6, 3, b'a', b'b', b'c', 0xff, 0xee,
     ], OkWith(vec![
-        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee] },
+        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee], after: 0 },
     ]));
 
     check(&parse_customsecs, &[
         0, 6, 3, b'a', b'b', b'c', 0xff, 0xee,
         0, 8, 4, b'd', b'e', b'f', b'g', 0xa0, 0xb1, 0xc2
     ], OkWith(vec![
-        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee] },
-        CustomSection { name: "defg".into(), bytes: vec![0xa0, 0xb1, 0xc2] },
+        CustomSection { name: "abc".into(), bytes: vec![0xff, 0xee], after: 0 },
+        CustomSection { name: "defg".into(), bytes: vec![0xa0, 0xb1, 0xc2], after: 0 },
     ]));
 }
 
@@ -338,3 +351,535 @@ fn test_parse_code() {
         }
     ));
 }
+
+#[test]
+fn test_encode_uN_sN() {
+    for &x in &[0, 1, 0x3f, 0x40, 0x7f, 0x80, 0xff, 0x3fff, 0x4000,
+                ::std::u32::MAX as u64, ::std::u64::MAX] {
+        let mut b = vec![];
+        encode::encode_uN(&mut b, x);
+        check(&parse_u64, &b, OkWith(x));
+    }
+    for &x in &[0, 1, -1, 0x3f, 0x40, -0x40, -0x41, 0x7f, -0x80,
+                ::std::i32::MIN as i64, ::std::i64::MIN, ::std::i64::MAX] {
+        let mut b = vec![];
+        encode::encode_sN(&mut b, x);
+        check(&parse_s64, &b, OkWith(x));
+    }
+}
+
+#[test]
+fn test_encode_module() {
+    use greenwasm_structure::modules::*;
+
+    let module = Module {
+        types: wec![FuncType { args: wec![ValType::I32], results: wec![] }],
+        funcs: wec![Func {
+            type_: TypeIdx(0),
+            locals: wec![ValType::F32, ValType::F32, ValType::I32],
+            body: Expr { body: vec![
+                Instr::Block(None.into(), vec![
+                    Instr::GetLocal(LocalIdx(0)),
+                    Instr::IfElse(ValType::I32.into(), vec![
+                        Instr::I32Const(-1i32 as u32),
+                    ], vec![
+                        Instr::Loop(None.into(), vec![Instr::Br(LabelIdx(2))]),
+                        Instr::I32Const(7),
+                    ]),
+                    Instr::BrIf(LabelIdx(0)),
+                ]),
+                Instr::IfElse(None.into(), vec![Instr::Nop], vec![]),
+            ]},
+        }],
+        tables: wec![],
+        mems: wec![Mem { type_: MemType { limits: Limits { min: 1, max: None } } }],
+        globals: wec![],
+        elem: wec![],
+        data: wec![Data {
+            data: MemIdx(0),
+            offset: Expr { body: vec![Instr::I32Const(0)] },
+            init: wec![b'h', b'i'],
+        }],
+        start: None,
+        imports: wec![],
+        exports: wec![Export { name: "f".into(), desc: ExportDesc::Func(FuncIdx(0)) }],
+    };
+    let customs = vec![
+        CustomSection { name: "first".into(), bytes: vec![1, 2], after: 0 },
+        CustomSection { name: "mid".into(), bytes: vec![], after: 5 },
+        CustomSection { name: "last".into(), bytes: vec![3], after: 11 },
+    ];
+
+    let b = encode_binary_format(&module, &customs);
+    let (module2, customs2) = parse_binary_format(&b).unwrap();
+    assert_eq!(module, module2);
+    assert_eq!(customs, customs2);
+
+    // Custom sections after an unknown id are kept at the end
+    let mut customs3 = customs;
+    customs3.push(CustomSection { name: "later".into(), bytes: vec![4], after: 12 });
+    let b = encode_binary_format(&module, &customs3);
+    let (_, customs4) = parse_binary_format(&b).unwrap();
+    assert_eq!(customs4.last(), Some(&CustomSection { name: "later".into(), bytes: vec![4], after: 11 }));
+    assert_eq!(customs4.len(), 4);
+}
+
+#[test]
+fn test_name_section() {
+    let b = [
+        0x00, 0x04, 0x03, b'm', b'o', b'd',
+        0x01, 0x07, 0x02, 0x00, 0x01, b'a', 0x02, 0x01, b'b',
+        0x02, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, b'x',
+        0x07, 0x01, 0xff,
+    ];
+    assert_eq!(parse_name_section(&b), Some(NameSection {
+        module: Some("mod".into()),
+        funcs: vec![(FuncIdx(0), "a".into()), (FuncIdx(2), "b".into())],
+        locals: vec![(FuncIdx(2), vec![(LocalIdx(1), "x".into())])],
+    }));
+
+    assert_eq!(parse_name_section(&[]), Some(Default::default()));
+    assert_eq!(parse_name_section(&[0x01, 0x03, 0x01, 0x00]), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    fn err(b: &[u8]) -> ParseError {
+        parse_binary_format(b).unwrap_err()
+    }
+    fn module(sections: &[u8]) -> Vec<u8> {
+        let mut b = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        b.extend(sections);
+        b
+    }
+
+    let e = err(&[0x00, 0x61, 0x73, 0x6e, 0x01, 0x00, 0x00, 0x00]);
+    assert_eq!((e.offset, e.section, e.kind), (0, None, ParseErrorKind::BadMagic));
+    let e = err(&[0x00, 0x61, 0x73, 0x6d, 0x02, 0x00, 0x00, 0x00]);
+    assert_eq!((e.offset, e.section, e.kind), (4, None, ParseErrorKind::BadVersion));
+
+    let e = err(&module(&[0x01, 0x06, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (14, Some(1), ParseErrorKind::BadLeb128));
+
+    let e = err(&module(&[0x01, 0x02, 0x00, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (11, Some(1), ParseErrorKind::SizeMismatch));
+
+    // A huge vector length does not allocate more than the section holds
+    let e = err(&module(&[0x04, 0x05, 0xff, 0xff, 0xff, 0xff, 0x0f]));
+    assert_eq!((e.offset, e.section, e.kind), (15, Some(4), ParseErrorKind::UnexpectedEnd));
+    let e = err(&module(&[0x01, 0x05, 0xff, 0xff, 0xff, 0xff, 0x0f]));
+    assert_eq!((e.offset, e.section, e.kind), (10, Some(1), ParseErrorKind::LimitExceeded(Limit::Types)));
+
+    let e = err(&module(&[0x01, 0x05, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (9, Some(1), ParseErrorKind::SizeMismatch));
+
+    let e = err(&module(&[0x03, 0x01, 0x00, 0x01, 0x01, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (11, Some(1), ParseErrorKind::SectionOrder(1)));
+
+    let e = err(&module(&[0x0c, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (8, Some(12), ParseErrorKind::UnknownSection(12)));
+
+    let e = err(&module(&[0x00, 0x02, 0x01, 0xff]));
+    assert_eq!((e.offset, e.section, e.kind), (10, Some(0), ParseErrorKind::InvalidUtf8));
+
+    let e = err(&module(&[0x01, 0x04, 0x01, 0x60, 0x01, 0x7b]));
+    assert_eq!((e.offset, e.section, e.kind), (13, Some(1), ParseErrorKind::UnexpectedByte {
+        expected: "value type",
+        found: 0x7b,
+    }));
+
+    let e = err(&module(&[
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x02, 0x07, 0x01, 0x01, b'm', 0x01, b'f', 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0xff,
+    ]));
+    assert_eq!(e, ParseError {
+        offset: 36,
+        section: Some(10),
+        func: Some(FuncIdx(2)),
+        kind: ParseErrorKind::UnknownOpcode(0xff),
+    });
+    assert_eq!(e.to_string(), "unknown opcode 0xff at offset 0x24 in code section of function 2");
+
+    let e = err(&module(&[
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x06, 0x01, 0x04, 0x00, 0x02, 0x00, 0x0b,
+    ]));
+    assert_eq!((e.offset, e.kind), (24, ParseErrorKind::UnexpectedByte {
+        expected: "block type",
+        found: 0x00,
+    }));
+
+    let e = err(&module(&[
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x05, 0x01, 0x03, 0x00, 0x05, 0x0b,
+    ]));
+    assert_eq!((e.offset, e.func, e.kind), (23, Some(FuncIdx(0)), ParseErrorKind::ElseWithoutIf));
+
+    let e = err(&module(&[
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x01,
+    ]));
+    assert_eq!((e.offset, e.func, e.kind), (24, Some(FuncIdx(0)), ParseErrorKind::UnexpectedEnd));
+
+    let e = err(&module(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00]));
+    assert_eq!((e.offset, e.section, e.kind), (18, None, ParseErrorKind::FuncCodeMismatch));
+}
+
+#[test]
+fn test_stream_parser() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b,
+        0x00, 0x02, 0x01, b'x',
+    ];
+    let func = Func {
+        type_: TypeIdx(0),
+        locals: wec![],
+        body: Expr { body: vec![] },
+    };
+
+    let mut p = StreamParser::new();
+    p.feed(&b[..12]);
+    assert_eq!(p.next_event(), Ok(None));
+    p.feed(&b[12..24]);
+    assert_eq!(p.next_event(), Ok(Some(Event::Types(wec![FuncType { args: wec![], results: wec![] }]))));
+    assert_eq!(p.next_event(), Ok(Some(Event::Funcs(wec![TypeIdx(0), TypeIdx(0)]))));
+    assert_eq!(p.next_event(), Ok(None));
+    p.feed(&b[24..]);
+    assert_eq!(p.next_event(), Ok(Some(Event::Func(FuncIdx(0), func.clone()))));
+    assert_eq!(p.next_event(), Ok(Some(Event::Func(FuncIdx(1), func))));
+    assert_eq!(p.next_event(), Ok(Some(Event::Custom(CustomSection {
+        name: "x".into(),
+        bytes: vec![],
+        after: 10,
+    }))));
+    assert_eq!(p.next_event(), Ok(None));
+    p.finish();
+    assert_eq!(p.next_event(), Ok(Some(Event::End)));
+    assert_eq!(p.next_event(), Ok(None));
+
+    // Truncated inside of the second function body
+    let mut p = StreamParser::new();
+    p.feed(&b[..26]);
+    p.finish();
+    let mut events = 0;
+    let e = loop {
+        match p.next_event() {
+            Ok(Some(_)) => events += 1,
+            Ok(None) => panic!("should have failed"),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(events, 3);
+    assert_eq!(e, ParseError {
+        offset: 25,
+        section: Some(10),
+        func: Some(FuncIdx(1)),
+        kind: ParseErrorKind::SizeMismatch,
+    });
+
+    let mut p = StreamParser::new();
+    p.feed(&b[..8]);
+    p.feed(&[0x03, 0x01, 0x00, 0x01, 0x01, 0x00]);
+    assert_eq!(p.next_event(), Ok(Some(Event::Funcs(wec![]))));
+    assert_eq!(p.next_event(), Err(ParseError {
+        offset: 11,
+        section: Some(1),
+        func: None,
+        kind: ParseErrorKind::SectionOrder(1),
+    }));
+}
+
+#[test]
+fn test_section_reader() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x00, 0x03, 0x01, b'x', 0xaa,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b,
+    ];
+
+    let sections = SectionReader::new(&b).collect::<Result<Vec<_>, _>>().unwrap();
+    let ids: Vec<_> = sections.iter().map(|s| s.id).collect();
+    assert_eq!(ids, [1, 0, 3, 10]);
+    assert_eq!(sections[1].name(), "custom");
+    assert_eq!(sections[1].range(), 16..19);
+    assert_eq!(sections[1].section_range(), 14..19);
+    assert_eq!(sections[1].bytes(), &[0x01, b'x', 0xaa]);
+
+    match sections[1].payload().unwrap() {
+        Payload::Custom(name, bytes) => {
+            assert_eq!(&*name, "x");
+            assert_eq!(bytes, &[0xaa]);
+        }
+        _ => panic!("expected a custom section"),
+    }
+    match sections[2].payload().unwrap() {
+        Payload::Funcs(r) => {
+            assert_eq!(r.remaining(), 2);
+            assert_eq!(r.collect::<Result<Vec<_>, _>>(), Ok(vec![TypeIdx(0), TypeIdx(0)]));
+        }
+        _ => panic!("expected a function section"),
+    }
+    match sections[3].payload().unwrap() {
+        Payload::Code(r) => {
+            let ranges: Vec<_> = r.map(|body| body.unwrap().range()).collect();
+            assert_eq!(ranges, [28..30, 31..33]);
+        }
+        _ => panic!("expected a code section"),
+    }
+
+    // Bytes left over after the last entry
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x03, 0x03, 0x01, 0x00, 0x00,
+    ];
+    let section = SectionReader::new(&b).next().unwrap().unwrap();
+    let mut r = match section.payload().unwrap() {
+        Payload::Funcs(r) => r,
+        _ => panic!("expected a function section"),
+    };
+    assert_eq!(r.next(), Some(Ok(TypeIdx(0))));
+    assert_eq!(r.next().unwrap().unwrap_err().kind, ParseErrorKind::SizeMismatch);
+    assert_eq!(r.next(), None);
+
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x03, 0x01, 0x00, 0x01, 0x01, 0x00,
+    ];
+    let mut sections = SectionReader::new(&b);
+    assert!(sections.next().unwrap().is_ok());
+    assert_eq!(sections.next().unwrap().unwrap_err().kind, ParseErrorKind::SectionOrder(1));
+    assert!(sections.next().is_none());
+}
+
+#[test]
+fn test_lazy_module() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x02, 0x07, 0x01, 0x01, b'm', 0x01, b'f', 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x07, 0x05, 0x01, 0x01, b'g', 0x00, 0x01,
+        0x0a, 0x09, 0x02, 0x04, 0x00, 0x10, 0x00, 0x0b, 0x02, 0x00, 0xff,
+    ];
+
+    // The broken second body is not decoded up front
+    let (module, customs) = parse_binary_format_lazy(&b).unwrap();
+    assert!(customs.is_empty());
+    assert_eq!(module.exports.len(), 1);
+    assert_eq!(module.funcs.len(), 2);
+    assert!(module.func(FuncIdx(0)).is_none());
+
+    let f = module.func(FuncIdx(1)).unwrap();
+    assert_eq!(f.range(), 39..43);
+    assert_eq!(f.bytes(), &[0x00, 0x10, 0x00, 0x0b]);
+    assert_eq!(f.decode(), Ok(Func {
+        type_: TypeIdx(0),
+        locals: wec![],
+        body: Expr { body: vec![Instr::Call(FuncIdx(0))] },
+    }));
+
+    let e = ParseError {
+        offset: 45,
+        section: Some(10),
+        func: Some(FuncIdx(2)),
+        kind: ParseErrorKind::UnknownOpcode(0xff),
+    };
+    assert_eq!(module.func(FuncIdx(2)).unwrap().decode(), Err(e.clone()));
+    assert_eq!(module.decode(), Err(e.clone()));
+    assert_eq!(parse_binary_format(&b).unwrap_err(), e);
+}
+
+#[test]
+fn test_lazy_alloc_budget() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x0a, 0x0f, 0x02, 0x06, 0x01, 0x02, 0x7f, 0x01, 0x01, 0x0b, 0x06, 0x01, 0x02, 0x7e, 0x01, 0x01, 0x0b,
+    ];
+
+    // The smallest budget the module can be parsed with
+    let config = |max_alloc| ParseConfig {
+        limits: ParseLimits { max_alloc, ..Default::default() },
+        ..Default::default()
+    };
+    let max_alloc = (0..).find(|&n| parse_binary_format_with_config(&b, &config(n)).is_ok()).unwrap();
+
+    let (module, _) = parse_binary_format_lazy_with_config(&b, &config(max_alloc)).unwrap();
+    for _ in 0..10 {
+        assert!(module.funcs[0].decode().is_ok());
+        assert!(module.funcs[1].decode().is_ok());
+    }
+    assert!(module.decode().is_ok());
+
+    // Decoding all bodies is still limited as a whole
+    let (module, _) = parse_binary_format_lazy_with_config(&b, &config(max_alloc - 1)).unwrap();
+    assert!(module.funcs[0].decode().is_ok());
+    assert_eq!(module.decode().unwrap_err().kind, ParseErrorKind::LimitExceeded(Limit::Alloc));
+}
+
+#[test]
+fn test_first_body_error() {
+    // One valid body, followed by bodies with unknown opcodes
+    let opcodes = [0x01, 0x06, 0x07, 0x08, 0x09, 0x12, 0x13, 0x14, 0x15];
+    let n = opcodes.len() as u8;
+    let mut b = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, n + 1, n,
+    ];
+    b.extend(opcodes.iter().map(|_| 0x00));
+    b.extend(&[0x0a, n * 4 + 1, n]);
+    for &op in &opcodes {
+        b.extend(&[0x03, 0x00, op, 0x0b]);
+    }
+
+    // NB: With the `parallel` feature, the bodies are decoded in any order
+    for _ in 0..20 {
+        let e = parse_binary_format(&b).unwrap_err();
+        assert_eq!((e.func, e.kind.clone()), (Some(FuncIdx(1)), ParseErrorKind::UnknownOpcode(0x06)));
+
+        let (module, _) = parse_binary_format_lazy(&b).unwrap();
+        assert_eq!(module.decode().unwrap_err(), e);
+    }
+}
+
+#[test]
+fn test_body_error_before_later_errors() {
+    let header = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    ];
+    // A body with an unknown opcode, followed by a malformed data section
+    let mut data = header.to_vec();
+    data.extend(&[0x03, 0x02, 0x01, 0x00]);
+    data.extend(&[0x0a, 0x05, 0x01, 0x03, 0x00, 0x06, 0x0b]);
+    data.extend(&[0x0b, 0x01, 0x05]);
+    // The same body, followed by one that does not fit into the code section
+    let mut code = header.to_vec();
+    code.extend(&[0x03, 0x03, 0x02, 0x00, 0x00]);
+    code.extend(&[0x0a, 0x09, 0x02, 0x03, 0x00, 0x06, 0x0b, 0x05, 0x00, 0x01, 0x0b]);
+
+    // NB: CI runs this with and without the `parallel` feature,
+    // which must not change the reported error
+    for &(ref b, offset) in &[(data, 23), (code, 24)] {
+        let e = parse_binary_format(b).unwrap_err();
+        assert_eq!((e.offset, e.section, e.func), (offset, Some(10), Some(FuncIdx(0))));
+        assert_eq!(e.kind, ParseErrorKind::UnknownOpcode(0x06));
+
+        let mut p = StreamParser::new();
+        p.feed(b);
+        p.finish();
+        let stream = loop {
+            match p.next_event() {
+                Ok(Some(Event::End)) | Ok(None) => panic!("no error"),
+                Ok(Some(_)) => {}
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(stream, e);
+    }
+}
+
+#[test]
+fn test_resource_limits() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x07, 0x02, 0x60, 0x00, 0x00, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x0c, 0x01, 0x0a, 0x01, 0x02, 0x7f, 0x02, 0x40, 0x02, 0x40, 0x0b, 0x0b, 0x0b,
+    ];
+
+    // The lazy and the stream parser report the same error as `parse_binary_format`
+    fn err(b: &[u8], limits: ParseLimits) -> (usize, Option<u8>, ParseErrorKind) {
+        let config = ParseConfig { limits, ..Default::default() };
+        let e = parse_binary_format_with_config(b, &config).unwrap_err();
+        let lazy = parse_binary_format_lazy_with_config(b, &config)
+            .and_then(|(module, _)| module.decode());
+        assert_eq!(lazy.unwrap_err(), e);
+
+        let mut p = StreamParser::with_config(&config);
+        p.feed(b);
+        p.finish();
+        let stream = loop {
+            match p.next_event() {
+                Ok(Some(Event::End)) => panic!("no error"),
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(stream, e);
+        (e.offset, e.section, e.kind)
+    }
+
+    assert!(parse_binary_format(&b).is_ok());
+    assert_eq!(err(&b, ParseLimits { max_types: 1, ..Default::default() }),
+               (10, Some(1), ParseErrorKind::LimitExceeded(Limit::Types)));
+    assert_eq!(err(&b, ParseLimits { max_locals: 1, ..Default::default() }),
+               (28, Some(10), ParseErrorKind::LimitExceeded(Limit::Locals)));
+    assert_eq!(err(&b, ParseLimits { max_nesting: 1, ..Default::default() }),
+               (30, Some(10), ParseErrorKind::LimitExceeded(Limit::Nesting)));
+    assert_eq!(err(&b, ParseLimits { max_body_size: 9, ..Default::default() }),
+               (24, Some(10), ParseErrorKind::LimitExceeded(Limit::BodySize)));
+
+    let limits = ParseLimits { max_alloc: 64, ..Default::default() };
+    let e = parse_binary_format_with_config(&b, &ParseConfig { limits, ..Default::default() });
+    assert_eq!(e.unwrap_err().kind, ParseErrorKind::LimitExceeded(Limit::Alloc));
+    assert_eq!(format!("{}", ParseErrorKind::LimitExceeded(Limit::Locals)),
+               "number of locals exceeds the limit");
+}
+
+#[test]
+fn test_features() {
+    let b = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x41, 0x00, 0xc0, 0x1a, 0x0b,
+    ];
+    let e = parse_binary_format(&b).unwrap_err();
+    assert_eq!((e.offset, e.section, e.func), (25, Some(10), Some(FuncIdx(0))));
+    assert_eq!(e.kind, ParseErrorKind::FeatureDisabled(Feature::SignExtension));
+    assert_eq!(format!("{}", e.kind), "feature disabled: sign-extension operators");
+
+    let lazy = parse_binary_format_lazy(&b).and_then(|(module, _)| module.decode());
+    assert_eq!(lazy.unwrap_err(), e);
+
+    let mut p = StreamParser::new();
+    p.feed(&b);
+    p.finish();
+    let stream = loop {
+        match p.next_event() {
+            Ok(Some(Event::End)) | Ok(None) => panic!("no error"),
+            Ok(Some(_)) => {}
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(stream, e);
+
+    let all = ParseConfig { features: WasmFeatures::all(), ..Default::default() };
+    let (module, _) = parse_binary_format_with_config(&b, &all).unwrap();
+    let (lazy, _) = parse_binary_format_lazy_with_config(&b, &all).unwrap();
+    assert_eq!(lazy.decode().unwrap(), module);
+    assert_eq!(module.funcs[0].body.body, vec![Instr::I32Const(0), Instr::I32Extend8S, Instr::Drop]);
+
+    let mut p = StreamParser::with_config(&all);
+    p.feed(&b);
+    p.finish();
+    while let Some(event) = p.next_event().unwrap() {
+        if let Event::Func(_, func) = event {
+            assert_eq!(func, module.funcs[0]);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! A single module instance with its own store.
+//!
+//! The functions in `modules` work on a `Store` and `Stack` that borrow
+//! the AST of the module. An `Instance` owns both of them, borrows the
+//! module from the caller, and gives access to its exports by name.
+
+use greenwasm_structure::types::FuncType;
+use greenwasm_validation::ValidatedModule;
+
+use std::result::Result as StdResult;
+
+use runtime_structure::*;
+use runtime_structure::Result as IResult;
+use modules::invocation::{invoke, InvokeError};
+use linker::{Linker, LinkError};
+
+#[derive(Debug)]
+pub enum CallError {
+    /// The instance has no function export of that name.
+    UnknownFunc(String),
+    Invoke(InvokeError),
+    Trap,
+    /// A host function failed.
+    Host(HostError),
+}
+
+impl From<InvokeError> for CallError {
+    fn from(v: InvokeError) -> Self {
+        CallError::Invoke(v)
+    }
+}
+
+impl ::std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            CallError::UnknownFunc(ref name) => write!(f, "unknown function export \"{}\"", name),
+            CallError::Invoke(ref e) => write!(f, "invocation failed: {:?}", e),
+            CallError::Trap => write!(f, "trap"),
+            CallError::Host(ref e) => write!(f, "host function failed: {}", e),
+        }
+    }
+}
+
+pub struct Instance<'m> {
+    store: Store<'m>,
+    stack: Stack<'m>,
+    addr: ModuleAddr,
+}
+
+impl<'m> Instance<'m> {
+    /// Instantiates `module` in a new store. Modules with imports
+    /// fail with `LinkError::UnknownImport`.
+    pub fn new(module: &'m ValidatedModule) -> StdResult<Self, LinkError> {
+        Instance::with_linker(module, Store::new(), &Linker::new())
+    }
+
+    /// Instantiates `module` in `store`, with its imports resolved by `linker`.
+    ///
+    /// NB: The external values of `linker` have to be allocated in `store`,
+    /// like host functions from `alloc_host_function`.
+    pub fn with_linker(module: &'m ValidatedModule, mut store: Store<'m>, linker: &Linker)
+        -> StdResult<Self, LinkError>
+    {
+        let mut stack = Stack::new();
+        let addr = linker.instantiate(&mut store, &mut stack, module)?;
+        Ok(Instance { store, stack, addr })
+    }
+
+    fn export(&self, name: &str) -> Option<ExternVal> {
+        let module = &self.store.modules[self.addr];
+        module.exports.iter().find(|e| ***e.name == *name).map(|e| e.value)
+    }
+
+    /// The names of the exports, in the order of the module.
+    pub fn export_names(&self) -> impl Iterator<Item = &str> {
+        self.store.modules[self.addr].exports.iter().map(|e| &e.name[..])
+    }
+
+    fn get_func(&self, name: &str) -> Option<FuncAddr> {
+        match self.export(name)? {
+            ExternVal::Func(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// The type of the function export `name`.
+    pub fn func_type(&self, name: &str) -> Option<&FuncType> {
+        self.get_func(name).map(|a| self.store.funcs[a].type_())
+    }
+
+    pub fn get_memory(&self, name: &str) -> Option<&MemInst> {
+        match self.export(name)? {
+            ExternVal::Mem(a) => Some(&self.store.mems[a]),
+            _ => None,
+        }
+    }
+
+    pub fn get_memory_mut(&mut self, name: &str) -> Option<&mut MemInst> {
+        match self.export(name)? {
+            ExternVal::Mem(a) => Some(&mut self.store.mems[a]),
+            _ => None,
+        }
+    }
+
+    /// The current value of the global export `name`.
+    pub fn get_global(&self, name: &str) -> Option<Val> {
+        match self.export(name)? {
+            ExternVal::Global(a) => Some(self.store.globals[a].value),
+            _ => None,
+        }
+    }
+
+    // NB: Private, since `func` has to be an address in this store.
+    fn invoke(&mut self, func: FuncAddr, args: &[Val]) -> StdResult<Vec<Val>, CallError> {
+        match invoke(&mut self.store, &mut self.stack, func, args)? {
+            IResult::Vals(vals) => Ok(vals),
+            IResult::Trap => Err(CallError::Trap),
+            IResult::Host(e) => Err(CallError::Host(e)),
+        }
+    }
+
+    /// Invokes the function export `name`.
+    pub fn call(&mut self, name: &str, args: &[Val]) -> StdResult<Vec<Val>, CallError> {
+        let func = self.get_func(name).ok_or_else(|| CallError::UnknownFunc(name.to_owned()))?;
+        self.invoke(func, args)
+    }
+}
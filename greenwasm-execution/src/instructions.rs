@@ -11,6 +11,7 @@ use DEBUG_EXECUTION;
 pub enum ExecutionError {
     Trap,
     StackExhaustion,
+    Host(HostError),
 }
 use self::ExecutionError::Trap;
 impl From<StackExhaustion> for ExecutionError {
@@ -460,9 +461,34 @@ impl<'instr, 'ctx> ExecCtx<'instr, 'ctx>
 
                 self.jump(instrs)
             }
-            FuncInst::Host { .. } => {
-                println!("Host behavior not implemented yet");
-                unimplemented!()
+            FuncInst::Host { type_, hostcode } => {
+                let type_ = *type_;
+                let n = type_.args.len();
+                let code = hostcode.code.clone();
+
+                let mut args = vec![];
+                for _ in 0..n {
+                    args.push(stack.pop_val());
+                }
+                args.reverse();
+
+                let module = stack.current_module();
+                let results = code(&mut Caller { store: self.store, module }, &args)
+                    .map_err(ExecutionError::Host)?;
+
+                // NB: The code after the call relies on the declared result
+                // types, so results that do not match them are an error.
+                let types: Vec<_> = results.iter().map(|v| v.ty()).collect();
+                if types[..] != type_.results[..] {
+                    Err(ExecutionError::Host(HostError::new(format!(
+                        "host function returned {:?} instead of {:?}", types, &type_.results[..]))))?;
+                }
+
+                for val in results {
+                    self.stack.push_val(val)?;
+                }
+
+                self.jump_next()
             }
         })
     }
@@ -736,6 +762,12 @@ impl<'instr, 'ctx> ExecCtx<'instr, 'ctx>
                 F32ReinterpretI32 => self.cvtop(reinterpret_i32_f32),
                 F64ReinterpretI64 => self.cvtop(reinterpret_i64_f64),
 
+                I32Extend8S => self.unop(extend8_s_i32),
+                I32Extend16S => self.unop(extend16_s_i32),
+                I64Extend8S => self.unop(extend8_s_i64),
+                I64Extend16S => self.unop(extend16_s_i64),
+                I64Extend32S => self.unop(extend32_s_i64),
+
                 // parametric instructions
                 Drop => {
                     let stack = &mut *self.stack;
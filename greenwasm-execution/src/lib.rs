@@ -13,3 +13,5 @@ pub mod runtime_structure;
 pub mod modules;
 pub mod numerics;
 pub mod instructions;
+pub mod linker;
+pub mod instance;
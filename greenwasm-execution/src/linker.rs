@@ -0,0 +1,131 @@
+//! Resolution of the imports of a module by name.
+//!
+//! `instantiate_module` expects the external values in the order of the
+//! imports of the module. A `Linker` instead holds external values
+//! under a module and field name, and looks up each import by its names.
+
+use greenwasm_structure::types::*;
+use greenwasm_validation::ValidatedModule;
+
+use std::collections::HashMap;
+use std::result::Result as StdResult;
+
+use runtime_structure::*;
+use modules::external_typing;
+use modules::import_matching;
+use modules::instantiation::{instantiate_module_with_config, InstantiationConfig, InstantiationError};
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// The import is not defined in the linker.
+    UnknownImport {
+        module: String,
+        name: String,
+    },
+    /// The import is defined with a type that does not match the import.
+    IncompatibleImport {
+        module: String,
+        name: String,
+        expected: Box<ExternType>,
+        found: Box<ExternType>,
+    },
+    /// All imports have been resolved, but the instantiation failed.
+    Instantiation(InstantiationError),
+}
+
+impl From<InstantiationError> for LinkError {
+    fn from(v: InstantiationError) -> Self {
+        LinkError::Instantiation(v)
+    }
+}
+
+impl ::std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            LinkError::UnknownImport { ref module, ref name } => {
+                write!(f, "unknown import \"{}\" \"{}\"", module, name)
+            }
+            LinkError::IncompatibleImport { ref module, ref name, ref expected, ref found } => {
+                write!(f, "incompatible import \"{}\" \"{}\": expected {:?}, found {:?}",
+                       module, name, expected, found)
+            }
+            LinkError::Instantiation(ref e) => write!(f, "instantiation failed: {:?}", e),
+        }
+    }
+}
+
+pub type LResult<T> = StdResult<T, LinkError>;
+
+/// External values by module and field name.
+#[derive(Clone, Debug, Default)]
+pub struct Linker {
+    externs: HashMap<String, HashMap<String, ExternVal>>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Linker::default()
+    }
+
+    /// Defines `value` as the field `name` of `module`,
+    /// replacing a previous definition.
+    pub fn define(&mut self, module: &str, name: &str, value: ExternVal) {
+        self.externs.entry(module.to_owned()).or_default().insert(name.to_owned(), value);
+    }
+
+    /// Defines all exports of the instance `inst` as fields of `module`.
+    pub fn define_instance(&mut self, s: &Store, module: &str, inst: ModuleAddr) {
+        for export in &s.modules[inst].exports {
+            self.define(module, &export.name[..], export.value);
+        }
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<ExternVal> {
+        self.externs.get(module)?.get(name).cloned()
+    }
+
+    /// The external values for the imports of `module`,
+    /// in the order `instantiate_module` expects them.
+    pub fn resolve(&self, s: &Store, module: &ValidatedModule) -> LResult<Vec<ExternVal>> {
+        let externtypes = &module.import_export_mapping().imports;
+        let mut externvals = vec![];
+
+        for (import, expected) in module.imports.iter().zip(externtypes.iter()) {
+            let (m, n) = (&import.module[..], &import.name[..]);
+            let value = self.get(m, n).ok_or_else(|| LinkError::UnknownImport {
+                module: m.to_owned(),
+                name: n.to_owned(),
+            })?;
+
+            let found = external_typing::extern_val(s, value);
+            if !import_matching::extern_type(&found, expected) {
+                Err(LinkError::IncompatibleImport {
+                    module: m.to_owned(),
+                    name: n.to_owned(),
+                    expected: Box::new(expected.clone()),
+                    found: Box::new(found),
+                })?;
+            }
+
+            externvals.push(value);
+        }
+
+        Ok(externvals)
+    }
+
+    /// Resolves the imports of `module` and instantiates it.
+    pub fn instantiate<'ast>(&self, s: &mut Store<'ast>, stack: &mut Stack<'ast>,
+                             module: &'ast ValidatedModule) -> LResult<ModuleAddr>
+    {
+        self.instantiate_with_config(s, stack, module, &InstantiationConfig::default())
+    }
+
+    /// Like `instantiate`, but with the features of `config`.
+    pub fn instantiate_with_config<'ast>(&self, s: &mut Store<'ast>, stack: &mut Stack<'ast>,
+                                         module: &'ast ValidatedModule,
+                                         config: &InstantiationConfig) -> LResult<ModuleAddr>
+    {
+        let externvals = self.resolve(s, module)?;
+        Ok(instantiate_module_with_config(s, stack, module, &externvals, config)?)
+    }
+}
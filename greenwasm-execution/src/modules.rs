@@ -1,5 +1,6 @@
 use greenwasm_structure::types::*;
 use greenwasm_structure::modules::*;
+use greenwasm_structure::features::{WasmFeatures, Feature};
 use greenwasm_validation::ValidatedModule;
 
 use std::result::Result as StdResult;
@@ -71,6 +72,17 @@ pub mod external_typing {
             valtype: t,
         })
     }
+
+    pub fn extern_val<'ast>(s: &Store<'ast>, v: ExternVal) -> ExternType
+
+    {
+        match v {
+            ExternVal::Func(a) => func(s, a),
+            ExternVal::Table(a) => table(s, a),
+            ExternVal::Mem(a) => mem(s, a),
+            ExternVal::Global(a) => global(s, a),
+        }
+    }
 }
 
 pub mod import_matching {
@@ -134,14 +146,14 @@ pub mod allocation {
         a
     }
     pub fn alloc_host_function<'ast>(s: &mut Store<'ast>,
-                                     hostfunc: HostFunc,
+                                     hostfunc: Box<HostCode<'ast>>,
                                      functype: &'ast FuncType) -> FuncAddr
 
     {
         let a = s.funcs.next_addr();
         let funcinst = FuncInst::Host {
             type_: functype,
-            hostcode: hostfunc,
+            hostcode: HostFunc { code: hostfunc.into() },
         };
         s.funcs.push(funcinst);
 
@@ -334,6 +346,10 @@ pub mod instantiation {
         DataIdxOutOfBounds,
         Trap,
         StackExhaustion,
+        /// A host function called by the start function failed.
+        Host(HostError),
+        /// The module uses a proposal that is not enabled in the `WasmFeatures`.
+        FeatureDisabled(Feature),
     }
     use self::InstantiationError::*;
     impl From<ExecutionError> for InstantiationError {
@@ -341,6 +357,7 @@ pub mod instantiation {
             match v {
                 ExecutionError::Trap => InstantiationError::Trap,
                 ExecutionError::StackExhaustion => InstantiationError::StackExhaustion,
+                ExecutionError::Host(e) => InstantiationError::Host(e),
             }
         }
     }
@@ -350,10 +367,31 @@ pub mod instantiation {
 
     pub type IResult = StdResult<ModuleAddr, InstantiationError>;
 
+    /// The enabled features of an instantiation.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct InstantiationConfig {
+        /// Modules that use a proposal that is not enabled fail with
+        /// `FeatureDisabled`, even if they have been validated with it.
+        pub features: WasmFeatures,
+    }
+
     pub fn instantiate_module<'ast>(s: &mut Store<'ast>, stack: &mut Stack<'ast>,
                                     module: &'ast ValidatedModule,
                                     externvals: &[ExternVal]) -> IResult
     {
+        instantiate_module_with_config(s, stack, module, externvals, &InstantiationConfig::default())
+    }
+
+    /// Like `instantiate_module`, but with the features of `config`.
+    pub fn instantiate_module_with_config<'ast>(s: &mut Store<'ast>, stack: &mut Stack<'ast>,
+                                                module: &'ast ValidatedModule,
+                                                externvals: &[ExternVal],
+                                                config: &InstantiationConfig) -> IResult
+    {
+        if let Some(feature) = module.features().first_missing(&config.features) {
+            return Err(FeatureDisabled(feature));
+        }
+
         // NB: We need to keep the stack in a clean state even in case
         // of an error
 
@@ -396,20 +434,7 @@ pub mod instantiation {
         for (externvali, externtypei_) in externvals.iter().zip(externtypes_im.iter()) {
             // TODO: Verify that validation can never fail here
 
-            let externtypei = match externvali {
-                ExternVal::Func(x) => {
-                    external_typing::func(ctx.store, *x)
-                }
-                ExternVal::Table(x) => {
-                    external_typing::table(ctx.store, *x)
-                }
-                ExternVal::Mem(x) => {
-                    external_typing::mem(ctx.store, *x)
-                }
-                ExternVal::Global(x) => {
-                    external_typing::global(ctx.store, *x)
-                }
-            };
+            let externtypei = external_typing::extern_val(ctx.store, *externvali);
 
             if !import_matching::extern_type(&externtypei, &externtypei_) {
                 Err(WrongExternTypeInImport)?;
@@ -609,6 +634,12 @@ pub mod invocation {
                 assert!(stack.is_empty());
                 Ok(Result::Trap)
             }
+            Err(ExecutionError::Host(e)) => {
+                stack.unwind_to(0);
+
+                assert!(stack.is_empty());
+                Ok(Result::Host(e))
+            }
             Err(ExecutionError::StackExhaustion) => {
                 stack.unwind_to(0);
 
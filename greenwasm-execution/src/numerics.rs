@@ -295,6 +295,26 @@ pub fn extend_s(i: I32) -> I64 {
     I64::rsigned(I32::signed(i) as S64)
 }
 #[inline(always)]
+pub fn extend8_s_i32(i: I32) -> I32 {
+    I32::rsigned(i as i8 as S32)
+}
+#[inline(always)]
+pub fn extend16_s_i32(i: I32) -> I32 {
+    I32::rsigned(i as i16 as S32)
+}
+#[inline(always)]
+pub fn extend8_s_i64(i: I64) -> I64 {
+    I64::rsigned(i as i8 as S64)
+}
+#[inline(always)]
+pub fn extend16_s_i64(i: I64) -> I64 {
+    I64::rsigned(i as i16 as S64)
+}
+#[inline(always)]
+pub fn extend32_s_i64(i: I64) -> I64 {
+    I64::rsigned(i as i32 as S64)
+}
+#[inline(always)]
 pub fn wrap(i: I64) -> I32 {
     i as I32
 }
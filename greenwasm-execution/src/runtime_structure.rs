@@ -1,5 +1,6 @@
 use std::marker::PhantomData;
 use std::ops::{Index, IndexMut};
+use std::rc::Rc;
 
 use greenwasm_structure::types::*;
 use greenwasm_structure::instructions::*;
@@ -92,6 +93,8 @@ impl Val {
 pub enum Result {
     Vals(Vec<Val>),
     Trap,
+    /// A host function failed, which unwinds the calling code like a trap.
+    Host(HostError),
 }
 
 #[derive(Default)]
@@ -167,7 +170,7 @@ pub enum FuncInst<'ast>
     },
     Host {
         type_: &'ast FuncType,
-        hostcode: HostFunc
+        hostcode: HostFunc<'ast>
     },
 }
 impl<'ast> FuncInst<'ast>  {
@@ -182,9 +185,74 @@ impl<'ast> FuncInst<'ast>  {
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct HostFunc {
-    pub id: u32
+/// The code of a host function, which is called with the arguments
+/// of the function and returns its results.
+///
+/// NB: Results that do not match the type the function has been
+/// allocated with are reported as a `HostError`.
+pub type HostCode<'ast> = dyn Fn(&mut Caller<'_, 'ast>, &[Val])
+    -> ::std::result::Result<Vec<Val>, HostError> + 'ast;
+
+#[derive(Clone)]
+pub struct HostFunc<'ast> {
+    // NB: Shared, so that the store can be borrowed by the `Caller` during a call
+    pub code: Rc<HostCode<'ast>>,
+}
+
+/// An error raised by a host function. It unwinds the calling code the
+/// same as a trap of an instruction, and is passed on to the embedder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostError {
+    message: String,
+}
+
+impl HostError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        HostError { message: message.into() }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ::std::fmt::Display for HostError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ::std::error::Error for HostError {}
+
+/// The view of the store that a host function is called with.
+pub struct Caller<'a, 'ast: 'a> {
+    pub(crate) store: &'a mut Store<'ast>,
+    pub(crate) module: Option<ModuleAddr>,
+}
+
+impl<'a, 'ast> Caller<'a, 'ast> {
+    /// The instance of the calling function, or `None` if the
+    /// host function has been invoked directly.
+    pub fn module(&self) -> Option<ModuleAddr> {
+        self.module
+    }
+
+    pub fn store(&mut self) -> &mut Store<'ast> {
+        self.store
+    }
+
+    /// The export `name` of the calling instance.
+    pub fn get_export(&self, name: &str) -> Option<ExternVal> {
+        let module = &self.store.modules[self.module?];
+        module.exports.iter().find(|e| ***e.name == *name).map(|e| e.value)
+    }
+
+    /// The memory of the calling instance, if it has one.
+    pub fn memory(&mut self) -> Option<&mut MemInst> {
+        let module = &self.store.modules[self.module?];
+        let addr = *module.memaddrs.get(MemIdx(0))?;
+        Some(&mut self.store.mems[addr])
+    }
 }
 
 pub struct TableInst {
@@ -369,6 +437,16 @@ impl<'instr> Stack<'instr> {
         }
     }
 
+    /// The module of the innermost frame, if there is one.
+    pub fn current_module(&self) -> Option<ModuleAddr> {
+        let cfi = *self.frame_indices.last()?;
+        if let StackElem::Activation(ref a) = self.data[cfi] {
+            Some(a.frame.module)
+        } else {
+            panic!("No Frame at top of stack")
+        }
+    }
+
     pub fn current_frame(&mut self) -> &mut Frame {
         &mut self.current_activation().frame
     }
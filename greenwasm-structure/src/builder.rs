@@ -0,0 +1,349 @@
+//! Builders for constructing a `Module` by hand.
+//!
+//! `ModuleBuilder` deduplicates function types and hands out indices
+//! into the index spaces of the module, which start with the imports.
+//! `FunctionBuilder` keeps track of locals and of the nesting of
+//! structured instructions, so that branches can refer to a `Label`
+//! handle instead of a relative `LabelIdx`.
+
+use std::collections::HashMap;
+
+use super::types::*;
+use super::instructions::*;
+use super::modules::*;
+
+/// Handle to the label of an enclosing block, loop, if or function body.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    // Number of labels in scope, including this one
+    level: u32,
+    // NB: Distinguishes the labels of sibling blocks at the same level
+    id: u32,
+}
+
+pub struct FunctionBuilder {
+    type_: FuncType,
+    locals: Vec<ValType>,
+    names: HashMap<String, LocalIdx>,
+
+    // The innermost instruction sequence is at the top,
+    // with the id of its label at the same position in `labels`
+    bodies: Vec<Vec<Instr>>,
+    labels: Vec<u32>,
+    next_label: u32,
+}
+
+impl FunctionBuilder {
+    pub fn new(args: &[ValType], results: &[ValType]) -> Self {
+        FunctionBuilder {
+            type_: FuncType {
+                args: args.to_vec().into(),
+                results: results.to_vec().into(),
+            },
+            locals: vec![],
+            names: HashMap::new(),
+            bodies: vec![vec![]],
+            labels: vec![0],
+            next_label: 1,
+        }
+    }
+
+    /// The local holding the `n`th parameter.
+    pub fn param(&self, n: usize) -> LocalIdx {
+        assert!(n < self.type_.args.len(), "parameter {} does not exist", n);
+        LocalIdx(n as u32)
+    }
+
+    /// Gives the `n`th parameter a name, so it can be looked up with `local_named()`.
+    pub fn name_param(&mut self, n: usize, name: &str) -> LocalIdx {
+        let x = self.param(n);
+        self.bind_name(name, x);
+        x
+    }
+
+    /// Appends a new local after the parameters and the previous locals.
+    pub fn local(&mut self, t: ValType) -> LocalIdx {
+        self.locals.push(t);
+        LocalIdx((self.type_.args.len() + self.locals.len() - 1) as u32)
+    }
+
+    pub fn named_local(&mut self, name: &str, t: ValType) -> LocalIdx {
+        let x = self.local(t);
+        self.bind_name(name, x);
+        x
+    }
+
+    /// Looks up a named parameter or local.
+    pub fn local_named(&self, name: &str) -> LocalIdx {
+        match self.names.get(name) {
+            Some(&x) => x,
+            None => panic!("no local named {:?}", name),
+        }
+    }
+
+    fn bind_name(&mut self, name: &str, x: LocalIdx) {
+        let prev = self.names.insert(name.to_owned(), x);
+        assert!(prev.is_none(), "local {:?} is already defined", name);
+    }
+
+    /// The label of the function body itself. Branching to
+    /// it is equivalent to a `return`.
+    pub fn body_label(&self) -> Label {
+        Label { level: 1, id: 0 }
+    }
+
+    pub fn instr(&mut self, instr: Instr) -> &mut Self {
+        self.bodies.last_mut().unwrap().push(instr);
+        self
+    }
+
+    pub fn instrs<I: IntoIterator<Item = Instr>>(&mut self, instrs: I) -> &mut Self {
+        self.bodies.last_mut().unwrap().extend(instrs);
+        self
+    }
+
+    fn nested<F: FnOnce(&mut Self, Label)>(&mut self, f: F) -> Vec<Instr> {
+        let label = Label { level: self.bodies.len() as u32 + 1, id: self.next_label };
+        self.next_label += 1;
+        self.bodies.push(vec![]);
+        self.labels.push(label.id);
+        f(self, label);
+        self.labels.pop();
+        self.bodies.pop().unwrap()
+    }
+
+    pub fn block<F: FnOnce(&mut Self, Label)>(&mut self, rt: ResultType, f: F) -> &mut Self {
+        let body = self.nested(f);
+        self.instr(Instr::Block(rt, body))
+    }
+
+    pub fn loop_<F: FnOnce(&mut Self, Label)>(&mut self, rt: ResultType, f: F) -> &mut Self {
+        let body = self.nested(f);
+        self.instr(Instr::Loop(rt, body))
+    }
+
+    pub fn if_<F: FnOnce(&mut Self, Label)>(&mut self, rt: ResultType, then: F) -> &mut Self {
+        let then = self.nested(then);
+        self.instr(Instr::IfElse(rt, then, vec![]))
+    }
+
+    /// Both branches get the same label.
+    pub fn if_else<F, G>(&mut self, rt: ResultType, then: F, else_: G) -> &mut Self
+        where F: FnOnce(&mut Self, Label),
+              G: FnOnce(&mut Self, Label),
+    {
+        let then = self.nested(then);
+        let else_ = self.nested(else_);
+        self.instr(Instr::IfElse(rt, then, else_))
+    }
+
+    /// The relative `LabelIdx` of `label` at the current position.
+    ///
+    /// Panics if the block of `label` has already been closed.
+    pub fn label_idx(&self, label: Label) -> LabelIdx {
+        let level = self.bodies.len() as u32;
+        let open = self.labels.get(label.level as usize - 1) == Some(&label.id);
+        assert!(open, "label is not in scope");
+        LabelIdx(level - label.level)
+    }
+
+    pub fn br(&mut self, label: Label) -> &mut Self {
+        let l = self.label_idx(label);
+        self.instr(Instr::Br(l))
+    }
+
+    pub fn br_if(&mut self, label: Label) -> &mut Self {
+        let l = self.label_idx(label);
+        self.instr(Instr::BrIf(l))
+    }
+
+    pub fn br_table(&mut self, labels: &[Label], default: Label) -> &mut Self {
+        let ls: Vec<_> = labels.iter().map(|&l| self.label_idx(l)).collect();
+        let l = self.label_idx(default);
+        self.instr(Instr::BrTable(ls.into(), l))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BuildError {
+    /// A function has been declared with `declare_func()`, but never defined.
+    UndefinedFunc(FuncIdx),
+}
+
+impl ::std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            BuildError::UndefinedFunc(FuncIdx(x)) => {
+                write!(f, "function {} is declared but not defined", x)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ModuleBuilder {
+    types: Vec<FuncType>,
+    // NB: The type of a declared function is fixed before its body is defined
+    funcs: Vec<(TypeIdx, Option<Func>)>,
+    tables: Vec<Table>,
+    mems: Vec<Mem>,
+    globals: Vec<Global>,
+    elem: Vec<Elem>,
+    data: Vec<Data>,
+    start: Option<Start>,
+    imports: Vec<Import>,
+    exports: Vec<Export>,
+
+    imported_funcs: u32,
+    imported_tables: u32,
+    imported_mems: u32,
+    imported_globals: u32,
+}
+
+impl ModuleBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the index of the given function type,
+    /// adding it only if it does not exist yet.
+    pub fn type_(&mut self, args: &[ValType], results: &[ValType]) -> TypeIdx {
+        let ft = FuncType {
+            args: args.to_vec().into(),
+            results: results.to_vec().into(),
+        };
+        self.intern_type(ft)
+    }
+
+    fn intern_type(&mut self, ft: FuncType) -> TypeIdx {
+        match self.types.iter().position(|t| *t == ft) {
+            Some(x) => TypeIdx(x as u32),
+            None => {
+                self.types.push(ft);
+                TypeIdx((self.types.len() - 1) as u32)
+            }
+        }
+    }
+
+    fn import(&mut self, module: &str, name: &str, desc: ImportDesc) {
+        self.imports.push(Import { module: module.into(), name: name.into(), desc });
+    }
+
+    // NB: Imports come first in each index space, so they can not be
+    // added after an index of a definition has been handed out.
+
+    pub fn import_func(&mut self, module: &str, name: &str, args: &[ValType], results: &[ValType]) -> FuncIdx {
+        assert!(self.funcs.is_empty(), "function imports must be added before function definitions");
+        let x = self.type_(args, results);
+        self.import(module, name, ImportDesc::Func(x));
+        self.imported_funcs += 1;
+        FuncIdx(self.imported_funcs - 1)
+    }
+
+    pub fn import_table(&mut self, module: &str, name: &str, type_: TableType) -> TableIdx {
+        assert!(self.tables.is_empty(), "table imports must be added before table definitions");
+        self.import(module, name, ImportDesc::Table(type_));
+        self.imported_tables += 1;
+        TableIdx(self.imported_tables - 1)
+    }
+
+    pub fn import_mem(&mut self, module: &str, name: &str, type_: MemType) -> MemIdx {
+        assert!(self.mems.is_empty(), "memory imports must be added before memory definitions");
+        self.import(module, name, ImportDesc::Mem(type_));
+        self.imported_mems += 1;
+        MemIdx(self.imported_mems - 1)
+    }
+
+    pub fn import_global(&mut self, module: &str, name: &str, type_: GlobalType) -> GlobalIdx {
+        assert!(self.globals.is_empty(), "global imports must be added before global definitions");
+        self.import(module, name, ImportDesc::Global(type_));
+        self.imported_globals += 1;
+        GlobalIdx(self.imported_globals - 1)
+    }
+
+    /// Reserves the index of a function with the given type, so that it
+    /// can be called before its body is defined with `define_func()`.
+    pub fn declare_func(&mut self, args: &[ValType], results: &[ValType]) -> FuncIdx {
+        let type_ = self.type_(args, results);
+        self.funcs.push((type_, None));
+        FuncIdx(self.imported_funcs + self.funcs.len() as u32 - 1)
+    }
+
+    /// Panics if the type of `f` differs from the declared one.
+    pub fn define_func(&mut self, x: FuncIdx, f: FunctionBuilder) {
+        assert!(x.0 >= self.imported_funcs, "can not define an imported function");
+        let type_ = self.intern_type(f.type_.clone());
+        let (declared, ref mut slot) = self.funcs[(x.0 - self.imported_funcs) as usize];
+        assert!(type_ == declared, "function {} is defined with a different type than declared", x.0);
+        assert!(slot.is_none(), "function {} is already defined", x.0);
+
+        let FunctionBuilder { locals, mut bodies, .. } = f;
+        assert!(bodies.len() == 1);
+        *slot = Some(Func {
+            type_,
+            locals: locals.into(),
+            body: Expr { body: bodies.pop().unwrap() },
+        });
+    }
+
+    pub fn func(&mut self, f: FunctionBuilder) -> FuncIdx {
+        let x = {
+            let ft = &f.type_;
+            self.declare_func(&ft.args, &ft.results)
+        };
+        self.define_func(x, f);
+        x
+    }
+
+    pub fn table(&mut self, type_: TableType) -> TableIdx {
+        self.tables.push(Table { type_ });
+        TableIdx(self.imported_tables + self.tables.len() as u32 - 1)
+    }
+
+    pub fn mem(&mut self, type_: MemType) -> MemIdx {
+        self.mems.push(Mem { type_ });
+        MemIdx(self.imported_mems + self.mems.len() as u32 - 1)
+    }
+
+    pub fn global(&mut self, type_: GlobalType, init: Vec<Instr>) -> GlobalIdx {
+        self.globals.push(Global { type_, init: Expr { body: init } });
+        GlobalIdx(self.imported_globals + self.globals.len() as u32 - 1)
+    }
+
+    pub fn export(&mut self, name: &str, desc: ExportDesc) {
+        self.exports.push(Export { name: name.into(), desc });
+    }
+
+    pub fn start(&mut self, func: FuncIdx) {
+        self.start = Some(Start { func });
+    }
+
+    pub fn elem(&mut self, table: TableIdx, offset: Vec<Instr>, init: Vec<FuncIdx>) {
+        self.elem.push(Elem { table, offset: Expr { body: offset }, init: init.into() });
+    }
+
+    pub fn data(&mut self, data: MemIdx, offset: Vec<Instr>, init: Vec<Byte>) {
+        self.data.push(Data { data, offset: Expr { body: offset }, init: init.into() });
+    }
+
+    /// Fails if a declared function has not been defined.
+    pub fn build(self) -> Result<Module, BuildError> {
+        let imported_funcs = self.imported_funcs;
+        let funcs = self.funcs.into_iter().enumerate().map(|(i, (_, f))| {
+            f.ok_or(BuildError::UndefinedFunc(FuncIdx(imported_funcs + i as u32)))
+        }).collect::<Result<_, _>>()?;
+
+        Ok(Module {
+            types: self.types.into(),
+            funcs,
+            tables: self.tables.into(),
+            mems: self.mems.into(),
+            globals: self.globals.into(),
+            elem: self.elem.into(),
+            data: self.data.into(),
+            start: self.start,
+            imports: self.imports.into(),
+            exports: self.exports.into(),
+        })
+    }
+}
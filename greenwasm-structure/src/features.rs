@@ -0,0 +1,77 @@
+//! The WebAssembly proposals that can be enabled on top of the MVP.
+//!
+//! The same `WasmFeatures` are passed to the parser, the validator
+//! and instantiation, so that an embedder can pin the exact set
+//! of features a module may use.
+
+use std::fmt;
+
+/// A set of enabled proposals. The default is the MVP only.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct WasmFeatures {
+    /// `i32.extend8_s`, `i32.extend16_s`, `i64.extend8_s`,
+    /// `i64.extend16_s` and `i64.extend32_s`.
+    pub sign_extension: bool,
+}
+
+impl WasmFeatures {
+    pub fn mvp() -> Self {
+        WasmFeatures::default()
+    }
+
+    /// All proposals that are implemented.
+    pub fn all() -> Self {
+        WasmFeatures {
+            sign_extension: true,
+        }
+    }
+
+    pub fn enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::SignExtension => self.sign_extension,
+        }
+    }
+
+    pub fn enable(&mut self, feature: Feature) {
+        match feature {
+            Feature::SignExtension => self.sign_extension = true,
+        }
+    }
+
+    /// The features that are enabled in `self` or `other`.
+    pub fn union(&self, other: &WasmFeatures) -> Self {
+        let mut r = *self;
+        for &f in Feature::ALL {
+            if other.enabled(f) {
+                r.enable(f);
+            }
+        }
+        r
+    }
+
+    /// The first feature of `self` that is not enabled in `other`.
+    pub fn first_missing(&self, other: &WasmFeatures) -> Option<Feature> {
+        Feature::ALL.iter().cloned()
+            .find(|&f| self.enabled(f) && !other.enabled(f))
+    }
+}
+
+/// A single proposal, as named in error messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Feature {
+    SignExtension,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[
+        Feature::SignExtension,
+    ];
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Feature::SignExtension => write!(f, "sign-extension operators"),
+        }
+    }
+}
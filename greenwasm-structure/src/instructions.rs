@@ -172,6 +172,13 @@ pub enum Instr {
     F32ReinterpretI32,
     F64ReinterpretI64,
 
+    // sign-extension operators
+    I32Extend8S,
+    I32Extend16S,
+    I64Extend8S,
+    I64Extend16S,
+    I64Extend32S,
+
     // parametric instructions
     Drop,
     Select,
@@ -4,3 +4,6 @@
 pub mod types;
 pub mod instructions;
 pub mod modules;
+pub mod visit;
+pub mod builder;
+pub mod features;
@@ -102,13 +102,13 @@ pub struct Start {
     pub func: FuncIdx,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Export {
     pub name: Name,
     pub desc: ExportDesc,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExportDesc {
     Func(FuncIdx),
     Table(TableIdx),
@@ -116,14 +116,14 @@ pub enum ExportDesc {
     Global(GlobalIdx),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Import {
     pub module: Name,
     pub name: Name,
     pub desc: ImportDesc,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ImportDesc {
     Func(TypeIdx),
     Table(TableType),
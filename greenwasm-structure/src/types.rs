@@ -12,7 +12,7 @@ pub const WEC_MAX_SIZE: usize = ::std::u32::MAX as usize;
 /// a size <= WEC_MAX_SIZE.
 ///
 /// Called Wec to prevent confusion with Rusts Vec type.
-#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct Wec<A> {
     inner: Vec<A>
 }
@@ -137,7 +137,7 @@ impl ::std::ops::Deref for Name {
 }
 pub type Codepoint = char;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum ValType {
     I32,
     I64,
@@ -185,7 +185,7 @@ impl Debug for ResultType {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FuncType {
     pub args: Wec<ValType>,
     pub results: Wec<ValType>,
@@ -0,0 +1,445 @@
+//! Traversal of modules and instruction sequences.
+//!
+//! `Visit` and `VisitMut` have a hook for every node, which by
+//! default just walks into its children. Implementations only
+//! override the hooks they are interested in, and can call
+//! the matching `walk_*` function to continue the traversal.
+//!
+//! The hooks of both traits have the same names, `VisitMut` just
+//! takes mutable references.
+//!
+//! NB: The traversal recurses into nested blocks, so
+//! its stack usage grows with the nesting depth.
+
+use super::types::*;
+use super::instructions::*;
+use super::modules::*;
+
+/// Calls `$m!` with the list of all instructions that
+/// do not contain nested instructions, and their hooks.
+macro_rules! for_each_plain_instr {
+    ($m:ident) => ($m! {
+        // numeric instructions
+        I32Const(n: I32) => visit_i32_const,
+        I64Const(n: I64) => visit_i64_const,
+        F32Const(z: F32) => visit_f32_const,
+        F64Const(z: F64) => visit_f64_const,
+
+        I32Clz => visit_i32_clz,
+        I32Ctz => visit_i32_ctz,
+        I32Popcnt => visit_i32_popcnt,
+
+        I64Clz => visit_i64_clz,
+        I64Ctz => visit_i64_ctz,
+        I64Popcnt => visit_i64_popcnt,
+
+        F32Abs => visit_f32_abs,
+        F32Neg => visit_f32_neg,
+        F32Sqrt => visit_f32_sqrt,
+        F32Ceil => visit_f32_ceil,
+        F32Floor => visit_f32_floor,
+        F32Trunc => visit_f32_trunc,
+        F32Nearest => visit_f32_nearest,
+
+        F64Abs => visit_f64_abs,
+        F64Neg => visit_f64_neg,
+        F64Sqrt => visit_f64_sqrt,
+        F64Ceil => visit_f64_ceil,
+        F64Floor => visit_f64_floor,
+        F64Trunc => visit_f64_trunc,
+        F64Nearest => visit_f64_nearest,
+
+        I32Add => visit_i32_add,
+        I32Sub => visit_i32_sub,
+        I32Mul => visit_i32_mul,
+        I32DivU => visit_i32_div_u,
+        I32DivS => visit_i32_div_s,
+        I32RemU => visit_i32_rem_u,
+        I32RemS => visit_i32_rem_s,
+        I32And => visit_i32_and,
+        I32Or => visit_i32_or,
+        I32Xor => visit_i32_xor,
+        I32Shl => visit_i32_shl,
+        I32ShrU => visit_i32_shr_u,
+        I32ShrS => visit_i32_shr_s,
+        I32Rotl => visit_i32_rotl,
+        I32Rotr => visit_i32_rotr,
+
+        I64Add => visit_i64_add,
+        I64Sub => visit_i64_sub,
+        I64Mul => visit_i64_mul,
+        I64DivU => visit_i64_div_u,
+        I64DivS => visit_i64_div_s,
+        I64RemU => visit_i64_rem_u,
+        I64RemS => visit_i64_rem_s,
+        I64And => visit_i64_and,
+        I64Or => visit_i64_or,
+        I64Xor => visit_i64_xor,
+        I64Shl => visit_i64_shl,
+        I64ShrU => visit_i64_shr_u,
+        I64ShrS => visit_i64_shr_s,
+        I64Rotl => visit_i64_rotl,
+        I64Rotr => visit_i64_rotr,
+
+        F32Add => visit_f32_add,
+        F32Sub => visit_f32_sub,
+        F32Mul => visit_f32_mul,
+        F32Div => visit_f32_div,
+        F32Min => visit_f32_min,
+        F32Max => visit_f32_max,
+        F32CopySign => visit_f32_copysign,
+
+        F64Add => visit_f64_add,
+        F64Sub => visit_f64_sub,
+        F64Mul => visit_f64_mul,
+        F64Div => visit_f64_div,
+        F64Min => visit_f64_min,
+        F64Max => visit_f64_max,
+        F64CopySign => visit_f64_copysign,
+
+        I32EqZ => visit_i32_eqz,
+        I64EqZ => visit_i64_eqz,
+
+        I32Eq => visit_i32_eq,
+        I32Ne => visit_i32_ne,
+        I32LtU => visit_i32_lt_u,
+        I32LtS => visit_i32_lt_s,
+        I32GtU => visit_i32_gt_u,
+        I32GtS => visit_i32_gt_s,
+        I32LeU => visit_i32_le_u,
+        I32LeS => visit_i32_le_s,
+        I32GeU => visit_i32_ge_u,
+        I32GeS => visit_i32_ge_s,
+
+        I64Eq => visit_i64_eq,
+        I64Ne => visit_i64_ne,
+        I64LtU => visit_i64_lt_u,
+        I64LtS => visit_i64_lt_s,
+        I64GtU => visit_i64_gt_u,
+        I64GtS => visit_i64_gt_s,
+        I64LeU => visit_i64_le_u,
+        I64LeS => visit_i64_le_s,
+        I64GeU => visit_i64_ge_u,
+        I64GeS => visit_i64_ge_s,
+
+        F32Eq => visit_f32_eq,
+        F32Ne => visit_f32_ne,
+        F32Lt => visit_f32_lt,
+        F32Gt => visit_f32_gt,
+        F32Le => visit_f32_le,
+        F32Ge => visit_f32_ge,
+
+        F64Eq => visit_f64_eq,
+        F64Ne => visit_f64_ne,
+        F64Lt => visit_f64_lt,
+        F64Gt => visit_f64_gt,
+        F64Le => visit_f64_le,
+        F64Ge => visit_f64_ge,
+
+        I32WrapI64 => visit_i32_wrap_i64,
+        I64ExtendUI32 => visit_i64_extend_u_i32,
+        I64ExtendSI32 => visit_i64_extend_s_i32,
+
+        I32TruncUF32 => visit_i32_trunc_u_f32,
+        I32TruncUF64 => visit_i32_trunc_u_f64,
+        I32TruncSF32 => visit_i32_trunc_s_f32,
+        I32TruncSF64 => visit_i32_trunc_s_f64,
+        I64TruncUF32 => visit_i64_trunc_u_f32,
+        I64TruncUF64 => visit_i64_trunc_u_f64,
+        I64TruncSF32 => visit_i64_trunc_s_f32,
+        I64TruncSF64 => visit_i64_trunc_s_f64,
+
+        F32DemoteF64 => visit_f32_demote_f64,
+        F64PromoteF32 => visit_f64_promote_f32,
+
+        F32ConvertUI32 => visit_f32_convert_u_i32,
+        F64ConvertUI32 => visit_f64_convert_u_i32,
+        F32ConvertSI32 => visit_f32_convert_s_i32,
+        F64ConvertSI32 => visit_f64_convert_s_i32,
+        F32ConvertUI64 => visit_f32_convert_u_i64,
+        F64ConvertUI64 => visit_f64_convert_u_i64,
+        F32ConvertSI64 => visit_f32_convert_s_i64,
+        F64ConvertSI64 => visit_f64_convert_s_i64,
+
+        I32ReinterpretF32 => visit_i32_reinterpret_f32,
+        I64ReinterpretF64 => visit_i64_reinterpret_f64,
+        F32ReinterpretI32 => visit_f32_reinterpret_i32,
+        F64ReinterpretI64 => visit_f64_reinterpret_i64,
+
+        I32Extend8S => visit_i32_extend8_s,
+        I32Extend16S => visit_i32_extend16_s,
+        I64Extend8S => visit_i64_extend8_s,
+        I64Extend16S => visit_i64_extend16_s,
+        I64Extend32S => visit_i64_extend32_s,
+
+        // parametric instructions
+        Drop => visit_drop,
+        Select => visit_select,
+
+        // variable instructions
+        GetLocal(x: LocalIdx) => visit_get_local,
+        SetLocal(x: LocalIdx) => visit_set_local,
+        TeeLocal(x: LocalIdx) => visit_tee_local,
+        GetGlobal(x: GlobalIdx) => visit_get_global,
+        SetGlobal(x: GlobalIdx) => visit_set_global,
+
+        // memory instructions
+        I32Load(m: Memarg) => visit_i32_load,
+        I64Load(m: Memarg) => visit_i64_load,
+        F32Load(m: Memarg) => visit_f32_load,
+        F64Load(m: Memarg) => visit_f64_load,
+
+        I32Store(m: Memarg) => visit_i32_store,
+        I64Store(m: Memarg) => visit_i64_store,
+        F32Store(m: Memarg) => visit_f32_store,
+        F64Store(m: Memarg) => visit_f64_store,
+
+        I32Load8U(m: Memarg) => visit_i32_load8_u,
+        I32Load8S(m: Memarg) => visit_i32_load8_s,
+        I64Load8U(m: Memarg) => visit_i64_load8_u,
+        I64Load8S(m: Memarg) => visit_i64_load8_s,
+
+        I32Load16U(m: Memarg) => visit_i32_load16_u,
+        I32Load16S(m: Memarg) => visit_i32_load16_s,
+        I64Load16U(m: Memarg) => visit_i64_load16_u,
+        I64Load16S(m: Memarg) => visit_i64_load16_s,
+
+        I64Load32U(m: Memarg) => visit_i64_load32_u,
+        I64Load32S(m: Memarg) => visit_i64_load32_s,
+
+        I32Store8(m: Memarg) => visit_i32_store8,
+        I64Store8(m: Memarg) => visit_i64_store8,
+        I32Store16(m: Memarg) => visit_i32_store16,
+        I64Store16(m: Memarg) => visit_i64_store16,
+        I64Store32(m: Memarg) => visit_i64_store32,
+
+        CurrentMemory => visit_current_memory,
+        GrowMemory => visit_grow_memory,
+
+        // control instructions
+        Nop => visit_nop,
+        Unreachable => visit_unreachable,
+        Br(l: LabelIdx) => visit_br,
+        BrIf(l: LabelIdx) => visit_br_if,
+        BrTable(ls: Wec<LabelIdx>, l: LabelIdx) => visit_br_table,
+        Return => visit_return,
+        Call(x: FuncIdx) => visit_call,
+        CallIndirect(x: TypeIdx) => visit_call_indirect,
+    })
+}
+
+macro_rules! visit_trait {
+    ($($variant:ident $(($($arg:ident: $ty:ty),*))? => $hook:ident,)*) => (
+        /// A traversal over shared references.
+        pub trait Visit {
+            fn visit_module(&mut self, module: &Module) {
+                walk_module(self, module)
+            }
+            fn visit_func(&mut self, func: &Func) {
+                walk_func(self, func)
+            }
+            fn visit_global(&mut self, global: &Global) {
+                walk_global(self, global)
+            }
+            fn visit_elem(&mut self, elem: &Elem) {
+                walk_elem(self, elem)
+            }
+            fn visit_data(&mut self, data: &Data) {
+                walk_data(self, data)
+            }
+            fn visit_expr(&mut self, expr: &Expr) {
+                walk_expr(self, expr)
+            }
+
+            /// Called for every instruction, before the
+            /// hook of the specific instruction.
+            fn visit_instr(&mut self, instr: &Instr) {
+                walk_instr(self, instr)
+            }
+
+            fn visit_block(&mut self, _resulttype: &ResultType, body: &[Instr]) {
+                walk_instrs(self, body)
+            }
+            fn visit_loop(&mut self, _resulttype: &ResultType, body: &[Instr]) {
+                walk_instrs(self, body)
+            }
+            fn visit_if_else(&mut self, _resulttype: &ResultType, then: &[Instr], else_: &[Instr]) {
+                walk_instrs(self, then);
+                walk_instrs(self, else_);
+            }
+
+            $(
+                fn $hook(&mut self $($(, $arg: &$ty)*)?) {
+                    $($(let _ = $arg;)*)?
+                }
+            )*
+        }
+
+        /// Calls the hook matching the kind of `instr`.
+        pub fn walk_instr<V: Visit + ?Sized>(v: &mut V, instr: &Instr) {
+            match *instr {
+                Instr::Block(ref rt, ref body) => v.visit_block(rt, body),
+                Instr::Loop(ref rt, ref body) => v.visit_loop(rt, body),
+                Instr::IfElse(ref rt, ref then, ref else_) => v.visit_if_else(rt, then, else_),
+                $(
+                    Instr::$variant $(($(ref $arg),*))? => v.$hook($($($arg),*)?),
+                )*
+            }
+        }
+    )
+}
+
+macro_rules! visit_mut_trait {
+    ($($variant:ident $(($($arg:ident: $ty:ty),*))? => $hook:ident,)*) => (
+        /// A traversal over mutable references, for rewriting in place.
+        pub trait VisitMut {
+            fn visit_module(&mut self, module: &mut Module) {
+                walk_module_mut(self, module)
+            }
+            fn visit_func(&mut self, func: &mut Func) {
+                walk_func_mut(self, func)
+            }
+            fn visit_global(&mut self, global: &mut Global) {
+                walk_global_mut(self, global)
+            }
+            fn visit_elem(&mut self, elem: &mut Elem) {
+                walk_elem_mut(self, elem)
+            }
+            fn visit_data(&mut self, data: &mut Data) {
+                walk_data_mut(self, data)
+            }
+
+            /// Can replace the whole instruction sequence,
+            /// for example to insert or remove instructions.
+            fn visit_expr(&mut self, expr: &mut Expr) {
+                walk_expr_mut(self, expr)
+            }
+
+            /// Called for every instruction, before the
+            /// hook of the specific instruction.
+            fn visit_instr(&mut self, instr: &mut Instr) {
+                walk_instr_mut(self, instr)
+            }
+
+            fn visit_block(&mut self, _resulttype: &mut ResultType, body: &mut Vec<Instr>) {
+                walk_instrs_mut(self, body)
+            }
+            fn visit_loop(&mut self, _resulttype: &mut ResultType, body: &mut Vec<Instr>) {
+                walk_instrs_mut(self, body)
+            }
+            fn visit_if_else(&mut self,
+                                 _resulttype: &mut ResultType,
+                                 then: &mut Vec<Instr>,
+                                 else_: &mut Vec<Instr>) {
+                walk_instrs_mut(self, then);
+                walk_instrs_mut(self, else_);
+            }
+
+            $(
+                fn $hook(&mut self $($(, $arg: &mut $ty)*)?) {
+                    $($(let _ = $arg;)*)?
+                }
+            )*
+        }
+
+        /// Calls the hook matching the kind of `instr`.
+        pub fn walk_instr_mut<V: VisitMut + ?Sized>(v: &mut V, instr: &mut Instr) {
+            match *instr {
+                Instr::Block(ref mut rt, ref mut body) => v.visit_block(rt, body),
+                Instr::Loop(ref mut rt, ref mut body) => v.visit_loop(rt, body),
+                Instr::IfElse(ref mut rt, ref mut then, ref mut else_) => {
+                    v.visit_if_else(rt, then, else_)
+                }
+                $(
+                    Instr::$variant $(($(ref mut $arg),*))? => v.$hook($($($arg),*)?),
+                )*
+            }
+        }
+    )
+}
+
+for_each_plain_instr!(visit_trait);
+
+pub fn walk_module<V: Visit + ?Sized>(v: &mut V, module: &Module) {
+    for func in &module.funcs {
+        v.visit_func(func);
+    }
+    for global in &module.globals {
+        v.visit_global(global);
+    }
+    for elem in &module.elem {
+        v.visit_elem(elem);
+    }
+    for data in &module.data {
+        v.visit_data(data);
+    }
+}
+
+pub fn walk_func<V: Visit + ?Sized>(v: &mut V, func: &Func) {
+    v.visit_expr(&func.body)
+}
+
+pub fn walk_global<V: Visit + ?Sized>(v: &mut V, global: &Global) {
+    v.visit_expr(&global.init)
+}
+
+pub fn walk_elem<V: Visit + ?Sized>(v: &mut V, elem: &Elem) {
+    v.visit_expr(&elem.offset)
+}
+
+pub fn walk_data<V: Visit + ?Sized>(v: &mut V, data: &Data) {
+    v.visit_expr(&data.offset)
+}
+
+pub fn walk_expr<V: Visit + ?Sized>(v: &mut V, expr: &Expr) {
+    walk_instrs(v, &expr.body)
+}
+
+pub fn walk_instrs<V: Visit + ?Sized>(v: &mut V, instrs: &[Instr]) {
+    for instr in instrs {
+        v.visit_instr(instr);
+    }
+}
+
+for_each_plain_instr!(visit_mut_trait);
+
+pub fn walk_module_mut<V: VisitMut + ?Sized>(v: &mut V, module: &mut Module) {
+    for func in module.funcs.iter_mut() {
+        v.visit_func(func);
+    }
+    for global in module.globals.iter_mut() {
+        v.visit_global(global);
+    }
+    for elem in module.elem.iter_mut() {
+        v.visit_elem(elem);
+    }
+    for data in module.data.iter_mut() {
+        v.visit_data(data);
+    }
+}
+
+pub fn walk_func_mut<V: VisitMut + ?Sized>(v: &mut V, func: &mut Func) {
+    v.visit_expr(&mut func.body)
+}
+
+pub fn walk_global_mut<V: VisitMut + ?Sized>(v: &mut V, global: &mut Global) {
+    v.visit_expr(&mut global.init)
+}
+
+pub fn walk_elem_mut<V: VisitMut + ?Sized>(v: &mut V, elem: &mut Elem) {
+    v.visit_expr(&mut elem.offset)
+}
+
+pub fn walk_data_mut<V: VisitMut + ?Sized>(v: &mut V, data: &mut Data) {
+    v.visit_expr(&mut data.offset)
+}
+
+pub fn walk_expr_mut<V: VisitMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    walk_instrs_mut(v, &mut expr.body)
+}
+
+pub fn walk_instrs_mut<V: VisitMut + ?Sized>(v: &mut V, instrs: &mut [Instr]) {
+    for instr in instrs.iter_mut() {
+        v.visit_instr(instr);
+    }
+}
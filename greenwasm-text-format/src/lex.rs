@@ -0,0 +1,278 @@
+//! 6.2. Lexical Format
+//!
+//! Turns the source text into a tree of S-expressions,
+//! which the rest of the parser works on.
+
+use super::ParseError;
+use super::ParseErrorKind::*;
+use super::Pos;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(String),
+    Id(String),
+    Num(String),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sexpr {
+    Atom(Token),
+    /// A parenthesized list, with the position of its `(`
+    /// and the position of its `)`
+    List(Vec<Sexpr>, Pos, Pos),
+}
+impl Sexpr {
+    pub fn pos(&self) -> Pos {
+        match *self {
+            Sexpr::Atom(ref t) => t.pos,
+            Sexpr::List(_, pos, _) => pos,
+        }
+    }
+}
+
+enum RawToken {
+    LParen,
+    RParen,
+    Token(TokenKind),
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    offset: usize,
+    line: u32,
+    column: u32,
+}
+
+// 6.2.2. Tokens
+fn is_idchar(c: char) -> bool {
+    match c {
+        '0'..='9' | 'A'..='Z' | 'a'..='z' => true,
+        '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '.' | '/' => true,
+        ':' | '<' | '=' | '>' | '?' | '@' | '\\' | '^' | '_' | '`' | '|' | '~' => true,
+        _ => false,
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Lexer { src, offset: 0, line: 1, column: 1 }
+    }
+
+    fn pos(&self) -> Pos {
+        Pos { line: self.line, column: self.column }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, pos: Pos, kind: super::ParseErrorKind) -> Result<T, ParseError> {
+        Err(ParseError { pos, kind })
+    }
+
+    // 6.2.4. White Space
+    // 6.2.5. Comments
+    fn skip_whitespace(&mut self) -> Result<(), ParseError> {
+        loop {
+            let rest = self.rest();
+            if rest.starts_with(";;") {
+                while let Some(c) = self.bump() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else if rest.starts_with("(;") {
+                let start = self.pos();
+                self.bump();
+                self.bump();
+                let mut depth = 1;
+                while depth > 0 {
+                    let rest = self.rest();
+                    if rest.starts_with("(;") {
+                        self.bump();
+                        self.bump();
+                        depth += 1;
+                    } else if rest.starts_with(";)") {
+                        self.bump();
+                        self.bump();
+                        depth -= 1;
+                    } else if self.bump().is_none() {
+                        return self.error(start, UnterminatedComment);
+                    }
+                }
+            } else {
+                match self.peek() {
+                    Some(' ') | Some('\t') | Some('\n') | Some('\r') => {
+                        self.bump();
+                    }
+                    _ => return Ok(()),
+                }
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(RawToken, Pos)>, ParseError> {
+        self.skip_whitespace()?;
+        let pos = self.pos();
+
+        let c = if let Some(c) = self.peek() { c } else { return Ok(None) };
+        let token = match c {
+            '(' => {
+                self.bump();
+                RawToken::LParen
+            }
+            ')' => {
+                self.bump();
+                RawToken::RParen
+            }
+            '"' => {
+                self.bump();
+                RawToken::Token(TokenKind::Str(self.string(pos)?))
+            }
+            c if is_idchar(c) => {
+                let start = self.offset;
+                while self.peek().map(is_idchar).unwrap_or(false) {
+                    self.bump();
+                }
+                let s = &self.src[start..self.offset];
+
+                // A token has to be followed by a separator
+                match self.peek() {
+                    None | Some(' ') | Some('\t') | Some('\n') | Some('\r')
+                    | Some('(') | Some(')') | Some(';') => (),
+                    Some(c) => return self.error(self.pos(), UnexpectedChar(c)),
+                }
+
+                let kind = match c {
+                    '$' if s.len() > 1 => TokenKind::Id(s[1..].to_owned()),
+                    'a'..='z' => TokenKind::Keyword(s.to_owned()),
+                    '0'..='9' | '+' | '-' => TokenKind::Num(s.to_owned()),
+                    _ => return self.error(pos, ReservedToken(s.to_owned())),
+                };
+                RawToken::Token(kind)
+            }
+            c => return self.error(pos, UnexpectedChar(c)),
+        };
+        Ok(Some((token, pos)))
+    }
+
+    // 6.3.3. Strings
+    fn string(&mut self, start: Pos) -> Result<Vec<u8>, ParseError> {
+        let mut bytes = vec![];
+        loop {
+            let pos = self.pos();
+            match self.bump() {
+                None => return self.error(start, UnterminatedString),
+                Some('"') => return Ok(bytes),
+                Some('\\') => {
+                    match self.bump() {
+                        Some('t') => bytes.push(b'\t'),
+                        Some('n') => bytes.push(b'\n'),
+                        Some('r') => bytes.push(b'\r'),
+                        Some('"') => bytes.push(b'"'),
+                        Some('\'') => bytes.push(b'\''),
+                        Some('\\') => bytes.push(b'\\'),
+                        Some('u') => {
+                            if self.bump() != Some('{') {
+                                return self.error(pos, InvalidEscape);
+                            }
+                            let mut n: u32 = 0;
+                            let mut digits = 0;
+                            loop {
+                                match self.bump() {
+                                    Some('}') if digits > 0 => break,
+                                    Some(c) if c.is_digit(16) => {
+                                        n = n.checked_mul(16)
+                                            .and_then(|n| n.checked_add(c.to_digit(16).unwrap()))
+                                            .ok_or(ParseError { pos, kind: InvalidEscape })?;
+                                        digits += 1;
+                                    }
+                                    _ => return self.error(pos, InvalidEscape),
+                                }
+                            }
+                            let c = ::std::char::from_u32(n)
+                                .ok_or(ParseError { pos, kind: InvalidEscape })?;
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        Some(h) if h.is_digit(16) => {
+                            match self.bump() {
+                                Some(l) if l.is_digit(16) => {
+                                    let n = h.to_digit(16).unwrap() * 16 + l.to_digit(16).unwrap();
+                                    bytes.push(n as u8);
+                                }
+                                _ => return self.error(pos, InvalidEscape),
+                            }
+                        }
+                        _ => return self.error(pos, InvalidEscape),
+                    }
+                }
+                Some(c) if (c as u32) < 0x20 || c == '\u{7f}' => {
+                    return self.error(pos, UnexpectedChar(c));
+                }
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Splits the source text into tokens and groups them
+/// according to their parentheses.
+pub fn sexprs(src: &str) -> Result<Vec<Sexpr>, ParseError> {
+    let mut lexer = Lexer::new(src);
+
+    // NB: Like the binary parser, this builds nested lists with a
+    // stack on the heap instead of recursing.
+    let mut stack: Vec<(Vec<Sexpr>, Pos)> = vec![(vec![], lexer.pos())];
+
+    while let Some((token, pos)) = lexer.next_token()? {
+        match token {
+            RawToken::LParen => {
+                stack.push((vec![], pos));
+            }
+            RawToken::RParen => {
+                if stack.len() == 1 {
+                    return lexer.error(pos, UnbalancedParen);
+                }
+                let (items, start) = stack.pop().unwrap();
+                stack.last_mut().unwrap().0.push(Sexpr::List(items, start, pos));
+            }
+            RawToken::Token(kind) => {
+                stack.last_mut().unwrap().0.push(Sexpr::Atom(Token { kind, pos }));
+            }
+        }
+    }
+
+    if stack.len() > 1 {
+        let (_, start) = stack.pop().unwrap();
+        return lexer.error(start, UnbalancedParen);
+    }
+
+    Ok(stack.pop().unwrap().0)
+}
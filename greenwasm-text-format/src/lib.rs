@@ -0,0 +1,90 @@
+#![allow(non_snake_case)]
+
+extern crate greenwasm_structure;
+extern crate greenwasm_binary_format;
+
+use std::fmt;
+
+mod lex;
+mod num;
+mod parse;
+mod print;
+
+/// A position in the source text. Both fields start counting at 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pos {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    // lexical errors
+    UnterminatedComment,
+    UnterminatedString,
+    UnexpectedChar(char),
+    ReservedToken(String),
+    InvalidEscape,
+    UnbalancedParen,
+
+    // syntactic errors
+    Expected(&'static str),
+    UnknownInstruction(String),
+    InvalidNumber(String),
+    InvalidAlignment,
+    InvalidUtf8,
+
+    // errors while resolving identifiers and abbreviations
+    UnknownId(String),
+    DuplicateId(String),
+    MismatchedLabel(String),
+    InlineTypeMismatch,
+    ImportAfterDefinition,
+    MultipleStart,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseErrorKind::*;
+        match *self {
+            UnterminatedComment => write!(f, "unterminated block comment"),
+            UnterminatedString => write!(f, "unterminated string"),
+            UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ReservedToken(ref s) => write!(f, "reserved token `{}`", s),
+            InvalidEscape => write!(f, "invalid escape sequence"),
+            UnbalancedParen => write!(f, "unbalanced parentheses"),
+            Expected(what) => write!(f, "expected {}", what),
+            UnknownInstruction(ref s) => write!(f, "unknown instruction `{}`", s),
+            InvalidNumber(ref s) => write!(f, "invalid or out of range number `{}`", s),
+            InvalidAlignment => write!(f, "alignment must be a power of two"),
+            InvalidUtf8 => write!(f, "name is not valid UTF-8"),
+            UnknownId(ref s) => write!(f, "unknown identifier `${}`", s),
+            DuplicateId(ref s) => write!(f, "duplicate identifier `${}`", s),
+            MismatchedLabel(ref s) => write!(f, "mismatching label `${}`", s),
+            InlineTypeMismatch => write!(f, "inline function type does not match type use"),
+            ImportAfterDefinition => write!(f, "import after function, table, memory or global definition"),
+            MultipleStart => write!(f, "multiple start functions"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.kind)
+    }
+}
+
+impl ::std::error::Error for ParseError {}
+
+pub use parse::parse_text_format;
+pub use print::print_text_format;
+
+#[cfg(test)]
+#[path="tests_text_format.rs"]
+mod tests;
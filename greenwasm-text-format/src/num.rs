@@ -0,0 +1,266 @@
+//! 6.3. Values
+//!
+//! Conversion of number tokens into integers and floats.
+//! All functions return `None` if the token is malformed or out of range.
+
+// 6.3.1. Integers
+
+fn strip_underscores(s: &str) -> Option<String> {
+    // Underscores may only appear between two digits
+    let b = s.as_bytes();
+    for (i, &c) in b.iter().enumerate() {
+        if c == b'_' {
+            let before = i > 0 && (b[i - 1] as char).is_digit(16);
+            let after = i + 1 < b.len() && (b[i + 1] as char).is_digit(16);
+            if !(before && after) {
+                return None;
+            }
+        }
+    }
+    Some(s.chars().filter(|&c| c != '_').collect())
+}
+
+fn split_sign(s: &str) -> (bool, &str) {
+    if s.starts_with('-') {
+        (true, &s[1..])
+    } else if s.starts_with('+') {
+        (false, &s[1..])
+    } else {
+        (false, s)
+    }
+}
+
+fn parse_nat(s: &str) -> Option<u64> {
+    let s = strip_underscores(s)?;
+    let (digits, radix) = if s.starts_with("0x") {
+        (&s[2..], 16)
+    } else {
+        (&s[..], 10)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok()
+}
+
+/// Parses a `uN`.
+pub fn parse_uN(s: &str, N: u32) -> Option<u64> {
+    let n = parse_nat(s)?;
+    if N < 64 && n >= (1 << N) {
+        return None;
+    }
+    Some(n)
+}
+
+/// Parses a `sN`.
+pub fn parse_sN(s: &str, N: u32) -> Option<i64> {
+    let (neg, rest) = split_sign(s);
+    if rest.starts_with('+') || rest.starts_with('-') {
+        return None;
+    }
+    let n = parse_nat(rest)? as u128;
+    let bound = 1u128 << (N - 1);
+    if neg {
+        if n > bound { return None; }
+        Some((n as i128).wrapping_neg() as i64)
+    } else {
+        if n >= bound { return None; }
+        Some(n as i64)
+    }
+}
+
+/// Parses a `iN`, which accepts both the signed and unsigned range
+/// and returns the two's complement bit pattern.
+pub fn parse_iN(s: &str, N: u32) -> Option<u64> {
+    let mask = if N == 64 { !0 } else { (1u64 << N) - 1 };
+    if s.starts_with('-') {
+        parse_sN(s, N).map(|n| n as u64 & mask)
+    } else {
+        let (_, rest) = split_sign(s);
+        parse_uN(rest, N)
+    }
+}
+
+// 6.3.2. Floating-Point
+
+trait FloatBits {
+    const MANT_BITS: u32;
+    const EXP_BITS: u32;
+}
+impl FloatBits for f32 {
+    const MANT_BITS: u32 = 23;
+    const EXP_BITS: u32 = 8;
+}
+impl FloatBits for f64 {
+    const MANT_BITS: u32 = 52;
+    const EXP_BITS: u32 = 11;
+}
+
+/// Rounds `m * 2^e` to the nearest float with the given layout,
+/// ties to even. `sticky` marks nonzero bits below `m` that did not fit.
+///
+/// Returns the bit pattern without sign, or `None` on overflow.
+fn round_to_bits(m: u64, sticky: bool, e: i64, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    if m == 0 {
+        return Some(0);
+    }
+
+    let M = mant_bits as i64;
+    let bias = (1i64 << (exp_bits - 1)) - 1;
+    let emin = 1 - bias;
+
+    // exponent of the leading bit
+    let msb = 63 - m.leading_zeros() as i64;
+    let exp = msb + e;
+
+    // weight of the last bit that fits into the mantissa
+    let lsb_exp = ::std::cmp::max(exp, emin) - M;
+    let shift = lsb_exp - e;
+
+    let mut lsb_exp = lsb_exp;
+    let mut mant = if shift <= 0 {
+        m << (-shift)
+    } else if shift > 64 {
+        // all of `m` is below half an ulp
+        0
+    } else {
+        let (kept, rem, half) = if shift == 64 {
+            (0, m, 1u64 << 63)
+        } else {
+            (m >> shift, m & ((1u64 << shift) - 1), 1u64 << (shift - 1))
+        };
+        let round_up = rem > half
+            || (rem == half && (sticky || kept & 1 == 1));
+        kept + round_up as u64
+    };
+
+    if mant >> (M + 1) != 0 {
+        mant >>= 1;
+        lsb_exp += 1;
+    }
+
+    if mant >> M != 0 {
+        let biased = lsb_exp + M + bias;
+        if biased >= (1 << exp_bits) - 1 {
+            return None;
+        }
+        Some(((biased as u64) << M) | (mant & ((1u64 << M) - 1)))
+    } else {
+        Some(mant)
+    }
+}
+
+fn parse_hexfloat(s: &str, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    let (mant, exp) = match s.find(|c| c == 'p' || c == 'P') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (int, frac) = match mant.find('.') {
+        Some(i) => (&mant[..i], &mant[i + 1..]),
+        None => (mant, ""),
+    };
+    if int.is_empty() {
+        return None;
+    }
+
+    let mut m: u64 = 0;
+    let mut e: i64 = 0;
+    let mut sticky = false;
+
+    let mut digit = |d: u32, is_frac: bool, m: &mut u64, e: &mut i64| {
+        if *m >> 60 == 0 {
+            *m = *m * 16 + d as u64;
+            if is_frac { *e -= 4; }
+        } else {
+            sticky |= d != 0;
+            if !is_frac { *e += 4; }
+        }
+    };
+
+    for c in strip_underscores(int)?.chars() {
+        digit(c.to_digit(16)?, false, &mut m, &mut e);
+    }
+    if !frac.is_empty() {
+        for c in strip_underscores(frac)?.chars() {
+            digit(c.to_digit(16)?, true, &mut m, &mut e);
+        }
+    }
+
+    if let Some(exp) = exp {
+        let (neg, digits) = split_sign(exp);
+        let digits = strip_underscores(digits)?;
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) {
+            return None;
+        }
+        // Saturate huge exponents, they over- or underflow either way
+        let p = digits.parse::<i64>().unwrap_or(1 << 32).min(1 << 32);
+        e += if neg { -p } else { p };
+    }
+
+    round_to_bits(m, sticky, e, mant_bits, exp_bits)
+}
+
+fn is_decfloat(s: &str) -> bool {
+    // num ('.' num?)? (('e' | 'E') sign? num)?
+    let (mant, exp) = match s.find(|c| c == 'e' || c == 'E') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (int, frac) = match mant.find('.') {
+        Some(i) => (&mant[..i], Some(&mant[i + 1..])),
+        None => (mant, None),
+    };
+    let num = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_digit(10));
+
+    num(int)
+        && frac.map(|f| f.is_empty() || num(f)).unwrap_or(true)
+        && exp.map(|e| num(split_sign(e).1)).unwrap_or(true)
+}
+
+fn parse_float<F, P>(s: &str, parse_dec: P) -> Option<u64>
+    where F: FloatBits,
+          P: Fn(&str) -> Option<u64>,
+{
+    let (neg, rest) = split_sign(s);
+    let sign = if neg { 1u64 << (F::MANT_BITS + F::EXP_BITS) } else { 0 };
+    let inf = ((1u64 << F::EXP_BITS) - 1) << F::MANT_BITS;
+
+    let bits = if rest == "inf" {
+        inf
+    } else if rest == "nan" {
+        // canonical NaN
+        inf | (1 << (F::MANT_BITS - 1))
+    } else if rest.starts_with("nan:0x") {
+        let payload = parse_nat(&rest[4..])?;
+        if payload == 0 || payload >> F::MANT_BITS != 0 {
+            return None;
+        }
+        inf | payload
+    } else if rest.starts_with("0x") {
+        parse_hexfloat(&rest[2..], F::MANT_BITS, F::EXP_BITS)?
+    } else {
+        let rest = strip_underscores(rest)?;
+        if !is_decfloat(&rest) {
+            return None;
+        }
+        parse_dec(&rest)?
+    };
+
+    Some(sign | bits)
+}
+
+/// Parses a `f32`.
+pub fn parse_f32(s: &str) -> Option<f32> {
+    parse_float::<f32, _>(s, |s| {
+        let z = s.parse::<f32>().ok()?;
+        if z.is_infinite() { None } else { Some(z.to_bits() as u64) }
+    }).map(|bits| f32::from_bits(bits as u32))
+}
+
+/// Parses a `f64`.
+pub fn parse_f64(s: &str) -> Option<f64> {
+    parse_float::<f64, _>(s, |s| {
+        let z = s.parse::<f64>().ok()?;
+        if z.is_infinite() { None } else { Some(z.to_bits()) }
+    }).map(f64::from_bits)
+}
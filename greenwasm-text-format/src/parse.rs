@@ -0,0 +1,1343 @@
+//! 6.4. Types
+//! 6.5. Instructions
+//! 6.6. Modules
+//!
+//! Turns the S-expressions produced by the lexer into a `Module`,
+//! resolving identifiers and expanding abbreviations on the way.
+
+use std::collections::HashMap;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+
+use super::lex::{sexprs, Sexpr, Token, TokenKind};
+use super::num::*;
+use super::{ParseError, ParseErrorKind, Pos};
+use super::ParseErrorKind::*;
+
+type PResult<T> = Result<T, ParseError>;
+
+fn err<T>(pos: Pos, kind: ParseErrorKind) -> PResult<T> {
+    Err(ParseError { pos, kind })
+}
+
+/// A cursor over the items of a list.
+#[derive(Clone)]
+struct Cursor<'a> {
+    items: &'a [Sexpr],
+    next: usize,
+    close: Pos,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(items: &'a [Sexpr], close: Pos) -> Self {
+        Cursor { items, next: 0, close }
+    }
+
+    fn peek(&self) -> Option<&'a Sexpr> {
+        self.items.get(self.next)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.next >= self.items.len()
+    }
+
+    /// Position of the next item, or of the closing paren
+    /// if there is none.
+    fn pos(&self) -> Pos {
+        self.peek().map(|s| s.pos()).unwrap_or(self.close)
+    }
+
+    fn next(&mut self) -> Option<&'a Sexpr> {
+        let s = self.peek();
+        if s.is_some() {
+            self.next += 1;
+        }
+        s
+    }
+
+    fn peek_atom(&self) -> Option<&'a Token> {
+        match self.peek() {
+            Some(&Sexpr::Atom(ref t)) => Some(t),
+            _ => None,
+        }
+    }
+
+    fn peek_keyword(&self) -> Option<&'a str> {
+        match self.peek_atom() {
+            Some(&Token { kind: TokenKind::Keyword(ref k), .. }) => Some(k),
+            _ => None,
+        }
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        if self.peek_keyword() == Some(kw) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &'static str) -> PResult<()> {
+        if self.keyword(kw) {
+            Ok(())
+        } else {
+            err(self.pos(), Expected(kw))
+        }
+    }
+
+    fn any_keyword(&mut self) -> PResult<(&'a str, Pos)> {
+        let pos = self.pos();
+        match self.peek_keyword() {
+            Some(k) => {
+                self.next += 1;
+                Ok((k, pos))
+            }
+            None => err(pos, Expected("a keyword")),
+        }
+    }
+
+    fn id(&mut self) -> Option<(&'a str, Pos)> {
+        match self.peek_atom() {
+            Some(&Token { kind: TokenKind::Id(ref id), pos }) => {
+                self.next += 1;
+                Some((id, pos))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_list(&self, kw: &str) -> bool {
+        match self.peek() {
+            Some(&Sexpr::List(ref items, _, _)) => match items.first() {
+                Some(&Sexpr::Atom(Token { kind: TokenKind::Keyword(ref k), .. })) => k == kw,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Consumes the next item if it is a list starting with `kw`,
+    /// and returns a cursor positioned after the keyword.
+    fn list(&mut self, kw: &str) -> Option<Cursor<'a>> {
+        if self.is_list(kw) {
+            let mut c = self.any_list().unwrap();
+            c.next = 1;
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn any_list(&mut self) -> Option<Cursor<'a>> {
+        match self.peek() {
+            Some(&Sexpr::List(ref items, _, close)) => {
+                self.next += 1;
+                Some(Cursor::new(items, close))
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_list(&mut self, kw: &'static str) -> PResult<Cursor<'a>> {
+        match self.list(kw) {
+            Some(c) => Ok(c),
+            None => err(self.pos(), Expected(kw)),
+        }
+    }
+
+    fn string(&mut self) -> PResult<&'a [u8]> {
+        match self.peek_atom() {
+            Some(&Token { kind: TokenKind::Str(ref s), .. }) => {
+                self.next += 1;
+                Ok(s)
+            }
+            _ => err(self.pos(), Expected("a string")),
+        }
+    }
+
+    // 6.3.4. Names
+    fn name(&mut self) -> PResult<Name> {
+        let pos = self.pos();
+        let s = self.string()?;
+        match ::std::str::from_utf8(s) {
+            Ok(s) => Ok(s.into()),
+            Err(_) => err(pos, InvalidUtf8),
+        }
+    }
+
+    /// A number token. Float keywords like `inf` and `nan`
+    /// are accepted as well, the caller decides how to interpret it.
+    fn num(&mut self) -> PResult<(&'a str, Pos)> {
+        let pos = self.pos();
+        match self.peek_atom() {
+            Some(&Token { kind: TokenKind::Num(ref s), .. })
+            | Some(&Token { kind: TokenKind::Keyword(ref s), .. }) => {
+                self.next += 1;
+                Ok((s, pos))
+            }
+            _ => err(pos, Expected("a number")),
+        }
+    }
+
+    fn is_num(&self) -> bool {
+        match self.peek_atom() {
+            Some(&Token { kind: TokenKind::Num(_), .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn u32(&mut self) -> PResult<u32> {
+        if !self.is_num() {
+            return err(self.pos(), Expected("a number"));
+        }
+        let (s, pos) = self.num()?;
+        match parse_uN(s, 32) {
+            Some(n) => Ok(n as u32),
+            None => err(pos, InvalidNumber(s.to_owned())),
+        }
+    }
+
+    fn finish(&self) -> PResult<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            err(self.pos(), Expected("`)`"))
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Space {
+    Type,
+    Func,
+    Table,
+    Mem,
+    Global,
+}
+
+/// Identifier context for a single function body.
+#[derive(Default)]
+struct FuncCtx {
+    locals: HashMap<String, u32>,
+    labels: Vec<Option<String>>,
+}
+
+struct ModuleParser {
+    ids: HashMap<(u8, String), u32>,
+
+    types: Vec<FuncType>,
+    funcs: Vec<Func>,
+    tables: Vec<Table>,
+    mems: Vec<Mem>,
+    globals: Vec<Global>,
+    elem: Vec<Elem>,
+    data: Vec<Data>,
+    start: Option<Start>,
+    imports: Vec<Import>,
+    exports: Vec<Export>,
+}
+
+// 6.4. Types
+
+fn valtype(c: &mut Cursor) -> PResult<ValType> {
+    let pos = c.pos();
+    let t = match c.peek_keyword() {
+        Some("i32") => ValType::I32,
+        Some("i64") => ValType::I64,
+        Some("f32") => ValType::F32,
+        Some("f64") => ValType::F64,
+        _ => return err(pos, Expected("a value type")),
+    };
+    c.next();
+    Ok(t)
+}
+
+fn resulttype(c: &mut Cursor) -> PResult<ResultType> {
+    let mut results = vec![];
+    while let Some(mut r) = c.list("result") {
+        while !r.is_empty() {
+            results.push((valtype(&mut r)?, r.pos()));
+        }
+    }
+    match results.len() {
+        0 => Ok(None.into()),
+        1 => Ok(results[0].0.into()),
+        _ => err(results[1].1, Expected("at most one result type")),
+    }
+}
+
+/// Parses `(param ...)* (result ...)*`, returning
+/// the parameter types with their ids and the result types.
+fn params_results<'a>(c: &mut Cursor<'a>)
+    -> PResult<(Vec<ValType>, Vec<Option<(&'a str, Pos)>>, Vec<ValType>)>
+{
+    let mut args = vec![];
+    let mut names = vec![];
+    let mut results = vec![];
+
+    while let Some(mut p) = c.list("param") {
+        if let Some(id) = p.id() {
+            args.push(valtype(&mut p)?);
+            names.push(Some(id));
+            p.finish()?;
+        } else {
+            while !p.is_empty() {
+                args.push(valtype(&mut p)?);
+                names.push(None);
+            }
+        }
+    }
+    while let Some(mut r) = c.list("result") {
+        while !r.is_empty() {
+            results.push(valtype(&mut r)?);
+        }
+    }
+
+    Ok((args, names, results))
+}
+
+fn functype(c: &mut Cursor) -> PResult<FuncType> {
+    let mut f = c.expect_list("func")?;
+    let (args, _, results) = params_results(&mut f)?;
+    f.finish()?;
+    Ok(FuncType { args: args.into(), results: results.into() })
+}
+
+fn limits(c: &mut Cursor) -> PResult<Limits> {
+    let min = c.u32()?;
+    let max = if c.is_num() { Some(c.u32()?) } else { None };
+    Ok(Limits { min, max })
+}
+
+fn elemtype(c: &mut Cursor) -> PResult<ElemType> {
+    if c.keyword("anyfunc") || c.keyword("funcref") {
+        Ok(ElemType::AnyFunc)
+    } else {
+        err(c.pos(), Expected("`anyfunc`"))
+    }
+}
+
+fn tabletype(c: &mut Cursor) -> PResult<TableType> {
+    let limits = limits(c)?;
+    let elemtype = elemtype(c)?;
+    Ok(TableType { limits, elemtype })
+}
+
+fn memtype(c: &mut Cursor) -> PResult<MemType> {
+    Ok(MemType { limits: limits(c)? })
+}
+
+fn globaltype(c: &mut Cursor) -> PResult<GlobalType> {
+    if let Some(mut m) = c.list("mut") {
+        let valtype = valtype(&mut m)?;
+        m.finish()?;
+        Ok(GlobalType { mutability: Mut::Var, valtype })
+    } else {
+        Ok(GlobalType { mutability: Mut::Const, valtype: valtype(c)? })
+    }
+}
+
+// 6.5.6. Numeric Instructions
+
+fn const_int(c: &mut Cursor, N: u32) -> PResult<u64> {
+    let (s, pos) = c.num()?;
+    match parse_iN(s, N) {
+        Some(n) => Ok(n),
+        None => err(pos, InvalidNumber(s.to_owned())),
+    }
+}
+
+fn const_f32(c: &mut Cursor) -> PResult<f32> {
+    let (s, pos) = c.num()?;
+    match parse_f32(s) {
+        Some(z) => Ok(z),
+        None => err(pos, InvalidNumber(s.to_owned())),
+    }
+}
+
+fn const_f64(c: &mut Cursor) -> PResult<f64> {
+    let (s, pos) = c.num()?;
+    match parse_f64(s) {
+        Some(z) => Ok(z),
+        None => err(pos, InvalidNumber(s.to_owned())),
+    }
+}
+
+// 6.5.5. Memory Instructions
+
+/// Parses `offset=n? align=m?`, defaulting the alignment to
+/// the natural alignment of the instruction, given as exponent.
+fn memarg(c: &mut Cursor, natural: u32) -> PResult<Memarg> {
+    let mut offset = 0;
+    let mut align = natural;
+
+    if let Some(k) = c.peek_keyword().filter(|k| k.starts_with("offset=")) {
+        let pos = c.pos();
+        c.next();
+        offset = match parse_uN(&k[7..], 32) {
+            Some(n) => n as u32,
+            None => return err(pos, InvalidNumber(k[7..].to_owned())),
+        };
+    }
+    if let Some(k) = c.peek_keyword().filter(|k| k.starts_with("align=")) {
+        let pos = c.pos();
+        c.next();
+        let n = match parse_uN(&k[6..], 32) {
+            Some(n) => n as u32,
+            None => return err(pos, InvalidNumber(k[6..].to_owned())),
+        };
+        if !n.is_power_of_two() {
+            return err(pos, InvalidAlignment);
+        }
+        align = n.trailing_zeros();
+    }
+
+    Ok(Memarg { offset, align })
+}
+
+/// Instructions without immediates.
+fn plain_instr(name: &str) -> Option<Instr> {
+    use self::Instr::*;
+
+    // NB: Both the current instruction names and the ones
+    // used before their renaming are accepted.
+    Some(match name {
+        "unreachable" => Unreachable,
+        "nop" => Nop,
+        "return" => Return,
+
+        "drop" => Drop,
+        "select" => Select,
+
+        "memory.size" | "current_memory" => CurrentMemory,
+        "memory.grow" | "grow_memory" => GrowMemory,
+
+        "i32.clz" => I32Clz,
+        "i32.ctz" => I32Ctz,
+        "i32.popcnt" => I32Popcnt,
+        "i64.clz" => I64Clz,
+        "i64.ctz" => I64Ctz,
+        "i64.popcnt" => I64Popcnt,
+
+        "f32.abs" => F32Abs,
+        "f32.neg" => F32Neg,
+        "f32.sqrt" => F32Sqrt,
+        "f32.ceil" => F32Ceil,
+        "f32.floor" => F32Floor,
+        "f32.trunc" => F32Trunc,
+        "f32.nearest" => F32Nearest,
+
+        "f64.abs" => F64Abs,
+        "f64.neg" => F64Neg,
+        "f64.sqrt" => F64Sqrt,
+        "f64.ceil" => F64Ceil,
+        "f64.floor" => F64Floor,
+        "f64.trunc" => F64Trunc,
+        "f64.nearest" => F64Nearest,
+
+        "i32.add" => I32Add,
+        "i32.sub" => I32Sub,
+        "i32.mul" => I32Mul,
+        "i32.div_u" => I32DivU,
+        "i32.div_s" => I32DivS,
+        "i32.rem_u" => I32RemU,
+        "i32.rem_s" => I32RemS,
+        "i32.and" => I32And,
+        "i32.or" => I32Or,
+        "i32.xor" => I32Xor,
+        "i32.shl" => I32Shl,
+        "i32.shr_u" => I32ShrU,
+        "i32.shr_s" => I32ShrS,
+        "i32.rotl" => I32Rotl,
+        "i32.rotr" => I32Rotr,
+
+        "i64.add" => I64Add,
+        "i64.sub" => I64Sub,
+        "i64.mul" => I64Mul,
+        "i64.div_u" => I64DivU,
+        "i64.div_s" => I64DivS,
+        "i64.rem_u" => I64RemU,
+        "i64.rem_s" => I64RemS,
+        "i64.and" => I64And,
+        "i64.or" => I64Or,
+        "i64.xor" => I64Xor,
+        "i64.shl" => I64Shl,
+        "i64.shr_u" => I64ShrU,
+        "i64.shr_s" => I64ShrS,
+        "i64.rotl" => I64Rotl,
+        "i64.rotr" => I64Rotr,
+
+        "f32.add" => F32Add,
+        "f32.sub" => F32Sub,
+        "f32.mul" => F32Mul,
+        "f32.div" => F32Div,
+        "f32.min" => F32Min,
+        "f32.max" => F32Max,
+        "f32.copysign" => F32CopySign,
+
+        "f64.add" => F64Add,
+        "f64.sub" => F64Sub,
+        "f64.mul" => F64Mul,
+        "f64.div" => F64Div,
+        "f64.min" => F64Min,
+        "f64.max" => F64Max,
+        "f64.copysign" => F64CopySign,
+
+        "i32.eqz" => I32EqZ,
+        "i64.eqz" => I64EqZ,
+
+        "i32.eq" => I32Eq,
+        "i32.ne" => I32Ne,
+        "i32.lt_u" => I32LtU,
+        "i32.lt_s" => I32LtS,
+        "i32.gt_u" => I32GtU,
+        "i32.gt_s" => I32GtS,
+        "i32.le_u" => I32LeU,
+        "i32.le_s" => I32LeS,
+        "i32.ge_u" => I32GeU,
+        "i32.ge_s" => I32GeS,
+
+        "i64.eq" => I64Eq,
+        "i64.ne" => I64Ne,
+        "i64.lt_u" => I64LtU,
+        "i64.lt_s" => I64LtS,
+        "i64.gt_u" => I64GtU,
+        "i64.gt_s" => I64GtS,
+        "i64.le_u" => I64LeU,
+        "i64.le_s" => I64LeS,
+        "i64.ge_u" => I64GeU,
+        "i64.ge_s" => I64GeS,
+
+        "f32.eq" => F32Eq,
+        "f32.ne" => F32Ne,
+        "f32.lt" => F32Lt,
+        "f32.gt" => F32Gt,
+        "f32.le" => F32Le,
+        "f32.ge" => F32Ge,
+
+        "f64.eq" => F64Eq,
+        "f64.ne" => F64Ne,
+        "f64.lt" => F64Lt,
+        "f64.gt" => F64Gt,
+        "f64.le" => F64Le,
+        "f64.ge" => F64Ge,
+
+        "i32.wrap_i64" | "i32.wrap/i64" => I32WrapI64,
+        "i64.extend_i32_u" | "i64.extend_u/i32" => I64ExtendUI32,
+        "i64.extend_i32_s" | "i64.extend_s/i32" => I64ExtendSI32,
+
+        "i32.trunc_f32_u" | "i32.trunc_u/f32" => I32TruncUF32,
+        "i32.trunc_f64_u" | "i32.trunc_u/f64" => I32TruncUF64,
+        "i32.trunc_f32_s" | "i32.trunc_s/f32" => I32TruncSF32,
+        "i32.trunc_f64_s" | "i32.trunc_s/f64" => I32TruncSF64,
+        "i64.trunc_f32_u" | "i64.trunc_u/f32" => I64TruncUF32,
+        "i64.trunc_f64_u" | "i64.trunc_u/f64" => I64TruncUF64,
+        "i64.trunc_f32_s" | "i64.trunc_s/f32" => I64TruncSF32,
+        "i64.trunc_f64_s" | "i64.trunc_s/f64" => I64TruncSF64,
+
+        "f32.demote_f64" | "f32.demote/f64" => F32DemoteF64,
+        "f64.promote_f32" | "f64.promote/f32" => F64PromoteF32,
+
+        "f32.convert_i32_u" | "f32.convert_u/i32" => F32ConvertUI32,
+        "f64.convert_i32_u" | "f64.convert_u/i32" => F64ConvertUI32,
+        "f32.convert_i32_s" | "f32.convert_s/i32" => F32ConvertSI32,
+        "f64.convert_i32_s" | "f64.convert_s/i32" => F64ConvertSI32,
+        "f32.convert_i64_u" | "f32.convert_u/i64" => F32ConvertUI64,
+        "f64.convert_i64_u" | "f64.convert_u/i64" => F64ConvertUI64,
+        "f32.convert_i64_s" | "f32.convert_s/i64" => F32ConvertSI64,
+        "f64.convert_i64_s" | "f64.convert_s/i64" => F64ConvertSI64,
+
+        "i32.reinterpret_f32" | "i32.reinterpret/f32" => I32ReinterpretF32,
+        "i64.reinterpret_f64" | "i64.reinterpret/f64" => I64ReinterpretF64,
+        "f32.reinterpret_i32" | "f32.reinterpret/i32" => F32ReinterpretI32,
+        "f64.reinterpret_i64" | "f64.reinterpret/i64" => F64ReinterpretI64,
+
+        "i32.extend8_s" => I32Extend8S,
+        "i32.extend16_s" => I32Extend16S,
+        "i64.extend8_s" => I64Extend8S,
+        "i64.extend16_s" => I64Extend16S,
+        "i64.extend32_s" => I64Extend32S,
+
+        _ => return None,
+    })
+}
+
+/// Memory instructions, with the exponent of their natural alignment.
+fn memory_instr(name: &str) -> Option<(fn(Memarg) -> Instr, u32)> {
+    use self::Instr::*;
+
+    Some(match name {
+        "i32.load" => (I32Load, 2),
+        "i64.load" => (I64Load, 3),
+        "f32.load" => (F32Load, 2),
+        "f64.load" => (F64Load, 3),
+
+        "i32.store" => (I32Store, 2),
+        "i64.store" => (I64Store, 3),
+        "f32.store" => (F32Store, 2),
+        "f64.store" => (F64Store, 3),
+
+        "i32.load8_u" => (I32Load8U, 0),
+        "i32.load8_s" => (I32Load8S, 0),
+        "i64.load8_u" => (I64Load8U, 0),
+        "i64.load8_s" => (I64Load8S, 0),
+
+        "i32.load16_u" => (I32Load16U, 1),
+        "i32.load16_s" => (I32Load16S, 1),
+        "i64.load16_u" => (I64Load16U, 1),
+        "i64.load16_s" => (I64Load16S, 1),
+
+        "i64.load32_u" => (I64Load32U, 2),
+        "i64.load32_s" => (I64Load32S, 2),
+
+        "i32.store8" => (I32Store8, 0),
+        "i64.store8" => (I64Store8, 0),
+        "i32.store16" => (I32Store16, 1),
+        "i64.store16" => (I64Store16, 1),
+        "i64.store32" => (I64Store32, 2),
+
+        _ => return None,
+    })
+}
+
+impl ModuleParser {
+    fn new() -> Self {
+        ModuleParser {
+            ids: HashMap::new(),
+
+            types: vec![],
+            funcs: vec![],
+            tables: vec![],
+            mems: vec![],
+            globals: vec![],
+            elem: vec![],
+            data: vec![],
+            start: None,
+            imports: vec![],
+            exports: vec![],
+        }
+    }
+
+    fn bind(&mut self, space: Space, id: Option<(&str, Pos)>, idx: u32) -> PResult<()> {
+        if let Some((id, pos)) = id {
+            let key = (space as u8, id.to_owned());
+            if self.ids.contains_key(&key) {
+                return err(pos, DuplicateId(id.to_owned()));
+            }
+            self.ids.insert(key, idx);
+        }
+        Ok(())
+    }
+
+    fn idx(&self, c: &mut Cursor, space: Space) -> PResult<u32> {
+        if let Some((id, pos)) = c.id() {
+            match self.ids.get(&(space as u8, id.to_owned())) {
+                Some(&idx) => Ok(idx),
+                None => err(pos, UnknownId(id.to_owned())),
+            }
+        } else if c.is_num() {
+            c.u32()
+        } else {
+            err(c.pos(), Expected("an index"))
+        }
+    }
+
+    fn is_idx(c: &Cursor) -> bool {
+        match c.peek_atom() {
+            Some(&Token { kind: TokenKind::Num(_), .. }) => true,
+            Some(&Token { kind: TokenKind::Id(_), .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// First pass over the module fields: Collects the explicitly defined
+    /// types and binds the ids of all index spaces, so that they
+    /// can be referred to before their definition.
+    fn bind_ids(&mut self, fields: &[Cursor]) -> PResult<()> {
+        let mut counts = [0u32; 5];
+        let mut seen_definition = false;
+
+        for field in fields {
+            let mut c = field.clone();
+            let (kw, pos) = c.any_keyword()?;
+
+            let space = match kw {
+                "type" => {
+                    let id = c.id();
+                    let ft = functype(&mut c)?;
+                    c.finish()?;
+                    let idx = self.types.len() as u32;
+                    self.bind(Space::Type, id, idx)?;
+                    self.types.push(ft);
+                    continue;
+                }
+                "import" => {
+                    c.string()?;
+                    c.string()?;
+                    let mut desc = match c.any_list() {
+                        Some(desc) => desc,
+                        None => return err(c.pos(), Expected("an import description")),
+                    };
+                    let space = match desc.any_keyword()?.0 {
+                        "func" => Space::Func,
+                        "table" => Space::Table,
+                        "memory" => Space::Mem,
+                        "global" => Space::Global,
+                        _ => return err(desc.pos(), Expected("an import description")),
+                    };
+                    if seen_definition {
+                        return err(pos, ImportAfterDefinition);
+                    }
+                    c = desc;
+                    space
+                }
+                "func" | "table" | "memory" | "global" => {
+                    let space = match kw {
+                        "func" => Space::Func,
+                        "table" => Space::Table,
+                        "memory" => Space::Mem,
+                        _ => Space::Global,
+                    };
+
+                    let mut rest = c.clone();
+                    rest.id();
+                    while rest.list("export").is_some() {}
+                    if rest.is_list("import") {
+                        if seen_definition {
+                            return err(pos, ImportAfterDefinition);
+                        }
+                    } else {
+                        seen_definition = true;
+                    }
+                    space
+                }
+                _ => continue,
+            };
+
+            let id = c.id();
+            let idx = counts[space as usize];
+            self.bind(space, id, idx)?;
+            counts[space as usize] += 1;
+        }
+
+        Ok(())
+    }
+
+    // 6.6.3. Type Uses
+
+    /// Parses a typeuse, returning its type index and
+    /// the ids of its parameters.
+    fn typeuse<'a>(&mut self, c: &mut Cursor<'a>) -> PResult<(TypeIdx, Vec<Option<(&'a str, Pos)>>)> {
+        let pos = c.pos();
+        let explicit = if let Some(mut t) = c.list("type") {
+            let x = self.idx(&mut t, Space::Type)?;
+            t.finish()?;
+            Some(x)
+        } else {
+            None
+        };
+        let (args, mut names, results) = params_results(c)?;
+        let ft = FuncType { args: args.into(), results: results.into() };
+
+        let x = match explicit {
+            Some(x) => {
+                let inline = !ft.args.is_empty() || !ft.results.is_empty();
+                match self.types.get(x as usize) {
+                    Some(ty) => {
+                        if inline && *ty != ft {
+                            return err(pos, InlineTypeMismatch);
+                        }
+                        names.resize(ty.args.len(), None);
+                    }
+                    // NB: Out of range indices are left to validation,
+                    // but there is nothing to compare the inline type with.
+                    None if inline => return err(pos, InlineTypeMismatch),
+                    None => (),
+                }
+                x
+            }
+            None => {
+                match self.types.iter().position(|ty| *ty == ft) {
+                    Some(x) => x as u32,
+                    None => {
+                        self.types.push(ft);
+                        (self.types.len() - 1) as u32
+                    }
+                }
+            }
+        };
+
+        Ok((TypeIdx(x), names))
+    }
+
+    /// Parses the inline `(export "name")*` abbreviation.
+    fn inline_exports(&mut self, c: &mut Cursor, desc: ExportDesc) -> PResult<()> {
+        let mut descs = vec![];
+        while let Some(mut e) = c.list("export") {
+            let name = e.name()?;
+            e.finish()?;
+            descs.push(name);
+        }
+        for name in descs {
+            let desc = match desc {
+                ExportDesc::Func(x) => ExportDesc::Func(x),
+                ExportDesc::Table(x) => ExportDesc::Table(x),
+                ExportDesc::Mem(x) => ExportDesc::Mem(x),
+                ExportDesc::Global(x) => ExportDesc::Global(x),
+            };
+            self.exports.push(Export { name, desc });
+        }
+        Ok(())
+    }
+
+    /// Parses the inline `(import "module" "name")` abbreviation.
+    fn inline_import(c: &mut Cursor) -> PResult<Option<(Name, Name)>> {
+        if let Some(mut i) = c.list("import") {
+            let module = i.name()?;
+            let name = i.name()?;
+            i.finish()?;
+            Ok(Some((module, name)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn num_imports(&self, f: fn(&ImportDesc) -> bool) -> u32 {
+        self.imports.iter().filter(|i| f(&i.desc)).count() as u32
+    }
+
+    fn next_funcidx(&self) -> u32 {
+        self.num_imports(|d| if let ImportDesc::Func(_) = *d { true } else { false })
+            + self.funcs.len() as u32
+    }
+    fn next_tableidx(&self) -> u32 {
+        self.num_imports(|d| if let ImportDesc::Table(_) = *d { true } else { false })
+            + self.tables.len() as u32
+    }
+    fn next_memidx(&self) -> u32 {
+        self.num_imports(|d| if let ImportDesc::Mem(_) = *d { true } else { false })
+            + self.mems.len() as u32
+    }
+    fn next_globalidx(&self) -> u32 {
+        self.num_imports(|d| if let ImportDesc::Global(_) = *d { true } else { false })
+            + self.globals.len() as u32
+    }
+
+    // 6.5. Instructions
+
+    fn label(&self, c: &mut Cursor, ctx: &FuncCtx) -> PResult<LabelIdx> {
+        if let Some((id, pos)) = c.id() {
+            let depth = ctx.labels.iter().rev().position(|l| {
+                l.as_ref().map(|l| l == id).unwrap_or(false)
+            });
+            match depth {
+                Some(l) => Ok(LabelIdx(l as u32)),
+                None => err(pos, UnknownId(id.to_owned())),
+            }
+        } else if c.is_num() {
+            Ok(LabelIdx(c.u32()?))
+        } else {
+            err(c.pos(), Expected("a label"))
+        }
+    }
+
+    fn localidx(&self, c: &mut Cursor, ctx: &FuncCtx) -> PResult<LocalIdx> {
+        if let Some((id, pos)) = c.id() {
+            match ctx.locals.get(id) {
+                Some(&x) => Ok(LocalIdx(x)),
+                None => err(pos, UnknownId(id.to_owned())),
+            }
+        } else if c.is_num() {
+            Ok(LocalIdx(c.u32()?))
+        } else {
+            err(c.pos(), Expected("a local index"))
+        }
+    }
+
+    /// Checks the optional label id repeated after `else` or `end`.
+    fn trailing_label(c: &mut Cursor, label: &Option<(&str, Pos)>) -> PResult<()> {
+        if let Some((id, pos)) = c.id() {
+            match *label {
+                Some((l, _)) if l == id => (),
+                _ => return err(pos, MismatchedLabel(id.to_owned())),
+            }
+        }
+        Ok(())
+    }
+
+    fn push_label(ctx: &mut FuncCtx, label: Option<(&str, Pos)>) {
+        ctx.labels.push(label.map(|(l, _)| l.to_owned()));
+    }
+
+    /// Parses a sequence of instructions in both flat and folded form.
+    /// Stops at the end of the list or at an `else` or `end` keyword.
+    fn instrs(&mut self, c: &mut Cursor, ctx: &mut FuncCtx, out: &mut Vec<Instr>) -> PResult<()> {
+        loop {
+            if let Some(mut folded) = c.any_list() {
+                self.folded_instr(&mut folded, ctx, out)?;
+                continue;
+            }
+
+            let (kw, pos) = match c.peek_keyword() {
+                None => break,
+                Some("end") | Some("else") => break,
+                Some(_) => c.any_keyword()?,
+            };
+
+            match kw {
+                "block" | "loop" => {
+                    let label = c.id();
+                    let rt = resulttype(c)?;
+                    Self::push_label(ctx, label);
+                    let mut body = vec![];
+                    self.instrs(c, ctx, &mut body)?;
+                    c.expect_keyword("end")?;
+                    Self::trailing_label(c, &label)?;
+                    ctx.labels.pop();
+
+                    out.push(if kw == "block" {
+                        Instr::Block(rt, body)
+                    } else {
+                        Instr::Loop(rt, body)
+                    });
+                }
+                "if" => {
+                    let label = c.id();
+                    let rt = resulttype(c)?;
+                    Self::push_label(ctx, label);
+                    let mut then = vec![];
+                    let mut else_ = vec![];
+                    self.instrs(c, ctx, &mut then)?;
+                    if c.keyword("else") {
+                        Self::trailing_label(c, &label)?;
+                        self.instrs(c, ctx, &mut else_)?;
+                    }
+                    c.expect_keyword("end")?;
+                    Self::trailing_label(c, &label)?;
+                    ctx.labels.pop();
+
+                    out.push(Instr::IfElse(rt, then, else_));
+                }
+                _ => {
+                    let instr = self.plain(kw, pos, c, ctx)?;
+                    out.push(instr);
+                }
+            }
+        }
+        if c.peek().is_some() && c.peek_keyword().is_none() {
+            return err(c.pos(), Expected("an instruction"));
+        }
+        Ok(())
+    }
+
+    // 6.5.8. Folded Instructions
+    fn folded_instr(&mut self, c: &mut Cursor, ctx: &mut FuncCtx, out: &mut Vec<Instr>) -> PResult<()> {
+        let (kw, pos) = c.any_keyword()?;
+        match kw {
+            "block" | "loop" => {
+                let label = c.id();
+                let rt = resulttype(c)?;
+                Self::push_label(ctx, label);
+                let mut body = vec![];
+                self.instrs(c, ctx, &mut body)?;
+                c.finish()?;
+                ctx.labels.pop();
+
+                out.push(if kw == "block" {
+                    Instr::Block(rt, body)
+                } else {
+                    Instr::Loop(rt, body)
+                });
+            }
+            "if" => {
+                let label = c.id();
+                let rt = resulttype(c)?;
+                while !c.is_list("then") {
+                    match c.any_list() {
+                        Some(mut cond) => self.folded_instr(&mut cond, ctx, out)?,
+                        None => return err(c.pos(), Expected("`then`")),
+                    }
+                }
+
+                Self::push_label(ctx, label);
+                let mut then = vec![];
+                let mut else_ = vec![];
+                let mut t = c.expect_list("then")?;
+                self.instrs(&mut t, ctx, &mut then)?;
+                t.finish()?;
+                if let Some(mut e) = c.list("else") {
+                    self.instrs(&mut e, ctx, &mut else_)?;
+                    e.finish()?;
+                }
+                c.finish()?;
+                ctx.labels.pop();
+
+                out.push(Instr::IfElse(rt, then, else_));
+            }
+            _ => {
+                let instr = self.plain(kw, pos, c, ctx)?;
+                while let Some(mut operand) = c.any_list() {
+                    self.folded_instr(&mut operand, ctx, out)?;
+                }
+                c.finish()?;
+                out.push(instr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses an instruction other than `block`, `loop` and `if`.
+    fn plain(&mut self, kw: &str, pos: Pos, c: &mut Cursor, ctx: &mut FuncCtx) -> PResult<Instr> {
+        if let Some(instr) = plain_instr(kw) {
+            return Ok(instr);
+        }
+        if let Some((instr, natural)) = memory_instr(kw) {
+            return Ok(instr(memarg(c, natural)?));
+        }
+
+        Ok(match kw {
+            "br" => Instr::Br(self.label(c, ctx)?),
+            "br_if" => Instr::BrIf(self.label(c, ctx)?),
+            "br_table" => {
+                let mut labels = vec![self.label(c, ctx)?];
+                while Self::is_idx(c) {
+                    labels.push(self.label(c, ctx)?);
+                }
+                let default = labels.pop().unwrap();
+                Instr::BrTable(labels.into(), default)
+            }
+            "call" => Instr::Call(FuncIdx(self.idx(c, Space::Func)?)),
+            "call_indirect" => {
+                let (x, names) = self.typeuse(c)?;
+                if let Some(&Some((_, pos))) = names.iter().find(|n| n.is_some()) {
+                    return err(pos, Expected("a parameter without id"));
+                }
+                Instr::CallIndirect(x)
+            }
+
+            "local.get" | "get_local" => Instr::GetLocal(self.localidx(c, ctx)?),
+            "local.set" | "set_local" => Instr::SetLocal(self.localidx(c, ctx)?),
+            "local.tee" | "tee_local" => Instr::TeeLocal(self.localidx(c, ctx)?),
+            "global.get" | "get_global" => Instr::GetGlobal(GlobalIdx(self.idx(c, Space::Global)?)),
+            "global.set" | "set_global" => Instr::SetGlobal(GlobalIdx(self.idx(c, Space::Global)?)),
+
+            "i32.const" => Instr::I32Const(const_int(c, 32)? as u32),
+            "i64.const" => Instr::I64Const(const_int(c, 64)?),
+            "f32.const" => Instr::F32Const(const_f32(c)?),
+            "f64.const" => Instr::F64Const(const_f64(c)?),
+
+            _ => return err(pos, UnknownInstruction(kw.to_owned())),
+        })
+    }
+
+    fn expr(&mut self, c: &mut Cursor, ctx: &mut FuncCtx) -> PResult<Expr> {
+        let mut body = vec![];
+        self.instrs(c, ctx, &mut body)?;
+        c.finish()?;
+        Ok(Expr { body })
+    }
+
+    /// Parses an `(offset expr)`, or a single folded
+    /// instruction as its abbreviation.
+    fn offset(&mut self, c: &mut Cursor) -> PResult<Expr> {
+        let mut ctx = FuncCtx::default();
+        if let Some(mut o) = c.list("offset") {
+            self.expr(&mut o, &mut ctx)
+        } else if let Some(mut instr) = c.any_list() {
+            let mut body = vec![];
+            self.folded_instr(&mut instr, &mut ctx, &mut body)?;
+            Ok(Expr { body })
+        } else {
+            err(c.pos(), Expected("an offset expression"))
+        }
+    }
+
+    // 6.6. Modules
+
+    fn field(&mut self, mut c: Cursor) -> PResult<()> {
+        let (kw, pos) = c.any_keyword()?;
+        match kw {
+            // Already handled in `bind_ids()`
+            "type" => (),
+
+            // 6.6.5. Imports
+            "import" => {
+                let module = c.name()?;
+                let name = c.name()?;
+                let mut d = c.any_list().unwrap();
+                let (kind, _) = d.any_keyword()?;
+                d.id();
+                let desc = match kind {
+                    "func" => ImportDesc::Func(self.typeuse(&mut d)?.0),
+                    "table" => ImportDesc::Table(tabletype(&mut d)?),
+                    "memory" => ImportDesc::Mem(memtype(&mut d)?),
+                    _ => ImportDesc::Global(globaltype(&mut d)?),
+                };
+                d.finish()?;
+                c.finish()?;
+                self.imports.push(Import { module, name, desc });
+            }
+
+            // 6.6.6. Functions
+            "func" => {
+                c.id();
+                let x = FuncIdx(self.next_funcidx());
+                self.inline_exports(&mut c, ExportDesc::Func(x))?;
+                if let Some((module, name)) = Self::inline_import(&mut c)? {
+                    let (type_, _) = self.typeuse(&mut c)?;
+                    c.finish()?;
+                    let desc = ImportDesc::Func(type_);
+                    self.imports.push(Import { module, name, desc });
+                    return Ok(());
+                }
+
+                let (type_, params) = self.typeuse(&mut c)?;
+                let mut ctx = FuncCtx::default();
+                let mut index = 0;
+                let mut bind_local = |ctx: &mut FuncCtx, id: Option<(&str, Pos)>| {
+                    if let Some((id, pos)) = id {
+                        if ctx.locals.insert(id.to_owned(), index).is_some() {
+                            return err(pos, DuplicateId(id.to_owned()));
+                        }
+                    }
+                    index += 1;
+                    Ok(())
+                };
+                for id in params {
+                    bind_local(&mut ctx, id)?;
+                }
+
+                let mut locals = vec![];
+                while let Some(mut l) = c.list("local") {
+                    if let Some(id) = l.id() {
+                        locals.push(valtype(&mut l)?);
+                        bind_local(&mut ctx, Some(id))?;
+                        l.finish()?;
+                    } else {
+                        while !l.is_empty() {
+                            locals.push(valtype(&mut l)?);
+                            bind_local(&mut ctx, None)?;
+                        }
+                    }
+                }
+
+                let body = self.expr(&mut c, &mut ctx)?;
+                self.funcs.push(Func { type_, locals: locals.into(), body });
+            }
+
+            // 6.6.7. Tables
+            "table" => {
+                c.id();
+                let x = TableIdx(self.next_tableidx());
+                self.inline_exports(&mut c, ExportDesc::Table(x))?;
+                if let Some((module, name)) = Self::inline_import(&mut c)? {
+                    let desc = ImportDesc::Table(tabletype(&mut c)?);
+                    c.finish()?;
+                    self.imports.push(Import { module, name, desc });
+                    return Ok(());
+                }
+
+                if c.is_num() {
+                    let type_ = tabletype(&mut c)?;
+                    c.finish()?;
+                    self.tables.push(Table { type_ });
+                } else {
+                    let elemtype = elemtype(&mut c)?;
+                    let mut e = c.expect_list("elem")?;
+                    let mut init = vec![];
+                    while !e.is_empty() {
+                        init.push(FuncIdx(self.idx(&mut e, Space::Func)?));
+                    }
+                    c.finish()?;
+
+                    let n = init.len() as u32;
+                    let limits = Limits { min: n, max: Some(n) };
+                    self.tables.push(Table { type_: TableType { limits, elemtype } });
+                    self.elem.push(Elem {
+                        table: x,
+                        offset: Expr { body: vec![Instr::I32Const(0)] },
+                        init: init.into(),
+                    });
+                }
+            }
+
+            // 6.6.8. Memories
+            "memory" => {
+                c.id();
+                let x = MemIdx(self.next_memidx());
+                self.inline_exports(&mut c, ExportDesc::Mem(x))?;
+                if let Some((module, name)) = Self::inline_import(&mut c)? {
+                    let desc = ImportDesc::Mem(memtype(&mut c)?);
+                    c.finish()?;
+                    self.imports.push(Import { module, name, desc });
+                    return Ok(());
+                }
+
+                if let Some(mut d) = c.list("data") {
+                    let mut init = vec![];
+                    while !d.is_empty() {
+                        init.extend_from_slice(d.string()?);
+                    }
+                    c.finish()?;
+
+                    let pages = ((init.len() + 0xFFFF) / 0x10000) as u32;
+                    let limits = Limits { min: pages, max: Some(pages) };
+                    self.mems.push(Mem { type_: MemType { limits } });
+                    self.data.push(Data {
+                        data: x,
+                        offset: Expr { body: vec![Instr::I32Const(0)] },
+                        init: init.into(),
+                    });
+                } else {
+                    let type_ = memtype(&mut c)?;
+                    c.finish()?;
+                    self.mems.push(Mem { type_ });
+                }
+            }
+
+            // 6.6.9. Globals
+            "global" => {
+                c.id();
+                let x = GlobalIdx(self.next_globalidx());
+                self.inline_exports(&mut c, ExportDesc::Global(x))?;
+                if let Some((module, name)) = Self::inline_import(&mut c)? {
+                    let desc = ImportDesc::Global(globaltype(&mut c)?);
+                    c.finish()?;
+                    self.imports.push(Import { module, name, desc });
+                    return Ok(());
+                }
+
+                let type_ = globaltype(&mut c)?;
+                let init = self.expr(&mut c, &mut FuncCtx::default())?;
+                self.globals.push(Global { type_, init });
+            }
+
+            // 6.6.10. Exports
+            "export" => {
+                let name = c.name()?;
+                let mut d = match c.any_list() {
+                    Some(d) => d,
+                    None => return err(c.pos(), Expected("an export description")),
+                };
+                let desc = match d.any_keyword()?.0 {
+                    "func" => ExportDesc::Func(FuncIdx(self.idx(&mut d, Space::Func)?)),
+                    "table" => ExportDesc::Table(TableIdx(self.idx(&mut d, Space::Table)?)),
+                    "memory" => ExportDesc::Mem(MemIdx(self.idx(&mut d, Space::Mem)?)),
+                    "global" => ExportDesc::Global(GlobalIdx(self.idx(&mut d, Space::Global)?)),
+                    _ => return err(d.pos(), Expected("an export description")),
+                };
+                d.finish()?;
+                c.finish()?;
+                self.exports.push(Export { name, desc });
+            }
+
+            // 6.6.11. Start Function
+            "start" => {
+                let func = FuncIdx(self.idx(&mut c, Space::Func)?);
+                c.finish()?;
+                if self.start.is_some() {
+                    return err(pos, MultipleStart);
+                }
+                self.start = Some(Start { func });
+            }
+
+            // 6.6.12. Element Segments
+            "elem" => {
+                let table = if Self::is_idx(&c) {
+                    TableIdx(self.idx(&mut c, Space::Table)?)
+                } else {
+                    TableIdx(0)
+                };
+                let offset = self.offset(&mut c)?;
+                let mut init = vec![];
+                while !c.is_empty() {
+                    init.push(FuncIdx(self.idx(&mut c, Space::Func)?));
+                }
+                self.elem.push(Elem { table, offset, init: init.into() });
+            }
+
+            // 6.6.13. Data Segments
+            "data" => {
+                let data = if Self::is_idx(&c) {
+                    MemIdx(self.idx(&mut c, Space::Mem)?)
+                } else {
+                    MemIdx(0)
+                };
+                let offset = self.offset(&mut c)?;
+                let mut init = vec![];
+                while !c.is_empty() {
+                    init.extend_from_slice(c.string()?);
+                }
+                self.data.push(Data { data, offset, init: init.into() });
+            }
+
+            _ => return err(pos, Expected("a module field")),
+        }
+        Ok(())
+    }
+
+    fn into_module(self) -> Module {
+        Module {
+            types: self.types.into(),
+            funcs: self.funcs.into(),
+            tables: self.tables.into(),
+            mems: self.mems.into(),
+            globals: self.globals.into(),
+            elem: self.elem.into(),
+            data: self.data.into(),
+            start: self.start,
+            imports: self.imports.into(),
+            exports: self.exports.into(),
+        }
+    }
+}
+
+// 6.6.14. Modules
+
+/// Parses a module in the `.wat` text format.
+///
+/// Both a single `(module ...)` and a bare sequence of
+/// module fields are accepted.
+pub fn parse_text_format(src: &str) -> Result<Module, ParseError> {
+    let top = sexprs(src)?;
+
+    let mut fields = vec![];
+    let is_module = match top.first() {
+        Some(&Sexpr::List(ref items, _, _)) => match items.first() {
+            Some(&Sexpr::Atom(Token { kind: TokenKind::Keyword(ref k), .. })) => k == "module",
+            _ => false,
+        },
+        _ => false,
+    };
+
+    if is_module {
+        let mut c = Cursor::new(&top, Pos { line: 0, column: 0 });
+        let mut m = c.list("module").unwrap();
+        if let Some(s) = c.peek() {
+            return err(s.pos(), Expected("end of input"));
+        }
+        m.id();
+        while let Some(field) = m.any_list() {
+            fields.push(field);
+        }
+        m.finish()?;
+    } else {
+        for s in &top {
+            match *s {
+                Sexpr::List(ref items, _, close) => fields.push(Cursor::new(items, close)),
+                Sexpr::Atom(ref t) => return err(t.pos, Expected("a module field")),
+            }
+        }
+    }
+
+    let mut p = ModuleParser::new();
+    p.bind_ids(&fields)?;
+    for field in fields {
+        p.field(field)?;
+    }
+
+    Ok(p.into_module())
+}
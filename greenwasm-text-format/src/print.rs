@@ -0,0 +1,705 @@
+//! Rendering of a `Module` as `.wat` text.
+//!
+//! The output uses the flat instruction syntax and the instruction
+//! names of the MVP spec, and is accepted by both the parser
+//! in this crate and by wabt.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+use greenwasm_binary_format::{CustomSection, NameSection, parse_name_section};
+
+/// Symbolic identifiers taken from the name section.
+#[derive(Default)]
+struct Ids {
+    module: Option<String>,
+    funcs: Vec<Option<String>>,
+    locals: Vec<Vec<Option<String>>>,
+}
+
+// 6.3.5. Identifiers
+fn is_idchar(c: char) -> bool {
+    match c {
+        '0'..='9' | 'A'..='Z' | 'a'..='z' => true,
+        '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '.' | '/' => true,
+        ':' | '<' | '=' | '>' | '?' | '@' | '\\' | '^' | '_' | '`' | '|' | '~' => true,
+        _ => false,
+    }
+}
+
+/// Turns an arbitrary name into a valid identifier that
+/// is not yet contained in `used`.
+fn make_id(name: &str, used: &mut HashSet<String>) -> String {
+    let mut id: String = name.chars().map(|c| if is_idchar(c) { c } else { '_' }).collect();
+    if id.is_empty() {
+        id.push('_');
+    }
+    if used.contains(&id) {
+        let mut n = 1;
+        while used.contains(&format!("{}.{}", id, n)) {
+            n += 1;
+        }
+        id = format!("{}.{}", id, n);
+    }
+    used.insert(id.clone());
+    id
+}
+
+impl Ids {
+    fn new(names: &NameSection) -> Self {
+        let mut ids = Ids::default();
+        ids.module = names.module.as_ref().map(|n| make_id(n, &mut HashSet::new()));
+
+        let mut used = HashSet::new();
+        for &(FuncIdx(x), ref name) in &names.funcs {
+            let x = x as usize;
+            if ids.funcs.len() <= x {
+                ids.funcs.resize(x + 1, None);
+            }
+            ids.funcs[x] = Some(make_id(name, &mut used));
+        }
+
+        for &(FuncIdx(x), ref locals) in &names.locals {
+            let x = x as usize;
+            if ids.locals.len() <= x {
+                ids.locals.resize(x + 1, vec![]);
+            }
+            let mut used = HashSet::new();
+            for &(LocalIdx(y), ref name) in locals {
+                let y = y as usize;
+                let l = &mut ids.locals[x];
+                if l.len() <= y {
+                    l.resize(y + 1, None);
+                }
+                l[y] = Some(make_id(name, &mut used));
+            }
+        }
+
+        ids
+    }
+
+    fn func(&self, x: FuncIdx) -> String {
+        match self.funcs.get(x.0 as usize) {
+            Some(&Some(ref id)) => format!("${}", id),
+            _ => format!("{}", x.0),
+        }
+    }
+
+    /// The identifier to use where a function is defined
+    /// or imported, or a comment with its index.
+    fn func_decl(&self, x: FuncIdx) -> String {
+        match self.funcs.get(x.0 as usize) {
+            Some(&Some(ref id)) => format!("${}", id),
+            _ => format!("(;{};)", x.0),
+        }
+    }
+
+    fn local_id(&self, x: FuncIdx, y: LocalIdx) -> Option<&str> {
+        match self.locals.get(x.0 as usize).and_then(|l| l.get(y.0 as usize)) {
+            Some(&Some(ref id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    fn local(&self, x: FuncIdx, y: LocalIdx) -> String {
+        match self.local_id(x, y) {
+            Some(id) => format!("${}", id),
+            None => format!("{}", y.0),
+        }
+    }
+}
+
+fn valtype(t: ValType) -> &'static str {
+    match t {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+fn globaltype(t: &GlobalType) -> String {
+    match t.mutability {
+        Mut::Const => valtype(t.valtype).to_owned(),
+        Mut::Var => format!("(mut {})", valtype(t.valtype)),
+    }
+}
+
+fn limits(l: &Limits) -> String {
+    match l.max {
+        Some(max) => format!("{} {}", l.min, max),
+        None => format!("{}", l.min),
+    }
+}
+
+fn tabletype(t: &TableType) -> String {
+    match t.elemtype {
+        ElemType::AnyFunc => format!("{} anyfunc", limits(&t.limits)),
+    }
+}
+
+fn functype(out: &mut String, t: &FuncType) {
+    if !t.args.is_empty() {
+        out.push_str(" (param");
+        for &arg in &t.args {
+            write!(out, " {}", valtype(arg)).unwrap();
+        }
+        out.push(')');
+    }
+    if !t.results.is_empty() {
+        out.push_str(" (result");
+        for &res in &t.results {
+            write!(out, " {}", valtype(res)).unwrap();
+        }
+        out.push(')');
+    }
+}
+
+// 6.3.3. Strings
+fn string(bs: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bs {
+        match b {
+            b'"' | b'\\' => {
+                s.push('\\');
+                s.push(b as char);
+            }
+            0x20..=0x7e => s.push(b as char),
+            _ => write!(s, "\\{:02x}", b).unwrap(),
+        }
+    }
+    s.push('"');
+    s
+}
+
+// 6.3.2. Floating-Point
+macro_rules! float_fn {
+    ($name:ident, $f:ty, $mant_bits:expr) => (
+        fn $name(z: $f) -> String {
+            let bits = z.to_bits();
+            let sign = if z.is_sign_negative() { "-" } else { "" };
+            if z.is_nan() {
+                let payload = bits & ((1 << $mant_bits) - 1);
+                if payload == 1 << ($mant_bits - 1) {
+                    format!("{}nan", sign)
+                } else {
+                    format!("{}nan:0x{:x}", sign, payload)
+                }
+            } else if z.is_infinite() {
+                format!("{}inf", sign)
+            } else {
+                // NB: `Display` prints the shortest decimal
+                // representation that parses back to the same value.
+                format!("{}", z)
+            }
+        }
+    )
+}
+float_fn!(f32_const, f32, 23);
+float_fn!(f64_const, f64, 52);
+
+fn memarg(name: &str, m: &Memarg, natural: u32) -> String {
+    let mut s = name.to_owned();
+    if m.offset != 0 {
+        write!(s, " offset={}", m.offset).unwrap();
+    }
+    if m.align != natural {
+        // NB: An out of range alignment can not be represented,
+        // it is left for validation to reject it.
+        write!(s, " align={}", 1u64.checked_shl(m.align).unwrap_or(0)).unwrap();
+    }
+    s
+}
+
+struct Printer<'a> {
+    out: String,
+    ids: &'a Ids,
+    func: FuncIdx,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, depth: usize, s: &str) {
+        self.out.push('\n');
+        for _ in 0..depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(s);
+    }
+
+    fn blocktype(name: &str, rt: &ResultType) -> String {
+        match rt.first() {
+            Some(&t) => format!("{} (result {})", name, valtype(t)),
+            None => name.to_owned(),
+        }
+    }
+
+    /// Prints a sequence of instructions, one per line.
+    fn instrs(&mut self, depth: usize, instrs: &[Instr]) {
+        // NB: Like the binary encoder, this uses a stack on the heap
+        // instead of recursing into nested blocks.
+        let mut stack: Vec<(::std::slice::Iter<Instr>, Option<&[Instr]>)> = vec![
+            (instrs.iter(), None)
+        ];
+
+        while let Some((mut iter, else_)) = stack.pop() {
+            let depth = depth + stack.len();
+            let instr = match iter.next() {
+                Some(instr) => instr,
+                None => {
+                    if let Some(else_) = else_ {
+                        self.line(depth - 1, "else");
+                        stack.push((else_.iter(), None));
+                    } else if !stack.is_empty() {
+                        self.line(depth - 1, "end");
+                    }
+                    continue;
+                }
+            };
+            stack.push((iter, else_));
+
+            match *instr {
+                Instr::Block(ref rt, ref body) => {
+                    self.line(depth, &Self::blocktype("block", rt));
+                    stack.push((body.iter(), None));
+                }
+                Instr::Loop(ref rt, ref body) => {
+                    self.line(depth, &Self::blocktype("loop", rt));
+                    stack.push((body.iter(), None));
+                }
+                Instr::IfElse(ref rt, ref then, ref else_) => {
+                    self.line(depth, &Self::blocktype("if", rt));
+                    let else_ = if else_.is_empty() { None } else { Some(&else_[..]) };
+                    stack.push((then.iter(), else_));
+                }
+                ref instr => {
+                    let s = self.plain(instr);
+                    self.line(depth, &s);
+                }
+            }
+        }
+    }
+
+    /// Prints an instruction without nested instructions.
+    fn plain(&self, instr: &Instr) -> String {
+        use self::Instr::*;
+
+        let name = match *instr {
+            Unreachable => "unreachable",
+            Nop => "nop",
+            Return => "return",
+            Drop => "drop",
+            Select => "select",
+            CurrentMemory => "current_memory",
+            GrowMemory => "grow_memory",
+
+            Br(l) => return format!("br {}", l.0),
+            BrIf(l) => return format!("br_if {}", l.0),
+            BrTable(ref ls, l) => {
+                let mut s = String::from("br_table");
+                for l in ls.iter().chain(Some(&l)) {
+                    write!(s, " {}", l.0).unwrap();
+                }
+                return s;
+            }
+            Call(x) => return format!("call {}", self.ids.func(x)),
+            CallIndirect(x) => return format!("call_indirect (type {})", x.0),
+
+            GetLocal(x) => return format!("get_local {}", self.ids.local(self.func, x)),
+            SetLocal(x) => return format!("set_local {}", self.ids.local(self.func, x)),
+            TeeLocal(x) => return format!("tee_local {}", self.ids.local(self.func, x)),
+            GetGlobal(x) => return format!("get_global {}", x.0),
+            SetGlobal(x) => return format!("set_global {}", x.0),
+
+            I32Load(ref m) => return memarg("i32.load", m, 2),
+            I64Load(ref m) => return memarg("i64.load", m, 3),
+            F32Load(ref m) => return memarg("f32.load", m, 2),
+            F64Load(ref m) => return memarg("f64.load", m, 3),
+            I32Store(ref m) => return memarg("i32.store", m, 2),
+            I64Store(ref m) => return memarg("i64.store", m, 3),
+            F32Store(ref m) => return memarg("f32.store", m, 2),
+            F64Store(ref m) => return memarg("f64.store", m, 3),
+            I32Load8U(ref m) => return memarg("i32.load8_u", m, 0),
+            I32Load8S(ref m) => return memarg("i32.load8_s", m, 0),
+            I64Load8U(ref m) => return memarg("i64.load8_u", m, 0),
+            I64Load8S(ref m) => return memarg("i64.load8_s", m, 0),
+            I32Load16U(ref m) => return memarg("i32.load16_u", m, 1),
+            I32Load16S(ref m) => return memarg("i32.load16_s", m, 1),
+            I64Load16U(ref m) => return memarg("i64.load16_u", m, 1),
+            I64Load16S(ref m) => return memarg("i64.load16_s", m, 1),
+            I64Load32U(ref m) => return memarg("i64.load32_u", m, 2),
+            I64Load32S(ref m) => return memarg("i64.load32_s", m, 2),
+            I32Store8(ref m) => return memarg("i32.store8", m, 0),
+            I64Store8(ref m) => return memarg("i64.store8", m, 0),
+            I32Store16(ref m) => return memarg("i32.store16", m, 1),
+            I64Store16(ref m) => return memarg("i64.store16", m, 1),
+            I64Store32(ref m) => return memarg("i64.store32", m, 2),
+
+            I32Const(n) => return format!("i32.const {}", n as i32),
+            I64Const(n) => return format!("i64.const {}", n as i64),
+            F32Const(z) => return format!("f32.const {}", f32_const(z)),
+            F64Const(z) => return format!("f64.const {}", f64_const(z)),
+
+            I32Clz => "i32.clz",
+            I32Ctz => "i32.ctz",
+            I32Popcnt => "i32.popcnt",
+            I64Clz => "i64.clz",
+            I64Ctz => "i64.ctz",
+            I64Popcnt => "i64.popcnt",
+
+            F32Abs => "f32.abs",
+            F32Neg => "f32.neg",
+            F32Sqrt => "f32.sqrt",
+            F32Ceil => "f32.ceil",
+            F32Floor => "f32.floor",
+            F32Trunc => "f32.trunc",
+            F32Nearest => "f32.nearest",
+
+            F64Abs => "f64.abs",
+            F64Neg => "f64.neg",
+            F64Sqrt => "f64.sqrt",
+            F64Ceil => "f64.ceil",
+            F64Floor => "f64.floor",
+            F64Trunc => "f64.trunc",
+            F64Nearest => "f64.nearest",
+
+            I32Add => "i32.add",
+            I32Sub => "i32.sub",
+            I32Mul => "i32.mul",
+            I32DivU => "i32.div_u",
+            I32DivS => "i32.div_s",
+            I32RemU => "i32.rem_u",
+            I32RemS => "i32.rem_s",
+            I32And => "i32.and",
+            I32Or => "i32.or",
+            I32Xor => "i32.xor",
+            I32Shl => "i32.shl",
+            I32ShrU => "i32.shr_u",
+            I32ShrS => "i32.shr_s",
+            I32Rotl => "i32.rotl",
+            I32Rotr => "i32.rotr",
+
+            I64Add => "i64.add",
+            I64Sub => "i64.sub",
+            I64Mul => "i64.mul",
+            I64DivU => "i64.div_u",
+            I64DivS => "i64.div_s",
+            I64RemU => "i64.rem_u",
+            I64RemS => "i64.rem_s",
+            I64And => "i64.and",
+            I64Or => "i64.or",
+            I64Xor => "i64.xor",
+            I64Shl => "i64.shl",
+            I64ShrU => "i64.shr_u",
+            I64ShrS => "i64.shr_s",
+            I64Rotl => "i64.rotl",
+            I64Rotr => "i64.rotr",
+
+            F32Add => "f32.add",
+            F32Sub => "f32.sub",
+            F32Mul => "f32.mul",
+            F32Div => "f32.div",
+            F32Min => "f32.min",
+            F32Max => "f32.max",
+            F32CopySign => "f32.copysign",
+
+            F64Add => "f64.add",
+            F64Sub => "f64.sub",
+            F64Mul => "f64.mul",
+            F64Div => "f64.div",
+            F64Min => "f64.min",
+            F64Max => "f64.max",
+            F64CopySign => "f64.copysign",
+
+            I32EqZ => "i32.eqz",
+            I64EqZ => "i64.eqz",
+
+            I32Eq => "i32.eq",
+            I32Ne => "i32.ne",
+            I32LtU => "i32.lt_u",
+            I32LtS => "i32.lt_s",
+            I32GtU => "i32.gt_u",
+            I32GtS => "i32.gt_s",
+            I32LeU => "i32.le_u",
+            I32LeS => "i32.le_s",
+            I32GeU => "i32.ge_u",
+            I32GeS => "i32.ge_s",
+
+            I64Eq => "i64.eq",
+            I64Ne => "i64.ne",
+            I64LtU => "i64.lt_u",
+            I64LtS => "i64.lt_s",
+            I64GtU => "i64.gt_u",
+            I64GtS => "i64.gt_s",
+            I64LeU => "i64.le_u",
+            I64LeS => "i64.le_s",
+            I64GeU => "i64.ge_u",
+            I64GeS => "i64.ge_s",
+
+            F32Eq => "f32.eq",
+            F32Ne => "f32.ne",
+            F32Lt => "f32.lt",
+            F32Gt => "f32.gt",
+            F32Le => "f32.le",
+            F32Ge => "f32.ge",
+
+            F64Eq => "f64.eq",
+            F64Ne => "f64.ne",
+            F64Lt => "f64.lt",
+            F64Gt => "f64.gt",
+            F64Le => "f64.le",
+            F64Ge => "f64.ge",
+
+            I32WrapI64 => "i32.wrap/i64",
+            I64ExtendUI32 => "i64.extend_u/i32",
+            I64ExtendSI32 => "i64.extend_s/i32",
+
+            I32TruncUF32 => "i32.trunc_u/f32",
+            I32TruncUF64 => "i32.trunc_u/f64",
+            I32TruncSF32 => "i32.trunc_s/f32",
+            I32TruncSF64 => "i32.trunc_s/f64",
+            I64TruncUF32 => "i64.trunc_u/f32",
+            I64TruncUF64 => "i64.trunc_u/f64",
+            I64TruncSF32 => "i64.trunc_s/f32",
+            I64TruncSF64 => "i64.trunc_s/f64",
+
+            F32DemoteF64 => "f32.demote/f64",
+            F64PromoteF32 => "f64.promote/f32",
+
+            F32ConvertUI32 => "f32.convert_u/i32",
+            F64ConvertUI32 => "f64.convert_u/i32",
+            F32ConvertSI32 => "f32.convert_s/i32",
+            F64ConvertSI32 => "f64.convert_s/i32",
+            F32ConvertUI64 => "f32.convert_u/i64",
+            F64ConvertUI64 => "f64.convert_u/i64",
+            F32ConvertSI64 => "f32.convert_s/i64",
+            F64ConvertSI64 => "f64.convert_s/i64",
+
+            I32ReinterpretF32 => "i32.reinterpret/f32",
+            I64ReinterpretF64 => "i64.reinterpret/f64",
+            F32ReinterpretI32 => "f32.reinterpret/i32",
+            F64ReinterpretI64 => "f64.reinterpret/i64",
+
+            I32Extend8S => "i32.extend8_s",
+            I32Extend16S => "i32.extend16_s",
+            I64Extend8S => "i64.extend8_s",
+            I64Extend16S => "i64.extend16_s",
+            I64Extend32S => "i64.extend32_s",
+
+            Block(..) | Loop(..) | IfElse(..) => unreachable!(),
+        };
+        name.to_owned()
+    }
+
+    /// Prints a constant expression on a single line, with
+    /// each instruction in folded form.
+    fn const_expr(&self, e: &Expr) -> String {
+        let mut s = String::new();
+        for (i, instr) in e.body.iter().enumerate() {
+            if i > 0 {
+                s.push(' ');
+            }
+            match *instr {
+                Instr::Block(..) | Instr::Loop(..) | Instr::IfElse(..) => {
+                    // NB: Not constant, but printed anyway so that
+                    // validation can reject it.
+                    let mut p = Printer { out: String::new(), ids: self.ids, func: self.func };
+                    p.instrs(0, ::std::slice::from_ref(instr));
+                    s.push_str(&p.out[1..]);
+                }
+                ref instr => write!(s, "({})", self.plain(instr)).unwrap(),
+            }
+        }
+        s
+    }
+
+    /// Prints an offset expression, using the abbreviation
+    /// for a single instruction where possible.
+    fn offset(&self, e: &Expr) -> String {
+        match e.body.len() {
+            1 if !is_block(&e.body[0]) => self.const_expr(e),
+            _ => format!("(offset {})", self.const_expr(e)),
+        }
+    }
+}
+
+fn is_block(instr: &Instr) -> bool {
+    match *instr {
+        Instr::Block(..) | Instr::Loop(..) | Instr::IfElse(..) => true,
+        _ => false,
+    }
+}
+
+/// Renders a module as `.wat` text.
+///
+/// If `customs` contains a name section, the names in it are used
+/// as identifiers for the module, its functions and their locals.
+pub fn print_text_format(module: &Module, customs: &[CustomSection]) -> String {
+    let ids = customs.iter()
+        .filter(|cs| &cs.name[..] == "name")
+        .filter_map(|cs| parse_name_section(&cs.bytes))
+        .next()
+        .map(|names| Ids::new(&names))
+        .unwrap_or_default();
+
+    let mut p = Printer { out: String::new(), ids: &ids, func: FuncIdx(0) };
+
+    p.out.push_str("(module");
+    if let Some(ref id) = ids.module {
+        write!(p.out, " ${}", id).unwrap();
+    }
+
+    // 6.6.4. Types
+    for (i, t) in module.types.iter().enumerate() {
+        let mut s = format!("(type (;{};) (func", i);
+        functype(&mut s, t);
+        s.push_str("))");
+        p.line(1, &s);
+    }
+
+    // 6.6.5. Imports
+    let (mut funcs, mut tables, mut mems, mut globals) = (0, 0, 0, 0);
+    for import in &module.imports {
+        let mut s = format!("(import {} {} ", string(import.module.as_bytes()), string(import.name.as_bytes()));
+        match import.desc {
+            ImportDesc::Func(x) => {
+                write!(s, "(func {} (type {}))", ids.func_decl(FuncIdx(funcs)), x.0).unwrap();
+                funcs += 1;
+            }
+            ImportDesc::Table(ref t) => {
+                write!(s, "(table (;{};) {})", tables, tabletype(t)).unwrap();
+                tables += 1;
+            }
+            ImportDesc::Mem(ref t) => {
+                write!(s, "(memory (;{};) {})", mems, limits(&t.limits)).unwrap();
+                mems += 1;
+            }
+            ImportDesc::Global(ref t) => {
+                write!(s, "(global (;{};) {})", globals, globaltype(t)).unwrap();
+                globals += 1;
+            }
+        }
+        s.push(')');
+        p.line(1, &s);
+    }
+
+    // 6.6.6. Functions
+    for (i, func) in module.funcs.iter().enumerate() {
+        let x = FuncIdx(funcs + i as u32);
+        p.func = x;
+
+        let mut s = format!("(func {} (type {})", ids.func_decl(x), func.type_.0);
+
+        let t = module.types.get(func.type_.0 as usize);
+        let args = t.map(|t| &t.args[..]).unwrap_or(&[]);
+        let results = t.map(|t| &t.results[..]).unwrap_or(&[]);
+
+        let mut local = 0;
+        let decls = |s: &mut String, kw: &str, ts: &[ValType], local: &mut u32| {
+            let mut anonymous = vec![];
+            for &t in ts {
+                match ids.local_id(x, LocalIdx(*local)) {
+                    Some(id) => {
+                        if !anonymous.is_empty() {
+                            write!(s, " ({}{})", kw, anonymous.join("")).unwrap();
+                            anonymous.clear();
+                        }
+                        write!(s, " ({} ${} {})", kw, id, valtype(t)).unwrap();
+                    }
+                    None => anonymous.push(format!(" {}", valtype(t))),
+                }
+                *local += 1;
+            }
+            if !anonymous.is_empty() {
+                write!(s, " ({}{})", kw, anonymous.join("")).unwrap();
+            }
+        };
+
+        decls(&mut s, "param", args, &mut local);
+        if !results.is_empty() {
+            s.push_str(" (result");
+            for &t in results {
+                write!(s, " {}", valtype(t)).unwrap();
+            }
+            s.push(')');
+        }
+        p.line(1, &s);
+
+        if !func.locals.is_empty() {
+            let mut s = String::new();
+            decls(&mut s, "local", &func.locals, &mut local);
+            p.line(2, &s[1..]);
+        }
+
+        p.instrs(2, &func.body.body);
+        p.out.push(')');
+    }
+
+    // 6.6.7. Tables
+    for (i, table) in module.tables.iter().enumerate() {
+        let s = format!("(table (;{};) {})", tables + i as u32, tabletype(&table.type_));
+        p.line(1, &s);
+    }
+
+    // 6.6.8. Memories
+    for (i, mem) in module.mems.iter().enumerate() {
+        let s = format!("(memory (;{};) {})", mems + i as u32, limits(&mem.type_.limits));
+        p.line(1, &s);
+    }
+
+    // 6.6.9. Globals
+    for (i, global) in module.globals.iter().enumerate() {
+        let s = format!("(global (;{};) {} {})",
+                        globals + i as u32,
+                        globaltype(&global.type_),
+                        p.const_expr(&global.init));
+        p.line(1, &s);
+    }
+
+    // 6.6.10. Exports
+    for export in &module.exports {
+        let desc = match export.desc {
+            ExportDesc::Func(x) => format!("(func {})", ids.func(x)),
+            ExportDesc::Table(x) => format!("(table {})", x.0),
+            ExportDesc::Mem(x) => format!("(memory {})", x.0),
+            ExportDesc::Global(x) => format!("(global {})", x.0),
+        };
+        let s = format!("(export {} {})", string(export.name.as_bytes()), desc);
+        p.line(1, &s);
+    }
+
+    // 6.6.11. Start Function
+    if let Some(ref start) = module.start {
+        let s = format!("(start {})", ids.func(start.func));
+        p.line(1, &s);
+    }
+
+    // 6.6.12. Element Segments
+    for elem in &module.elem {
+        let mut s = String::from("(elem");
+        if elem.table.0 != 0 {
+            write!(s, " {}", elem.table.0).unwrap();
+        }
+        write!(s, " {}", p.offset(&elem.offset)).unwrap();
+        for &x in &elem.init {
+            write!(s, " {}", ids.func(x)).unwrap();
+        }
+        s.push(')');
+        p.line(1, &s);
+    }
+
+    // 6.6.13. Data Segments
+    for data in &module.data {
+        let mut s = String::from("(data");
+        if data.data.0 != 0 {
+            write!(s, " {}", data.data.0).unwrap();
+        }
+        write!(s, " {} {})", p.offset(&data.offset), string(&data.init)).unwrap();
+        p.line(1, &s);
+    }
+
+    p.out.push_str(")\n");
+    p.out
+}
@@ -0,0 +1,339 @@
+use super::*;
+use super::num::*;
+use greenwasm_structure::types::*;
+use greenwasm_structure::modules::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::instructions::Instr::*;
+
+fn parse(src: &str) -> Module {
+    match parse_text_format(src) {
+        Ok(m) => m,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+fn parse_err(src: &str) -> ParseError {
+    match parse_text_format(src) {
+        Ok(m) => panic!("Should have failed, but parsed with {:?}", m),
+        Err(e) => e,
+    }
+}
+
+fn func_body(src: &str) -> Vec<Instr> {
+    let m = parse(src);
+    m.funcs[0].body.body.clone()
+}
+
+#[test]
+fn test_integers() {
+    assert_eq!(parse_uN("0", 32), Some(0));
+    assert_eq!(parse_uN("4_294_967_295", 32), Some(0xFFFF_FFFF));
+    assert_eq!(parse_uN("4294967296", 32), None);
+    assert_eq!(parse_uN("0xFF_ff", 32), Some(0xFFFF));
+    assert_eq!(parse_uN("1__0", 32), None);
+    assert_eq!(parse_uN("_1", 32), None);
+    assert_eq!(parse_uN("+1", 32), None);
+
+    assert_eq!(parse_sN("-128", 8), Some(-128));
+    assert_eq!(parse_sN("-129", 8), None);
+    assert_eq!(parse_sN("+127", 8), Some(127));
+    assert_eq!(parse_sN("128", 8), None);
+
+    assert_eq!(parse_iN("-1", 32), Some(0xFFFF_FFFF));
+    assert_eq!(parse_iN("0xFFFFFFFF", 32), Some(0xFFFF_FFFF));
+    assert_eq!(parse_iN("-0x8000_0000", 32), Some(0x8000_0000));
+    assert_eq!(parse_iN("-0x8000_0001", 32), None);
+    assert_eq!(parse_iN("-9223372036854775808", 64), Some(0x8000_0000_0000_0000));
+    assert_eq!(parse_iN("18446744073709551615", 64), Some(!0));
+}
+
+#[test]
+fn test_floats() {
+    assert_eq!(parse_f32("1.5"), Some(1.5));
+    assert_eq!(parse_f32("-0x1p-1"), Some(-0.5));
+    assert_eq!(parse_f32("0x1.8p1"), Some(3.0));
+    assert_eq!(parse_f32("1e3"), Some(1000.0));
+    assert_eq!(parse_f32("1_000.0_1"), Some(1000.01));
+    assert_eq!(parse_f64("0x1.fffffffffffffp1023"), Some(::std::f64::MAX));
+    assert_eq!(parse_f32("0x1p128"), None);
+    assert_eq!(parse_f32("1e39"), None);
+    assert_eq!(parse_f32("0x1p-149"), Some(f32::from_bits(1)));
+    assert_eq!(parse_f32("0x1p-150"), Some(0.0));
+    assert_eq!(parse_f32("0x1.000001p-149"), Some(f32::from_bits(1)));
+
+    // ties to even
+    assert_eq!(parse_f32("0x1.000001p0").map(f32::to_bits), Some(0x3f80_0000));
+    assert_eq!(parse_f32("0x1.000003p0").map(f32::to_bits), Some(0x3f80_0002));
+    assert_eq!(parse_f32("0x1.0000010000000000001p0").map(f32::to_bits), Some(0x3f80_0001));
+
+    assert_eq!(parse_f32("inf"), Some(::std::f32::INFINITY));
+    assert_eq!(parse_f64("-inf"), Some(::std::f64::NEG_INFINITY));
+    assert_eq!(parse_f32("nan").map(f32::to_bits), Some(0x7fc0_0000));
+    assert_eq!(parse_f32("-nan:0x1").map(f32::to_bits), Some(0xff80_0001));
+    assert_eq!(parse_f32("nan:0x0"), None);
+    assert_eq!(parse_f32("nan:0x800000"), None);
+    assert_eq!(parse_f64("nan:0xf_ffff_ffff_ffff").map(f64::to_bits), Some(0x7fff_ffff_ffff_ffff));
+}
+
+#[test]
+fn test_error_position() {
+    let e = parse_err("(module\n  (func\n    (i32.const 1)\n    (i32.foo)))");
+    assert_eq!(e.pos, Pos { line: 4, column: 6 });
+    assert_eq!(e.kind, ParseErrorKind::UnknownInstruction("i32.foo".into()));
+    assert_eq!(format!("{}", e), "4:6: unknown instruction `i32.foo`");
+
+    let e = parse_err("(module (; (; ;) ;)\n  (func (call $f)))");
+    assert_eq!(e.pos, Pos { line: 2, column: 15 });
+    assert_eq!(e.kind, ParseErrorKind::UnknownId("f".into()));
+
+    let e = parse_err("(module\n  \"abc");
+    assert_eq!(e.pos, Pos { line: 2, column: 3 });
+    assert_eq!(e.kind, ParseErrorKind::UnterminatedString);
+
+    let e = parse_err("(module (func)");
+    assert_eq!(e.pos, Pos { line: 1, column: 1 });
+    assert_eq!(e.kind, ParseErrorKind::UnbalancedParen);
+
+    let e = parse_err("(module (func (i32.const 4294967296)))");
+    assert_eq!(e.pos, Pos { line: 1, column: 26 });
+    assert_eq!(e.kind, ParseErrorKind::InvalidNumber("4294967296".into()));
+}
+
+#[test]
+fn test_types_and_typeuse() {
+    let m = parse(r#"
+        (module
+            (type $t (func (param i32) (result i32)))
+            (func (type $t) (param $x i32) (result i32) (get_local $x))
+            (func (param f32))
+            (func (type 0))
+            (func (param f32)))
+    "#);
+    assert_eq!(m.types.len(), 2);
+    assert_eq!(m.types[1], FuncType { args: vec![ValType::F32].into(), results: vec![].into() });
+    let types: Vec<_> = m.funcs.iter().map(|f| f.type_).collect();
+    assert_eq!(types, vec![TypeIdx(0), TypeIdx(1), TypeIdx(0), TypeIdx(1)]);
+    assert_eq!(m.funcs[0].body.body, vec![GetLocal(LocalIdx(0))]);
+
+    let e = parse_err("(module (type (func)) (func (type 0) (param i32)))");
+    assert_eq!(e.kind, ParseErrorKind::InlineTypeMismatch);
+}
+
+#[test]
+fn test_ids() {
+    let m = parse(r#"
+        (module
+            (import "m" "g" (global $g i32))
+            (func $a (call $b))
+            (func $b (drop (global.get $g)) (call $a)))
+    "#);
+    assert_eq!(m.funcs[0].body.body, vec![Call(FuncIdx(1))]);
+    assert_eq!(m.funcs[1].body.body, vec![GetGlobal(GlobalIdx(0)), Drop, Call(FuncIdx(0))]);
+
+    let e = parse_err(r#"(module (func $a) (import "m" "f" (func $b)))"#);
+    assert_eq!(e.kind, ParseErrorKind::ImportAfterDefinition);
+
+    let e = parse_err("(module (func $a) (func $a))");
+    assert_eq!(e.kind, ParseErrorKind::DuplicateId("a".into()));
+}
+
+#[test]
+fn test_locals() {
+    let body = func_body(r#"
+        (func $b (param $p i32) (local $l i64) (local f32 f64) (local $m f32)
+            (set_local $l (i64.extend_u/i32 (get_local $p)))
+            (local.set $m (f32.const 0)))
+    "#);
+    assert_eq!(body, vec![
+        GetLocal(LocalIdx(0)),
+        I64ExtendUI32,
+        SetLocal(LocalIdx(1)),
+        F32Const(0.0),
+        SetLocal(LocalIdx(4)),
+    ]);
+}
+
+#[test]
+fn test_folded_instrs() {
+    let body = func_body(r#"
+        (func (result i32)
+            (block $outer (result i32)
+                (if $i (result i32) (i32.eqz (i32.const 0))
+                    (then (br $outer (i32.const 1)))
+                    (else (loop $l (br_if $l (i32.const 1))) (br $i (i32.const 2))))))
+    "#);
+    let i32 = ResultType::from(ValType::I32);
+    assert_eq!(body, vec![
+        Block(i32, vec![
+            I32Const(0),
+            I32EqZ,
+            IfElse(i32, vec![
+                I32Const(1),
+                Br(LabelIdx(1)),
+            ], vec![
+                Loop(None.into(), vec![
+                    I32Const(1),
+                    BrIf(LabelIdx(0)),
+                ]),
+                I32Const(2),
+                Br(LabelIdx(0)),
+            ]),
+        ]),
+    ]);
+}
+
+#[test]
+fn test_flat_instrs() {
+    let body = func_body(r#"
+        (func
+            block $a
+                i32.const 0
+                if $b
+                    br_table $a $b 0
+                else $b
+                    nop
+                end $b
+                loop
+                    br 1
+                end
+            end $a
+            i32.const 1
+            i32.load8_u offset=4 align=1
+            i64.const -1
+            i64.store align=4
+            memory.grow
+            drop)
+    "#);
+    let none = ResultType::from(None);
+    assert_eq!(body, vec![
+        Block(none, vec![
+            I32Const(0),
+            IfElse(none, vec![
+                BrTable(vec![LabelIdx(1), LabelIdx(0)].into(), LabelIdx(0)),
+            ], vec![
+                Nop,
+            ]),
+            Loop(none, vec![Br(LabelIdx(1))]),
+        ]),
+        I32Const(1),
+        I32Load8U(Memarg { offset: 4, align: 0 }),
+        I64Const(!0),
+        I64Store(Memarg { offset: 0, align: 2 }),
+        GrowMemory,
+        Drop,
+    ]);
+
+    let e = parse_err("(func block $a end $b)");
+    assert_eq!(e.kind, ParseErrorKind::MismatchedLabel("b".into()));
+
+    let e = parse_err("(func i32.load align=3)");
+    assert_eq!(e.kind, ParseErrorKind::InvalidAlignment);
+}
+
+#[test]
+fn test_inline_imports_exports() {
+    let m = parse(r#"
+        (module
+            (func $f (import "m" "f") (param i32))
+            (memory (import "m" "mem") 1)
+            (global $g (export "g") (export "g2") (mut i32) (i32.const 0))
+            (func (export "run") (call $f (i32.const 1)))
+            (table (export "t") anyfunc (elem $f 1))
+            (memory (data "ab" "\01\u{2764}"))
+            (export "f" (func $f))
+            (elem (i32.const 1) $f)
+            (data (offset (i32.const 8)) "x")
+            (start 1))
+    "#);
+
+    assert_eq!(m.imports.len(), 2);
+    assert_eq!(m.imports[0].desc, ImportDesc::Func(TypeIdx(0)));
+    assert_eq!(m.imports[1].desc, ImportDesc::Mem(MemType {
+        limits: Limits { min: 1, max: None }
+    }));
+
+    let exports: Vec<_> = m.exports.iter().map(|e| (&e.name[..], &e.desc)).collect();
+    assert_eq!(exports, vec![
+        ("g", &ExportDesc::Global(GlobalIdx(0))),
+        ("g2", &ExportDesc::Global(GlobalIdx(0))),
+        ("run", &ExportDesc::Func(FuncIdx(1))),
+        ("t", &ExportDesc::Table(TableIdx(0))),
+        ("f", &ExportDesc::Func(FuncIdx(0))),
+    ]);
+
+    assert_eq!(m.globals[0].type_, GlobalType { mutability: Mut::Var, valtype: ValType::I32 });
+    assert_eq!(m.tables[0].type_.limits, Limits { min: 2, max: Some(2) });
+    assert_eq!(m.mems[0].type_.limits, Limits { min: 1, max: Some(1) });
+
+    assert_eq!(m.elem.len(), 2);
+    assert_eq!(&m.elem[0].init[..], &[FuncIdx(0), FuncIdx(1)]);
+    assert_eq!(m.elem[1].offset.body, vec![I32Const(1)]);
+
+    assert_eq!(m.data.len(), 2);
+    assert_eq!(m.data[0].data, MemIdx(1));
+    assert_eq!(&m.data[0].init[..], b"ab\x01\xe2\x9d\xa4");
+    assert_eq!(m.data[1].offset.body, vec![I32Const(8)]);
+
+    assert_eq!(m.start, Some(Start { func: FuncIdx(1) }));
+}
+
+#[test]
+fn test_print() {
+    use greenwasm_binary_format::CustomSection;
+
+    let src = r#"
+        (module
+            (type $t (func (param i32) (result i32)))
+            (import "env" "f" (func (param f32)))
+            (func (type $t) (param $x i32) (result i32) (local i64 f64)
+                block (result i32)
+                    get_local $x
+                    if
+                        f32.const -nan:0x1
+                        call 0
+                    else
+                        f32.const 0x1p-149
+                        call 0
+                    end
+                    loop
+                        br 1
+                    end
+                    i32.const -1
+                end
+                (i32.load16_u offset=4 align=1 (i32.const 8))
+                (i64.store (i32.const 0) (i64.const 0x7fff_ffff_ffff_ffff))
+                i32.add)
+            (table 2 anyfunc)
+            (memory 1)
+            (global (mut f64) (f64.const -0))
+            (export "run" (func 1))
+            (elem (i32.const 0) 1 0)
+            (data (i32.const 16) "a\"\\\00\ff"))
+    "#;
+    let m = parse(src);
+
+    let s = print_text_format(&m, &[]);
+    assert_eq!(format!("{:?}", parse(&s)), format!("{:?}", m), "{}", s);
+    assert!(s.contains("\n  (func (;1;) (type 0) (param i32) (result i32)\n    (local i64 f64)\n    block (result i32)\n"), "{}", s);
+    assert!(s.contains("f32.const -nan:0x1"), "{}", s);
+    assert!(s.contains("i32.load16_u offset=4 align=1"), "{}", s);
+    assert!(s.contains("(data (i32.const 16) \"a\\\"\\\\\\00\\ff\")"), "{}", s);
+
+    // function 0 is "imp", function 1 is "main" with locals "x" and "y z"
+    let names = CustomSection {
+        name: "name".into(),
+        bytes: vec![
+            0x00, 0x02, 0x01, b'm',
+            0x01, 0x0c, 0x02, 0x00, 0x03, b'i', b'm', b'p', 0x01, 0x04, b'm', b'a', b'i', b'n',
+            0x02, 0x0b, 0x01, 0x01, 0x02, 0x00, 0x01, b'x', 0x02, 0x03, b'y', b' ', b'z',
+        ],
+        after: 11,
+    };
+    let s = print_text_format(&m, &[names]);
+    assert_eq!(format!("{:?}", parse(&s)), format!("{:?}", m), "{}", s);
+    assert!(s.starts_with("(module $m\n"), "{}", s);
+    assert!(s.contains("(import \"env\" \"f\" (func $imp (type 1)))"), "{}", s);
+    assert!(s.contains("(func $main (type 0) (param $x i32) (result i32)\n    (local i64) (local $y_z f64)"), "{}", s);
+    assert!(s.contains("call $imp"), "{}", s);
+    assert!(s.contains("(elem (i32.const 0) $main $imp)"), "{}", s);
+}
@@ -1,6 +1,8 @@
 #![feature(macro_at_most_once_rep)]
 
 extern crate greenwasm_structure;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 
 use greenwasm_structure::types::FuncType;
 use greenwasm_structure::types::TableType;
@@ -37,12 +39,43 @@ use greenwasm_structure::modules::TableIdx;
 use greenwasm_structure::modules::TypeIdx;
 use greenwasm_structure::modules::LabelIdx;
 
+use greenwasm_structure::features::WasmFeatures;
+use greenwasm_structure::features::Feature;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+mod source;
+pub use source::{ModuleSource, FuncSource, InstrSource};
+pub use source::{GlobalSource, ElemSource, DataSource};
+
+mod policy;
+pub use policy::{ValidationPolicy, Deterministic, IntegerOnly, ImportAllowList};
+
+mod lint;
+pub use lint::{lint_module, Lint, LintId, LintLocation, LintConfig, Severity};
+
 pub type VResult<T> = Result<T, ValidationError>;
-#[derive(Debug)]
-pub struct ValidationError {
+
+/// NB: The details are boxed to keep `VResult` small, since the
+/// instruction validator recurses once per nested block and has
+/// many early returns in a single stack frame.
+#[derive(Clone, PartialEq)]
+pub struct ValidationError(Box<ValidationErrorDetails>);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationErrorDetails {
     pub kind: ValidationErrorEnum,
+    /// The function whose body is not valid.
+    pub func: Option<FuncIdx>,
+    /// The location of the offending instruction, as the index of the
+    /// instruction in each enclosing instruction sequence, outermost first.
+    ///
+    /// The instructions of an else branch are counted after those of
+    /// the if branch. Empty if the error is not caused by an instruction,
+    /// or by the implicit end of an expression.
+    pub instr: Vec<u32>,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValidationErrorEnum {
     LimitMaxSmallerMin,
     FunctionTypeResultArityGreaterOne,
@@ -51,201 +84,415 @@ pub enum ValidationErrorEnum {
     InstrStoreOveraligned,
     InstrBrTableNotSameLabelType,
     InstrCallIndirectElemTypeNotAnyFunc,
-    InstrWithTypeNotValid, // TODO: Exact error codes
+    /// The operand on top of the stack has a different type than expected.
+    InstrOperandTypeMismatch {
+        expected: ValType,
+        actual: ValType,
+    },
+    /// The operand stack of the current block is empty.
+    /// `expected` is `None` if an operand of any type would have been valid.
+    InstrOperandStackUnderflow {
+        expected: Option<ValType>,
+    },
+    /// A block leaves other operands on the stack than its result type.
+    /// Operands of unknown type in unreachable code are `None`.
+    InstrOperandStackNotEmpty {
+        expected: Vec<ValType>,
+        actual: Vec<Option<ValType>>,
+    },
+    InstrEndWithoutBlock,
+    InstrElseWithoutIf,
+    /// An operator after the end of the function body.
+    InstrAfterEnd,
+    /// The function body ends without its final end.
+    InstrMissingEnd,
     ConstExprGetGlobalNotConst,
     ConstExprIlligalInstruction,
     ElemElemTypeNotAnyFunc,
-    ModulePrepassImportFuncTypeIdxDoesNotExist,
+    ModulePrepassImportFuncTypeIdxDoesNotExist(TypeIdx),
     ModuleTablesLengthNotOne,
     ModuleMemsLengthNotOne,
     ModuleExportDuplicateName,
-    CtxLocalsIdxDoesNotExist,
-    CtxGlobalsIdxDoesNotExist,
-    CtxMemsIdxDoesNotExist,
-    CtxFuncsIdxDoesNotExist,
-    CtxTablesIdxDoesNotExist,
-    CtxTypesIdxDoesNotExist,
-    CtxLabelsIdxDoesNotExist,
+    CtxLocalsIdxDoesNotExist(LocalIdx),
+    CtxGlobalsIdxDoesNotExist(GlobalIdx),
+    CtxMemsIdxDoesNotExist(MemIdx),
+    CtxFuncsIdxDoesNotExist(FuncIdx),
+    CtxTablesIdxDoesNotExist(TableIdx),
+    CtxTypesIdxDoesNotExist(TypeIdx),
+    CtxLabelsIdxDoesNotExist(LabelIdx),
     CtxReturnDoesNotExist,
     StartFunNotValidWithEmptyEmpty,
     MemoryBoundsOutside32Bit,
+    // NB: The payload of the following variants is the
+    // field of the `ValidationLimits` that has been exceeded.
+    TooManyMemoryPages(u32),
+    TooManyTableElements(u32),
+    /// The number of locals of a function, including its parameters.
+    TooManyLocals(u32),
+    FuncBodyTooLarge(u32),
+    NestingTooDeep(u32),
+    TooManyGlobals(u32),
+    TooManyExports(u32),
+    /// An instruction that the `ValidationPolicy` does not allow.
+    PolicyInstrNotAllowed,
+    /// A value type that the `ValidationPolicy` does not allow.
+    PolicyTypeNotAllowed(ValType),
+    /// An import that the `ValidationPolicy` does not allow,
+    /// given by its position in the module.
+    PolicyImportNotAllowed(u32),
+    /// An instruction of a proposal that is not enabled in the `WasmFeatures`.
+    FeatureDisabled(Feature),
 }
 use self::ValidationErrorEnum::*;
 
-/// A context member is a flattened linked list of lists on the stack
-enum CtxMember<'a, T: 'a> {
-    Unset,
-    Set(T),
-    Prepended(T, &'a CtxMember<'a, T>),
-    Delegated(&'a CtxMember<'a, T>),
+impl ValidationError {
+    fn new(kind: ValidationErrorEnum) -> Self {
+        ValidationError(Box::new(ValidationErrorDetails {
+            kind,
+            func: None,
+            instr: Vec::new(),
+        }))
+    }
+}
+
+impl ::std::ops::Deref for ValidationError {
+    type Target = ValidationErrorDetails;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl ::std::fmt::Debug for ValidationError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+fn valtype_name(t: ValType) -> &'static str {
+    match t {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+struct Types<'a, T: 'a>(&'a [T]);
+
+impl<'a> ::std::fmt::Display for Types<'a, ValType> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let names: Vec<_> = self.0.iter().map(|&t| valtype_name(t)).collect();
+        write!(f, "[{}]", names.join(" "))
+    }
+}
+
+impl<'a> ::std::fmt::Display for Types<'a, Option<ValType>> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let names: Vec<_> = self.0.iter().map(|t| t.map_or("unknown", valtype_name)).collect();
+        write!(f, "[{}]", names.join(" "))
+    }
+}
+
+impl ::std::fmt::Display for ValidationErrorEnum {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            LimitMaxSmallerMin => write!(f, "limits maximum is smaller than minimum"),
+            FunctionTypeResultArityGreaterOne => write!(f, "function type has more than one result"),
+            InstrSetGlobalNotVar => write!(f, "set_global of an immutable global"),
+            InstrLoadOveraligned => write!(f, "load alignment larger than natural"),
+            InstrStoreOveraligned => write!(f, "store alignment larger than natural"),
+            InstrBrTableNotSameLabelType => write!(f, "br_table labels have different types"),
+            InstrCallIndirectElemTypeNotAnyFunc => write!(f, "call_indirect on a table without anyfunc elements"),
+            InstrOperandTypeMismatch { expected, actual } => {
+                write!(f, "type mismatch: expected {}, found {}",
+                       valtype_name(expected), valtype_name(actual))
+            }
+            InstrOperandStackUnderflow { expected: Some(t) } => {
+                write!(f, "type mismatch: expected {}, found empty stack", valtype_name(t))
+            }
+            InstrOperandStackUnderflow { expected: None } => {
+                write!(f, "type mismatch: expected an operand, found empty stack")
+            }
+            InstrOperandStackNotEmpty { ref expected, ref actual } => {
+                write!(f, "type mismatch: block expects {} at the end, found {}",
+                       Types(expected), Types(actual))
+            }
+            InstrEndWithoutBlock => write!(f, "end without matching block"),
+            InstrElseWithoutIf => write!(f, "else without matching if"),
+            InstrAfterEnd => write!(f, "operator after the end of the function"),
+            InstrMissingEnd => write!(f, "function body is missing its end"),
+            ConstExprGetGlobalNotConst => write!(f, "constant expression reads a mutable global"),
+            ConstExprIlligalInstruction => write!(f, "constant expression required"),
+            ElemElemTypeNotAnyFunc => write!(f, "element segment for a table without anyfunc elements"),
+            ModulePrepassImportFuncTypeIdxDoesNotExist(TypeIdx(x)) => {
+                write!(f, "unknown type {} of imported function", x)
+            }
+            ModuleTablesLengthNotOne => write!(f, "multiple tables"),
+            ModuleMemsLengthNotOne => write!(f, "multiple memories"),
+            ModuleExportDuplicateName => write!(f, "duplicate export name"),
+            CtxLocalsIdxDoesNotExist(LocalIdx(x)) => write!(f, "unknown local {}", x),
+            CtxGlobalsIdxDoesNotExist(GlobalIdx(x)) => write!(f, "unknown global {}", x),
+            CtxMemsIdxDoesNotExist(MemIdx(x)) => write!(f, "unknown memory {}", x),
+            CtxFuncsIdxDoesNotExist(FuncIdx(x)) => write!(f, "unknown function {}", x),
+            CtxTablesIdxDoesNotExist(TableIdx(x)) => write!(f, "unknown table {}", x),
+            CtxTypesIdxDoesNotExist(TypeIdx(x)) => write!(f, "unknown type {}", x),
+            CtxLabelsIdxDoesNotExist(LabelIdx(x)) => write!(f, "unknown label {}", x),
+            CtxReturnDoesNotExist => write!(f, "return outside of a function"),
+            StartFunNotValidWithEmptyEmpty => write!(f, "start function has parameters or results"),
+            MemoryBoundsOutside32Bit => write!(f, "memory size must be at most 65536 pages (4GiB)"),
+            TooManyMemoryPages(n) => write!(f, "memory size exceeds the limit of {} pages", n),
+            TooManyTableElements(n) => write!(f, "table size exceeds the limit of {} elements", n),
+            TooManyLocals(n) => write!(f, "function has more than {} locals", n),
+            FuncBodyTooLarge(n) => write!(f, "function body has more than {} instructions", n),
+            NestingTooDeep(n) => write!(f, "blocks are nested deeper than {}", n),
+            TooManyGlobals(n) => write!(f, "module has more than {} globals", n),
+            TooManyExports(n) => write!(f, "module has more than {} exports", n),
+            PolicyInstrNotAllowed => write!(f, "instruction not allowed by the validation policy"),
+            PolicyTypeNotAllowed(t) => {
+                write!(f, "type {} not allowed by the validation policy", valtype_name(t))
+            }
+            PolicyImportNotAllowed(x) => write!(f, "import {} not allowed by the validation policy", x),
+            FeatureDisabled(feature) => write!(f, "feature disabled: {}", feature),
+        }
+    }
+}
+
+impl ::std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(FuncIdx(x)) = self.func {
+            write!(f, " in function {}", x)?;
+        }
+        if !self.instr.is_empty() {
+            let path: Vec<_> = self.instr.iter().map(|i| i.to_string()).collect();
+            write!(f, " at instruction {}", path.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl ::std::error::Error for ValidationError {}
+
+/// Caps on the size of the modules that are valid.
+///
+/// The default values only enforce the limits of the spec, so that
+/// embedders can choose smaller ones to reject oversized modules
+/// before instantiating them.
+///
+/// NB: Memories are never larger than 65536 pages, even with a larger `max_memory_pages`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ValidationLimits {
+    /// The minimum and maximum size of a memory, in pages of 64KiB.
+    pub max_memory_pages: u32,
+    /// The minimum and maximum number of elements of a table.
+    pub max_table_size: u32,
+    /// The number of locals of a function, including its parameters.
+    pub max_locals: u32,
+    /// The number of instructions of a function body, counting
+    /// blocks, loops and ifs once, and not counting else and end.
+    pub max_body_size: u32,
+    /// The number of blocks, loops and ifs enclosing an instruction.
+    pub max_nesting: u32,
+    /// The number of globals, including the imported ones.
+    pub max_globals: u32,
+    pub max_exports: u32,
 }
 
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        ValidationLimits {
+            max_memory_pages: 65536,
+            max_table_size: ::std::u32::MAX,
+            max_locals: ::std::u32::MAX,
+            max_body_size: ::std::u32::MAX,
+            max_nesting: ::std::u32::MAX,
+            max_globals: ::std::u32::MAX,
+            max_exports: ::std::u32::MAX,
+        }
+    }
+}
 
-// TODO: Optimize memory layout later (no needless 1-elem allocations)
-// TODO: Special case for return, which should only be a 0 or 1 list
+/// The options of a validation. The default only accepts
+/// the default features, within the limits of the spec.
+#[derive(Copy, Clone, Default)]
+pub struct ValidationConfig<'a> {
+    /// The proposals whose instructions are valid.
+    /// Other proposals fail with `FeatureDisabled`.
+    pub features: WasmFeatures,
+    pub limits: ValidationLimits,
+    /// Restricts the valid instructions, types and imports further.
+    pub policy: Option<&'a dyn ValidationPolicy>,
+}
+
+impl<'a> ::std::fmt::Debug for ValidationConfig<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("ValidationConfig")
+            .field("features", &self.features)
+            .field("limits", &self.limits)
+            .field("policy", &self.policy.map(|_| ".."))
+            .finish()
+    }
+}
+
+/// The validation context `C` of the spec.
+///
+/// The index spaces are flat tables, so that looking up an index neither
+/// allocates nor clones. The contexts of function bodies and constant
+/// expressions borrow the tables of the context they are derived from.
 pub struct Ctx<'a> {
-    types:   CtxMember<'a, Vec<FuncType>>,
-    funcs:   CtxMember<'a, Vec<FuncType>>,
-    tables:  CtxMember<'a, Vec<TableType>>,
-    mems:    CtxMember<'a, Vec<MemType>>,
-    globals: CtxMember<'a, Vec<GlobalType>>,
-    locals:  CtxMember<'a, Vec<ValType>>,
-    labels:  CtxMember<'a, Vec<ResultType>>,
-    return_: CtxMember<'a, Vec<ResultType>>,
+    /// The distinct function types, which the type index space
+    /// refers to by their position.
+    func_types: Cow<'a, [FuncType]>,
+    types:   Cow<'a, [u32]>,
+    funcs:   Cow<'a, [TypeIdx]>,
+    tables:  Cow<'a, [TableType]>,
+    mems:    Cow<'a, [MemType]>,
+    globals: Cow<'a, [GlobalType]>,
+    locals:  Cow<'a, [ValType]>,
+    return_: Option<ResultType>,
+    features: WasmFeatures,
+    limits: ValidationLimits,
+    policy: Option<&'a dyn ValidationPolicy>,
 }
 
 macro_rules! ctx_set {
     ($fn_name:ident($self:ident, $var_name:ident: $var_type:ty)) => (
         ctx_set!($fn_name($self, $var_name: $var_type)
-            -> |_| CtxMember::Set($var_name));
+            -> Cow::Owned($var_name));
     );
     ($fn_name:ident($self:ident, $var_name:ident: $var_type:ty) -> $mapped:expr) => (
         fn $fn_name(mut $self, $var_name: $var_type) -> Self {
-            if let CtxMember::Delegated(r) = $self.$var_name {
-                $self.$var_name = $mapped(r);
-            } else {
-                panic!("can only overwrite Delegated()");
-            }
+            $self.$var_name = $mapped;
             $self
         }
     )
 }
 
 macro_rules! ctx_idx {
-    ($self:ident, $name:ident: $type:ty, $idxty:ty, $err:ident) => (
-        fn $name(&$self, mut x: $idxty) -> VResult<$type> {
-            use self::CtxMember::*;
-
-            let mut cursor = &$self.$name;
-
-            loop {
-                match cursor {
-                    Delegated(next) => {
-                        cursor = next;
-                    }
-                    Set(v) if (x.0 as usize) < v.len() => {
-                        return Ok(v[x.0 as usize].clone());
-                    }
-                    Prepended(v, _) if (x.0 as usize) < v.len() => {
-                        return Ok(v[x.0 as usize].clone());
-                    }
-                    Prepended(v, next) if (x.0 as usize) >= v.len() => {
-                        x.0 -= v.len() as u32;
-                        cursor = next;
-                    }
-                    _ => $self.error($err)?,
-                }
+    ($self:ident, $name:ident: $type:ty, $idxty:ty, $err:expr) => (
+        fn $name(&$self, idx: $idxty) -> VResult<$type> {
+            match $self.$name.get(idx.0 as usize) {
+                Some(&x) => Ok(x),
+                None => Err(ValidationError::new($err(idx))),
             }
         }
     )
 }
 
 impl<'a> Ctx<'a> {
-    pub fn new() -> Self {
+    /// An empty context in which the instructions of the features of `config`
+    /// are valid, and that enforces its limits and policy.
+    pub fn new(config: &ValidationConfig<'a>) -> Self {
+        let ValidationConfig { features, limits, policy } = *config;
         Ctx {
-            types:   CtxMember::Unset,
-            funcs:   CtxMember::Unset,
-            tables:  CtxMember::Unset,
-            mems:    CtxMember::Unset,
-            globals: CtxMember::Unset,
-            locals:  CtxMember::Unset,
-            labels:  CtxMember::Unset,
-            return_: CtxMember::Unset,
+            func_types: Cow::Borrowed(&[]),
+            types:   Cow::Borrowed(&[]),
+            funcs:   Cow::Borrowed(&[]),
+            tables:  Cow::Borrowed(&[]),
+            mems:    Cow::Borrowed(&[]),
+            globals: Cow::Borrowed(&[]),
+            locals:  Cow::Borrowed(&[]),
+            return_: None,
+            features,
+            limits,
+            policy,
         }
     }
 
+    /// The context of the function bodies of `module`, for use with `FuncValidator`.
+    ///
+    /// Only the types, imports, tables, memories and globals, and the type
+    /// indices of the functions are used, so the bodies may still be empty.
+    pub fn for_module<M: ModuleSource>(module: M, config: &ValidationConfig<'a>) -> VResult<Ctx<'a>> {
+        let empty_c = Ctx::new(config);
+        validate::source_prepass(&empty_c, &module)?;
+        Ok(validate::module_ctx(&empty_c, &module))
+    }
+
     fn with(&'a self) -> Ctx<'a> {
         Ctx {
-            types:   CtxMember::Delegated(&self.types),
-            funcs:   CtxMember::Delegated(&self.funcs),
-            tables:  CtxMember::Delegated(&self.tables),
-            mems:    CtxMember::Delegated(&self.mems),
-            globals: CtxMember::Delegated(&self.globals),
-            locals:  CtxMember::Delegated(&self.locals),
-            labels:  CtxMember::Delegated(&self.labels),
-            return_: CtxMember::Delegated(&self.return_),
+            func_types: Cow::Borrowed(&self.func_types),
+            types:   Cow::Borrowed(&self.types),
+            funcs:   Cow::Borrowed(&self.funcs),
+            tables:  Cow::Borrowed(&self.tables),
+            mems:    Cow::Borrowed(&self.mems),
+            globals: Cow::Borrowed(&self.globals),
+            locals:  Cow::Borrowed(&self.locals),
+            return_: self.return_,
+            features: self.features,
+            limits: self.limits,
+            policy: self.policy,
+        }
+    }
+
+    fn allowed_valtypes(&self, types: &[ValType]) -> VResult<()> {
+        if let Some(policy) = self.policy {
+            for &t in types {
+                if !policy.allow_valtype(t) {
+                    self.error(PolicyTypeNotAllowed(t))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn allowed_instr(&self, instr: &Instr) -> VResult<()> {
+        match self.policy {
+            Some(policy) if !policy.allow_instr(instr) => self.error(PolicyInstrNotAllowed),
+            _ => Ok(()),
         }
     }
 
     fn error(&self, error: ValidationErrorEnum) -> VResult<()> {
-        Err(ValidationError {
-            kind: error
-        })
+        Err(ValidationError::new(error))
     }
 
     ctx_idx!(self, locals: ValType,     LocalIdx,  CtxLocalsIdxDoesNotExist );
     ctx_idx!(self, globals: GlobalType, GlobalIdx, CtxGlobalsIdxDoesNotExist);
     ctx_idx!(self, mems: MemType,       MemIdx,    CtxMemsIdxDoesNotExist   );
-    ctx_idx!(self, funcs: FuncType,     FuncIdx,   CtxFuncsIdxDoesNotExist  );
     ctx_idx!(self, tables: TableType,   TableIdx,  CtxTablesIdxDoesNotExist );
-    ctx_idx!(self, types: FuncType,     TypeIdx,   CtxTypesIdxDoesNotExist  );
-    ctx_idx!(self, labels: ResultType,  LabelIdx,  CtxLabelsIdxDoesNotExist );
-    ctx_idx!(self, return_: ResultType, LabelIdx,  CtxReturnDoesNotExist    );
-
-    ctx_set!(set_locals(self,  locals:  Vec<ValType>));
-    ctx_set!(set_types(self,   types:   Vec<FuncType>));
-    ctx_set!(set_funcs(self,   funcs:   Vec<FuncType>));
-    ctx_set!(set_tables(self,  tables:  Vec<TableType>));
-    ctx_set!(set_mems(self,    mems:    Vec<MemType>));
-    ctx_set!(set_globals(self, globals: Vec<GlobalType>));
 
-    ctx_set!(set_label(self, labels: ResultType)
-        -> |_| CtxMember::Set(vec![labels]));
+    fn types(&self, idx: TypeIdx) -> VResult<&FuncType> {
+        match self.types.get(idx.0 as usize) {
+            Some(&id) => Ok(&self.func_types[id as usize]),
+            None => Err(ValidationError::new(CtxTypesIdxDoesNotExist(idx))),
+        }
+    }
 
-    ctx_set!(prepend_label(self, labels: ResultType)
-        -> |r| CtxMember::Prepended(vec![labels], r));
+    fn funcs(&self, idx: FuncIdx) -> VResult<&FuncType> {
+        match self.funcs.get(idx.0 as usize) {
+            Some(&x) => self.types(x),
+            None => Err(ValidationError::new(CtxFuncsIdxDoesNotExist(idx))),
+        }
+    }
 
-    ctx_set!(set_return_(self, return_: ResultType)
-        -> |_| CtxMember::Set(vec![return_]));
+    fn return_(&self) -> VResult<ResultType> {
+        self.return_.ok_or_else(|| ValidationError::new(CtxReturnDoesNotExist))
+    }
 
-    fn length_mems(&self) -> u32 {
-        use self::CtxMember::*;
+    /// Sets the type index space, with each distinct type stored once.
+    fn set_types(mut self, types: &[FuncType]) -> Self {
+        let mut func_types = Vec::new();
+        let mut ids = HashMap::new();
+        let types = types.iter().map(|t| {
+            *ids.entry(t).or_insert_with(|| {
+                func_types.push(t.clone());
+                (func_types.len() - 1) as u32
+            })
+        }).collect();
 
-        let mut len = 0;
-        let mut cursor = &self.mems;
-        loop {
-            match cursor {
-                Delegated(next) => {
-                    cursor = next;
-                }
-                Prepended(v, next) => {
-                    len += v.len() as u32;
-                    cursor = next;
-                }
-                Set(v) => {
-                    len += v.len() as u32;
-                    return len;
-                }
-                Unset => {
-                    return len;
-                }
-            }
-        }
-    }
-    fn length_tables(&self) -> u32 {
-        use self::CtxMember::*;
-
-        let mut len = 0;
-        let mut cursor = &self.tables;
-        loop {
-            match cursor {
-                Delegated(next) => {
-                    cursor = next;
-                }
-                Prepended(v, next) => {
-                    len += v.len() as u32;
-                    cursor = next;
-                }
-                Set(v) => {
-                    len += v.len() as u32;
-                    return len;
-                }
-                Unset => {
-                    return len;
-                }
-            }
-        }
+        self.func_types = Cow::Owned(func_types);
+        self.types = Cow::Owned(types);
+        self
     }
+
+    ctx_set!(set_locals(self,  locals:  Vec<ValType>));
+    ctx_set!(set_funcs(self,   funcs:   Vec<TypeIdx>));
+    ctx_set!(set_tables(self,  tables:  Vec<TableType>));
+    ctx_set!(set_mems(self,    mems:    Vec<MemType>));
+    ctx_set!(set_globals(self, globals: Cow<'a, [GlobalType]>) -> globals);
+
+    ctx_set!(set_return_(self, return_: ResultType) -> Some(return_));
 }
 
 macro_rules! valid_with {
@@ -265,6 +512,49 @@ pub struct ImportExportMapping {
 
 pub struct Valid;
 
+/// Side tables of a function body, computed during validation.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FuncInfo {
+    /// The maximum number of operands on the stack.
+    pub max_stack_depth: u32,
+    /// The maximum number of labels in scope, including the
+    /// one of the function body itself.
+    pub max_label_depth: u32,
+    /// All br, br_if, br_table and return instructions, in order.
+    pub branches: Vec<Branch>,
+    /// The proposals whose instructions are used.
+    pub features: WasmFeatures,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    /// The location of the instruction, as in `ValidationError::instr`.
+    pub instr: Vec<u32>,
+    /// The resolved labels. For br_table, the default label is last.
+    pub targets: Vec<BranchTarget>,
+}
+
+impl Branch {
+    /// The location of the block, loop or if instruction that `target`
+    /// refers to, or an empty path for the function body.
+    pub fn target_instr(&self, target: &BranchTarget) -> &[u32] {
+        &self.instr[..target.depth as usize]
+    }
+}
+
+/// The stack adjustment of a branch is to keep the top `arity` operands,
+/// and to drop the `drop` operands below them.
+///
+/// NB: In unreachable code the operand stack is not known,
+/// so `drop` only counts the operands that are known to be there.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BranchTarget {
+    /// The nesting depth of the target, with 0 being the function body.
+    pub depth: u32,
+    pub arity: u32,
+    pub drop: u32,
+}
+
 pub mod validate {
     use super::*;
 
@@ -283,12 +573,22 @@ pub mod validate {
             c.error(FunctionTypeResultArityGreaterOne)?
         }
 
+        c.allowed_valtypes(&function_type.args)?;
+        c.allowed_valtypes(&function_type.results)?;
+
         Valid
     });
 
     valid_with!((c, table_type: TableType) -> Valid {
         validate::limit(c, &table_type.limits)?;
 
+        let min = table_type.limits.min;
+        let max = table_type.limits.max.unwrap_or(min);
+
+        if min > c.limits.max_table_size || max > c.limits.max_table_size {
+            c.error(TooManyTableElements(c.limits.max_table_size))?;
+        }
+
         Valid
     });
 
@@ -305,24 +605,22 @@ pub mod validate {
             c.error(MemoryBoundsOutside32Bit)?;
         }
 
+        if min > c.limits.max_memory_pages || max > c.limits.max_memory_pages {
+            c.error(TooManyMemoryPages(c.limits.max_memory_pages))?;
+        }
+
         Valid
     });
 
     valid_with!((c, global_type: GlobalType) -> Valid {
-        let _ = c;
-        let _ = global_type;
+        c.allowed_valtypes(&[global_type.valtype])?;
 
         Valid
     });
 
     // Stack based instruction validator as presented in the spec appendix:
 
-    #[derive(Debug, PartialEq, Copy, Clone)]
-    enum ValTypeOrUnknown {
-        ValType(ValType),
-        Unknown
-    }
-    use self::ValTypeOrUnknown::Unknown;
+    // NB: Operands of unknown type are `None`
 
     #[derive(Debug, PartialEq, Clone)]
     struct CtrlFrame {
@@ -330,6 +628,8 @@ pub mod validate {
         end_types: Vec<ValType>,
         height: u32,
         unreachable: bool,
+        // An if whose else has not been seen yet
+        is_if: bool,
     }
 
     struct Stack<T> {
@@ -375,8 +675,13 @@ pub mod validate {
     }
 
     pub struct InstrCtx {
-        opds: Stack<ValTypeOrUnknown>,
+        opds: Stack<Option<ValType>>,
         ctrls: Stack<CtrlFrame>,
+        // Location of the current instruction
+        path: Vec<u32>,
+        // Number of instructions so far, see `ValidationLimits::max_body_size`
+        instrs: u32,
+        info: FuncInfo,
     }
 
     impl InstrCtx {
@@ -384,44 +689,67 @@ pub mod validate {
             InstrCtx{
                 ctrls: Stack::new(),
                 opds: Stack::new(),
+                path: Vec::new(),
+                instrs: 0,
+                info: FuncInfo::default(),
+            }
+        }
+
+        /// Starts a sequence with the result type `with`.
+        fn with_label(with: ResultType) -> Self {
+            let mut ic = InstrCtx::new();
+            ic.push_ctrl(&with, &with);
+            ic.path.push(0);
+            ic
+        }
+
+        fn finish(self) -> VResult<FuncInfo> {
+            if self.ctrls.size() != 0 {
+                return Err(ValidationError::new(InstrMissingEnd));
             }
+            Ok(self.info)
         }
 
-        fn push_opd(&mut self, type_: ValTypeOrUnknown) {
+        fn push_opd(&mut self, type_: Option<ValType>) {
             self.opds.push(type_);
+            self.info.max_stack_depth = self.info.max_stack_depth.max(self.opds.size());
+        }
+
+        fn pop_opd(&mut self) -> VResult<Option<ValType>> {
+            self.pop_opd_expect(None)
         }
 
-        fn pop_opd(&mut self) -> VResult<ValTypeOrUnknown> {
+        fn pop_opd_expect(&mut self, expect: Option<ValType>) -> VResult<Option<ValType>> {
             if self.opds.size() ==  self.ctrls.at(0).height
                 &&  self.ctrls.at(0).unreachable
             {
-                return Ok(Unknown);
+                return Ok(expect);
             }
             if self.opds.size() == self.ctrls.at(0).height {
-                self.error()?;
+                self.error(InstrOperandStackUnderflow {
+                    expected: expect,
+                })?;
             }
-            return Ok(self.opds.pop());
-        }
-
-        fn pop_opd_expect(&mut self, expect: ValTypeOrUnknown) -> VResult<ValTypeOrUnknown> {
-            let actual = self.pop_opd()?;
-            if actual == Unknown { return Ok(expect); }
-            if expect == Unknown { return Ok(actual); }
-            if actual != expect {
-                self.error()?;
+            let actual = self.opds.pop();
+            if actual.is_none() { return Ok(expect); }
+            if expect.is_none() { return Ok(actual); }
+            if let (Some(expected), Some(actual)) = (expect, actual) {
+                if actual != expected {
+                    self.error(InstrOperandTypeMismatch { expected, actual })?;
+                }
             }
             return Ok(actual);
         }
 
         fn push_opds(&mut self, types: &[ValType]) {
             for t in types {
-                self.push_opd(ValTypeOrUnknown::ValType(*t));
+                self.push_opd(Some(*t));
             }
         }
 
         fn pop_opds(&mut self, types: &[ValType]) -> VResult<Valid> {
             for t in types.iter().rev() {
-                self.pop_opd_expect(ValTypeOrUnknown::ValType(*t))?;
+                self.pop_opd_expect(Some(*t))?;
             }
             Ok(Valid)
         }
@@ -432,18 +760,52 @@ pub mod validate {
                 end_types: out.to_owned(),
                 height: self.opds.size(),
                 unreachable: false,
+                is_if: false,
             };
             self.ctrls.push(frame);
+            self.info.max_label_depth = self.info.max_label_depth.max(self.ctrls.size());
+        }
+
+        /// Resolves label `n` with the operands that are currently on the stack.
+        fn branch_target(&self, n: u32) -> BranchTarget {
+            let frame = self.ctrls.at(n);
+            let arity = frame.label_types.len() as u32;
+            BranchTarget {
+                depth: self.ctrls.size() - 1 - n,
+                arity,
+                drop: self.opds.size().saturating_sub(frame.height + arity),
+            }
+        }
+
+        /// Fails unless `feature` is enabled, and records that it is used.
+        fn use_feature(&mut self, c: &Ctx, feature: Feature) -> VResult<Valid> {
+            if !c.features.enabled(feature) {
+                self.error(FeatureDisabled(feature))?;
+            }
+            self.info.features.enable(feature);
+            Ok(Valid)
+        }
+
+        fn branch(&mut self, targets: Vec<BranchTarget>) {
+            self.info.branches.push(Branch {
+                instr: self.path.clone(),
+                targets,
+            });
         }
 
         fn pop_ctrl(&mut self) -> VResult<Vec<ValType>> {
             if self.ctrls.size() == 0 {
-                self.error()?;
+                self.error(InstrEndWithoutBlock)?;
             }
             let frame = self.ctrls.at(0).clone(); // TODO: Bad clone
+            let height = frame.height as usize;
+            let actual = self.opds.data[height.min(self.opds.data.len())..].to_vec();
             self.pop_opds(&frame.end_types)?;
             if self.opds.size() != frame.height {
-                self.error()?;
+                self.error(InstrOperandStackNotEmpty {
+                    expected: frame.end_types.clone(),
+                    actual,
+                })?;
             }
             self.ctrls.pop();
             return Ok(frame.end_types);
@@ -454,10 +816,8 @@ pub mod validate {
             self.ctrls.at_mut(0).unreachable = true;
         }
 
-        fn error(&mut self) -> VResult<Valid> {
-            Err(ValidationError{
-                kind: InstrWithTypeNotValid
-            })
+        fn error(&mut self, error: ValidationErrorEnum) -> VResult<Valid> {
+            Err(ValidationError::new(error))
         }
 
         fn simple_instr(&mut self, args: &[ValType], results: &[ValType]) -> VResult<Valid> {
@@ -471,6 +831,55 @@ pub mod validate {
             self.push_opds(&results);
             Ok(Valid)
         }
+
+        /// Counts an instruction towards the size of the body.
+        fn count_instr(&mut self, c: &Ctx) -> VResult<Valid> {
+            self.instrs += 1;
+            if self.instrs > c.limits.max_body_size {
+                self.error(FuncBodyTooLarge(c.limits.max_body_size))?;
+            }
+            Ok(Valid)
+        }
+
+        /// Fails if a new block would be nested too deep,
+        /// or has a result type that is not allowed.
+        fn enter_block(&mut self, c: &Ctx, resulttype: ResultType) -> VResult<Valid> {
+            // NB: The label of the body itself does not count
+            if self.ctrls.size() > c.limits.max_nesting {
+                self.error(NestingTooDeep(c.limits.max_nesting))?;
+            }
+            c.allowed_valtypes(&resulttype)?;
+            Ok(Valid)
+        }
+
+        fn block(&mut self, c: &Ctx, resulttype: ResultType) -> VResult<Valid> {
+            self.enter_block(c, resulttype)?;
+            self.push_ctrl(&resulttype, &resulttype);
+            Ok(Valid)
+        }
+
+        fn loop_(&mut self, c: &Ctx, resulttype: ResultType) -> VResult<Valid> {
+            self.enter_block(c, resulttype)?;
+            self.push_ctrl(&[], &resulttype);
+            Ok(Valid)
+        }
+
+        fn if_(&mut self, c: &Ctx, resulttype: ResultType) -> VResult<Valid> {
+            self.enter_block(c, resulttype)?;
+            self.pop_opd_expect(Some(ValType::I32))?;
+            self.push_ctrl(&resulttype, &resulttype);
+            self.ctrls.at_mut(0).is_if = true;
+            Ok(Valid)
+        }
+
+        fn else_(&mut self) -> VResult<Valid> {
+            if self.ctrls.size() == 0 || !self.ctrls.at(0).is_if {
+                self.error(InstrElseWithoutIf)?;
+            }
+            let results = self.pop_ctrl()?;
+            self.push_ctrl(&results, &results);
+            Ok(Valid)
+        }
     }
 
     valid_with!((c, instruction: Instr, ic: &mut InstrCtx) -> Valid {
@@ -481,6 +890,7 @@ pub mod validate {
             (:$ci:expr; $($arg:expr),* ; $($result:expr),*) => (
                 {
                     let ic = $ci;
+                    c.allowed_valtypes(&[$($arg,)* $($result),*])?;
                     ic.simple_instr(&[$($arg),*], &[$($result),*])?;
                     Valid
                 }
@@ -490,6 +900,12 @@ pub mod validate {
             )
         }
 
+        ic.count_instr(c)?;
+        match *instruction {
+            Block(..) | Loop(..) | IfElse(..) => (),
+            _ => c.allowed_instr(instruction)?,
+        }
+
         let ty = match *instruction {
             // numeric instructions
             I32Const(_) => ity![ ; I32],
@@ -621,6 +1037,21 @@ pub mod validate {
             F32ReinterpretI32 => ity![I32 ; F32],
             F64ReinterpretI64 => ity![I64 ; F64],
 
+            // sign-extension operators
+            | I32Extend8S
+            | I32Extend16S
+            => {
+                ic.use_feature(c, Feature::SignExtension)?;
+                ity![I32 ; I32]
+            }
+            | I64Extend8S
+            | I64Extend16S
+            | I64Extend32S
+            => {
+                ic.use_feature(c, Feature::SignExtension)?;
+                ity![I64 ; I64]
+            }
+
             I32TruncUF32 | I32TruncSF32 => ity![F32 ; I32],
             I32TruncUF64 | I32TruncSF64 => ity![F64 ; I32],
             I64TruncUF32 | I64TruncSF32 => ity![F32 ; I64],
@@ -644,7 +1075,7 @@ pub mod validate {
                 Valid
             },
             Select => {
-                ic.pop_opd_expect(ValTypeOrUnknown::ValType(I32))?;
+                ic.pop_opd_expect(Some(I32))?;
                 let t1 = ic.pop_opd()?;
                 let t2 = ic.pop_opd_expect(t1)?;
                 ic.push_opd(t2);
@@ -763,13 +1194,9 @@ pub mod validate {
                 Valid
             },
             Block(resulttype, ref block) => {
-                let c_ = c.with().prepend_label(resulttype);
-
-                // "block" event
-                let a = &resulttype;
-                ic.push_ctrl(&a, &a);
+                ic.block(c, resulttype)?;
 
-                validate::instruction_sequence(&c_, block, ic)?;
+                validate::instruction_sequence(c, block, ic)?;
 
                 // "end" event
                 ic.end_instr()?;
@@ -777,13 +1204,9 @@ pub mod validate {
                 Valid
             }
             Loop(resulttype, ref block) => {
-                let c_ = c.with().prepend_label(None.into());
+                ic.loop_(c, resulttype)?;
 
-                // "loop" event
-                let a = &resulttype;
-                ic.push_ctrl(&[], &a);
-
-                validate::instruction_sequence(&c_, block, ic)?;
+                validate::instruction_sequence(c, block, ic)?;
 
                 // "end" event
                 ic.end_instr()?;
@@ -791,20 +1214,13 @@ pub mod validate {
                 Valid
             }
             IfElse(resulttype, ref if_block, ref else_block) => {
-                let c_ = c.with().prepend_label(resulttype);
-
-                // "if" event
-                ic.pop_opd_expect(ValTypeOrUnknown::ValType(I32))?;
-                let a = &resulttype;
-                ic.push_ctrl(&a, &a);
+                ic.if_(c, resulttype)?;
 
-                validate::instruction_sequence(&c_, if_block, ic)?;
+                validate::instruction_sequence(c, if_block, ic)?;
 
-                // "else" event
-                let results = ic.pop_ctrl()?;
-                ic.push_ctrl(&results, &results);
+                ic.else_()?;
 
-                validate::instruction_sequence(&c_, else_block, ic)?;
+                validate::sequence_from(c, else_block, ic, if_block.len() as u32)?;
 
                 // "end" event
                 ic.end_instr()?;
@@ -812,53 +1228,53 @@ pub mod validate {
                 Valid
             }
             Br(labelidx) => {
-                let resulttype = c.labels(labelidx)?;
-
                 let n = labelidx.0;
                 if !(n < ic.ctrls.size()) {
-                    ic.error()?;
+                    ic.error(CtxLabelsIdxDoesNotExist(labelidx))?;
                 }
+                let target = ic.branch_target(n);
+                ic.branch(vec![target]);
                 let tmp = ic.ctrls.at(n).label_types.to_owned(); // TODO: Bad copy
-                assert_eq!(*resulttype, tmp[..]);
                 ic.pop_opds(&tmp)?;
                 ic.unreachable();
 
                 Valid
             }
             BrIf(labelidx) => {
-                let resulttype = c.labels(labelidx)?;
-
                 let n = labelidx.0;
                 if !(n < ic.ctrls.size()) {
-                    ic.error()?;
+                    ic.error(CtxLabelsIdxDoesNotExist(labelidx))?;
                 }
-                ic.pop_opd_expect(ValTypeOrUnknown::ValType(I32))?;
+                ic.pop_opd_expect(Some(I32))?;
+                let target = ic.branch_target(n);
+                ic.branch(vec![target]);
                 let tmp = ic.ctrls.at(n).label_types.to_owned(); // TODO: Bad copy
-                assert_eq!(*resulttype, tmp[..]);
                 ic.pop_opds(&tmp)?;
                 ic.push_opds(&tmp);
 
                 Valid
             }
             BrTable(ref labelindices, labelidx_n) => {
-                let resulttype = c.labels(labelidx_n)?;
-
                 let ns = labelindices;
                 let m = labelidx_n.0;
 
                 if !(m < ic.ctrls.size()) {
-                    ic.error()?;
+                    ic.error(CtxLabelsIdxDoesNotExist(labelidx_n))?;
                 }
                 for &LabelIdx(n) in ns {
-                    if !(n < ic.ctrls.size())
-                    || ic.ctrls.at(n).label_types != ic.ctrls.at(m).label_types
-                    {
-                        ic.error()?;
+                    if !(n < ic.ctrls.size()) {
+                        ic.error(CtxLabelsIdxDoesNotExist(LabelIdx(n)))?;
+                    }
+                    if ic.ctrls.at(n).label_types != ic.ctrls.at(m).label_types {
+                        ic.error(InstrBrTableNotSameLabelType)?;
                     }
                 }
-                ic.pop_opd_expect(ValTypeOrUnknown::ValType(I32))?;
+                ic.pop_opd_expect(Some(I32))?;
+                let targets = ns.iter().map(|&LabelIdx(n)| n).chain(Some(m))
+                    .map(|n| ic.branch_target(n))
+                    .collect();
+                ic.branch(targets);
                 let tmp = ic.ctrls.at(m).label_types.to_owned(); // TODO: Bad copy
-                assert_eq!(*resulttype, tmp[..]);
                 ic.pop_opds(&tmp)?;
                 ic.unreachable();
 
@@ -869,13 +1285,15 @@ pub mod validate {
                 // Empty result != missing result
                 // See note on 3.3.5.9 in spec
 
-                let resulttype = c.return_(LabelIdx(0))?;
+                let resulttype = c.return_()?;
 
                 // TODO: Not sure if implemented correctly
                 // Following the wording on 2.4.5., returns is equivalent
                 // to br(max_label)
 
                 let n = ic.ctrls.size() - 1;
+                let target = ic.branch_target(n);
+                ic.branch(vec![target]);
                 let tmp = ic.ctrls.at(n).label_types.to_owned(); // TODO: Bad copy
                 assert_eq!(*resulttype, tmp[..]);
                 ic.pop_opds(&tmp)?;
@@ -900,7 +1318,7 @@ pub mod validate {
                 }
                 let ty = c.types(x)?;
 
-                ic.pop_opd_expect(ValTypeOrUnknown::ValType(I32))?;
+                ic.pop_opd_expect(Some(I32))?;
                 ic.pop_opds(&ty.args)?;
                 ic.push_opds(&ty.results);
 
@@ -912,77 +1330,239 @@ pub mod validate {
     });
 
     valid_with!((c, instruction_sequence: [Instr], ic: &mut InstrCtx) -> Valid {
-        for instr_n in instruction_sequence {
-            validate::instruction(&c, instr_n, ic)?;
-        }
-        Valid
+        validate::sequence_from(c, instruction_sequence, ic, 0)?
     });
 
+    /// Validates a sequence whose first instruction has the index `first`
+    /// in the instruction path, which is not 0 for else branches.
+    fn sequence_from(c: &Ctx, instrs: &[Instr], ic: &mut InstrCtx, first: u32) -> VResult<Valid> {
+        ic.path.push(first);
+        for instr_n in instrs {
+            validate::instruction(c, instr_n, ic).map_err(|mut e| {
+                // NB: Only the innermost sequence knows the full path
+                if e.instr.is_empty() {
+                    e.0.instr = ic.path.clone();
+                }
+                e
+            })?;
+            *ic.path.last_mut().unwrap() += 1;
+        }
+        ic.path.pop();
+        Ok(Valid)
+    }
+
     valid_with!((c, expr: Expr, with: ResultType) -> Valid {
-        let mut ic = InstrCtx::new();
+        validate::expr_info(c, expr, with)?;
+        Valid
+    });
 
-        // "block" event
-        let a = &with;
-        ic.push_ctrl(&a, &a);
+    /// Like `expr`, but also returns the side tables of the expression.
+    pub fn expr_info(c: &Ctx, expr: &Expr, with: ResultType) -> VResult<FuncInfo> {
+        validate::source_info(c, expr, with)
+    }
 
+    /// Like `expr_info`, for any representation of the instructions.
+    pub fn source_info<S: InstrSource + ?Sized>(c: &Ctx, src: &S, with: ResultType)
+        -> VResult<FuncInfo>
+    {
         // TODO: According to the wording of the spec, pushing
         // the with label is unneeded, but it seems weird not to do it...
+        let mut ic = InstrCtx::with_label(with);
 
-        // let c_ = c.with().prepend_label(with);
-        validate::instruction_sequence(&c, &expr.body, &mut ic)?;
+        src.operators(&mut |op| validate::operator(c, &mut ic, op))?;
 
-        // "end" event
-        ic.end_instr()?;
+        ic.finish()
+    }
 
-        Valid
-    });
+    /// Validates the next operator of a sequence, and keeps
+    /// track of the location of the instructions.
+    fn operator(c: &Ctx, ic: &mut InstrCtx, op: Operator) -> VResult<Valid> {
+        if ic.ctrls.size() == 0 {
+            ic.error(InstrAfterEnd)?;
+        }
+
+        // NB: Else and end are located at their if or block instruction
+        let last = match op {
+            Operator::Else | Operator::End => ic.path.pop(),
+            _ => None,
+        };
+
+        let r = match op {
+            Operator::Instr(instr) => validate::instruction(c, instr, ic),
+            Operator::Block(resulttype) => {
+                ic.count_instr(c).and_then(|_| ic.block(c, resulttype))
+            }
+            Operator::Loop(resulttype) => {
+                ic.count_instr(c).and_then(|_| ic.loop_(c, resulttype))
+            }
+            Operator::If(resulttype) => {
+                ic.count_instr(c).and_then(|_| ic.if_(c, resulttype))
+            }
+            Operator::Else => ic.else_(),
+            Operator::End => {
+                // An if without else has an empty else branch
+                if ic.ctrls.at(0).is_if {
+                    ic.else_().and_then(|_| ic.end_instr())
+                } else {
+                    ic.end_instr()
+                }
+            }
+        };
+        if let Err(mut e) = r {
+            if e.instr.is_empty() {
+                e.0.instr = ic.path.clone();
+            }
+            return Err(e);
+        }
+
+        match op {
+            Operator::Instr(_) => *ic.path.last_mut().unwrap() += 1,
+            Operator::Block(_) | Operator::Loop(_) | Operator::If(_) => ic.path.push(0),
+            Operator::Else => ic.path.push(last.unwrap()),
+            Operator::End => {
+                if let Some(n) = ic.path.last_mut() {
+                    *n += 1;
+                }
+            }
+        }
+        Ok(Valid)
+    }
+
+    /// A single operator of a function body, in the order of the binary format.
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum Operator<'i> {
+        /// Any instruction. Block, loop and if instructions
+        /// are validated together with their nested instructions.
+        Instr(&'i Instr),
+        Block(ResultType),
+        Loop(ResultType),
+        If(ResultType),
+        Else,
+        End,
+    }
+
+    /// Validates a function body one operator at a time, without
+    /// building a `Func` first.
+    ///
+    /// The body is completed by the `End` operator of the function itself.
+    /// Errors are located like those of `validate_module`, but without the
+    /// function index. After an error, the validator should not be used anymore.
+    pub struct FuncValidator<'a> {
+        c: Ctx<'a>,
+        ic: InstrCtx,
+    }
+
+    impl<'a> FuncValidator<'a> {
+        /// Starts the body of a function of type `ty` with the declared `locals`.
+        ///
+        /// `c` is the context of the module, see `Ctx::for_module`.
+        pub fn new(c: &'a Ctx<'a>, ty: &FuncType, locals: &[ValType]) -> VResult<Self> {
+            validate::function_type(c, ty)?;
+            let (c, result) = func_ctx(c, ty, locals)?;
+
+            Ok(FuncValidator { c, ic: InstrCtx::with_label(result) })
+        }
+
+        /// Validates the next operator, and returns the operand stack after it.
+        pub fn op(&mut self, op: Operator) -> VResult<&[Option<ValType>]> {
+            validate::operator(&self.c, &mut self.ic, op)?;
+            Ok(&self.ic.opds.data)
+        }
+
+        /// The operand stack. Operands of unknown type in unreachable code are `None`.
+        pub fn operands(&self) -> &[Option<ValType>] {
+            &self.ic.opds.data
+        }
+
+        /// The number of labels in scope, including the one of the function body.
+        pub fn label_depth(&self) -> u32 {
+            self.ic.ctrls.size()
+        }
+
+        /// Whether the rest of the current block is unreachable.
+        pub fn is_unreachable(&self) -> bool {
+            self.ic.ctrls.size() > 0 && self.ic.ctrls.at(0).unreachable
+        }
+
+        /// Completes the body, and returns its side tables.
+        pub fn finish(self) -> VResult<FuncInfo> {
+            self.ic.finish()
+        }
+    }
 
     valid_with!((c, const_expr: Expr) -> Valid {
-        for instr in &const_expr.body {
+        validate::const_source(c, const_expr)?
+    });
+
+    /// Like `const_expr`, for any representation of the instructions.
+    pub fn const_source<S: InstrSource + ?Sized>(c: &Ctx, src: &S) -> VResult<Valid> {
+        src.operators(&mut |op| {
             use self::Instr::*;
-            match *instr {
-                I32Const(_) => (),
-                I64Const(_) => (),
-                F32Const(_) => (),
-                F64Const(_) => (),
-                GetGlobal(x) => {
+            match op {
+                Operator::Instr(&I32Const(_)) => (),
+                Operator::Instr(&I64Const(_)) => (),
+                Operator::Instr(&F32Const(_)) => (),
+                Operator::Instr(&F64Const(_)) => (),
+                Operator::Instr(&GetGlobal(x)) => {
                     if c.globals(x)?.mutability != Mut::Const {
                         c.error(ConstExprGetGlobalNotConst)?;
                     }
                 }
+                Operator::End => (),
                 _ => {
                     c.error(ConstExprIlligalInstruction)?;
                 }
             }
-        }
-
-        Valid
-    });
+            Ok(Valid)
+        })
+    }
 
-    valid_with!((c, func: Func) -> FuncType {
-        let Func { type_: x, locals: t, body: expr } = func;
-        let ty = c.types(*x)?;
+    /// The context of a function body, and its result type.
+    fn func_ctx<'a>(c: &'a Ctx<'a>, ty: &FuncType, t: &[ValType]) -> VResult<(Ctx<'a>, ResultType)> {
+        let locals: Vec<_> = ty.args.iter().chain(t).cloned().collect();
 
-        let locals = ty.args.iter().chain(t).cloned().collect();
+        if locals.len() as u64 > c.limits.max_locals as u64 {
+            c.error(TooManyLocals(c.limits.max_locals))?;
+        }
+        c.allowed_valtypes(t)?;
 
-        assert!(ty.results.len() <= 1); // should be enforced by type validation
         let t2_opt = ty.results.get(0).cloned();
         let result: ResultType = t2_opt.into();
 
         let c_ = c.with()
             .set_locals(locals)
-            .set_label(result)
             .set_return_(result);
 
+        Ok((c_, result))
+    }
+
+    valid_with!((c, func: Func) -> FuncType {
+        validate::func_info(c, func)?.0
+    });
+
+    /// Like `func`, but also returns the side tables of the body.
+    pub fn func_info(c: &Ctx, func: &Func) -> VResult<(FuncType, FuncInfo)> {
+        validate::func_source_info(c, func)
+    }
+
+    /// Like `func_info`, for any representation of the function.
+    pub fn func_source_info<F: FuncSource + ?Sized>(c: &Ctx, func: &F)
+        -> VResult<(FuncType, FuncInfo)>
+    {
+        let ty = c.types(func.type_())?;
+
+        assert!(ty.results.len() <= 1); // should be enforced by type validation
+        let (c_, result) = func_ctx(c, ty, &func.locals())?;
+
         // TODO: The wording for function validation and expression
         // validation conflict in wether
         // the expr should be valid with [t2?] or t2?.
         //
         // Verify this from official sources at some point.
-        validate::expr(&c_, expr, result)?;
+        let info = validate::source_info(&c_, func, result)?;
 
-        ty
-    });
+        Ok((ty.clone(), info))
+    }
 
     valid_with!((c, table: Table) -> TableType {
         validate::table_type(c, &table.type_)?;
@@ -995,65 +1575,89 @@ pub mod validate {
     });
 
     valid_with!((c, global: Global) -> GlobalType {
-        let Global {
+        validate::global_source(c, &GlobalSource {
+            type_: global.type_,
+            init: &global.init,
+        })?
+    });
+
+    /// Like `global`, for any representation of the init expression.
+    pub fn global_source<E: InstrSource>(c: &Ctx, global: &GlobalSource<E>) -> VResult<GlobalType> {
+        let GlobalSource {
             type_,
-            init: expr
-        } = global;
+            init: ref expr
+        } = *global;
 
         let t = type_.valtype;
 
         validate::global_type(c, &type_)?;
-        validate::expr(c, &expr, t.into())?;
-        validate::const_expr(c, &expr)?;
+        validate::source_info(c, expr, t.into())?;
+        validate::const_source(c, expr)?;
 
-        *type_
-    });
+        Ok(type_)
+    }
 
     valid_with!((c, elem: Elem) -> Valid {
-        let Elem {
+        validate::elem_source(c, &ElemSource {
+            table: elem.table,
+            offset: &elem.offset,
+            init: Cow::Borrowed(&elem.init),
+        })?
+    });
+
+    /// Like `elem`, for any representation of the offset expression.
+    pub fn elem_source<E: InstrSource>(c: &Ctx, elem: &ElemSource<E>) -> VResult<Valid> {
+        let ElemSource {
             table: x,
-            offset: expr,
-            init: y
-        } = elem;
+            offset: ref expr,
+            init: ref y
+        } = *elem;
 
         let TableType {
             limits: _,
             elemtype
-        } = c.tables(*x)?;
+        } = c.tables(x)?;
 
         if elemtype != ElemType::AnyFunc {
             c.error(ElemElemTypeNotAnyFunc)?;
         }
 
-        validate::expr(c, expr, ValType::I32.into())?;
-        validate::const_expr(c, expr)?;
-        for yi in y {
+        validate::source_info(c, expr, ValType::I32.into())?;
+        validate::const_source(c, expr)?;
+        for yi in y.iter() {
             c.funcs(*yi)?;
         }
 
-        Valid
-    });
+        Ok(Valid)
+    }
 
     valid_with!((c, data: Data) -> Valid {
-        let Data {
+        validate::data_source(c, &DataSource {
+            data: data.data,
+            offset: &data.offset,
+        })?
+    });
+
+    /// Like `data`, for any representation of the offset expression.
+    pub fn data_source<E: InstrSource>(c: &Ctx, data: &DataSource<E>) -> VResult<Valid> {
+        let DataSource {
             data: x,
-            offset: expr,
-            init: _,
-        } = data;
+            offset: ref expr,
+        } = *data;
 
-        c.mems(*x)?;
-        validate::expr(c, expr, ValType::I32.into())?;
-        validate::const_expr(c, expr)?;
+        c.mems(x)?;
+        validate::source_info(c, expr, ValType::I32.into())?;
+        validate::const_source(c, expr)?;
 
-        Valid
-    });
+        Ok(Valid)
+    }
 
     valid_with!((c, start: Start) -> Valid {
         let Start {
             func: x
         } = start;
 
-        let ty: FuncType = c.funcs(*x)?;
+        let ty = c.funcs(*x)?;
 
         if !(ty.args.is_empty() && ty.results.is_empty()) {
             c.error(StartFunNotValidWithEmptyEmpty)?;
@@ -1067,7 +1671,7 @@ pub mod validate {
             ExportDesc::Func(x) => {
                 let func = c.funcs(x)?;
 
-                ExternType::Func(func)
+                ExternType::Func(func.clone())
             }
             ExportDesc::Table(x) => {
                 let table = c.tables(x)?;
@@ -1092,7 +1696,7 @@ pub mod validate {
             ImportDesc::Func(x) => {
                 let func_type = c.types(x)?;
 
-                ExternType::Func(func_type)
+                ExternType::Func(func_type.clone())
             }
             ImportDesc::Table(tabletype) => {
                 validate::table_type(c, &tabletype)?;
@@ -1113,26 +1717,30 @@ pub mod validate {
     });
 
     valid_with!((empty_c, module_prepass: Module) -> () {
-        let types = &module_prepass.types;
-        let imports = &module_prepass.imports;
-        for import in imports {
+        validate::source_prepass(empty_c, &module_prepass)?
+    });
+
+    /// Like `module_prepass`, for any representation of the module.
+    pub fn source_prepass<M: ModuleSource>(empty_c: &Ctx, module: &M) -> VResult<()> {
+        let types = module.types();
+        let imports = module.imports();
+        for import in imports.iter() {
             if let ImportDesc::Func(x) = import.desc {
                 if types.get(x.0 as usize).is_none() {
-                    empty_c.error(ModulePrepassImportFuncTypeIdxDoesNotExist)?;
+                    empty_c.error(ModulePrepassImportFuncTypeIdxDoesNotExist(x))?;
                 }
             }
         }
-    });
+        Ok(())
+    }
 
-    fn import_filter_funcs<'a>(types: &'a [FuncType],
-                               imports: &'a [Import])
-        -> impl Iterator<Item=&'a FuncType> + 'a
+    fn import_filter_funcs<'a>(imports: &'a [Import])
+        -> impl Iterator<Item=TypeIdx> + 'a
     {
         imports.iter().filter_map(move |import| {
             match import.desc {
                 ImportDesc::Func(x) => {
-                    // TODO: Catch None case early in validation
-                    types.get(x.0 as usize)
+                    Some(x)
                 }
                 _ => None,
             }
@@ -1178,104 +1786,185 @@ pub mod validate {
         })
     }
 
+    /// The context `C` of a module, which has to pass `module_prepass` first.
+    pub(crate) fn module_ctx<'a, M: ModuleSource>(empty_c: &Ctx<'a>, module: &M) -> Ctx<'a> {
+        let types = module.types();
+        let imports = module.imports();
+
+        let funcs_it   = import_filter_funcs(imports);
+        let tables_it  = import_filter_tables(imports).cloned();
+        let mems_it    = import_filter_mems(imports).cloned();
+        let globals_it = import_filter_globals(imports).cloned();
+
+        // NB: The functions refer to their type by index, so that a
+        // missing type is only an error if the function is used.
+        let fts = module.funcs().map(|x| x.type_());
+        let tts = module.tables();
+        let mts = module.mems();
+        let gts = module.globals().map(|x| x.type_);
+
+        let conc_funcs   = funcs_it  .chain(fts);
+        let conc_tables  = tables_it .chain(tts);
+        let conc_mems    = mems_it   .chain(mts);
+        let conc_globals = globals_it.chain(gts);
+
+        let c = Ctx::new(&ValidationConfig {
+            features: empty_c.features,
+            limits: empty_c.limits,
+            policy: empty_c.policy,
+        });
+        c.set_types(types)
+            .set_funcs(conc_funcs.collect())
+            .set_tables(conc_tables.collect())
+            .set_mems(conc_mems.collect())
+            .set_globals(Cow::Owned(conc_globals.collect()))
+    }
+
     valid_with!((empty_c, module: Module) -> ImportExportMapping {
-        let Module {
-            types,
-            funcs,
-            tables,
-            mems,
-            globals,
-            elem,
-            data,
-            start,
-            imports,
-            exports,
-        } = module;
-
-        validate::module_prepass(empty_c, module)?;
-
-        let c = {
-            let functypes = types;
-
-            let funcs_it   = import_filter_funcs(functypes, imports);
-            let tables_it  = import_filter_tables(imports);
-            let mems_it    = import_filter_mems(imports);
-            let globals_it = import_filter_globals(imports);
-
-            // TODO: Catch None case early in validation
-            let fts = funcs.iter().flat_map(|x| {
-                functypes.get(x.type_.0 as usize)
-            });
-            let tts = tables.iter() .map(|x| &x.type_);
-            let mts = mems.iter()   .map(|x| &x.type_);
-            let gts = globals.iter().map(|x| &x.type_);
-
-            let conc_funcs   = funcs_it  .chain(fts);
-            let conc_tables  = tables_it .chain(tts);
-            let conc_mems    = mems_it   .chain(mts);
-            let conc_globals = globals_it.chain(gts);
-
-            &empty_c.with()
-            .set_types(types.clone().into())
-            .set_funcs(conc_funcs.cloned().collect())
-            .set_tables(conc_tables.cloned().collect())
-            .set_mems(conc_mems.cloned().collect())
-            .set_globals(conc_globals.cloned().collect())
-        };
+        let mut errors = Vec::new();
+        match module_errors(empty_c, &module, &mut errors, false) {
+            Some((mapping, _infos)) => mapping,
+            None => return Err(errors.remove(0)),
+        }
+    });
+
+    /// Validates `module`, and pushes the errors to `errors`.
+    ///
+    /// Stops at the first error unless `all` is set. Returns the import and
+    /// export types and the side tables of the functions if no errors have been found.
+    pub(crate) fn module_errors<M: ModuleSource>(empty_c: &Ctx, module: &M,
+                                                 errors: &mut Vec<ValidationError>, all: bool)
+        -> Option<(ImportExportMapping, Vec<FuncInfo>)>
+    {
+        macro_rules! check {
+            ($e:expr) => (
+                match $e {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        errors.push(e);
+                        if !all {
+                            return None;
+                        }
+                        None
+                    }
+                }
+            )
+        }
+
+        let types   = module.types();
+        let start   = module.start();
+        let imports = module.imports();
+        let exports = module.exports();
+
+        // NB: The context can not be constructed with
+        // missing import types, so this stops even if `all` is set.
+        if let Err(e) = validate::source_prepass(empty_c, module) {
+            errors.push(e);
+            return None;
+        }
+
+        let c = &module_ctx(empty_c, module);
+
+        let imported_globals = import_filter_globals(imports).count();
+        let num_globals = imported_globals + module.globals().len();
+        if num_globals as u64 > c.limits.max_globals as u64 {
+            check!(c.error(TooManyGlobals(c.limits.max_globals)));
+        }
+
+        if exports.len() as u64 > c.limits.max_exports as u64 {
+            check!(c.error(TooManyExports(c.limits.max_exports)));
+        }
 
         let c_ = &empty_c.with()
-            .set_globals(import_filter_globals(&imports).cloned().collect());
+            .set_globals(Cow::Borrowed(&c.globals[..imported_globals]));
 
-        for functypei in types {
-            let Valid = validate::function_type(c, functypei)?;
+        for functypei in types.iter() {
+            check!(validate::function_type(c, functypei));
         }
 
-        for funci in funcs {
-            let _fti = validate::func(c, funci)?;
+        // Errors in function bodies are reported with the
+        // index of the function in the function index space
+        let imported_funcs = import_filter_funcs(imports).count();
+        let in_func = |i: usize| move |mut e: ValidationError| {
+            e.0.func = Some(FuncIdx((imported_funcs + i) as u32));
+            e
+        };
+
+        let mut infos = Vec::new();
+
+        #[cfg(not(feature = "parallel"))]
+        for (i, funci) in module.funcs().enumerate() {
+            if let Some((_fti, info)) = check!(validate::func_source_info(c, &funci).map_err(in_func(i))) {
+                infos.push(info);
+            }
+        }
+
+        // NB: The bodies are validated in any order, but the errors
+        // are reported by function index, same as with sequential validation.
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let funcs: Vec<_> = module.funcs().collect();
+            let ftis: Vec<_> = funcs.par_iter().enumerate()
+                .map(|(i, funci)| validate::func_source_info(c, funci).map_err(in_func(i)))
+                .collect();
+            for fti in ftis {
+                if let Some((_fti, info)) = check!(fti) {
+                    infos.push(info);
+                }
+            }
         }
 
-        for tablei in tables {
-            let _tabletypei = validate::table(c, tablei)?;
+        for tablei in module.tables() {
+            check!(validate::table_type(c, &tablei));
         }
 
-        for memi in mems {
-            let _memtypei = validate::mem(c, memi)?;
+        for memi in module.mems() {
+            check!(validate::memory_type(c, &memi));
         }
 
-        for globali in globals {
-            let _globaltypei = validate::global(c_, globali)?;
+        for globali in module.globals() {
+            check!(validate::global_source(c_, &globali));
         }
 
-        for elemi in elem {
-            let Valid = validate::elem(c, elemi)?;
+        for elemi in module.elem() {
+            check!(validate::elem_source(c, &elemi));
         }
 
-        for datai in data {
-            let Valid = validate::data(c, datai)?;
+        for datai in module.data() {
+            check!(validate::data_source(c, &datai));
         }
 
-        if let Some(ref start) = start {
-            let Valid = validate::start(c, start)?;
+        if let Some(func) = start {
+            check!(validate::start(c, &Start { func }));
         }
 
         let mut its = Vec::new();
-        for importi in imports {
-            let iti = validate::import(c, importi)?;
-            its.push(iti);
+        for (i, importi) in imports.iter().enumerate() {
+            if let Some(policy) = c.policy {
+                if !policy.allow_import(importi) {
+                    check!(c.error(PolicyImportNotAllowed(i as u32)));
+                }
+            }
+            if let Some(iti) = check!(validate::import(c, importi)) {
+                its.push(iti);
+            }
         }
 
         let mut ets = Vec::new();
-        for exporti in exports {
-            let eti = validate::export(c, exporti)?;
-            ets.push(eti);
+        for exporti in exports.iter() {
+            if let Some(eti) = check!(validate::export(c, exporti)) {
+                ets.push(eti);
+            }
         }
 
-        if c.length_tables() > 1 {
-            c.error(ModuleTablesLengthNotOne)?;
+        if c.tables.len() > 1 {
+            check!(c.error(ModuleTablesLengthNotOne));
         }
 
-        if c.length_mems() > 1 {
-            c.error(ModuleMemsLengthNotOne)?;
+        if c.mems.len() > 1 {
+            check!(c.error(ModuleMemsLengthNotOne));
         }
 
         {
@@ -1285,22 +1974,39 @@ pub mod validate {
             for w in export_names.windows(2) {
                 if let [a, b] = w {
                     if a == b {
-                        c.error(ModuleExportDuplicateName)?;
+                        check!(c.error(ModuleExportDuplicateName));
                     }
                 }
             }
         }
 
-        ImportExportMapping {
+        if !errors.is_empty() {
+            return None;
+        }
+
+        Some((ImportExportMapping {
             imports: its,
             exports: ets,
-        }
-    });
+        }, infos))
+    }
+}
+
+pub use validate::{FuncValidator, Operator};
+
+/// What validation finds out about a module, independent of its representation.
+#[derive(Debug, PartialEq)]
+pub struct ModuleInfo {
+    pub import_export_mapping: ImportExportMapping,
+    /// The side tables of the functions defined by the module.
+    pub func_infos: Vec<FuncInfo>,
+    /// The proposals the module uses, which is a subset
+    /// of the features it has been validated with.
+    pub features: WasmFeatures,
 }
 
 pub struct ValidatedModule {
     module: Module,
-    import_export_mapping: ImportExportMapping
+    info: ModuleInfo,
 }
 impl ::std::ops::Deref for ValidatedModule {
     type Target = Module;
@@ -1310,14 +2016,86 @@ impl ::std::ops::Deref for ValidatedModule {
 }
 impl ValidatedModule {
     pub fn import_export_mapping(&self) -> &ImportExportMapping {
-        &self.import_export_mapping
+        &self.info.import_export_mapping
+    }
+
+    /// The side tables of the functions defined by the module,
+    /// in the same order as `funcs`.
+    pub fn func_infos(&self) -> &[FuncInfo] {
+        &self.info.func_infos
+    }
+
+    /// The proposals the module uses, which is a subset
+    /// of the features it has been validated with.
+    pub fn features(&self) -> &WasmFeatures {
+        &self.info.features
+    }
+}
+
+fn validate_source_<M: ModuleSource>(module: M, config: &ValidationConfig, all: bool)
+    -> Result<ModuleInfo, Vec<ValidationError>>
+{
+    let mut errors = Vec::new();
+    let c = Ctx::new(config);
+    match validate::module_errors(&c, &module, &mut errors, all) {
+        Some((import_export_mapping, func_infos)) => {
+            let features = func_infos.iter()
+                .fold(WasmFeatures::mvp(), |f, info| f.union(&info.features));
+            Ok(ModuleInfo {
+                import_export_mapping,
+                func_infos,
+                features,
+            })
+        }
+        None => Err(errors),
     }
 }
 
+fn validate_module_(module: Module, config: &ValidationConfig, all: bool)
+    -> Result<ValidatedModule, Vec<ValidationError>>
+{
+    let info = validate_source_(&module, config, all)?;
+    Ok(ValidatedModule { module, info })
+}
+
 pub fn validate_module(module: Module) -> VResult<ValidatedModule> {
-    let import_export_mapping = validate::module(&Ctx::new(), &module)?;
-    Ok(ValidatedModule {
-        module,
-        import_export_mapping,
-    })
+    validate_module_with(module, &ValidationConfig::default())
+}
+
+/// Like `validate_module`, but with the features, limits and policy of the `config`.
+pub fn validate_module_with(module: Module, config: &ValidationConfig)
+    -> VResult<ValidatedModule>
+{
+    validate_module_(module, config, false).map_err(|mut errors| errors.remove(0))
+}
+
+/// Like `validate_module`, but keeps validating after an error,
+/// and returns all errors in the order they were found.
+///
+/// NB: The validation of a function body stops at its first error,
+/// so there is at most one error per function.
+pub fn validate_module_all(module: Module) -> Result<ValidatedModule, Vec<ValidationError>> {
+    validate_module_all_with(module, &ValidationConfig::default())
+}
+
+/// Combines `validate_module_all` and `validate_module_with`.
+pub fn validate_module_all_with(module: Module, config: &ValidationConfig)
+    -> Result<ValidatedModule, Vec<ValidationError>>
+{
+    validate_module_(module, config, true)
+}
+
+/// Validates a module in any representation. See `ModuleSource`.
+pub fn validate_source<M: ModuleSource>(module: M, config: &ValidationConfig)
+    -> VResult<ModuleInfo>
+{
+    validate_source_(module, config, false).map_err(|mut errors| errors.remove(0))
+}
+
+/// Like `validate_source`, but keeps validating after an error,
+/// as with `validate_module_all`.
+pub fn validate_source_all<M: ModuleSource>(module: M, config: &ValidationConfig)
+    -> Result<ModuleInfo, Vec<ValidationError>>
+{
+    validate_source_(module, config, true)
 }
@@ -0,0 +1,520 @@
+//! Non-fatal findings on valid modules.
+//!
+//! The lints point at code that is valid, but likely not intended,
+//! or that could be removed without changing the behavior of the module.
+//! Each lint has a `LintId`, a `Severity` that is configured
+//! with `LintConfig`, and a `LintLocation`.
+
+use std::fmt;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+use greenwasm_structure::visit::*;
+
+use super::ValidatedModule;
+use super::FuncInfo;
+
+/// A kind of finding, as named in the output of `Lint`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LintId {
+    /// Instructions after a br, br_table or return in the same block.
+    UnreachableCode,
+    /// A declared local that is never read or written. Parameters are not linted.
+    UnusedLocal,
+    /// An import that is not used by the code that can be reached
+    /// from the exports, the start function or the tables.
+    UnusedImport,
+    /// A function that can not be reached from the exports,
+    /// the start function or the tables.
+    UnusedFunc,
+    /// A select whose condition is a constant, so
+    /// that it always chooses the same operand.
+    SelectOnConstant,
+    /// A block that no branch targets, so that its
+    /// instructions could be inlined into the enclosing block.
+    RedundantBlock,
+    /// A memory that is neither exported nor accessed
+    /// by the code that can be reached.
+    UnusedMemory,
+    /// A mutable global that is exported.
+    ExportedMutableGlobal,
+}
+
+impl LintId {
+    pub const ALL: &'static [LintId] = &[
+        LintId::UnreachableCode,
+        LintId::UnusedLocal,
+        LintId::UnusedImport,
+        LintId::UnusedFunc,
+        LintId::SelectOnConstant,
+        LintId::RedundantBlock,
+        LintId::UnusedMemory,
+        LintId::ExportedMutableGlobal,
+    ];
+
+    fn description(&self) -> &'static str {
+        match *self {
+            LintId::UnreachableCode => "unreachable code",
+            LintId::UnusedLocal => "local is never used",
+            LintId::UnusedImport => "import is never used",
+            LintId::UnusedFunc => "function is never used",
+            LintId::SelectOnConstant => "select on a constant condition",
+            LintId::RedundantBlock => "block is never the target of a branch",
+            LintId::UnusedMemory => "memory is never accessed",
+            LintId::ExportedMutableGlobal => "mutable global is exported",
+        }
+    }
+}
+
+impl fmt::Display for LintId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            LintId::UnreachableCode => "unreachable-code",
+            LintId::UnusedLocal => "unused-local",
+            LintId::UnusedImport => "unused-import",
+            LintId::UnusedFunc => "unused-func",
+            LintId::SelectOnConstant => "select-on-constant",
+            LintId::RedundantBlock => "redundant-block",
+            LintId::UnusedMemory => "unused-memory",
+            LintId::ExportedMutableGlobal => "exported-mutable-global",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The lint is not reported.
+    Allow,
+    Warn,
+    /// The lint is reported as an error, for example to fail a CI check.
+    Deny,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Severity::Allow => "allow",
+            Severity::Warn => "warning",
+            Severity::Deny => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// The severity of each lint. The default is to warn about all of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintConfig {
+    levels: Vec<Severity>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig::all(Severity::Warn)
+    }
+}
+
+impl LintConfig {
+    /// All lints with the same `severity`.
+    pub fn all(severity: Severity) -> Self {
+        LintConfig {
+            levels: vec![severity; LintId::ALL.len()],
+        }
+    }
+
+    pub fn severity(&self, id: LintId) -> Severity {
+        self.levels[id as usize]
+    }
+
+    pub fn set(&mut self, id: LintId, severity: Severity) {
+        self.levels[id as usize] = severity;
+    }
+}
+
+/// What a lint refers to.
+///
+/// Functions are given by their index in the function index space, including
+/// the imported ones. Imports and exports are given by their position in the module.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintLocation {
+    /// An instruction, located as in `ValidationError::instr`.
+    Instr {
+        func: FuncIdx,
+        instr: Vec<u32>,
+    },
+    Local {
+        func: FuncIdx,
+        local: LocalIdx,
+    },
+    Func(FuncIdx),
+    Import(u32),
+    Mem(MemIdx),
+    Export(u32),
+}
+
+impl fmt::Display for LintLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LintLocation::Instr { func: FuncIdx(x), ref instr } => {
+                let path: Vec<_> = instr.iter().map(|i| i.to_string()).collect();
+                write!(f, "function {} at instruction {}", x, path.join("."))
+            }
+            LintLocation::Local { func: FuncIdx(x), local: LocalIdx(l) } => {
+                write!(f, "local {} of function {}", l, x)
+            }
+            LintLocation::Func(FuncIdx(x)) => write!(f, "function {}", x),
+            LintLocation::Import(x) => write!(f, "import {}", x),
+            LintLocation::Mem(MemIdx(x)) => write!(f, "memory {}", x),
+            LintLocation::Export(x) => write!(f, "export {}", x),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    pub id: LintId,
+    pub severity: Severity,
+    pub location: LintLocation,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {} ({})", self.severity, self.id, self.id.description(), self.location)
+    }
+}
+
+/// The state of the instruction sequence that is being scanned.
+struct Sequence {
+    after_branch: bool,
+    reported: bool,
+    after_const: bool,
+}
+
+/// What a function body refers to, and the lints of its instructions.
+struct FuncScan {
+    calls: Vec<FuncIdx>,
+    globals: Vec<GlobalIdx>,
+    accesses_mem: bool,
+    accesses_table: bool,
+    locals_used: Vec<bool>,
+    // Locations of the blocks, loops and ifs that are branch targets
+    targets: Vec<Vec<u32>>,
+    lints: Vec<(LintId, Vec<u32>)>,
+    // The location of the current instruction, and the
+    // state of each enclosing instruction sequence
+    path: Vec<u32>,
+    sequences: Vec<Sequence>,
+}
+
+impl FuncScan {
+    fn new(num_locals: usize, info: &FuncInfo) -> Self {
+        let mut targets: Vec<_> = info.branches.iter()
+            .flat_map(|b| b.targets.iter().map(move |t| b.target_instr(t).to_vec()))
+            .collect();
+        targets.sort();
+        targets.dedup();
+
+        FuncScan {
+            calls: Vec::new(),
+            globals: Vec::new(),
+            accesses_mem: false,
+            accesses_table: false,
+            locals_used: vec![false; num_locals],
+            targets,
+            lints: Vec::new(),
+            path: Vec::new(),
+            sequences: Vec::new(),
+        }
+    }
+
+    fn local(&mut self, x: LocalIdx) {
+        self.locals_used[x.0 as usize] = true;
+    }
+
+    fn lint(&mut self, id: LintId) {
+        self.lints.push((id, self.path.clone()));
+    }
+
+    fn sequence(&mut self) -> &mut Sequence {
+        self.sequences.last_mut().unwrap()
+    }
+
+    // NB: The instructions are numbered as by the validator, so
+    // that the locations of the branch targets can be compared.
+    fn walk_sequence(&mut self, instrs: &[Instr], first: u32) {
+        self.path.push(first);
+        self.sequences.push(Sequence {
+            after_branch: false,
+            reported: false,
+            after_const: false,
+        });
+        walk_instrs(self, instrs);
+        self.sequences.pop();
+        self.path.pop();
+    }
+}
+
+macro_rules! mem_hooks {
+    ($($hook:ident $(($arg:ident: $ty:ty))*,)*) => ($(
+        fn $hook(&mut self $(, $arg: &$ty)*) {
+            $(let _ = $arg;)*
+            self.accesses_mem = true;
+        }
+    )*)
+}
+
+impl Visit for FuncScan {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.walk_sequence(&expr.body, 0);
+    }
+
+    fn visit_instr(&mut self, instr: &Instr) {
+        // NB: Only the first unreachable instruction of a sequence is reported
+        if self.sequence().after_branch && !self.sequence().reported {
+            self.lint(LintId::UnreachableCode);
+            self.sequence().reported = true;
+        }
+        walk_instr(self, instr);
+        self.sequence().after_const = matches!(*instr, Instr::I32Const(_));
+        *self.path.last_mut().unwrap() += 1;
+    }
+
+    fn visit_block(&mut self, _resulttype: &ResultType, body: &[Instr]) {
+        if self.targets.binary_search(&self.path).is_err() {
+            self.lint(LintId::RedundantBlock);
+        }
+        self.walk_sequence(body, 0);
+    }
+    fn visit_loop(&mut self, _resulttype: &ResultType, body: &[Instr]) {
+        self.walk_sequence(body, 0);
+    }
+    fn visit_if_else(&mut self, _resulttype: &ResultType, then: &[Instr], else_: &[Instr]) {
+        self.walk_sequence(then, 0);
+        self.walk_sequence(else_, then.len() as u32);
+    }
+
+    fn visit_br(&mut self, _: &LabelIdx) {
+        self.sequence().after_branch = true;
+    }
+    fn visit_br_table(&mut self, _: &Wec<LabelIdx>, _: &LabelIdx) {
+        self.sequence().after_branch = true;
+    }
+    fn visit_return(&mut self) {
+        self.sequence().after_branch = true;
+    }
+
+    fn visit_select(&mut self) {
+        if self.sequence().after_const {
+            self.lint(LintId::SelectOnConstant);
+        }
+    }
+
+    fn visit_get_local(&mut self, x: &LocalIdx) {
+        self.local(*x);
+    }
+    fn visit_set_local(&mut self, x: &LocalIdx) {
+        self.local(*x);
+    }
+    fn visit_tee_local(&mut self, x: &LocalIdx) {
+        self.local(*x);
+    }
+    fn visit_get_global(&mut self, x: &GlobalIdx) {
+        self.globals.push(*x);
+    }
+    fn visit_set_global(&mut self, x: &GlobalIdx) {
+        self.globals.push(*x);
+    }
+
+    fn visit_call(&mut self, x: &FuncIdx) {
+        self.calls.push(*x);
+    }
+    fn visit_call_indirect(&mut self, _: &TypeIdx) {
+        self.accesses_table = true;
+    }
+
+    mem_hooks! {
+        visit_i32_load(m: Memarg),
+        visit_i64_load(m: Memarg),
+        visit_f32_load(m: Memarg),
+        visit_f64_load(m: Memarg),
+        visit_i32_store(m: Memarg),
+        visit_i64_store(m: Memarg),
+        visit_f32_store(m: Memarg),
+        visit_f64_store(m: Memarg),
+        visit_i32_load8_u(m: Memarg),
+        visit_i32_load8_s(m: Memarg),
+        visit_i64_load8_u(m: Memarg),
+        visit_i64_load8_s(m: Memarg),
+        visit_i32_load16_u(m: Memarg),
+        visit_i32_load16_s(m: Memarg),
+        visit_i64_load16_u(m: Memarg),
+        visit_i64_load16_s(m: Memarg),
+        visit_i64_load32_u(m: Memarg),
+        visit_i64_load32_s(m: Memarg),
+        visit_i32_store8(m: Memarg),
+        visit_i64_store8(m: Memarg),
+        visit_i32_store16(m: Memarg),
+        visit_i64_store16(m: Memarg),
+        visit_i64_store32(m: Memarg),
+        visit_current_memory,
+        visit_grow_memory,
+    }
+}
+
+fn const_globals(expr: &Expr, globals: &mut Vec<GlobalIdx>) {
+    for instr in &expr.body {
+        if let Instr::GetGlobal(x) = *instr {
+            globals.push(x);
+        }
+    }
+}
+
+/// Reports the lints of `module` that are not allowed by `config`,
+/// first those of the function bodies in order, then those of the module.
+pub fn lint_module(module: &ValidatedModule, config: &LintConfig) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut report = |id: LintId, location: LintLocation| {
+        let severity = config.severity(id);
+        if severity != Severity::Allow {
+            lints.push(Lint { id, severity, location });
+        }
+    };
+
+    // The imports in each index space
+    let mut func_imports = Vec::new();
+    let mut table_imports = Vec::new();
+    let mut mem_imports = Vec::new();
+    let mut global_imports = Vec::new();
+    for (i, import) in module.imports.iter().enumerate() {
+        match import.desc {
+            ImportDesc::Func(_) => func_imports.push(i),
+            ImportDesc::Table(_) => table_imports.push(i),
+            ImportDesc::Mem(_) => mem_imports.push(i),
+            ImportDesc::Global(_) => global_imports.push(i),
+        }
+    }
+    let func_offset = func_imports.len();
+
+    let mut scans = Vec::with_capacity(module.funcs.len());
+    for (i, (func, info)) in module.funcs.iter().zip(module.func_infos()).enumerate() {
+        let func_idx = FuncIdx((func_offset + i) as u32);
+        let params = module.types[func.type_.0 as usize].args.len();
+
+        let mut scan = FuncScan::new(params + func.locals.len(), info);
+        scan.visit_func(func);
+
+        for (id, instr) in scan.lints.drain(..) {
+            report(id, LintLocation::Instr { func: func_idx, instr });
+        }
+        for (l, &used) in scan.locals_used.iter().enumerate().skip(params) {
+            if !used {
+                report(LintId::UnusedLocal, LintLocation::Local {
+                    func: func_idx,
+                    local: LocalIdx(l as u32),
+                });
+            }
+        }
+        scans.push(scan);
+    }
+
+    // The functions that can be called, starting from the roots
+    let num_funcs = func_offset + module.funcs.len();
+    let mut reachable = vec![false; num_funcs];
+    let mut worklist = Vec::new();
+    worklist.extend(module.exports.iter().filter_map(|e| match e.desc {
+        ExportDesc::Func(x) => Some(x),
+        _ => None,
+    }));
+    worklist.extend(module.start.as_ref().map(|s| s.func));
+    for elem in &module.elem {
+        worklist.extend(elem.init.iter().cloned());
+    }
+    while let Some(FuncIdx(x)) = worklist.pop() {
+        let x = x as usize;
+        if reachable[x] {
+            continue;
+        }
+        reachable[x] = true;
+        if x >= func_offset {
+            worklist.extend(scans[x - func_offset].calls.iter().cloned());
+        }
+    }
+
+    // NB: Segments of imported tables and memories are visible
+    // to the outside, so they count as a use of the import.
+    let mut globals_used = Vec::new();
+    let mut table_used = !module.elem.is_empty() && !table_imports.is_empty();
+    let mut mem_used = !module.data.is_empty() && !mem_imports.is_empty();
+    for (scan, _) in scans.iter().zip(&reachable[func_offset..]).filter(|x| *x.1) {
+        globals_used.extend(scan.globals.iter().cloned());
+        table_used |= scan.accesses_table;
+        mem_used |= scan.accesses_mem;
+    }
+    for global in &module.globals {
+        const_globals(&global.init, &mut globals_used);
+    }
+    for elem in &module.elem {
+        const_globals(&elem.offset, &mut globals_used);
+    }
+    for data in &module.data {
+        const_globals(&data.offset, &mut globals_used);
+    }
+    for export in &module.exports {
+        match export.desc {
+            ExportDesc::Global(x) => globals_used.push(x),
+            ExportDesc::Table(_) => table_used = true,
+            ExportDesc::Mem(_) => mem_used = true,
+            ExportDesc::Func(_) => (),
+        }
+    }
+
+    for (i, &r) in reachable.iter().enumerate().skip(func_offset) {
+        if !r {
+            report(LintId::UnusedFunc, LintLocation::Func(FuncIdx(i as u32)));
+        }
+    }
+
+    for (i, import) in module.imports.iter().enumerate() {
+        let used = match import.desc {
+            ImportDesc::Func(_) => {
+                let x = func_imports.iter().position(|&j| j == i).unwrap();
+                reachable[x]
+            }
+            ImportDesc::Table(_) => table_used,
+            ImportDesc::Mem(_) => mem_used,
+            ImportDesc::Global(_) => {
+                let x = global_imports.iter().position(|&j| j == i).unwrap();
+                globals_used.contains(&GlobalIdx(x as u32))
+            }
+        };
+        if !used {
+            report(LintId::UnusedImport, LintLocation::Import(i as u32));
+        }
+    }
+
+    if !mem_used {
+        for i in 0..module.mems.len() {
+            report(LintId::UnusedMemory, LintLocation::Mem(MemIdx((mem_imports.len() + i) as u32)));
+        }
+    }
+
+    for (i, export) in module.exports.iter().enumerate() {
+        if let ExportDesc::Global(GlobalIdx(x)) = export.desc {
+            let x = x as usize;
+            let mutability = if x < global_imports.len() {
+                match module.imports[global_imports[x]].desc {
+                    ImportDesc::Global(ref t) => t.mutability,
+                    _ => unreachable!(),
+                }
+            } else {
+                module.globals[x - global_imports.len()].type_.mutability
+            };
+            if mutability == Mut::Var {
+                report(LintId::ExportedMutableGlobal, LintLocation::Export(i as u32));
+            }
+        }
+    }
+
+    lints
+}
@@ -0,0 +1,117 @@
+//! Restrictions of the valid modules to a subset, on top of the spec.
+//!
+//! A `ValidationPolicy` is consulted by the validator for each instruction,
+//! value type and import, and the parts it rejects are reported
+//! as `ValidationError`s at their location.
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+
+/// Decides which parts of a module are allowed. The default methods allow everything.
+pub trait ValidationPolicy: Sync {
+    /// Whether `instr` may be used.
+    ///
+    /// NB: This is not called for block, loop and if, whose
+    /// result types are checked with `allow_valtype` instead.
+    fn allow_instr(&self, instr: &Instr) -> bool {
+        let _ = instr;
+        true
+    }
+
+    /// Whether values of type `t` may be used, in function types, locals,
+    /// globals and block results, or as the operands of an instruction.
+    fn allow_valtype(&self, t: ValType) -> bool {
+        let _ = t;
+        true
+    }
+
+    fn allow_import(&self, import: &Import) -> bool {
+        let _ = import;
+        true
+    }
+}
+
+/// Allows what both policies allow.
+impl<A: ValidationPolicy, B: ValidationPolicy> ValidationPolicy for (A, B) {
+    fn allow_instr(&self, instr: &Instr) -> bool {
+        self.0.allow_instr(instr) && self.1.allow_instr(instr)
+    }
+
+    fn allow_valtype(&self, t: ValType) -> bool {
+        self.0.allow_valtype(t) && self.1.allow_valtype(t)
+    }
+
+    fn allow_import(&self, import: &Import) -> bool {
+        self.0.allow_import(import) && self.1.allow_import(import)
+    }
+}
+
+/// Rejects the float instructions whose results can be a NaN
+/// with a nondeterministic bit pattern.
+///
+/// The float instructions that only move, compare or reinterpret bits,
+/// like `f32.abs` or `f64.load`, are still allowed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Deterministic;
+
+impl ValidationPolicy for Deterministic {
+    fn allow_instr(&self, instr: &Instr) -> bool {
+        use self::Instr::*;
+
+        !matches!(*instr,
+            | F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max
+            | F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max
+            | F32Sqrt | F32Ceil | F32Floor | F32Trunc | F32Nearest
+            | F64Sqrt | F64Ceil | F64Floor | F64Trunc | F64Nearest
+            | F32DemoteF64 | F64PromoteF32)
+    }
+}
+
+/// Rejects the types `f32` and `f64`, and with them every
+/// instruction that has a float operand or result.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IntegerOnly;
+
+impl ValidationPolicy for IntegerOnly {
+    fn allow_valtype(&self, t: ValType) -> bool {
+        match t {
+            ValType::I32 | ValType::I64 => true,
+            ValType::F32 | ValType::F64 => false,
+        }
+    }
+}
+
+/// Only allows imports whose module and field names are on the list.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImportAllowList {
+    // A name of `None` allows all fields of the module
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl ImportAllowList {
+    pub fn new() -> Self {
+        ImportAllowList::default()
+    }
+
+    /// Allows the import of `name` from `module`.
+    pub fn allow(mut self, module: &str, name: &str) -> Self {
+        self.entries.push((module.to_owned(), Some(name.to_owned())));
+        self
+    }
+
+    /// Allows all imports from `module`.
+    pub fn allow_module(mut self, module: &str) -> Self {
+        self.entries.push((module.to_owned(), None));
+        self
+    }
+}
+
+impl ValidationPolicy for ImportAllowList {
+    fn allow_import(&self, import: &Import) -> bool {
+        self.entries.iter().any(|(module, name)| {
+            **import.module == *module
+                && name.as_ref().map(|n| **import.name == *n).unwrap_or(true)
+        })
+    }
+}
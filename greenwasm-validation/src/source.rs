@@ -0,0 +1,162 @@
+//! The parts of a module that validation looks at, as traits.
+//!
+//! `ModuleSource` describes the declarations of a module, and
+//! `InstrSource` the instruction stream of a function body or a constant
+//! expression, so that other representations than `Module`, like an IR or a
+//! view of the binary format, can be validated without converting them first.
+//!
+//! `&Module` is the implementation for the types of `greenwasm_structure`.
+
+use std::borrow::Cow;
+
+use greenwasm_structure::types::*;
+use greenwasm_structure::instructions::*;
+use greenwasm_structure::modules::*;
+
+use super::VResult;
+use super::Valid;
+use super::validate::Operator;
+
+/// A sequence of instructions, such as a function body or a constant expression.
+pub trait InstrSource {
+    /// Calls `f` with each operator of the sequence in order, ending with
+    /// the `End` of the sequence itself. Stops at the first error of `f`.
+    fn operators(&self, f: &mut dyn FnMut(Operator) -> VResult<Valid>) -> VResult<Valid>;
+}
+
+/// A function defined by a module.
+pub trait FuncSource: InstrSource {
+    fn type_(&self) -> TypeIdx;
+    /// The declared locals, without the parameters.
+    fn locals(&self) -> Cow<'_, [ValType]>;
+}
+
+/// A module, with the instructions of its function bodies and
+/// constant expressions given as `InstrSource`s.
+///
+/// NB: The validator may iterate over the entries more than once,
+/// so implementations that decode them should do so cheaply, or cache them.
+pub trait ModuleSource {
+    // NB: With the `parallel` feature, the function bodies are validated on a thread pool
+    #[cfg(feature = "parallel")]
+    type Func: FuncSource + Sync;
+    #[cfg(not(feature = "parallel"))]
+    type Func: FuncSource;
+    type Expr: InstrSource;
+
+    fn types(&self) -> &[FuncType];
+    fn imports(&self) -> &[Import];
+    /// The functions defined by the module, without the imported ones.
+    fn funcs(&self) -> impl Iterator<Item = Self::Func> + '_;
+    fn tables(&self) -> impl Iterator<Item = TableType> + '_;
+    fn mems(&self) -> impl Iterator<Item = MemType> + '_;
+    fn globals(&self) -> impl ExactSizeIterator<Item = GlobalSource<Self::Expr>> + '_;
+    fn elem(&self) -> impl Iterator<Item = ElemSource<'_, Self::Expr>> + '_;
+    fn data(&self) -> impl Iterator<Item = DataSource<Self::Expr>> + '_;
+    fn start(&self) -> Option<FuncIdx>;
+    fn exports(&self) -> &[Export];
+}
+
+pub struct GlobalSource<E> {
+    pub type_: GlobalType,
+    pub init: E,
+}
+
+pub struct ElemSource<'a, E> {
+    pub table: TableIdx,
+    pub offset: E,
+    pub init: Cow<'a, [FuncIdx]>,
+}
+
+/// NB: The initial bytes are not needed for validation.
+pub struct DataSource<E> {
+    pub data: MemIdx,
+    pub offset: E,
+}
+
+impl<T: InstrSource + ?Sized> InstrSource for &T {
+    fn operators(&self, f: &mut dyn FnMut(Operator) -> VResult<Valid>) -> VResult<Valid> {
+        (**self).operators(f)
+    }
+}
+
+impl<T: FuncSource + ?Sized> FuncSource for &T {
+    fn type_(&self) -> TypeIdx {
+        (**self).type_()
+    }
+    fn locals(&self) -> Cow<'_, [ValType]> {
+        (**self).locals()
+    }
+}
+
+// NB: Nested instructions are passed as a whole, since the
+// validator handles them without flattening them first.
+impl InstrSource for Expr {
+    fn operators(&self, f: &mut dyn FnMut(Operator) -> VResult<Valid>) -> VResult<Valid> {
+        for instr in &self.body {
+            f(Operator::Instr(instr))?;
+        }
+        f(Operator::End)
+    }
+}
+
+impl InstrSource for Func {
+    fn operators(&self, f: &mut dyn FnMut(Operator) -> VResult<Valid>) -> VResult<Valid> {
+        self.body.operators(f)
+    }
+}
+
+impl FuncSource for Func {
+    fn type_(&self) -> TypeIdx {
+        self.type_
+    }
+    fn locals(&self) -> Cow<'_, [ValType]> {
+        Cow::Borrowed(&self.locals)
+    }
+}
+
+impl<'a> ModuleSource for &'a Module {
+    type Func = &'a Func;
+    type Expr = &'a Expr;
+
+    fn types(&self) -> &[FuncType] {
+        &self.types
+    }
+    fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+    fn funcs(&self) -> impl Iterator<Item = &'a Func> + '_ {
+        self.funcs.iter()
+    }
+    fn tables(&self) -> impl Iterator<Item = TableType> + '_ {
+        self.tables.iter().map(|x| x.type_)
+    }
+    fn mems(&self) -> impl Iterator<Item = MemType> + '_ {
+        self.mems.iter().map(|x| x.type_)
+    }
+    fn globals(&self) -> impl ExactSizeIterator<Item = GlobalSource<&'a Expr>> + '_ {
+        self.globals.iter().map(|x| GlobalSource {
+            type_: x.type_,
+            init: &x.init,
+        })
+    }
+    fn elem(&self) -> impl Iterator<Item = ElemSource<'_, &'a Expr>> + '_ {
+        self.elem.iter().map(|x| ElemSource {
+            table: x.table,
+            offset: &x.offset,
+            init: Cow::Borrowed(&x.init),
+        })
+    }
+    fn data(&self) -> impl Iterator<Item = DataSource<&'a Expr>> + '_ {
+        self.data.iter().map(|x| DataSource {
+            data: x.data,
+            offset: &x.offset,
+        })
+    }
+    fn start(&self) -> Option<FuncIdx> {
+        self.start.as_ref().map(|x| x.func)
+    }
+    fn exports(&self) -> &[Export] {
+        &self.exports
+    }
+}
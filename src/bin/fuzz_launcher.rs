@@ -2,12 +2,10 @@
 extern crate greenwasm;
 
 use greenwasm::structure::types::*;
-use greenwasm::binary_format::*;
-use greenwasm::validation::*;
-use greenwasm::execution::modules::*;
 use greenwasm::execution::runtime_structure::*;
-use greenwasm::execution::modules::instantiation::*;
-use greenwasm::binary_format;
+use greenwasm::execution::instance::CallError;
+use greenwasm::execution::linker::LinkError;
+use greenwasm::LoadError;
 
 fn main() {
     let details = ::std::env::args().nth(2) == Some("--details".to_string());
@@ -33,65 +31,44 @@ fn main() {
 
 #[derive(Debug)]
 enum FuzzError {
-    IoError(std::io::Error),
-    ParseError(binary_format::NomErrorKind),
-    ValidationError(ValidationError),
-    InstantiationError(InstantiationError),
+    Io(std::io::Error),
+    Load(LoadError),
+    Link(LinkError),
 }
 
 fn run(path: &str, details: bool) -> ::std::result::Result<(), FuzzError> {
     let file = std::fs::read(&path)
-        .map_err(FuzzError::IoError)?;
+        .map_err(FuzzError::Io)?;
 
-    println!("Parsing...");
-    let (module, _custom_sections) = parse_binary_format(&file).map_err(|e| {
-        if !details {
-            match e {
-                ParseError::NomError(e) => {
-                    e.into_error_kind()
-                }
-            }
-        } else {
-            unimplemented!()
+    println!("Loading...");
+    let loaded = greenwasm::load(&file).map_err(|e| {
+        if details {
+            println!("{}", e);
         }
+        e
     })
-    .map_err(FuzzError::ParseError)?;
-
-    println!("Validation...");
-    let validated_module = validate_module(module)
-        .map_err(FuzzError::ValidationError)?;
-
-    println!("Instantiation...");
-    let mut store = Default::default();
-    let mut stack = Stack::new();
-
-    let moduleaddr = instantiation::instantiate_module(&mut store, &mut stack, &validated_module, &[])
-        .map_err(FuzzError::InstantiationError)?;
+    .map_err(FuzzError::Load)?;
+    let mut instance = loaded.instantiate().map_err(|e| {
+        if details {
+            println!("{}", e);
+        }
+        e
+    })
+    .map_err(FuzzError::Link)?;
 
     println!("Invocation of exports...");
 
-    assert!(validated_module.start.is_none());
+    let invokers: Vec<_> = instance.export_names()
+        .filter(|name| name.ends_with("_invoker"))
+        .map(|name| name.to_owned())
+        .collect();
 
-    let moduleinst = &store.modules[moduleaddr];
+    if instance.func_type("hangLimitInitializer").is_some() {
+        expect_no_error(instance.call("hangLimitInitializer", &[]));
 
-    let mut hanglimitinit = None;
-    let mut export_addrs = vec![];
-    for e in &moduleinst.exports {
-        //println!("{:?}", e);
-        if **e.name == "hangLimitInitializer" {
-            hanglimitinit = Some(if let ExternVal::Func(faddr) = e.value { faddr } else { panic!() });
-        }
-        if e.name.ends_with("_invoker") {
-            export_addrs.push(if let ExternVal::Func(faddr) = e.value { faddr } else { panic!() });
-        }
-    }
-    if let Some(hanglimitinit) = hanglimitinit {
-        invocation::invoke(&mut store, &mut stack, hanglimitinit, &[]).unwrap();
-
-        for faddr in export_addrs {
-            let fty = store.funcs[faddr].type_();
+        for name in invokers {
             let mut vals = vec![];
-            for ty in &fty.args {
+            for ty in &instance.func_type(&name).unwrap().args {
                 match ty {
                     ValType::I32 => vals.push(Val::I32(0)),
                     ValType::I64 => vals.push(Val::I64(0)),
@@ -100,9 +77,17 @@ fn run(path: &str, details: bool) -> ::std::result::Result<(), FuzzError> {
                 }
             }
 
-            invocation::invoke(&mut store, &mut stack, faddr, &vals).unwrap();
+            expect_no_error(instance.call(&name, &vals));
         }
     }
 
     Ok(())
 }
+
+// NB: A trap is a valid outcome of an invocation
+fn expect_no_error(r: ::std::result::Result<Vec<Val>, CallError>) {
+    match r {
+        Ok(_) | Err(CallError::Trap) => {}
+        Err(e) => panic!("{}", e),
+    }
+}
@@ -4,3 +4,57 @@ pub extern crate greenwasm_structure as structure;
 pub extern crate greenwasm_validation as validation;
 pub extern crate greenwasm_binary_format as binary_format;
 pub extern crate greenwasm_execution as execution;
+pub extern crate greenwasm_text_format as text_format;
+
+use binary_format::{parse_binary_format, ParseError, CustomSection};
+use validation::{validate_module, ValidatedModule, ValidationError};
+use execution::instance::Instance;
+use execution::linker::{Linker, LinkError};
+use execution::runtime_structure::Store;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Parse(ParseError),
+    Validation(ValidationError),
+}
+
+impl ::std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            LoadError::Parse(ref e) => write!(f, "parsing failed: {}", e),
+            LoadError::Validation(ref e) => write!(f, "validation failed: {}", e),
+        }
+    }
+}
+
+/// A module parsed and validated by `load`.
+pub struct Loaded {
+    pub module: ValidatedModule,
+    /// The custom sections of the binary, in order.
+    pub custom_sections: Vec<CustomSection>,
+}
+
+impl Loaded {
+    /// Instantiates the module in a new store. Modules with imports
+    /// fail with `LinkError::UnknownImport`.
+    pub fn instantiate(&self) -> Result<Instance<'_>, LinkError> {
+        Instance::new(&self.module)
+    }
+
+    /// Instantiates the module in `store`, with its imports resolved by `linker`.
+    pub fn instantiate_with<'m>(&'m self, store: Store<'m>, linker: &Linker)
+        -> Result<Instance<'m>, LinkError>
+    {
+        Instance::with_linker(&self.module, store, linker)
+    }
+}
+
+/// Parses and validates the binary module `bytes`.
+///
+/// NB: An `Instance` borrows its module, so the `Loaded`
+/// module has to be kept alive while it is used.
+pub fn load(bytes: &[u8]) -> Result<Loaded, LoadError> {
+    let (module, custom_sections) = parse_binary_format(bytes).map_err(LoadError::Parse)?;
+    let module = validate_module(module).map_err(LoadError::Validation)?;
+    Ok(Loaded { module, custom_sections })
+}
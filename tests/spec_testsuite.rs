@@ -5,7 +5,7 @@ extern crate greenwasm_spectest;
 
 use greenwasm::binary_format::parse_binary_format;
 use greenwasm::validation::{validate_module};
-use greenwasm::execution::modules::instantiation::instantiate_module;
+use greenwasm::execution::linker::Linker;
 use greenwasm::execution::modules::invocation::*;
 use greenwasm::execution::runtime_structure::*;
 use greenwasm::execution::runtime_structure::Result as IResult;
@@ -95,6 +95,14 @@ impl StoreCtrl {
     }
 }
 
+fn linker(store: &Store, modules: &HashMap<String, ModuleAddr>) -> Linker {
+    let mut linker = Linker::new();
+    for (name, &moduleaddr) in modules {
+        linker.define_instance(store, name, moduleaddr);
+    }
+    linker
+}
+
 fn val_wabt2greenwasm(v: Value) -> Val {
     match v {
         Value::I32(v) => Val::I32(v as _),
@@ -135,23 +143,8 @@ impl ScriptHandler for StoreCtrl {
             let validated_module = mytry!(validate_module(module), stst, "validation failed");
 
             let mut stst = stst;
-            let mut exports = vec![];
-            for i in &validated_module.imports {
-                // println!("i: {:?}", i);
-                let exporting_module = *mytry!(modules.get(&i.module[..]).ok_or(()), stst, "import module not found");
-                let exporting_module = &stst.store.modules[exporting_module];
-                let mut value = None;
-                for e in &exporting_module.exports {
-                    // println!("  e: {:?}", e);
-                    if e.name[..] == i.name[..] {
-                        value = Some(e.value);
-                        break;
-                    }
-                }
-                exports.push(mytry!(value.ok_or(()), stst, "import not found in import modules exports"));
-            }
-
-            let moduleaddr = mytry!(instantiate_module(&mut stst.store, &mut stst.stack, &validated_module, &exports), stst, "instantiation failed");
+            let linker = linker(&stst.store, modules);
+            let moduleaddr = mytry!(linker.instantiate(&mut stst.store, &mut stst.stack, &validated_module), stst, "instantiation failed");
 
             tx.send(Ok(moduleaddr)).unwrap();
             store_thread_frame(stst)
@@ -187,23 +180,8 @@ impl ScriptHandler for StoreCtrl {
             let validated_module = antimytry!(validate_module(module), stst, "validation failed");
 
             let mut stst = stst;
-            let mut exports = vec![];
-            for i in &validated_module.imports {
-                // println!("i: {:?}", i);
-                let exporting_module = *antimytry!(modules.get(&i.module[..]).ok_or(()), stst, "import module not found");
-                let exporting_module = &stst.store.modules[exporting_module];
-                let mut value = None;
-                for e in &exporting_module.exports {
-                    // println!("  e: {:?}", e);
-                    if e.name[..] == i.name[..] {
-                        value = Some(e.value);
-                        break;
-                    }
-                }
-                exports.push(antimytry!(value.ok_or(()), stst, "import not found in import modules exports"));
-            }
-
-            antimytry!(instantiate_module(&mut stst.store, &mut stst.stack, &validated_module, &exports), stst, "instantiation failed");
+            let linker = linker(&stst.store, modules);
+            antimytry!(linker.instantiate(&mut stst.store, &mut stst.stack, &validated_module), stst, "instantiation failed");
 
             tx.send(Err("instantiation did not fail")).unwrap();
             store_thread_frame(stst)
@@ -237,7 +215,7 @@ impl ScriptHandler for StoreCtrl {
                     Ok(IResult::Vals(v)) => {
                         Ok(InvokationResult::Vals(v.into_iter().map(val_greenwasm2wabt).collect()))
                     }
-                    Ok(IResult::Trap) => {
+                    Ok(IResult::Trap) | Ok(IResult::Host(_)) => {
                         Ok(InvokationResult::Trap)
                     }
                     Err(InvokeError::StackExhaustion) => {
@@ -5,7 +5,13 @@ use greenwasm::structure::modules::*;
 use greenwasm::structure::instructions::Instr::*;
 use greenwasm::structure::instructions::*;
 use greenwasm::binary_format::parse_binary_format;
+use greenwasm::binary_format::encode_binary_format;
+use greenwasm::binary_format::{parse_binary_stream, Event, CustomSection};
+use greenwasm::binary_format::parse_binary_format_lazy;
+use greenwasm::text_format::parse_text_format;
+use greenwasm::text_format::print_text_format;
 use greenwasm::validation::validate_module;
+use greenwasm::validation::validate_module_all;
 use greenwasm::execution::modules::instantiation::instantiate_module;
 use greenwasm::execution::modules::allocation::*;
 use greenwasm::execution::runtime_structure::*;
@@ -38,16 +44,90 @@ fn diff_print<T: ::std::fmt::Debug>(value_is: &T, value_should: &T) -> String {
     format!("Is:\n{}{}\nShould:\n{}{}", p, value_is, p, value_should)
 }
 
+// Reads one byte at a time, to exercise every split point of the stream parser
+struct ByteReader<'a>(&'a [u8]);
+impl<'a> std::io::Read for ByteReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok(1)
+    }
+}
+
+fn parse_stream(file: &[u8]) -> (Module, Vec<CustomSection>) {
+    let mut module = Module {
+        types: vec![].into(),
+        funcs: vec![].into(),
+        tables: vec![].into(),
+        mems: vec![].into(),
+        globals: vec![].into(),
+        elem: vec![].into(),
+        data: vec![].into(),
+        start: None,
+        imports: vec![].into(),
+        exports: vec![].into(),
+    };
+    let mut funcs = vec![];
+    let mut customs = vec![];
+    for event in parse_binary_stream(ByteReader(file)) {
+        match event.unwrap() {
+            Event::Types(x) => module.types = x,
+            Event::Imports(x) => module.imports = x,
+            Event::Funcs(_) => {}
+            Event::Tables(x) => module.tables = x,
+            Event::Mems(x) => module.mems = x,
+            Event::Globals(x) => module.globals = x,
+            Event::Exports(x) => module.exports = x,
+            Event::Start(x) => module.start = Some(x),
+            Event::Elem(x) => module.elem = x,
+            Event::Func(_, f) => funcs.push(f),
+            Event::Data(x) => module.data = x,
+            Event::Custom(c) => customs.push(c),
+            Event::End => {}
+        }
+    }
+    module.funcs = funcs.into();
+    (module, customs)
+}
+
 macro_rules! test_file {
     (@ $name:ident, $path:expr, $args:expr, $imports:expr, $module:expr) => (
         #[test]
         fn $name() {
             let file = std::fs::read($path).unwrap();
-            let (module, _custom_sections) = parse_binary_format(&file).unwrap();
+            let (module, custom_sections) = parse_binary_format(&file).unwrap();
             if let Some(ref_module) = $module {
                 assert!(module == ref_module, "{}", diff_print(&module, &ref_module));
             }
 
+            let (module1, custom_sections1) = parse_stream(&file);
+            assert!(format!("{:?}", module) == format!("{:?}", module1),
+                    "{}", diff_print(&module1, &module));
+            assert_eq!(custom_sections, custom_sections1);
+
+            let (lazy_module, custom_sections1) = parse_binary_format_lazy(&file).unwrap();
+            let module1 = lazy_module.decode().unwrap();
+            assert!(format!("{:?}", module) == format!("{:?}", module1),
+                    "{}", diff_print(&module1, &module));
+            assert_eq!(custom_sections, custom_sections1);
+
+            // NB: Compare Debug output, since a module with NaN
+            // constants is not equal to itself.
+            let encoded = encode_binary_format(&module, &custom_sections);
+            let (module2, custom_sections2) = parse_binary_format(&encoded).unwrap();
+            assert!(format!("{:?}", module) == format!("{:?}", module2),
+                    "{}", diff_print(&module2, &module));
+            assert_eq!(custom_sections, custom_sections2);
+            assert_eq!(encoded, encode_binary_format(&module2, &custom_sections2));
+
+            let text = print_text_format(&module, &custom_sections);
+            let module3 = parse_text_format(&text).unwrap();
+            assert!(format!("{:?}", module) == format!("{:?}", module3),
+                    "{}", diff_print(&module3, &module));
+
             let validated_module = validate_module(module).unwrap();
 
             println!("Is valid with {:?}", validated_module.import_export_mapping());
@@ -124,7 +204,7 @@ test_file!(factorial, "tests/wasm_files/factorial.wasm", (), |_, _| vec![], Modu
 test_file!(stuff, "tests/wasm_files/stuff.wasm", vec![
     FuncType { args: vec![ValType::F32].into(), results: vec![].into() }
 ], |args, store| {
-    let addr = alloc_host_function(store, HostFunc { id: 0 }, &args[0]);
+    let addr = alloc_host_function(store, Box::new(|_, _| Ok(vec![])), &args[0]);
 
     vec![ExternVal::Func(addr)]
 }, Module {
@@ -220,8 +300,1032 @@ test_file!(pong, "tests/wasm_files/pong.wasm", (), |_, _| vec![]);
 test_file!(function_space, "tests/wasm_files/function_space.wasm", vec![
     FuncType { args: vec![ValType::F32].into(), results: vec![].into() }
 ], |args, store| {
-    let addr = alloc_host_function(store, HostFunc { id: 0 }, &args[0]);
+    let addr = alloc_host_function(store, Box::new(|_, _| Ok(vec![])), &args[0]);
 
     vec![ExternVal::Func(addr)]
 });
 test_file!(parser_abort, "tests/wasm_files/parser_abort.wasm", (), |_, _| vec![]);
+
+#[test]
+fn text_format_function_space() {
+    let src = std::fs::read_to_string("tests/function_space.wat").unwrap();
+    let module = parse_text_format(&src).unwrap();
+
+    let file = std::fs::read("tests/wasm_files/function_space.wasm").unwrap();
+    let (ref_module, _) = parse_binary_format(&file).unwrap();
+
+    assert!(module == ref_module, "{}", diff_print(&module, &ref_module));
+}
+
+#[test]
+fn builder_factorial() {
+    use greenwasm::structure::builder::*;
+
+    let mut m = ModuleBuilder::new();
+    let fac = m.declare_func(&[ValType::F64], &[ValType::F64]);
+
+    let mut f = FunctionBuilder::new(&[ValType::F64], &[ValType::F64]);
+    let n = f.name_param(0, "n");
+    f.instrs(vec![GetLocal(n), F64Const(1.0), F64Lt]);
+    f.if_else(ValType::F64.into(), |f, _| {
+        f.instr(F64Const(1.0));
+    }, |f, _| {
+        f.instrs(vec![GetLocal(n), GetLocal(n), F64Const(1.0), F64Sub, Call(fac), F64Mul]);
+    });
+    m.define_func(fac, f);
+    m.export("fac", ExportDesc::Func(fac));
+
+    let module = m.build().unwrap();
+    let file = std::fs::read("tests/wasm_files/factorial.wasm").unwrap();
+    let (ref_module, _) = parse_binary_format(&file).unwrap();
+    assert!(module == ref_module, "{}", diff_print(&module, &ref_module));
+}
+
+#[test]
+#[should_panic(expected = "function 0 is defined with a different type than declared")]
+fn builder_mismatched_definition() {
+    use greenwasm::structure::builder::*;
+
+    let mut m = ModuleBuilder::new();
+    let f = m.declare_func(&[ValType::I32], &[ValType::I32]);
+    m.define_func(f, FunctionBuilder::new(&[ValType::I64], &[ValType::I32]));
+}
+
+#[test]
+#[should_panic(expected = "label is not in scope")]
+fn builder_closed_label() {
+    use greenwasm::structure::builder::*;
+
+    let mut f = FunctionBuilder::new(&[], &[]);
+    let mut first = None;
+    f.block(None.into(), |_, l| first = Some(l));
+    // NB: The label of the sibling block is at the same level
+    f.block(None.into(), |f, _| {
+        f.br(first.unwrap());
+    });
+}
+
+#[test]
+fn builder_undefined_func() {
+    use greenwasm::structure::builder::*;
+
+    let mut m = ModuleBuilder::new();
+    m.import_func("env", "f", &[], &[]);
+    m.func(FunctionBuilder::new(&[], &[]));
+    m.declare_func(&[], &[]);
+    match m.build() {
+        Err(e @ BuildError::UndefinedFunc(FuncIdx(2))) => {
+            assert_eq!(e.to_string(), "function 2 is declared but not defined");
+        }
+        _ => panic!("expected an undefined function"),
+    }
+}
+
+#[test]
+fn builder_labels() {
+    use greenwasm::structure::builder::*;
+
+    let mut m = ModuleBuilder::new();
+    let print = m.import_func("env", "print", &[ValType::I32], &[]);
+    let ty = m.type_(&[ValType::I32], &[]);
+    assert_eq!(ty, TypeIdx(0));
+
+    let mut f = FunctionBuilder::new(&[], &[ValType::I32]);
+    let i = f.named_local("i", ValType::I32);
+    assert_eq!(f.local_named("i"), LocalIdx(0));
+    let body = f.body_label();
+    f.block(None.into(), |f, exit| {
+        f.loop_(None.into(), |f, top| {
+            f.instrs(vec![GetLocal(i), Call(print), GetLocal(i), I32Const(1), I32Add, TeeLocal(i)]);
+            f.instr(I32Const(10)).instr(I32GeU).br_if(exit);
+            f.instr(GetLocal(i)).instr(I32Const(5)).instr(I32Eq);
+            f.if_(None.into(), |f, _| {
+                f.instr(I32Const(5)).br(body);
+            });
+            f.br(top);
+        });
+    });
+    f.instr(GetLocal(i));
+    let x = m.func(f);
+    assert_eq!(x, FuncIdx(1));
+    m.export("run", ExportDesc::Func(x));
+
+    let module = m.build().unwrap();
+    assert_eq!(module.types.len(), 2);
+    match module.funcs[0].body.body[0] {
+        Block(_, ref b) => match b[0] {
+            Loop(_, ref l) => {
+                assert_eq!(l[8], BrIf(LabelIdx(1)));
+                match l[12] {
+                    IfElse(_, ref t, _) => assert_eq!(t[1], Br(LabelIdx(3))),
+                    ref i => panic!("{:?}", i),
+                }
+                assert_eq!(l[13], Br(LabelIdx(0)));
+            }
+            ref i => panic!("{:?}", i),
+        },
+        ref i => panic!("{:?}", i),
+    }
+
+    assert!(validate_module(module).is_ok());
+}
+
+#[test]
+fn visitors() {
+    use greenwasm::structure::visit::*;
+
+    let mut module = parse_text_format(r#"(module
+        (global i32 (i32.const 7))
+        (func (param i32) (result i32)
+            block
+                loop
+                    get_local 0
+                    if (result i32)
+                        i32.const 1
+                        nop
+                    else
+                        block (result i32)
+                            i32.const 2
+                        end
+                    end
+                    br_if 1
+                end
+            end
+            i32.const 3))"#).unwrap();
+
+    #[derive(Default)]
+    struct Count {
+        instrs: usize,
+        consts: Vec<u32>,
+        depth: usize,
+        max_depth: usize,
+    }
+    impl Count {
+        fn nested(&mut self, bodies: &[&[Instr]]) {
+            self.depth += 1;
+            self.max_depth = self.max_depth.max(self.depth);
+            for body in bodies {
+                walk_instrs(self, body);
+            }
+            self.depth -= 1;
+        }
+    }
+    impl Visit for Count {
+        fn visit_instr(&mut self, instr: &Instr) {
+            self.instrs += 1;
+            walk_instr(self, instr)
+        }
+        fn visit_i32_const(&mut self, n: &u32) {
+            self.consts.push(*n);
+        }
+        fn visit_block(&mut self, _: &ResultType, body: &[Instr]) {
+            self.nested(&[body]);
+        }
+        fn visit_loop(&mut self, _: &ResultType, body: &[Instr]) {
+            self.nested(&[body]);
+        }
+        fn visit_if_else(&mut self, _: &ResultType, then: &[Instr], else_: &[Instr]) {
+            self.nested(&[then, else_]);
+        }
+    }
+
+    let mut count = Count::default();
+    count.visit_module(&module);
+    assert_eq!(count.instrs, 11);
+    assert_eq!(count.consts, vec![1, 2, 3, 7]);
+    assert_eq!(count.max_depth, 4);
+
+    // Doubles the constants in function bodies, and replaces nops with unreachable
+    struct Rewrite;
+    impl VisitMut for Rewrite {
+        fn visit_global(&mut self, _: &mut Global) {}
+        fn visit_instr(&mut self, instr: &mut Instr) {
+            if *instr == Nop {
+                *instr = Unreachable;
+            }
+            walk_instr_mut(self, instr)
+        }
+        fn visit_i32_const(&mut self, n: &mut u32) {
+            *n *= 2;
+        }
+    }
+
+    Rewrite.visit_module(&mut module);
+    let mut count = Count::default();
+    count.visit_module(&module);
+    assert_eq!(count.consts, vec![2, 4, 6, 7]);
+    match module.funcs[0].body.body[0] {
+        Block(_, ref b) => match b[0] {
+            Loop(_, ref l) => match l[1] {
+                IfElse(_, ref t, _) => assert_eq!(t[..], [I32Const(2), Unreachable]),
+                ref i => panic!("{:?}", i),
+            },
+            ref i => panic!("{:?}", i),
+        },
+        ref i => panic!("{:?}", i),
+    }
+}
+
+#[test]
+fn validation_error_location() {
+    use greenwasm::validation::ValidationErrorEnum::*;
+
+    fn err(src: &str) -> greenwasm::validation::ValidationError {
+        let module = parse_text_format(src).unwrap();
+        validate_module(module).err().unwrap()
+    }
+
+    let e = err(r#"(module
+        (import "env" "f" (func))
+        (func (param i32) (result i32)
+            block (result i32)
+                i32.const 1
+                f32.const 2
+                i32.add
+            end))"#);
+    assert_eq!(e.kind, InstrOperandTypeMismatch { expected: ValType::I32, actual: ValType::F32 });
+    assert_eq!(e.func, Some(FuncIdx(1)));
+    assert_eq!(e.instr, vec![0, 2]);
+    assert_eq!(e.to_string(), "type mismatch: expected i32, found f32 in function 1 at instruction 0.2");
+
+    let e = err(r#"(module
+        (func (result i32)
+            i32.const 0
+            if (result i32)
+                i32.const 1
+            else
+                i32.const 1
+                i64.const 2
+                i32.add
+            end))"#);
+    assert_eq!(e.kind, InstrOperandTypeMismatch { expected: ValType::I32, actual: ValType::I64 });
+    assert_eq!((e.func, &e.instr[..]), (Some(FuncIdx(0)), &[1, 3][..]));
+
+    let e = err("(module (func) (func nop get_local 3 drop))");
+    assert_eq!(e.kind, CtxLocalsIdxDoesNotExist(LocalIdx(3)));
+    assert_eq!((e.func, &e.instr[..]), (Some(FuncIdx(1)), &[1][..]));
+
+    let e = err("(module (func unreachable select i32.const 1))");
+    assert_eq!(e.kind, InstrOperandStackNotEmpty { expected: vec![], actual: vec![None, Some(ValType::I32)] });
+    assert_eq!((e.func, &e.instr[..]), (Some(FuncIdx(0)), &[][..]));
+    assert_eq!(e.to_string(), "type mismatch: block expects [] at the end, found [unknown i32] in function 0");
+
+    let e = err("(module (func (result i32) drop))");
+    assert_eq!(e.kind, InstrOperandStackUnderflow { expected: None });
+
+    let e = err("(module (global i32 (i32.const 0)) (global i32 (get_global 7)))");
+    assert_eq!(e.kind, CtxGlobalsIdxDoesNotExist(GlobalIdx(7)));
+    assert_eq!(e.func, None);
+    assert_eq!(e.to_string(), "unknown global 7 at instruction 0");
+}
+
+#[test]
+fn validation_all_errors() {
+    use greenwasm::validation::ValidationErrorEnum::*;
+
+    let src = r#"(module
+        (func (result i32) f32.const 0)
+        (func nop)
+        (func get_local 0 drop)
+        (memory 1)
+        (memory 1)
+        (export "a" (func 1))
+        (export "a" (func 1)))"#;
+
+    let errors = validate_module_all(parse_text_format(src).unwrap()).err().unwrap();
+    let kinds: Vec<_> = errors.iter().map(|e| (e.func, e.kind.clone())).collect();
+    assert_eq!(kinds, vec![
+        (Some(FuncIdx(0)), InstrOperandTypeMismatch { expected: ValType::I32, actual: ValType::F32 }),
+        (Some(FuncIdx(2)), CtxLocalsIdxDoesNotExist(LocalIdx(0))),
+        (None, ModuleMemsLengthNotOne),
+        (None, ModuleExportDuplicateName),
+    ]);
+
+    // The first error is the one `validate_module` reports
+    let e = validate_module(parse_text_format(src).unwrap()).err().unwrap();
+    assert_eq!(e, errors[0]);
+
+    assert!(validate_module_all(parse_text_format("(module (func nop))").unwrap()).is_ok());
+}
+
+#[test]
+fn validation_first_func_error() {
+    // One valid function, followed by functions that each have a type error
+    let mut src = String::from("(module (func)");
+    for _ in 0..8 {
+        src.push_str(" (func (result i32) f32.const 0)");
+    }
+    src.push(')');
+
+    // NB: With the `parallel` feature, the bodies are validated in any order
+    for _ in 0..20 {
+        let e = validate_module(parse_text_format(&src).unwrap()).err().unwrap();
+        assert_eq!(e.func, Some(FuncIdx(1)));
+
+        let errors = validate_module_all(parse_text_format(&src).unwrap()).err().unwrap();
+        let funcs: Vec<_> = errors.iter().map(|e| e.func).collect();
+        assert_eq!(funcs, (1..9).map(|i| Some(FuncIdx(i))).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn validation_side_tables() {
+    use greenwasm::validation::{Branch, BranchTarget};
+
+    let module = parse_text_format(r#"(module
+        (func (param i32) (result i32)
+            block (result i32)
+                i32.const 1
+                i32.const 2
+                get_local 0
+                br_if 0
+                drop
+            end
+            loop
+                br 0
+            end
+            return)
+        (func
+            block
+                block
+                    i32.const 0
+                    br_table 0 1 1
+                end
+            end))"#).unwrap();
+    let validated = validate_module(module).unwrap();
+    let infos = validated.func_infos();
+    assert_eq!(infos.len(), 2);
+
+    let target = |depth, arity, drop| BranchTarget { depth, arity, drop };
+    assert_eq!(infos[0].max_stack_depth, 3);
+    assert_eq!(infos[0].max_label_depth, 2);
+    assert_eq!(infos[0].branches, vec![
+        Branch { instr: vec![0, 3], targets: vec![target(1, 1, 1)] },
+        Branch { instr: vec![1, 0], targets: vec![target(1, 0, 0)] },
+        Branch { instr: vec![2], targets: vec![target(0, 1, 0)] },
+    ]);
+    assert_eq!(infos[0].branches[0].target_instr(&target(1, 1, 1)), &[0]);
+
+    assert_eq!(infos[1].max_stack_depth, 1);
+    assert_eq!(infos[1].max_label_depth, 3);
+    assert_eq!(infos[1].branches, vec![
+        Branch { instr: vec![0, 0, 1], targets: vec![target(2, 0, 0), target(1, 0, 0), target(1, 0, 0)] },
+    ]);
+}
+
+#[test]
+fn wasm_features() {
+    use greenwasm::structure::features::{WasmFeatures, Feature};
+    use greenwasm::binary_format::{parse_binary_format_with_config, ParseErrorKind, ParseConfig};
+    use greenwasm::validation::{validate_module_with, ValidationConfig, ValidationErrorEnum};
+    use greenwasm::execution::modules::instantiation::{instantiate_module_with_config, InstantiationConfig, InstantiationError};
+    use greenwasm::execution::modules::invocation::invoke;
+
+    let src = r#"(module
+        (func (export "f") (param i32) (result i32)
+            get_local 0
+            i32.extend8_s))"#;
+    let all = WasmFeatures::all();
+
+    // The parser rejects the opcode unless the feature is enabled
+    let bytes = encode_binary_format(&parse_text_format(src).unwrap(), &[]);
+    let opcode = bytes.iter().rposition(|&b| b == 0xC0).unwrap();
+    let e = parse_binary_format(&bytes).err().unwrap();
+    assert_eq!(e.kind, ParseErrorKind::FeatureDisabled(Feature::SignExtension));
+    assert_eq!(e.offset, opcode);
+    assert_eq!(e.func, Some(FuncIdx(0)));
+    let config = ParseConfig { features: all, ..Default::default() };
+    let (module, _) = parse_binary_format_with_config(&bytes, &config).unwrap();
+    assert_eq!(module.funcs[0].body.body, vec![GetLocal(LocalIdx(0)), I32Extend8S]);
+
+    // So does the validator
+    let e = validate_module(parse_text_format(src).unwrap()).err().unwrap();
+    assert_eq!(e.kind, ValidationErrorEnum::FeatureDisabled(Feature::SignExtension));
+    assert_eq!(e.func, Some(FuncIdx(0)));
+    assert_eq!(&e.instr[..], &[1]);
+
+    let config = ValidationConfig { features: all, ..Default::default() };
+    let validated = validate_module_with(parse_text_format(src).unwrap(), &config).unwrap();
+    assert!(validated.features().sign_extension);
+    assert_eq!(validated.func_infos()[0].features, all);
+
+    // Instantiation checks the features the module actually uses
+    let mut store = Default::default();
+    let mut stack = Stack::new();
+    match instantiate_module(&mut store, &mut stack, &validated, &[]) {
+        Err(InstantiationError::FeatureDisabled(Feature::SignExtension)) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    let config = InstantiationConfig { features: all };
+    let moduleaddr = instantiate_module_with_config(&mut store, &mut stack, &validated, &[], &config).unwrap();
+
+    let funcaddr = match store.modules[moduleaddr].exports[0].value {
+        ExternVal::Func(funcaddr) => funcaddr,
+        _ => unreachable!(),
+    };
+    match invoke(&mut store, &mut stack, funcaddr, &[Val::I32(0x1_80)]) {
+        Ok(Result::Vals(vals)) => assert_eq!(vals, vec![Val::I32(0xFFFF_FF80)]),
+        _ => panic!("invocation failed"),
+    }
+}
+
+#[test]
+fn func_validator() {
+    use greenwasm::validation::{Ctx, FuncValidator, Operator, FuncInfo, ValidationConfig};
+    use greenwasm::validation::{VResult, ValidationErrorEnum};
+
+    // The operators of a body as they appear in the binary format
+    fn flatten<'i>(instrs: &'i [Instr], ops: &mut Vec<Operator<'i>>) {
+        for instr in instrs {
+            match *instr {
+                Block(resulttype, ref block) => {
+                    ops.push(Operator::Block(resulttype));
+                    flatten(block, ops);
+                    ops.push(Operator::End);
+                }
+                Loop(resulttype, ref block) => {
+                    ops.push(Operator::Loop(resulttype));
+                    flatten(block, ops);
+                    ops.push(Operator::End);
+                }
+                IfElse(resulttype, ref if_block, ref else_block) => {
+                    ops.push(Operator::If(resulttype));
+                    flatten(if_block, ops);
+                    if !else_block.is_empty() {
+                        ops.push(Operator::Else);
+                        flatten(else_block, ops);
+                    }
+                    ops.push(Operator::End);
+                }
+                ref instr => ops.push(Operator::Instr(instr)),
+            }
+        }
+    }
+
+    fn validate_ops(module: &Module, func: &Func) -> VResult<FuncInfo> {
+        let c = Ctx::for_module(module, &ValidationConfig::default())?;
+        let mut v = FuncValidator::new(&c, &module.types[func.type_.0 as usize], &func.locals)?;
+        let mut ops = vec![];
+        flatten(&func.body.body, &mut ops);
+        ops.push(Operator::End);
+        for op in ops {
+            v.op(op)?;
+        }
+        v.finish()
+    }
+
+    // Same side tables as `validate_module`
+    let module = parse_text_format(r#"(module
+        (memory 1)
+        (func (param i32) (result i32)
+            get_local 0
+            if (result i32)
+                i32.const 1
+            else
+                get_local 0
+                i32.load
+            end
+            block
+                loop
+                    get_local 0
+                    br_if 1
+                end
+                i32.const 0
+                br_table 0 0
+            end
+            return)
+        (func
+            i32.const 0
+            if
+                nop
+            end))"#).unwrap();
+    let infos: Vec<_> = module.funcs.iter().map(|f| validate_ops(&module, f).unwrap()).collect();
+    assert_eq!(&infos[..], validate_module(module).unwrap().func_infos());
+
+    // Same errors as `validate_module`, but without the function
+    for src in &[
+        "(module (func block i32.const 0 f32.neg drop end))",
+        "(module (func (result i32) i32.const 0 if (result i32) i32.const 1 else f32.const 0 end))",
+        "(module (func (result i32) i32.const 0 if (result i32) i32.const 1 end))",
+        "(module (func (result i32) block br 1 end))",
+        "(module (func block i32.const 0 end))",
+        "(module (func (result f32) i32.const 0))",
+    ] {
+        let module = parse_text_format(src).unwrap();
+        let e = validate_ops(&module, &module.funcs[0]).err().unwrap();
+        let expected = validate_module(module).err().unwrap();
+        assert_eq!((&e.kind, &e.instr), (&expected.kind, &expected.instr), "{}", src);
+        assert_eq!(e.func, None);
+    }
+
+    // The stack state after each operator
+    let module = parse_text_format("(module)").unwrap();
+    let c = Ctx::for_module(&module, &ValidationConfig::default()).unwrap();
+    let ty = FuncType { args: vec![ValType::I64].into(), results: vec![].into() };
+    let mut v = FuncValidator::new(&c, &ty, &[ValType::F32]).unwrap();
+    assert_eq!(v.op(Operator::Instr(&GetLocal(LocalIdx(1)))).unwrap(), &[Some(ValType::F32)]);
+    assert_eq!(v.op(Operator::Block(ValType::I64.into())).unwrap(), &[Some(ValType::F32)]);
+    assert_eq!(v.label_depth(), 2);
+    assert_eq!(v.op(Operator::Instr(&Unreachable)).unwrap(), &[Some(ValType::F32)]);
+    assert!(v.is_unreachable());
+    assert_eq!(v.op(Operator::Instr(&Select)).unwrap(), &[Some(ValType::F32), None]);
+    assert_eq!(v.op(Operator::End).unwrap(), &[Some(ValType::F32), Some(ValType::I64)]);
+    assert!(!v.is_unreachable());
+    assert_eq!(v.op(Operator::Else).err().unwrap().kind, ValidationErrorEnum::InstrElseWithoutIf);
+
+    let mut v = FuncValidator::new(&c, &ty, &[]).unwrap();
+    assert_eq!(v.op(Operator::End).unwrap(), &[]);
+    assert_eq!(v.op(Operator::Instr(&Nop)).err().unwrap().kind, ValidationErrorEnum::InstrAfterEnd);
+    assert!(v.finish().is_ok());
+
+    let mut v = FuncValidator::new(&c, &ty, &[]).unwrap();
+    v.op(Operator::Loop(None.into())).unwrap();
+    assert_eq!(v.finish().err().unwrap().kind, ValidationErrorEnum::InstrMissingEnd);
+}
+
+#[test]
+fn validate_generic_source() {
+    use std::borrow::Cow;
+    use std::iter;
+    use greenwasm::validation::{ModuleSource, FuncSource, InstrSource};
+    use greenwasm::validation::{GlobalSource, ElemSource, DataSource};
+    use greenwasm::validation::{validate_source, validate_source_all, ValidationConfig};
+    use greenwasm::validation::{Operator, VResult, Valid, ValidationErrorEnum};
+
+    // A module of functions of type [] -> [i32] in a custom IR,
+    // which is only turned into instructions while being validated
+    #[derive(Copy, Clone)]
+    enum Op { Const(u32), Add, Block, BrIf(u32), End }
+
+    struct Toy(Vec<FuncType>, Vec<Vec<Op>>);
+    struct ToyFunc<'a>(&'a [Op]);
+
+    impl<'a> InstrSource for ToyFunc<'a> {
+        fn operators(&self, f: &mut dyn FnMut(Operator) -> VResult<Valid>) -> VResult<Valid> {
+            for &op in self.0 {
+                match op {
+                    Op::Const(n) => f(Operator::Instr(&I32Const(n)))?,
+                    Op::Add => f(Operator::Instr(&I32Add))?,
+                    Op::Block => f(Operator::Block(None.into()))?,
+                    Op::BrIf(n) => f(Operator::Instr(&BrIf(LabelIdx(n))))?,
+                    Op::End => f(Operator::End)?,
+                };
+            }
+            Ok(Valid)
+        }
+    }
+
+    impl<'a> FuncSource for ToyFunc<'a> {
+        fn type_(&self) -> TypeIdx { TypeIdx(0) }
+        fn locals(&self) -> Cow<'_, [ValType]> { Cow::Borrowed(&[]) }
+    }
+
+    impl<'a> ModuleSource for &'a Toy {
+        type Func = ToyFunc<'a>;
+        type Expr = ToyFunc<'a>;
+
+        fn types(&self) -> &[FuncType] { &self.0 }
+        fn imports(&self) -> &[Import] { &[] }
+        fn funcs(&self) -> impl Iterator<Item = ToyFunc<'a>> + '_ { self.1.iter().map(|b| ToyFunc(b)) }
+        fn tables(&self) -> impl Iterator<Item = TableType> + '_ { iter::empty() }
+        fn mems(&self) -> impl Iterator<Item = MemType> + '_ { iter::empty() }
+        fn globals(&self) -> impl ExactSizeIterator<Item = GlobalSource<ToyFunc<'a>>> + '_ { iter::empty() }
+        fn elem(&self) -> impl Iterator<Item = ElemSource<'_, ToyFunc<'a>>> + '_ { iter::empty() }
+        fn data(&self) -> impl Iterator<Item = DataSource<ToyFunc<'a>>> + '_ { iter::empty() }
+        fn start(&self) -> Option<FuncIdx> { None }
+        fn exports(&self) -> &[Export] { &[] }
+    }
+
+    let config = ValidationConfig::default();
+    let types = vec![FuncType { args: vec![].into(), results: vec![ValType::I32].into() }];
+    let toy = Toy(types.clone(), vec![
+        vec![Op::Const(1), Op::Block, Op::Const(2), Op::BrIf(0), Op::End, Op::End],
+        vec![Op::Const(1), Op::Const(2), Op::Add, Op::End],
+    ]);
+    let info = validate_source(&toy, &config).unwrap();
+    assert_eq!(info.func_infos.len(), 2);
+    assert_eq!(info.func_infos[0].max_stack_depth, 2);
+    assert_eq!(info.func_infos[0].branches[0].instr, vec![1, 1]);
+
+    let toy = Toy(types, vec![
+        vec![Op::Add, Op::End],
+        vec![Op::Const(1)],
+        vec![Op::Const(1), Op::End, Op::End],
+    ]);
+    let errors = validate_source_all(&toy, &config).err().unwrap();
+    let errors: Vec<_> = errors.iter().map(|e| (e.func, e.kind.clone(), e.instr.clone())).collect();
+    assert_eq!(errors, vec![
+        (Some(FuncIdx(0)), ValidationErrorEnum::InstrOperandStackUnderflow { expected: Some(ValType::I32) }, vec![0]),
+        (Some(FuncIdx(1)), ValidationErrorEnum::InstrMissingEnd, vec![]),
+        (Some(FuncIdx(2)), ValidationErrorEnum::InstrAfterEnd, vec![]),
+    ]);
+
+    // `Module` is validated through the same traits
+    let module = parse_text_format(r#"(module
+        (import "env" "g" (global i32))
+        (global i32 (get_global 0))
+        (func (export "f") (result i32) get_global 1))"#).unwrap();
+    let info = validate_source(&module, &config).unwrap();
+    let validated = validate_module(module).unwrap();
+    assert_eq!(&info.import_export_mapping, validated.import_export_mapping());
+    assert_eq!(&info.func_infos[..], validated.func_infos());
+}
+
+#[test]
+fn validation_limits() {
+    use greenwasm::validation::{validate_module_with, ValidationConfig, ValidationLimits, ValidationErrorEnum};
+    use greenwasm::validation::{Ctx, FuncValidator, Operator};
+    use greenwasm::validation::ValidationErrorEnum::*;
+
+    let src = r#"(module
+        (memory 2)
+        (table 10 anyfunc)
+        (global i32 (i32.const 0))
+        (global (mut i32) (i32.const 0))
+        (func (export "f") (param i32) (local i32 i64)
+            block
+                loop
+                    get_local 0
+                    br_if 1
+                end
+            end)
+        (export "g" (global 0)))"#;
+    let validate = |limits: ValidationLimits| {
+        let config = ValidationConfig { limits, ..Default::default() };
+        validate_module_with(parse_text_format(src).unwrap(), &config)
+            .map(|_| ())
+            .map_err(|e| (e.kind.clone(), e.func, e.instr.clone()))
+    };
+    let default = ValidationLimits::default();
+
+    let cases: Vec<(ValidationLimits, (ValidationErrorEnum, Option<FuncIdx>, Vec<u32>))> = vec![
+        (ValidationLimits { max_memory_pages: 1, ..default }, (TooManyMemoryPages(1), None, vec![])),
+        (ValidationLimits { max_table_size: 9, ..default }, (TooManyTableElements(9), None, vec![])),
+        (ValidationLimits { max_locals: 2, ..default }, (TooManyLocals(2), Some(FuncIdx(0)), vec![])),
+        (ValidationLimits { max_body_size: 3, ..default }, (FuncBodyTooLarge(3), Some(FuncIdx(0)), vec![0, 0, 1])),
+        (ValidationLimits { max_nesting: 1, ..default }, (NestingTooDeep(1), Some(FuncIdx(0)), vec![0, 0])),
+        (ValidationLimits { max_globals: 1, ..default }, (TooManyGlobals(1), None, vec![])),
+        (ValidationLimits { max_exports: 1, ..default }, (TooManyExports(1), None, vec![])),
+    ];
+    for (limits, error) in cases {
+        assert_eq!(validate(limits), Err(error));
+    }
+
+    // The limits themselves are allowed
+    assert_eq!(validate(default), Ok(()));
+    assert_eq!(validate(ValidationLimits {
+        max_memory_pages: 2,
+        max_table_size: 10,
+        max_locals: 3,
+        max_body_size: 4,
+        max_nesting: 2,
+        max_globals: 2,
+        max_exports: 2,
+    }), Ok(()));
+
+    // Operators are counted the same as the instructions of a `Func`
+    let module = parse_text_format(src).unwrap();
+    let limits = ValidationLimits { max_body_size: 3, ..default };
+    let config = ValidationConfig { limits, ..Default::default() };
+    let c = Ctx::for_module(&module, &config).unwrap();
+    let ty = FuncType { args: vec![ValType::I32].into(), results: vec![].into() };
+    let mut v = FuncValidator::new(&c, &ty, &[]).unwrap();
+    v.op(Operator::Block(None.into())).unwrap();
+    v.op(Operator::Loop(None.into())).unwrap();
+    v.op(Operator::Instr(&GetLocal(LocalIdx(0)))).unwrap();
+    let e = v.op(Operator::Instr(&BrIf(LabelIdx(1)))).err().unwrap();
+    assert_eq!(e.kind, FuncBodyTooLarge(3));
+    assert_eq!(&e.instr[..], &[0, 0, 1]);
+}
+
+#[test]
+fn lints() {
+    use greenwasm::validation::{lint_module, Lint, LintId, LintLocation, LintConfig, Severity};
+
+    let src = r#"(module
+        (import "env" "f" (func))
+        (import "env" "g" (global i32))
+        (memory 1)
+        (global (mut i32) (i32.const 0))
+        (func (export "main") (param i32) (result i32) (local i32 i32)
+            block
+                nop
+            end
+            block
+                br 0
+                nop
+                nop
+            end
+            i32.const 1
+            i32.const 2
+            i32.const 0
+            select
+            get_global 0
+            i32.add
+            set_local 1
+            get_local 1)
+        (func)
+        (export "g" (global 1)))"#;
+    let module = validate_module(parse_text_format(src).unwrap()).unwrap();
+
+    let lint = |id, location| Lint { id, severity: Severity::Warn, location };
+    let lints = lint_module(&module, &LintConfig::default());
+    assert_eq!(lints, vec![
+        lint(LintId::RedundantBlock, LintLocation::Instr { func: FuncIdx(1), instr: vec![0] }),
+        lint(LintId::UnreachableCode, LintLocation::Instr { func: FuncIdx(1), instr: vec![1, 1] }),
+        lint(LintId::SelectOnConstant, LintLocation::Instr { func: FuncIdx(1), instr: vec![5] }),
+        lint(LintId::UnusedLocal, LintLocation::Local { func: FuncIdx(1), local: LocalIdx(2) }),
+        lint(LintId::UnusedFunc, LintLocation::Func(FuncIdx(2))),
+        lint(LintId::UnusedImport, LintLocation::Import(0)),
+        lint(LintId::UnusedMemory, LintLocation::Mem(MemIdx(0))),
+        lint(LintId::ExportedMutableGlobal, LintLocation::Export(1)),
+    ]);
+    assert_eq!(lints[1].to_string(),
+               "warning[unreachable-code]: unreachable code (function 1 at instruction 1.1)");
+
+    let mut config = LintConfig::all(Severity::Allow);
+    config.set(LintId::UnusedLocal, Severity::Deny);
+    let lints = lint_module(&module, &config);
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].to_string(),
+               "error[unused-local]: local is never used (local 2 of function 1)");
+}
+
+#[test]
+fn validation_policy() {
+    use greenwasm::validation::{validate_module_with, ValidationConfig, ValidationPolicy};
+    use greenwasm::validation::{Deterministic, IntegerOnly, ImportAllowList};
+    use greenwasm::validation::{Ctx, FuncValidator, Operator};
+    use greenwasm::validation::ValidationErrorEnum::*;
+
+    let validate = |src: &str, policy: &dyn ValidationPolicy| {
+        let module = parse_text_format(src).unwrap();
+        let config = ValidationConfig { policy: Some(policy), ..Default::default() };
+        validate_module_with(module, &config)
+            .map(|_| ())
+            .map_err(|e| (e.kind.clone(), e.func, e.instr.clone()))
+    };
+
+    let src = r#"(module
+        (import "env" "log" (func (param i32)))
+        (import "env" "mem" (memory 1))
+        (func (export "f") (param i32) (result i32)
+            get_local 0
+            call 0
+            f32.const 1
+            f32.abs
+            f32.const 2
+            f32.add
+            drop
+            get_local 0))"#;
+
+    // Only the arithmetic can produce nondeterministic NaNs
+    assert_eq!(validate(src, &Deterministic),
+               Err((PolicyInstrNotAllowed, Some(FuncIdx(1)), vec![5])));
+    assert_eq!(validate(src, &IntegerOnly),
+               Err((PolicyTypeNotAllowed(ValType::F32), Some(FuncIdx(1)), vec![2])));
+
+    // Types are also checked outside of instructions
+    assert_eq!(validate("(module (func (param f64)))", &IntegerOnly),
+               Err((PolicyTypeNotAllowed(ValType::F64), None, vec![])));
+    assert_eq!(validate("(module (func (local i32 f32)))", &IntegerOnly),
+               Err((PolicyTypeNotAllowed(ValType::F32), Some(FuncIdx(0)), vec![])));
+
+    let allow_log = ImportAllowList::new().allow("env", "log");
+    assert_eq!(validate(src, &allow_log), Err((PolicyImportNotAllowed(1), None, vec![])));
+    let allow_env = ImportAllowList::new().allow_module("env");
+    assert_eq!(validate(src, &allow_env), Ok(()));
+    assert_eq!(validate(src, &(allow_env, Deterministic)),
+               Err((PolicyInstrNotAllowed, Some(FuncIdx(1)), vec![5])));
+
+    // Block result types are checked when validating operators as well
+    let module = parse_text_format(src).unwrap();
+    let config = ValidationConfig { policy: Some(&IntegerOnly), ..Default::default() };
+    let c = Ctx::for_module(&module, &config).unwrap();
+    let ty = FuncType { args: vec![].into(), results: vec![].into() };
+    let mut v = FuncValidator::new(&c, &ty, &[]).unwrap();
+    let e = v.op(Operator::Block(ValType::F64.into())).err().unwrap();
+    assert_eq!(e.kind, PolicyTypeNotAllowed(ValType::F64));
+    assert_eq!(&e.instr[..], &[0]);
+}
+
+#[test]
+fn host_functions() {
+    use greenwasm::execution::modules::invocation::invoke;
+
+    let src = r#"(module
+        (import "env" "bump" (func $bump (param i32) (result i32)))
+        (memory (export "mem") 1)
+        (data (i32.const 8) "\2a")
+        (func (export "run") (param i32) (result i32)
+            get_local 0
+            call $bump
+            i32.const 1
+            i32.add))"#;
+    let validated = validate_module(parse_text_format(src).unwrap()).unwrap();
+    let ty = FuncType { args: vec![ValType::I32].into(), results: vec![ValType::I32].into() };
+
+    let mut store = Default::default();
+    let mut stack = Stack::new();
+
+    // Increments the byte at the address in the caller's memory, and returns its old value
+    let bump = alloc_host_function(&mut store, Box::new(|caller, args| {
+        caller.get_export("mem").ok_or_else(|| HostError::new("no exported memory"))?;
+        let addr = match args[0] {
+            Val::I32(addr) => addr as usize,
+            _ => unreachable!(),
+        };
+        let mem = caller.memory().ok_or_else(|| HostError::new("no memory"))?;
+        let byte = mem.data.get_mut(addr).ok_or_else(|| HostError::new(format!("{} is out of bounds", addr)))?;
+        *byte += 1;
+        Ok(vec![Val::I32(u32::from(*byte) - 1)])
+    }), &ty);
+
+    let moduleaddr = instantiate_module(&mut store, &mut stack, &validated, &[ExternVal::Func(bump)]).unwrap();
+    let run = match store.modules[moduleaddr].exports[1].value {
+        ExternVal::Func(funcaddr) => funcaddr,
+        _ => unreachable!(),
+    };
+
+    match invoke(&mut store, &mut stack, run, &[Val::I32(8)]) {
+        Ok(Result::Vals(vals)) => assert_eq!(vals, vec![Val::I32(43)]),
+        _ => panic!("invocation failed"),
+    }
+    match invoke(&mut store, &mut stack, run, &[Val::I32(8)]) {
+        Ok(Result::Vals(vals)) => assert_eq!(vals, vec![Val::I32(44)]),
+        _ => panic!("invocation failed"),
+    }
+
+    // An error of the host function unwinds the guest, and is passed on
+    match invoke(&mut store, &mut stack, run, &[Val::I32(0x1_0000)]) {
+        Ok(Result::Host(e)) => assert_eq!(e.to_string(), "65536 is out of bounds"),
+        _ => panic!("expected a host error"),
+    }
+    assert!(stack.is_empty());
+
+    // Called directly, there is no calling instance to get the memory from
+    match invoke(&mut store, &mut stack, bump, &[Val::I32(0)]) {
+        Ok(Result::Host(e)) => assert_eq!(e.message(), "no exported memory"),
+        _ => panic!("expected a host error"),
+    }
+
+    // Results that do not match the declared type are an error
+    for results in [vec![], vec![Val::I64(1)], vec![Val::I32(1), Val::I32(2)]] {
+        let bad = alloc_host_function(&mut store, Box::new(move |_, _| {
+            Ok(results.clone())
+        }), &ty);
+        let moduleaddr = instantiate_module(&mut store, &mut stack, &validated, &[ExternVal::Func(bad)]).unwrap();
+        let run = match store.modules[moduleaddr].exports[1].value {
+            ExternVal::Func(funcaddr) => funcaddr,
+            _ => unreachable!(),
+        };
+        match invoke(&mut store, &mut stack, run, &[Val::I32(8)]) {
+            Ok(Result::Host(e)) => assert!(e.message().ends_with("instead of [I32]"), "{}", e),
+            _ => panic!("expected a host error"),
+        }
+        assert!(stack.is_empty());
+    }
+}
+
+#[test]
+fn linker() {
+    use greenwasm::execution::linker::{Linker, LinkError};
+    use greenwasm::execution::modules::invocation::invoke;
+
+    let lib = validate_module(parse_text_format(r#"(module
+        (memory (export "mem") 1)
+        (global (export "base") i32 (i32.const 8))
+        (func (export "inc") (param i32) (result i32)
+            get_local 0
+            i32.const 1
+            i32.add))"#).unwrap()).unwrap();
+    let app = validate_module(parse_text_format(r#"(module
+        (import "host" "double" (func $double (param i32) (result i32)))
+        (import "lib" "inc" (func $inc (param i32) (result i32)))
+        (import "lib" "mem" (memory 1))
+        (import "lib" "base" (global i32))
+        (func (export "run") (result i32)
+            get_global 0
+            call $inc
+            call $double))"#).unwrap()).unwrap();
+    let ty = FuncType { args: vec![ValType::I32].into(), results: vec![ValType::I32].into() };
+
+    let mut store = Default::default();
+    let mut stack = Stack::new();
+    let mut linker = Linker::new();
+
+    let double = alloc_host_function(&mut store, Box::new(|_, args| match args[0] {
+        Val::I32(x) => Ok(vec![Val::I32(x * 2)]),
+        _ => unreachable!(),
+    }), &ty);
+    linker.define("host", "double", ExternVal::Func(double));
+
+    // The imports from "lib" are still missing
+    match linker.instantiate(&mut store, &mut stack, &app) {
+        Err(LinkError::UnknownImport { ref module, ref name }) if module == "lib" && name == "inc" => {}
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    let libaddr = linker.instantiate(&mut store, &mut stack, &lib).unwrap();
+    linker.define_instance(&store, "lib", libaddr);
+
+    let appaddr = linker.instantiate(&mut store, &mut stack, &app).unwrap();
+    let run = match store.modules[appaddr].exports[0].value {
+        ExternVal::Func(funcaddr) => funcaddr,
+        _ => unreachable!(),
+    };
+    match invoke(&mut store, &mut stack, run, &[]) {
+        Ok(Result::Vals(vals)) => assert_eq!(vals, vec![Val::I32(18)]),
+        _ => panic!("invocation failed"),
+    }
+
+    // A definition with the wrong type is reported with the import it is used for
+    linker.define("lib", "base", ExternVal::Func(double));
+    let e = linker.instantiate(&mut store, &mut stack, &app).err().unwrap();
+    assert!(e.to_string().starts_with("incompatible import \"lib\" \"base\""));
+    match e {
+        LinkError::IncompatibleImport { ref expected, ref found, .. } => {
+            assert!(matches!(**expected, ExternType::Global(_)));
+            assert!(matches!(**found, ExternType::Func(_)));
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn load_instance() {
+    use greenwasm::execution::instance::CallError;
+    use greenwasm::execution::linker::Linker;
+    use greenwasm::LoadError;
+
+    let src = r#"(module
+        (memory (export "mem") 1)
+        (global $count (mut i32) (i32.const 0))
+        (export "count" (global $count))
+        (func (export "add") (param i32 i32) (result i32)
+            get_global $count
+            i32.const 1
+            i32.add
+            set_global $count
+            get_local 0
+            get_local 1
+            i32.add)
+        (func (export "store") (param i32)
+            get_local 0
+            get_local 0
+            i32.store8)
+        (func (export "boom") unreachable))"#;
+    let bytes = encode_binary_format(&parse_text_format(src).unwrap(), &[]);
+
+    let loaded = greenwasm::load(&bytes).unwrap();
+    assert!(loaded.custom_sections.is_empty());
+    let mut instance = loaded.instantiate().unwrap();
+    assert_eq!(instance.export_names().collect::<Vec<_>>(), vec!["mem", "count", "add", "store", "boom"]);
+
+    assert_eq!(instance.call("add", &[Val::I32(2), Val::I32(3)]).unwrap(), vec![Val::I32(5)]);
+    assert_eq!(instance.get_global("count"), Some(Val::I32(1)));
+    instance.call("store", &[Val::I32(7)]).unwrap();
+    assert_eq!(instance.get_memory("mem").unwrap().data[7], 7);
+
+    assert_eq!(instance.func_type("add").unwrap().args.len(), 2);
+    assert!(instance.func_type("mem").is_none());
+    assert!(instance.get_memory("add").is_none());
+
+    match instance.call("boom", &[]) {
+        Err(CallError::Trap) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    match instance.call("nope", &[]) {
+        Err(CallError::UnknownFunc(ref name)) if name == "nope" => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert_eq!(instance.call("add", &[Val::I32(1), Val::I32(1)]).unwrap(), vec![Val::I32(2)]);
+
+    // Instances of the same module have their own store
+    let mut other = loaded.instantiate().unwrap();
+    assert_eq!(other.call("add", &[Val::I32(4), Val::I32(4)]).unwrap(), vec![Val::I32(8)]);
+    assert_eq!(other.get_global("count"), Some(Val::I32(1)));
+    assert_eq!(instance.get_global("count"), Some(Val::I32(2)));
+
+    let imports = encode_binary_format(&parse_text_format(r#"(module
+        (import "env" "f" (func (result i32)))
+        (func (export "g") (result i32) call 0))"#).unwrap(), &[]);
+    let loaded = greenwasm::load(&imports).unwrap();
+    match loaded.instantiate() {
+        Err(e) => assert_eq!(e.to_string(), "unknown import \"env\" \"f\""),
+        _ => panic!("expected a link error"),
+    }
+
+    let ty = FuncType { args: vec![].into(), results: vec![ValType::I32].into() };
+    let mut store = Store::new();
+    let f = alloc_host_function(&mut store, Box::new(|_, _| Ok(vec![Val::I32(42)])), &ty);
+    let mut linker = Linker::new();
+    linker.define("env", "f", ExternVal::Func(f));
+    let mut instance = loaded.instantiate_with(store, &linker).unwrap();
+    assert_eq!(instance.call("g", &[]).unwrap(), vec![Val::I32(42)]);
+
+    match greenwasm::load(b"\0asm") {
+        Err(LoadError::Parse(_)) => {}
+        _ => panic!("expected a parse error"),
+    }
+}